@@ -8,22 +8,35 @@ use std::{
     fmt::Debug,
     path::Path,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use chrono::Duration;
-use futures::{Sink, Stream, StreamExt, ready};
+use futures::{Sink, SinkExt, Stream, StreamExt, channel::mpsc, future, ready};
+use ordered_float::OrderedFloat;
 use pin_project::pin_project;
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use rustc_hash::FxHashMap;
 use serde::Serialize;
 use statrs::statistics::Statistics;
 
 use crate::{
-    Broker, BrokerEvent, ClientEvent, DataProvider, ExecType, Fill, FillState, InstId, LimitOrder,
-    MarketOrder, Order, OrderId, Portfolio, Timestamp, data::Bbo,
+    Broker, BrokerEvent, ClientEvent, ContractType, DataProvider, Engine, ExecType, Fill,
+    FillState, InstId, LimitOrder, MarketOrder, Order, OrderId, Portfolio, RejectReason, Timestamp,
+    clock::{Clock, DataClock},
+    data::{self, Bbo, Level, TopLevels, Trade},
+    strategy::Strategy,
 };
 
+/// 排队数量小于该值时视为耗尽
+const QUEUE_EPS: f64 = 1e-12;
+/// 一年的毫秒数，用于按年化利率计息
+const YEAR_MILLIS: f64 = 365. * 24. * 3600. * 1000.;
+/// fan_out中每个策略的数据通道的容量
+const FAN_OUT_BUFFER: usize = 1024;
+
 #[pin_project]
 pub struct SandboxBroker<DP, D, M> {
     limit_orders: FxHashMap<OrderId, LimitOrder>,
@@ -32,12 +45,51 @@ pub struct SandboxBroker<DP, D, M> {
     #[pin]
     data_provider: DP,
 
-    ts: Timestamp,
+    clock: DataClock,
+
+    /// 订单从发出到抵达交易所的延迟
+    latency: i64,
+    /// 尚未抵达交易所的ClientEvent及其抵达的时间戳
+    pending_client_events: VecDeque<(Timestamp, ClientEvent)>,
+
+    /// 市价单是否等到该产品的下一条数据到达后才成交
+    is_market_order_deferred: bool,
+    /// 等待下一条数据成交的市价单
+    pending_market_orders: Vec<MarketOrder>,
+
+    /// 订单推送从交易所到达策略的延迟
+    push_latency: i64,
+    /// 尚未到达策略的订单推送及其到达的时间戳
+    pending_order_events: VecDeque<(Timestamp, BrokerEvent<D>)>,
 
+    /// 是否模拟挂单的排队位置
+    is_queue_modeled: bool,
+    /// 各挂单之前排队的数量
+    queue_ahead: FxHashMap<OrderId, f64>,
+
+    /// 以结算货币计，正向合约为计价货币，反向合约为币
     cash: f64,
     transaction_cost_model: TransactionCostModel,
     portfolio: Portfolio,
     reporter: Reporter,
+
+    /// 现金为负（借款做多）时的年化利率
+    interest_rate_annual: f64,
+    /// 空头持仓市值的年化借贷利率
+    short_borrow_rate_annual: f64,
+
+    /// 买入持有基准：产品及名义持仓数量
+    benchmark: Option<(InstId, f64)>,
+
+    /// 所有随机的撮合与滑点决策都应使用该随机数生成器，以便用同一种子复现回测
+    rng: StdRng,
+    /// 挂单位于最优价时按概率成交
+    fill_probability_model: Option<FillProbabilityModel>,
+
+    /// 是否检查前视偏差
+    is_look_ahead_checked: bool,
+    /// 各订单最近一次下单或改单时，策略能看到的该产品最新数据的时间戳
+    submitted_ts: FxHashMap<OrderId, Timestamp>,
 }
 
 impl<DP, D, M> SandboxBroker<DP, D, M>
@@ -46,70 +98,585 @@ where
     D: MarketData<M>,
     M: MatchOrder,
 {
+    /// instruments为空，或数据耗尽前交易的产品都没有行情时返回错误
     pub async fn new(
         instruments: Vec<InstId>,
         mut data_provider: DP,
         cash: f64,
         transaction_cost_model: TransactionCostModel,
         report_frequency: Duration,
-    ) -> Self {
+    ) -> Result<Self> {
+        if instruments.is_empty() {
+            bail!("No instruments to backtest");
+        }
+        // 读取数据直到任一交易的产品有了行情。其余产品的数据可能较稀疏，在其数据到达前，该产品的订单会被拒绝
         let mut inst_matcher = FxHashMap::default();
-        let mut ts = 0;
-        while inst_matcher.len() < instruments.len() {
+        let mut ts = Timestamp::default();
+        while !instruments
+            .iter()
+            .any(|inst| inst_matcher.contains_key(inst))
+        {
             if let Some(data) = data_provider.next().await {
                 if let Some(matcher) = data.draw_matcher() {
                     ts = matcher.get_ts();
                     inst_matcher.insert(matcher.instrument_id(), matcher);
                 }
             } else {
-                tracing::error!("No enough data from the data provider");
-                break;
+                bail!("The data provider is exhausted before any of {instruments:?} has data");
             }
         }
 
-        let mut reporter = Reporter::new(report_frequency);
+        let mut reporter = Reporter::new(report_frequency, BucketPolicy::Last);
         reporter.insert(ts, cash);
 
-        Self {
+        Ok(Self {
             limit_orders: Default::default(),
             broker_events_buf: Default::default(),
             inst_matcher,
             data_provider,
-            ts,
+            clock: DataClock::new(ts),
+            latency: 0,
+            pending_client_events: Default::default(),
+            is_market_order_deferred: false,
+            pending_market_orders: Default::default(),
+            push_latency: 0,
+            pending_order_events: Default::default(),
+            is_queue_modeled: false,
+            queue_ahead: Default::default(),
             cash,
             transaction_cost_model,
             portfolio: Portfolio::new(),
             reporter,
-        }
+            interest_rate_annual: 0.,
+            short_borrow_rate_annual: 0.,
+            benchmark: None,
+            rng: StdRng::from_os_rng(),
+            fill_probability_model: None,
+            is_look_ahead_checked: false,
+            submitted_ts: Default::default(),
+        })
+    }
+
+    /// 启用成交概率模型。挂单位于同方向最优价时，每个新数据到达时按概率成交。
+    pub fn with_fill_probability_model(mut self, model: FillProbabilityModel) -> Self {
+        self.fill_probability_model = Some(model);
+        self
+    }
+
+    /// 指定产品的合约类型，用于产品信息中没有的产品。反向合约的现金与盈亏以币计。
+    ///
+    /// 现金只有一种结算货币，同一个SandboxBroker中交易的产品须为同一种合约类型
+    pub fn with_contract_type(
+        mut self,
+        instrument_id: InstId,
+        contract_type: ContractType,
+    ) -> Self {
+        self.portfolio =
+            std::mem::take(&mut self.portfolio).with_contract_type(instrument_id, contract_type);
+        self
+    }
+
+    /// 固定随机数种子。种子相同的两次回测结果完全一致。
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// 启用前视偏差的自检：订单只能以不早于其发出时该产品最新数据的数据成交，否则在debug构建中panic。
+    /// 用于防止撮合逻辑的改动引入对未来数据的使用。
+    pub fn with_look_ahead_check(mut self) -> Self {
+        self.is_look_ahead_checked = true;
+        self
+    }
+
+    /// 设置预热期。预热期内的净值记录仍会保存，但不参与绩效指标的计算。
+    pub fn with_warm_up(mut self, warm_up: Duration) -> Self {
+        self.reporter.set_warm_up(warm_up);
+        self
+    }
+
+    /// 设置订单延迟。在ts发出的ClientEvent，要到时间戳不早于 ts + latency 的数据到达时才会被处理。
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency.num_milliseconds();
+        self
+    }
+
+    /// 设置订单推送的延迟。在ts产生的下单、成交、撤单等推送，要到时间戳不早于 ts + push_latency 的数据到达时才推送给策略，
+    /// 与实盘中订单推送异步到达的时序一致。
+    pub fn with_push_latency(mut self, push_latency: Duration) -> Self {
+        self.push_latency = push_latency.num_milliseconds();
+        self
+    }
+
+    /// 市价单不以当前的数据立即成交，而是等该产品的下一条数据到达后以其价格成交。
+    /// 以逐笔成交回测时，下一笔成交的价格比上一笔过时的价格更接近实际的成交价。
+    pub fn with_deferred_market_orders(mut self) -> Self {
+        self.is_market_order_deferred = true;
+        self
+    }
+
+    /// 启用排队位置模型。挂单排在同价位已有挂单之后，随着同价位的数量减少而前移，排在其之前的数量耗尽时即成交。
+    pub fn with_queue_model(mut self) -> Self {
+        self.is_queue_modeled = true;
+        self
+    }
+
+    /// 设置借款利率。现金为负时，按相邻数据的时间间隔计提利息并从现金中扣除。
+    pub fn with_interest_rate(mut self, interest_rate_annual: f64) -> Self {
+        self.interest_rate_annual = interest_rate_annual;
+        self
+    }
+
+    /// 设置做空的借贷利率，按空头持仓的市值计提
+    pub fn with_short_borrow_rate(mut self, short_borrow_rate_annual: f64) -> Self {
+        self.short_borrow_rate_annual = short_borrow_rate_annual;
+        self
+    }
+
+    /// 以instrument_id的买入持有作为基准。在第一条数据时名义上用全部现金买入，之后逐tick按市价估值，
+    /// 并在reporter中记录与净值对齐的基准曲线。
+    pub fn with_benchmark(mut self, instrument_id: InstId) -> Self {
+        let Some(matcher) = self.inst_matcher.get(&instrument_id) else {
+            tracing::error!("No data of the benchmark instrument {instrument_id:?}");
+            return self;
+        };
+        let size = self.cash / matcher.market_price();
+        self.benchmark = Some((instrument_id, size));
+        self.reporter.set_benchmark(self.cash);
+        self
+    }
+
+    /// 设置第一个报告周期的起点，默认与频率的整数倍对齐
+    pub fn with_bin_origin(mut self, bin_origin: BinOrigin) -> Self {
+        self.reporter.set_bin_origin(bin_origin);
+        self
+    }
+
+    /// 设置同一报告周期内多个净值的记录方式，默认记录最后一个
+    pub fn with_bucket_policy(mut self, bucket_policy: BucketPolicy) -> Self {
+        self.reporter.bucket_policy = bucket_policy;
+        self
     }
 
     pub fn reporter(&self) -> &Reporter {
         &self.reporter
     }
 
+    pub fn into_reporter(self) -> Reporter {
+        self.reporter
+    }
+
+    pub fn portfolio(&self) -> &Portfolio {
+        &self.portfolio
+    }
+
+    /// 记录订单发出时该产品最新数据的时间戳。尚无该产品的数据时记录当前时刻
+    fn record_submission(&mut self, client_event: &ClientEvent) {
+        let (order_id, instrument_id) = match client_event {
+            ClientEvent::PlaceOrder(order) => (order.order_id(), order.instrument_id()),
+            ClientEvent::AmendOrder(order) => (order.order_id, order.instrument_id),
+            ClientEvent::CancelOrder(..) | ClientEvent::CancelAll(_) => return,
+        };
+        let ts = self
+            .inst_matcher
+            .get(&instrument_id)
+            .map_or(self.clock.now(), |matcher| matcher.get_ts());
+        self.submitted_ts.insert(order_id, ts);
+    }
+
+    /// 检查成交所用的数据不早于订单发出时的数据
+    fn check_look_ahead(&self, fill: &Fill) {
+        let (Some(submitted_ts), Some(matcher)) = (
+            self.submitted_ts.get(&fill.order_id),
+            self.inst_matcher.get(&fill.instrument_id),
+        ) else {
+            return;
+        };
+        debug_assert!(
+            matcher.get_ts() >= *submitted_ts,
+            "look-ahead bias: order {} submitted after data at {submitted_ts} is filled against data at {}",
+            fill.order_id,
+            matcher.get_ts()
+        );
+    }
+
     // 处理fill事件，更新资金和持仓，并记录到reporter中
     fn on_fill(&mut self, fill: &Fill) {
-        let cost = self.transaction_cost_model.calculate_cost(fill);
+        if self.is_look_ahead_checked {
+            self.check_look_ahead(fill);
+        }
+        let matcher = self.inst_matcher.get(&fill.instrument_id);
+        let relative_spread = matcher.map_or(0., |matcher| matcher.relative_spread());
+        let mid_price = matcher.and_then(|matcher| matcher.mid_price());
+        let contract_type = self.portfolio.contract_type(fill.instrument_id);
+        let cost_breakdown = self.transaction_cost_model.calculate_cost(
+            fill,
+            mid_price,
+            relative_spread,
+            contract_type,
+        );
+        // 价差成本已体现在成交价中，只扣除手续费与滑点
+        let cost = cost_breakdown.explicit();
         self.cash -= cost;
-        if fill.side {
-            self.cash -= fill.price * fill.filled_size;
+        let signed_size = if fill.side {
+            fill.filled_size
         } else {
-            self.cash += fill.price * fill.filled_size;
-        }
+            -fill.filled_size
+        };
+        self.cash -= contract_type.value(signed_size, fill.price);
 
         self.portfolio.update(fill);
-        let total_value = self.get_total_value();
-        self.reporter.insert(self.ts, total_value);
+        self.reporter.record_fill(fill, cost, contract_type);
+        self.reporter.record_cost(&cost_breakdown);
+        self.record_value();
         dbg!(fill);
+        self.cap_reduce_only_orders(fill.instrument_id);
+    }
+
+    /// 该方向的只减仓订单最多能成交的数量，即减至零持仓所需的数量
+    fn reducible_size(&self, instrument_id: InstId, side: bool) -> f64 {
+        let position = self.portfolio.position_size(instrument_id);
+        if side {
+            (-position).max(0.)
+        } else {
+            position.max(0.)
+        }
+    }
+
+    /// 持仓变化后，缩小超出持仓的只减仓挂单；没有可减的持仓时撤销
+    fn cap_reduce_only_orders(&mut self, instrument_id: InstId) {
+        let order_ids: Vec<_> = self
+            .limit_orders
+            .values()
+            .filter(|order| order.reduce_only && order.instrument_id == instrument_id)
+            .map(|order| order.order_id)
+            .collect();
+        for order_id in order_ids {
+            let order = self.limit_orders[&order_id];
+            let reducible = self.reducible_size(instrument_id, order.side);
+            if reducible < QUEUE_EPS {
+                self.limit_orders.remove(&order_id);
+                self.queue_ahead.remove(&order_id);
+                self.push_order_event(BrokerEvent::Canceled(order_id));
+            } else if order.unfilled_size() > reducible {
+                let order = self.limit_orders.get_mut(&order_id).unwrap();
+                order.size = order.filled_size + reducible;
+                let order = *order;
+                self.push_order_event(BrokerEvent::Amended(Order::Limit(order)));
+            }
+        }
     }
 
     // 处理新的市场数据，更新内部状态并尝试匹配限价单
-    pub fn on_data(&mut self, new_data: D) {
-        self.ts = new_data.get_ts();
+    /// 时间戳早于当前时刻的数据会被跳过并返回false，以免破坏Reporter的分桶
+    pub fn on_data(&mut self, new_data: D) -> bool {
+        let new_ts = new_data.get_ts();
+        if new_ts < self.clock.now() {
+            tracing::warn!(
+                "Skip out-of-order data: ts {new_ts} is earlier than the current ts {}",
+                self.clock.now()
+            );
+            return false;
+        }
+        // 新数据进入下一个频率桶前，以当前的状态记录上一个桶末的持仓快照
+        if self.reporter.is_past_bucket(new_ts) {
+            self.record_snapshots();
+        }
+        self.accrue_interest(new_ts);
+        self.clock.advance(new_ts);
         if let Some(matcher) = new_data.draw_matcher() {
-            self.inst_matcher.insert(matcher.instrument_id(), matcher);
+            let instrument_id = matcher.instrument_id();
+            self.portfolio
+                .update_price(instrument_id, matcher.market_price());
+            if let Some(prev_matcher) = self.inst_matcher.insert(instrument_id, matcher) {
+                self.update_queue_ahead(instrument_id, &prev_matcher);
+            }
+            self.fill_pending_market_orders(instrument_id);
             // 若有新的MatchOrder，尝试匹配所有的限价单。
             self.try_fill_placed_orders();
+            self.record_value();
+            self.mark_benchmark();
+        }
+        self.release_pending_client_events();
+        self.release_pending_order_events();
+        true
+    }
+
+    /// 以该产品最新的数据成交等待中的市价单
+    fn fill_pending_market_orders(&mut self, instrument_id: InstId) {
+        let (orders, pending_orders) = std::mem::take(&mut self.pending_market_orders)
+            .into_iter()
+            .partition(|order| order.instrument_id == instrument_id);
+        self.pending_market_orders = pending_orders;
+        for order in orders {
+            self.fill_market_order(&order);
+        }
+    }
+
+    /// 以最新的数据成交市价单。对手方没有报价时拒绝
+    fn fill_market_order(&mut self, order: &MarketOrder) {
+        match MatchOrder::fill_market_order(&self.inst_matcher, order) {
+            Some(fill) => {
+                self.on_fill(&fill);
+                self.push_order_event(BrokerEvent::Fill(fill));
+            }
+            None => {
+                tracing::warn!(
+                    "Market order {} rejected: no quotes on the opposite side",
+                    order.order_id
+                );
+                self.push_order_event(BrokerEvent::Rejected {
+                    order_id: order.order_id,
+                    reason: RejectReason::NoMarketData,
+                });
+            }
+        }
+    }
+
+    /// 按市价估值基准。须在同一时刻记录策略的净值之后调用，使两条曲线对齐
+    fn mark_benchmark(&mut self) {
+        let Some((instrument_id, size)) = self.benchmark else {
+            return;
+        };
+        let benchmark_value = self.inst_matcher[&instrument_id].market_price() * size;
+        self.reporter.set_benchmark(benchmark_value);
+    }
+
+    /// 按最新价格记录净值
+    fn record_value(&mut self) {
+        let total_value = self.get_total_value();
+        self.reporter.insert(self.clock.now(), total_value);
+    }
+
+    /// 记录各产品的持仓快照。快照只在频率桶结束时发布，故仅在跨桶与回测结束时构建
+    fn record_snapshots(&mut self) {
+        let inst_price = M::get_inst_market_price(&self.inst_matcher);
+        let mut snapshots: Vec<_> = inst_price
+            .iter()
+            .map(|(instrument_id, price)| {
+                let size = self.portfolio.position_size(*instrument_id);
+                InstrumentSnapshot {
+                    instrument_id: *instrument_id,
+                    size,
+                    price: *price,
+                    value: self
+                        .portfolio
+                        .contract_type(*instrument_id)
+                        .value(size, *price),
+                }
+            })
+            .collect();
+        snapshots.sort_by_key(|snapshot| snapshot.instrument_id as u8);
+        self.reporter.set_inst_snapshots(snapshots);
+    }
+
+    /// 按上一条数据到ts的时间间隔计提借款与做空的利息，以上一条数据的价格计算空头市值
+    fn accrue_interest(&mut self, ts: Timestamp) {
+        if ts <= self.clock.now()
+            || (self.interest_rate_annual == 0. && self.short_borrow_rate_annual == 0.)
+        {
+            return;
+        }
+        let inst_price = M::get_inst_market_price(&self.inst_matcher);
+        // 反向合约做空使现金为负，但这部分由持仓的价值抵消，不计为借款
+        let borrowed = (-self.cash - self.portfolio.get_inverse_value(&inst_price)).max(0.);
+        let short_value = if self.short_borrow_rate_annual > 0. {
+            self.portfolio.get_short_value(&inst_price)
+        } else {
+            0.
+        };
+        let years = (ts - self.clock.now()) as f64 / YEAR_MILLIS;
+        let interest = (borrowed * self.interest_rate_annual
+            + short_value * self.short_borrow_rate_annual)
+            * years;
+        if interest > 0. {
+            self.cash -= interest;
+            let total_value = self.get_total_value();
+            self.reporter.insert(ts, total_value);
+        }
+    }
+
+    /// 根据产品新旧的MatchOrder，更新该产品各挂单的排队位置
+    fn update_queue_ahead(&mut self, instrument_id: InstId, prev_matcher: &M) {
+        let matcher = &self.inst_matcher[&instrument_id];
+        for (order_id, ahead) in self.queue_ahead.iter_mut() {
+            let order = &self.limit_orders[order_id];
+            if order.instrument_id == instrument_id {
+                *ahead = matcher.update_queue_ahead(prev_matcher, order, *ahead);
+            }
+        }
+    }
+
+    /// 将挂单放入排队
+    fn enqueue(&mut self, order: &LimitOrder) {
+        if !self.is_queue_modeled {
+            return;
+        }
+        let ahead = self.inst_matcher[&order.instrument_id].queue_ahead(order);
+        self.queue_ahead.insert(order.order_id, ahead);
+    }
+
+    /// 推送订单事件。设置了推送延迟时，先缓存到到达时间
+    fn push_order_event(&mut self, event: BrokerEvent<D>) {
+        // 订单结束后不再需要其发出时刻
+        match &event {
+            BrokerEvent::Fill(fill) if fill.state == FillState::Filled => {
+                self.submitted_ts.remove(&fill.order_id);
+            }
+            BrokerEvent::Canceled(order_id) | BrokerEvent::Rejected { order_id, .. } => {
+                self.submitted_ts.remove(order_id);
+            }
+            _ => {}
+        }
+        if self.push_latency == 0 {
+            self.broker_events_buf.push_back(event);
+        } else {
+            let arrival_ts = self.clock.now() + self.push_latency;
+            self.pending_order_events.push_back((arrival_ts, event));
+        }
+    }
+
+    /// 将已到达的订单推送放入待推送的事件中
+    fn release_pending_order_events(&mut self) {
+        while let Some((arrival_ts, _)) = self.pending_order_events.front() {
+            if *arrival_ts > self.clock.now() {
+                break;
+            }
+            let (_, event) = self.pending_order_events.pop_front().unwrap();
+            self.broker_events_buf.push_back(event);
+        }
+    }
+
+    /// 处理所有已抵达交易所的ClientEvent
+    fn release_pending_client_events(&mut self) {
+        while let Some((arrival_ts, _)) = self.pending_client_events.front() {
+            if *arrival_ts > self.clock.now() {
+                break;
+            }
+            let (_, client_event) = self.pending_client_events.pop_front().unwrap();
+            self.handle_client_event(client_event);
+        }
+    }
+
+    // 处理抵达交易所的下单请求
+    fn place_order(&mut self, mut order: Order) {
+        if !self.inst_matcher.contains_key(&order.instrument_id()) {
+            tracing::warn!(
+                "Order {} rejected: no market data of {:?} yet",
+                order.order_id(),
+                order.instrument_id()
+            );
+            self.push_order_event(BrokerEvent::Rejected {
+                order_id: order.order_id(),
+                reason: RejectReason::NoMarketData,
+            });
+            return;
+        }
+        // 只减仓订单的数量不超过当前持仓
+        if order.reduce_only() {
+            let reducible = self.reducible_size(order.instrument_id(), order.side());
+            if reducible < QUEUE_EPS {
+                self.push_order_event(BrokerEvent::Rejected {
+                    order_id: order.order_id(),
+                    reason: RejectReason::ReduceOnly,
+                });
+                return;
+            }
+            match &mut order {
+                Order::Market(order) => order.size = order.size.min(reducible),
+                Order::Limit(order) => order.size = order.size.min(reducible),
+            }
+        }
+        match order {
+            Order::Market(order) if self.is_market_order_deferred => {
+                self.pending_market_orders.push(order);
+            }
+            Order::Market(order) => self.fill_market_order(&order),
+            Order::Limit(order) => {
+                if let Some(fill) =
+                    MatchOrder::try_fill_limit_order(&self.inst_matcher, &order, ExecType::Taker)
+                {
+                    self.on_fill(&fill);
+                    self.push_order_event(BrokerEvent::Fill(fill));
+                } else {
+                    self.enqueue(&order);
+                    self.limit_orders.insert(order.order_id, order);
+                    self.push_order_event(BrokerEvent::Placed(Order::Limit(order)));
+                }
+            }
+        }
+    }
+
+    // 处理抵达交易所的ClientEvent，例如下单、撤单、改单等
+    fn handle_client_event(&mut self, client_event: ClientEvent) {
+        match client_event {
+            ClientEvent::PlaceOrder(mut order) => {
+                if let Err(reason) = order.conform_to_profile() {
+                    let order_id = order.order_id();
+                    self.push_order_event(BrokerEvent::Rejected { order_id, reason });
+                    return;
+                }
+                self.place_order(order);
+            }
+            ClientEvent::AmendOrder(mut order) => {
+                order.conform_to_profile();
+                let Some(existing_order) = self.limit_orders.get_mut(&order.order_id) else {
+                    self.push_order_event(BrokerEvent::Rejected {
+                        order_id: order.order_id,
+                        reason: RejectReason::UnknownOrder,
+                    });
+                    return;
+                };
+                let is_repriced = existing_order.price != order.new_price;
+                existing_order.price = order.new_price;
+                existing_order.size = order.new_size;
+                let mut existing_order = *existing_order;
+                if existing_order.reduce_only {
+                    let reducible =
+                        self.reducible_size(existing_order.instrument_id, existing_order.side);
+                    existing_order.size = existing_order
+                        .size
+                        .min(existing_order.filled_size + reducible);
+                    self.limit_orders
+                        .insert(existing_order.order_id, existing_order);
+                }
+                self.push_order_event(BrokerEvent::Amended(Order::Limit(existing_order)));
+
+                // 改价后可以成交的挂单立即以挂单价成交
+                if let Some(fill) = MatchOrder::try_fill_limit_order(
+                    &self.inst_matcher,
+                    &existing_order,
+                    ExecType::Maker,
+                ) {
+                    self.limit_orders.remove(&order.order_id);
+                    self.queue_ahead.remove(&order.order_id);
+                    self.on_fill(&fill);
+                    self.push_order_event(BrokerEvent::Fill(fill));
+                } else if is_repriced {
+                    // 改价的挂单重新排队
+                    self.enqueue(&existing_order);
+                }
+            }
+            ClientEvent::CancelOrder(_, order_id) => {
+                self.limit_orders.remove(&order_id);
+                self.queue_ahead.remove(&order_id);
+                self.push_order_event(BrokerEvent::Canceled(order_id));
+            }
+            ClientEvent::CancelAll(instrument_id) => {
+                // 按order_id的顺序撤单，使事件的顺序确定
+                let mut order_ids: Vec<_> = self
+                    .limit_orders
+                    .values()
+                    .filter(|order| order.instrument_id == instrument_id)
+                    .map(|order| order.order_id)
+                    .collect();
+                order_ids.sort_unstable();
+                for order_id in order_ids {
+                    self.limit_orders.remove(&order_id);
+                    self.queue_ahead.remove(&order_id);
+                    self.push_order_event(BrokerEvent::Canceled(order_id));
+                }
+            }
         }
     }
 
@@ -120,16 +687,40 @@ where
             .iter()
             .filter_map(|(order_id, order)| {
                 MatchOrder::try_fill_limit_order(&self.inst_matcher, order, ExecType::Maker)
+                    .or_else(|| {
+                        // 排在之前的数量耗尽，则以挂单价成交
+                        let ahead = self.queue_ahead.get(order_id)?;
+                        (*ahead < QUEUE_EPS)
+                            .then(|| Fill::full(order, order.price, ExecType::Maker))
+                    })
+                    .or_else(|| {
+                        // 位于最优价的挂单按概率成交
+                        let model = self.fill_probability_model.as_ref()?;
+                        let touch_size = self
+                            .inst_matcher
+                            .get(&order.instrument_id)?
+                            .touch_size(order)?;
+                        model
+                            .is_filled(order, touch_size, &mut self.rng)
+                            .then(|| Fill::full(order, order.price, ExecType::Maker))
+                    })
                     .map(|fill| (*order_id, fill))
             })
             .collect();
 
-        // 将成交的挂单推入事件并移除
-        filled_orders.into_iter().for_each(|(order_id, fill)| {
-            self.limit_orders.remove(&order_id);
+        // 将成交的挂单推入事件并移除。只减仓挂单可能已因之前的成交被缩小或撤销
+        for (order_id, mut fill) in filled_orders {
+            let Some(order) = self.limit_orders.remove(&order_id) else {
+                continue;
+            };
+            if order.reduce_only {
+                fill.filled_size = fill.filled_size.min(order.unfilled_size());
+                fill.acc_filled_size = fill.acc_filled_size.min(order.size);
+            }
+            self.queue_ahead.remove(&order_id);
             self.on_fill(&fill);
-            self.broker_events_buf.push_back(BrokerEvent::Fill(fill));
-        })
+            self.push_order_event(BrokerEvent::Fill(fill));
+        }
     }
 
     pub fn get_total_value(&self) -> f64 {
@@ -144,43 +735,17 @@ where
     D: MarketData<M>,
     M: MatchOrder,
 {
-    // 处理ClientEvent，例如下单、撤单、改单等
+    // 处理ClientEvent。若设置了延迟，则先缓存，待数据推进到抵达时间后再处理
     async fn on_client_event(&mut self, client_event: ClientEvent) {
-        match client_event {
-            ClientEvent::PlaceOrder(order) => match order {
-                Order::Market(order) => {
-                    let fill = MatchOrder::fill_market_order(&self.inst_matcher, &order);
-                    self.on_fill(&fill);
-                    self.broker_events_buf.push_back(BrokerEvent::Fill(fill));
-                }
-                Order::Limit(order) => {
-                    if let Some(fill) = MatchOrder::try_fill_limit_order(
-                        &self.inst_matcher,
-                        &order,
-                        ExecType::Taker,
-                    ) {
-                        self.on_fill(&fill);
-                        self.broker_events_buf.push_back(BrokerEvent::Fill(fill));
-                    } else {
-                        self.limit_orders.insert(order.order_id, order);
-                        self.broker_events_buf
-                            .push_back(BrokerEvent::Placed(Order::Limit(order)));
-                    }
-                }
-            },
-            ClientEvent::AmendOrder(order) => {
-                if let Some(existing_order) = self.limit_orders.get_mut(&order.order_id) {
-                    existing_order.price = order.new_price;
-                    existing_order.size = order.new_size;
-                    self.broker_events_buf
-                        .push_back(BrokerEvent::Amended(Order::Limit(*existing_order)));
-                }
-            }
-            ClientEvent::CancelOrder(_, order_id) => {
-                self.limit_orders.remove(&order_id);
-                self.broker_events_buf
-                    .push_back(BrokerEvent::Canceled(order_id));
-            }
+        if self.is_look_ahead_checked {
+            self.record_submission(&client_event);
+        }
+        if self.latency == 0 {
+            self.handle_client_event(client_event);
+        } else {
+            let arrival_ts = self.clock.now() + self.latency;
+            self.pending_client_events
+                .push_back((arrival_ts, client_event));
         }
     }
 
@@ -192,20 +757,229 @@ where
         }
 
         // 获取最新的Bbo数据并更新字段，同时检查挂单能否被fill。将新的fill的挂单与Bbo放入buf中，并推送buf的第一条数据。
-        if let Some(data) = self.data_provider.next().await {
-            self.on_data(data.clone());
+        // 乱序的数据不推送给策略
+        while let Some(data) = self.data_provider.next().await {
+            if !self.on_data(data.clone()) {
+                continue;
+            }
             self.broker_events_buf.push_back(BrokerEvent::Data(data));
 
             return self.broker_events_buf.pop_front();
-        } else {
-            let total_value = self.get_total_value();
-            let ts = self.ts;
-            self.reporter.insert(ts, total_value);
-            self.reporter.end();
-            return None;
         }
 
+        let total_value = self.get_total_value();
+        let ts = self.clock.now();
+        self.reporter.insert(ts, total_value);
+        self.record_snapshots();
+        self.reporter.end();
+        None
+    }
+
+    async fn request_open_orders(&mut self) {
+        let open_orders = self.limit_orders.values().copied().collect();
+        self.broker_events_buf
+            .push_back(BrokerEvent::OpenOrders(open_orders));
+    }
+
+    fn positions_snapshot(&self) -> Option<FxHashMap<InstId, f64>> {
+        let positions = self
+            .portfolio
+            .positions()
+            .iter()
+            .map(|(instrument_id, position)| (*instrument_id, position.size()))
+            .collect();
+        Some(positions)
+    }
+}
+
+impl<DP, D, M, S> Engine<SandboxBroker<DP, D, M>, S, D>
+where
+    DP: DataProvider<D>,
+    D: MarketData<M>,
+    M: MatchOrder,
+    S: Strategy<D>,
+{
+    /// 与run相同，并以(已处理的事件数, 最新数据的时间戳, 当前净值)调用on_progress，用于显示进度。
+    ///
+    /// 每处理every_events个事件，或距上次调用超过interval时调用一次，结束时再调用一次。
+    /// 为不拖慢回测，每256个事件才检查一次时间
+    pub async fn run_with_progress(
+        &mut self,
+        every_events: usize,
+        interval: std::time::Duration,
+        mut on_progress: impl FnMut(usize, Timestamp, f64),
+    ) {
+        let every_events = every_events.max(1);
+        let mut processed_count = 0;
+        let mut last_reported_at = std::time::Instant::now();
+        while let Some(broker_event) = self.broker.next_broker_event().await {
+            self.handle_broker_event(broker_event).await;
+            processed_count += 1;
+            if processed_count % every_events == 0
+                || (processed_count % 256 == 0 && last_reported_at.elapsed() >= interval)
+            {
+                on_progress(
+                    processed_count,
+                    self.broker.clock.now(),
+                    self.broker.get_total_value(),
+                );
+                last_reported_at = std::time::Instant::now();
+            }
+        }
+        self.finish();
+        on_progress(
+            processed_count,
+            self.broker.clock.now(),
+            self.broker.get_total_value(),
+        );
+    }
+}
+
+/// 一次回测的绩效摘要
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReportSummary {
+    pub sharpe_ratio: f64,
+    pub sortino_ratio: Option<f64>,
+    pub final_value: Option<f64>,
+}
+
+impl ReportSummary {
+    pub fn from_reporter(reporter: &Reporter) -> Self {
+        Self {
+            sharpe_ratio: reporter.sharpe_ratio(),
+            sortino_ratio: reporter.sortino_ratio(),
+            final_value: reporter.last_value(),
+        }
+    }
+}
+
+/// 对每组参数各自创建数据源与SandboxBroker并回测，结果按夏普比率从高到低排列（NaN排在最后）。
+/// 各组回测在tokio的blocking线程池中并行执行。
+pub async fn sweep<P, D, M, DP, S, FD, FS>(
+    param_sets: Vec<P>,
+    data_provider_factory: FD,
+    build_strategy: FS,
+    instruments: Vec<InstId>,
+    cash: f64,
+    transaction_cost_model: TransactionCostModel,
+    report_frequency: Duration,
+) -> Vec<(P, ReportSummary)>
+where
+    P: Clone + Send + 'static,
+    D: MarketData<M>,
+    M: MatchOrder,
+    DP: DataProvider<D>,
+    S: Strategy<D>,
+    FD: Fn() -> DP + Send + Sync + 'static,
+    FS: Fn(P) -> S + Send + Sync + 'static,
+{
+    let data_provider_factory = Arc::new(data_provider_factory);
+    let build_strategy = Arc::new(build_strategy);
+
+    let handles: Vec<_> = param_sets
+        .into_iter()
+        .map(|params| {
+            let data_provider_factory = data_provider_factory.clone();
+            let build_strategy = build_strategy.clone();
+            let instruments = instruments.clone();
+            let transaction_cost_model = transaction_cost_model.clone();
+            let handle = tokio::runtime::Handle::current();
+            tokio::task::spawn_blocking(move || {
+                handle.block_on(async move {
+                    let broker = SandboxBroker::new(
+                        instruments,
+                        data_provider_factory(),
+                        cash,
+                        transaction_cost_model,
+                        report_frequency,
+                    )
+                    .await?;
+                    let mut engine = Engine::new(broker, build_strategy(params.clone()));
+                    engine.run().await;
+                    anyhow::Ok((
+                        params,
+                        ReportSummary::from_reporter(engine.broker().reporter()),
+                    ))
+                })
+            })
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        match handle.await {
+            Ok(Ok(result)) => results.push(result),
+            Ok(Err(e)) => tracing::error!("Failed to start the backtest of a parameter set: {e}"),
+            Err(e) => tracing::error!("Backtest of a parameter set failed: {e}"),
+        }
     }
+    results.sort_by(|(_, a), (_, b)| {
+        let key = |summary: &ReportSummary| {
+            OrderedFloat(if summary.sharpe_ratio.is_nan() {
+                f64::NEG_INFINITY
+            } else {
+                summary.sharpe_ratio
+            })
+        };
+        key(b).cmp(&key(a))
+    });
+    results
+}
+
+/// 将一个数据源分发给多个策略同时回测，避免每个策略各自重新读取数据。每个策略有各自的SandboxBroker，
+/// 资金与持仓互不影响。返回的Reporter与strategies一一对应。
+///
+/// 数据经容量为FAN_OUT_BUFFER的通道逐条复制给各策略，读取数据的速度取决于最慢的策略。
+/// 任一SandboxBroker初始化失败时返回错误
+pub async fn fan_out<D, M, DP, S>(
+    mut data_provider: DP,
+    strategies: Vec<S>,
+    instruments: Vec<InstId>,
+    cash: f64,
+    transaction_cost_model: TransactionCostModel,
+    report_frequency: Duration,
+) -> Result<Vec<Reporter>>
+where
+    D: MarketData<M> + Send,
+    M: MatchOrder,
+    DP: DataProvider<D>,
+    S: Strategy<D>,
+{
+    let (mut senders, receivers): (Vec<_>, Vec<_>) = strategies
+        .iter()
+        .map(|_| mpsc::channel(FAN_OUT_BUFFER))
+        .unzip();
+    let pump = async move {
+        while let Some(data) = data_provider.next().await {
+            for sender in &mut senders {
+                // 接收端已结束的策略不再需要数据
+                let _ = sender.send(data.clone()).await;
+            }
+        }
+        // senders在此处drop，各策略的数据随之结束
+    };
+    let backtests = strategies
+        .into_iter()
+        .zip(receivers)
+        .map(|(strategy, receiver)| {
+            let instruments = instruments.clone();
+            let transaction_cost_model = transaction_cost_model.clone();
+            async move {
+                let broker = SandboxBroker::new(
+                    instruments,
+                    receiver,
+                    cash,
+                    transaction_cost_model,
+                    report_frequency,
+                )
+                .await?;
+                let mut engine = Engine::new(broker, strategy);
+                engine.run().await;
+                Ok(engine.into_broker().into_reporter())
+            }
+        });
+    let ((), reporters) = futures::join!(pump, future::join_all(backtests));
+    reporters.into_iter().collect()
 }
 
 /// 市场数据类型。由DataProvider流式提供。从中可能提取Matcher，用于撮合交易。
@@ -226,11 +1000,25 @@ where
     }
 }
 
+/// 以Bbo撮合订单，逐笔成交只推送给策略
+impl MarketData<Bbo> for data::MarketData {
+    fn draw_matcher(self) -> Option<Bbo> {
+        match self {
+            data::MarketData::Bbo(bbo) => Some(bbo),
+            data::MarketData::Trade(_) => None,
+        }
+    }
+
+    fn get_ts(&self) -> Timestamp {
+        self.ts()
+    }
+}
+
 /// 能够用于撮合订单的市场数据。一般是bbo。
 pub trait MatchOrder: Sized {
-    /// 由现存的Bbo，立即成交市价单。
-    fn fill_market_order(inst_data: &FxHashMap<InstId, Self>, order: &MarketOrder) -> Fill;
-    /// 限价单到达时，尝试以Taker成交限价单。随后每期对限价单进行匹配。
+    /// 由现存的数据，立即成交市价单。没有该产品的数据，或对手方没有报价时返回None
+    fn fill_market_order(inst_data: &FxHashMap<InstId, Self>, order: &MarketOrder) -> Option<Fill>;
+    /// 限价单到达时，尝试以Taker成交限价单。随后每期对限价单进行匹配。没有该产品的数据时不成交。
     fn try_fill_limit_order(
         inst_data: &FxHashMap<InstId, Self>,
         order: &LimitOrder,
@@ -240,6 +1028,31 @@ pub trait MatchOrder: Sized {
     fn get_ts(&self) -> Timestamp;
     fn market_price(&self) -> f64;
 
+    /// 相对价差，用于计算与价差成比例的滑点。默认为0。
+    fn relative_spread(&self) -> f64 {
+        0.
+    }
+
+    /// 买卖价的中间价，用于计算价差成本。没有买卖价的数据返回None
+    fn mid_price(&self) -> Option<f64> {
+        None
+    }
+
+    /// 挂单到达时排在其之前的数量。默认为无穷，即挂单只能通过价格穿越成交。
+    fn queue_ahead(&self, _order: &LimitOrder) -> f64 {
+        f64::INFINITY
+    }
+
+    /// 由上一期的数据与本期的数据，更新排在挂单之前的数量
+    fn update_queue_ahead(&self, _prev: &Self, _order: &LimitOrder, ahead: f64) -> f64 {
+        ahead
+    }
+
+    /// 挂单价等于同方向最优价时，该价位的数量。默认为None，即不使用成交概率模型。
+    fn touch_size(&self, _order: &LimitOrder) -> Option<f64> {
+        None
+    }
+
     /// 通过由 产品名-MatchOrder 组成的HashMap，得到所有产品的价格
     fn get_inst_market_price(inst_data: &FxHashMap<InstId, Self>) -> FxHashMap<InstId, f64> {
         inst_data
@@ -250,14 +1063,14 @@ pub trait MatchOrder: Sized {
 }
 
 impl MatchOrder for Bbo {
-    fn fill_market_order(inst_bbo: &FxHashMap<InstId, Self>, order: &MarketOrder) -> Fill {
-        let bbo = inst_bbo.get(&order.instrument_id).unwrap();
+    fn fill_market_order(inst_bbo: &FxHashMap<InstId, Self>, order: &MarketOrder) -> Option<Fill> {
+        let bbo = inst_bbo.get(&order.instrument_id)?;
         let price = if order.side {
-            bbo.ask_price
+            bbo.best_ask.price
         } else {
-            bbo.bid_price
+            bbo.best_bid.price
         };
-        Fill {
+        Some(Fill {
             order_id: order.order_id,
             instrument_id: order.instrument_id,
             side: order.side,
@@ -266,7 +1079,7 @@ impl MatchOrder for Bbo {
             acc_filled_size: order.size,
             exec_type: ExecType::Taker,
             state: FillState::Filled,
-        }
+        })
     }
 
     // best ask等于买单价或best bid等于卖单价就成交。
@@ -276,32 +1089,22 @@ impl MatchOrder for Bbo {
         order: &LimitOrder,
         exec_type: ExecType,
     ) -> Option<Fill> {
-        let bbo = inst_bbo.get(&order.instrument_id).unwrap();
+        let bbo = inst_bbo.get(&order.instrument_id)?;
 
         // 若是Maker，成交会是挂单价；若是Taker，成交价会是最优买卖价
         let price = if exec_type == ExecType::Maker {
             order.price
         } else if order.side {
-            bbo.ask_price
+            bbo.best_ask.price
         } else {
-            bbo.bid_price
+            bbo.best_bid.price
         };
         // 若买单的价格高于最优卖单
-        if (order.side && order.price >= bbo.ask_price)
+        if (order.side && order.price >= bbo.best_ask.price)
         // 或卖单的价格低于最优买单
-            || (!order.side && order.price <= bbo.bid_price)
+            || (!order.side && order.price <= bbo.best_bid.price)
         {
-            let fill = Fill {
-                order_id: order.order_id,
-                instrument_id: order.instrument_id,
-                side: order.side,
-                price,
-                filled_size: order.size,
-                acc_filled_size: order.size,
-                exec_type,
-                state: FillState::Filled,
-            };
-            Some(fill)
+            Some(Fill::full(order, price, exec_type))
         } else {
             None
         }
@@ -312,122 +1115,809 @@ impl MatchOrder for Bbo {
     }
 
     fn get_ts(&self) -> Timestamp {
-        self.ts as Timestamp
+        self.ts
     }
 
     fn market_price(&self) -> f64 {
         self.get_unbiased_price()
     }
+
+    fn relative_spread(&self) -> f64 {
+        self.get_relevent_spread()
+    }
+
+    fn mid_price(&self) -> Option<f64> {
+        Some(self.get_mid_price())
+    }
+
+    // 挂单价等于同方向最优价时，排在该价位已有的全部数量之后。
+    // 其他价位的数量无从得知，保守地视为无穷，待最优价到达挂单价时再确定。
+    fn queue_ahead(&self, order: &LimitOrder) -> f64 {
+        let (best_price, best_size) = self.same_side_best(order.side);
+        if order.price == best_price {
+            best_size
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    fn touch_size(&self, order: &LimitOrder) -> Option<f64> {
+        let (best_price, best_size) = self.same_side_best(order.side);
+        (order.price == best_price).then_some(best_size)
+    }
+
+    fn update_queue_ahead(&self, prev: &Self, order: &LimitOrder, ahead: f64) -> f64 {
+        let (best_price, best_size) = self.same_side_best(order.side);
+        let is_passed = if order.side {
+            best_price < order.price
+        } else {
+            best_price > order.price
+        };
+        // 最优价越过挂单价，说明挂单所在价位已被消耗殆尽
+        if is_passed {
+            return 0.;
+        }
+        if best_price != order.price {
+            return ahead;
+        }
+
+        // 同价位数量的减少视为排在前面的订单成交或撤单。
+        // 新增的数量排在挂单之后，因此排在之前的数量不会超过该价位的数量。
+        let (prev_price, prev_size) = prev.same_side_best(order.side);
+        let ahead = if prev_price == order.price {
+            ahead - (prev_size - best_size).max(0.)
+        } else {
+            ahead
+        };
+        ahead.min(best_size)
+    }
+}
+
+/// 以逐笔成交撮合。市价单以成交价成交；挂单在成交价穿过挂单价，
+/// 或主动方在挂单价与挂单反向成交时，以挂单价成交。
+impl MatchOrder for Trade {
+    fn fill_market_order(
+        inst_trade: &FxHashMap<InstId, Self>,
+        order: &MarketOrder,
+    ) -> Option<Fill> {
+        let trade = inst_trade.get(&order.instrument_id)?;
+        Some(Fill {
+            order_id: order.order_id,
+            instrument_id: order.instrument_id,
+            side: order.side,
+            price: trade.price,
+            filled_size: order.size,
+            acc_filled_size: order.size,
+            exec_type: ExecType::Taker,
+            state: FillState::Filled,
+        })
+    }
+
+    fn try_fill_limit_order(
+        inst_trade: &FxHashMap<InstId, Self>,
+        order: &LimitOrder,
+        exec_type: ExecType,
+    ) -> Option<Fill> {
+        let trade = inst_trade.get(&order.instrument_id)?;
+        let is_filled = match exec_type {
+            // 到达时以最近的成交价作为对手价
+            ExecType::Taker if order.side => trade.price <= order.price,
+            ExecType::Taker => trade.price >= order.price,
+            ExecType::Maker if order.side => {
+                trade.price < order.price || (trade.price == order.price && !trade.side)
+            }
+            ExecType::Maker => {
+                trade.price > order.price || (trade.price == order.price && trade.side)
+            }
+        };
+        let price = match exec_type {
+            ExecType::Maker => order.price,
+            ExecType::Taker => trade.price,
+        };
+        is_filled.then(|| Fill::full(order, price, exec_type))
+    }
+
+    fn instrument_id(&self) -> InstId {
+        self.instrument_id
+    }
+
+    fn get_ts(&self) -> Timestamp {
+        Timestamp::from_millis(self.ts)
+    }
+
+    fn market_price(&self) -> f64 {
+        self.price
+    }
+}
+
+impl Bbo {
+    /// 与挂单同方向的最优价与数量
+    fn same_side_best(&self, side: bool) -> (f64, f64) {
+        if side {
+            (self.best_bid.price, self.best_bid.size)
+        } else {
+            (self.best_ask.price, self.best_ask.size)
+        }
+    }
+}
+
+/// 以多档深度撮合。市价单与可立即成交的限价单依次吃掉对手方的各档，以成交均价成交；
+/// 挂单的规则与Bbo相同，排队位置按挂单所在价位的数量估计
+impl<const N: usize> MatchOrder for TopLevels<N> {
+    // 超出可见深度的部分以最差一档的价格成交。对手方没有报价时无法成交
+    fn fill_market_order(
+        inst_levels: &FxHashMap<InstId, Self>,
+        order: &MarketOrder,
+    ) -> Option<Fill> {
+        let levels = inst_levels.get(&order.instrument_id)?;
+        let worst_price = levels.opposite_side(order.side).last()?.price;
+        let (notional, filled_size) = levels.sweep(order.side, order.size, None);
+        let price = (notional + (order.size - filled_size) * worst_price) / order.size;
+        Some(Fill {
+            order_id: order.order_id,
+            instrument_id: order.instrument_id,
+            side: order.side,
+            price,
+            filled_size: order.size,
+            acc_filled_size: order.size,
+            exec_type: ExecType::Taker,
+            state: FillState::Filled,
+        })
+    }
+
+    // Taker只吃不劣于限价的档位，超出可见深度或限价的部分以限价成交
+    fn try_fill_limit_order(
+        inst_levels: &FxHashMap<InstId, Self>,
+        order: &LimitOrder,
+        exec_type: ExecType,
+    ) -> Option<Fill> {
+        let levels = inst_levels.get(&order.instrument_id)?;
+        let best_price = levels.opposite_side(order.side).first()?.price;
+        let is_crossed = if order.side {
+            order.price >= best_price
+        } else {
+            order.price <= best_price
+        };
+        if !is_crossed {
+            return None;
+        }
+        let price = match exec_type {
+            ExecType::Maker => order.price,
+            ExecType::Taker => {
+                let (notional, filled_size) =
+                    levels.sweep(order.side, order.size, Some(order.price));
+                (notional + (order.size - filled_size) * order.price) / order.size
+            }
+        };
+        Some(Fill::full(order, price, exec_type))
+    }
+
+    fn instrument_id(&self) -> InstId {
+        self.instrument_id
+    }
+
+    fn get_ts(&self) -> Timestamp {
+        self.ts
+    }
+
+    fn market_price(&self) -> f64 {
+        self.to_bbo()
+            .map_or(f64::NAN, |bbo| bbo.get_unbiased_price())
+    }
+
+    fn relative_spread(&self) -> f64 {
+        self.to_bbo().map_or(0., |bbo| bbo.get_relevent_spread())
+    }
+
+    fn mid_price(&self) -> Option<f64> {
+        self.to_bbo().map(|bbo| bbo.get_mid_price())
+    }
+
+    // 挂单价位于可见深度内时，排在该价位已有的全部数量之后
+    fn queue_ahead(&self, order: &LimitOrder) -> f64 {
+        self.same_side_size_at(order.side, order.price)
+            .unwrap_or(f64::INFINITY)
+    }
+
+    fn touch_size(&self, order: &LimitOrder) -> Option<f64> {
+        let best = self.same_side(order.side).first()?;
+        (order.price == best.price).then_some(best.size)
+    }
+
+    fn update_queue_ahead(&self, prev: &Self, order: &LimitOrder, ahead: f64) -> f64 {
+        let Some(best) = self.same_side(order.side).first() else {
+            return ahead;
+        };
+        let is_passed = if order.side {
+            best.price < order.price
+        } else {
+            best.price > order.price
+        };
+        if is_passed {
+            return 0.;
+        }
+        let Some(size) = self.same_side_size_at(order.side, order.price) else {
+            return ahead;
+        };
+
+        // 与Bbo相同，同价位数量的减少视为排在前面的订单成交或撤单
+        let ahead = match prev.same_side_size_at(order.side, order.price) {
+            Some(prev_size) => ahead - (prev_size - size).max(0.),
+            None => ahead,
+        };
+        ahead.min(size)
+    }
+}
+
+impl<const N: usize> TopLevels<N> {
+    /// 与挂单同方向的各档
+    fn same_side(&self, side: bool) -> &[Level] {
+        if side { &self.bids } else { &self.asks }
+    }
+
+    /// 订单的对手方各档
+    fn opposite_side(&self, side: bool) -> &[Level] {
+        self.same_side(!side)
+    }
+
+    /// 同方向该价位的数量，不在可见深度内时为None
+    fn same_side_size_at(&self, side: bool, price: f64) -> Option<f64> {
+        self.same_side(side)
+            .iter()
+            .find(|level| level.price == price)
+            .map(|level| level.size)
+    }
+
+    /// 依次吃掉对手方不劣于limit_price的档位，直到成交size。返回成交金额与成交量
+    fn sweep(&self, side: bool, size: f64, limit_price: Option<f64>) -> (f64, f64) {
+        let mut notional = 0.;
+        let mut filled_size = 0.;
+        for level in self.opposite_side(side) {
+            let is_acceptable = limit_price.is_none_or(|limit_price| {
+                if side {
+                    level.price <= limit_price
+                } else {
+                    level.price >= limit_price
+                }
+            });
+            if !is_acceptable || filled_size >= size {
+                break;
+            }
+            let level_filled = level.size.min(size - filled_size);
+            notional += level_filled * level.price;
+            filled_size += level_filled;
+        }
+        (notional, filled_size)
+    }
+}
+
+/// 同一频率桶内有多个净值时，记录哪一个值
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BucketPolicy {
+    /// 桶内最后一个值
+    #[default]
+    Last,
+    /// 桶内所有值的平均
+    Mean,
+    /// 以收盘值作为净值，并另外记录开盘、最高、最低与收盘
+    Ohlc,
+}
+
+/// 第一个频率桶的起点
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BinOrigin {
+    /// 第一条数据的时间戳向下取整为频率的整数倍，各桶与整点对齐
+    #[default]
+    Floor,
+    /// 以第一条数据的时间戳为起点
+    FirstTs,
+}
+
+/// 当前频率桶内收到的净值，收盘值为value_buf
+#[derive(Debug, Default)]
+struct Bucket {
+    open: f64,
+    high: f64,
+    low: f64,
+    sum: f64,
+    count: usize,
+}
+
+impl Bucket {
+    fn push(&mut self, value: f64) {
+        if self.count == 0 {
+            self.open = value;
+            self.high = value;
+            self.low = value;
+        } else {
+            self.high = self.high.max(value);
+            self.low = self.low.min(value);
+        }
+        self.sum += value;
+        self.count += 1;
+    }
 }
 
 #[derive(Default)]
 pub struct Reporter {
     value_history: Vec<Record>,
-    frequency: u64,
+    frequency: i64,
+    bucket_policy: BucketPolicy,
+    bin_origin: BinOrigin,
+    /// 仅在BucketPolicy::Ohlc下记录，与value_history一一对应
+    ohlc_history: Vec<OhlcRecord>,
+
+    /// 基准的净值记录，与value_history一一对应
+    benchmark_history: Vec<Record>,
+    benchmark_buf: Option<f64>,
+
+    /// 各产品的持仓快照，与value_history一一对应
+    inst_snapshot_history: Vec<Vec<InstrumentSnapshot>>,
+    inst_snapshot_buf: Vec<InstrumentSnapshot>,
 
     /// 最后一个频率桶的时间戳
     last_ts_bin: Timestamp,
     value_buf: f64,
+    bucket: Bucket,
+
+    /// 第一条数据的时间戳
+    first_ts: Timestamp,
+    /// 预热期的长度。时间戳早于 first_ts + warm_up 的记录不参与指标计算
+    warm_up: i64,
 
     is_initialized: bool,
     is_end: bool,
+
+    /// 各产品尚未平仓的交易
+    open_trades: FxHashMap<InstId, OpenTrade>,
+    /// 已平仓交易的盈亏，按平仓顺序排列
+    trade_pnls: Vec<f64>,
+    /// 全部成交的交易成本之和
+    cost_breakdown: CostBreakdown,
+}
+
+/// 从开仓到持仓归零的一笔交易
+#[derive(Debug, Default)]
+struct OpenTrade {
+    /// 带方向的持仓
+    size: f64,
+    /// 成交与交易成本带来的现金变动之和
+    cash_flow: f64,
+}
+
+/// 以开仓到持仓归零为一笔交易的统计。亏损以负数表示
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TradeStats {
+    pub num_trades: usize,
+    pub win_rate: f64,
+    /// 总盈利 / 总亏损，没有亏损的交易时为无穷大
+    pub profit_factor: f64,
+    pub avg_win: f64,
+    pub avg_loss: f64,
+    /// 没有亏损的交易时为0
+    pub largest_loss: f64,
 }
 
 impl Reporter {
-    fn new(frequency: Duration) -> Self {
+    fn new(frequency: Duration, bucket_policy: BucketPolicy) -> Self {
         Self {
-            frequency: frequency.num_milliseconds() as u64,
+            frequency: frequency.num_milliseconds(),
+            bucket_policy,
             ..Default::default()
         }
     }
 
+    fn set_warm_up(&mut self, warm_up: Duration) {
+        self.warm_up = warm_up.num_milliseconds();
+    }
+
+    /// 尚未发布任何记录时，按新的起点重新划分第一个桶
+    fn set_bin_origin(&mut self, bin_origin: BinOrigin) {
+        self.bin_origin = bin_origin;
+        if self.is_initialized && self.value_history.is_empty() {
+            self.last_ts_bin = self.first_bin_start();
+        }
+    }
+
+    fn first_bin_start(&self) -> Timestamp {
+        match self.bin_origin {
+            BinOrigin::Floor => self.first_ts.floor_to(self.frequency),
+            BinOrigin::FirstTs => self.first_ts,
+        }
+    }
+
+    /// 发布当前的桶。没有新值的桶沿用上一个桶的收盘值
     fn pub_buf_record(&mut self) {
         let new_ts_bin = self.last_ts_bin + self.frequency;
-        let new_record = Record::new(new_ts_bin, self.value_buf);
-        self.value_history.push(new_record);
+        let bucket = std::mem::take(&mut self.bucket);
+        let close = self.value_buf;
+        let (open, high, low, mean) = if bucket.count == 0 {
+            (close, close, close, close)
+        } else {
+            let mean = bucket.sum / bucket.count as f64;
+            (bucket.open, bucket.high, bucket.low, mean)
+        };
+        let value = match self.bucket_policy {
+            BucketPolicy::Last | BucketPolicy::Ohlc => close,
+            BucketPolicy::Mean => mean,
+        };
+        self.value_history.push(Record::new(new_ts_bin, value));
+        if self.bucket_policy == BucketPolicy::Ohlc {
+            self.ohlc_history.push(OhlcRecord {
+                ts: new_ts_bin,
+                open,
+                high,
+                low,
+                close,
+            });
+        }
+        if let Some(benchmark_value) = self.benchmark_buf {
+            self.benchmark_history
+                .push(Record::new(new_ts_bin, benchmark_value));
+        }
+        self.inst_snapshot_history
+            .push(self.inst_snapshot_buf.clone());
         self.last_ts_bin += self.frequency;
     }
 
+    /// 更新基准的最新净值。须在同一时刻的insert之后调用。
+    fn set_benchmark(&mut self, value: f64) {
+        self.benchmark_buf = Some(value);
+    }
+
+    /// ts是否落在当前频率桶之后，即插入ts前会先发布当前的桶
+    fn is_past_bucket(&self, ts: Timestamp) -> bool {
+        self.is_initialized && self.last_ts_bin + self.frequency <= ts
+    }
+
+    /// 更新各产品最新的持仓快照，随下一次发布的桶一同记录
+    fn set_inst_snapshots(&mut self, snapshots: Vec<InstrumentSnapshot>) {
+        self.inst_snapshot_buf = snapshots;
+    }
+
     fn insert(&mut self, ts: Timestamp, value: f64) {
         if !self.is_initialized {
-            self.last_ts_bin = ts / self.frequency * self.frequency;
+            self.first_ts = ts;
+            self.last_ts_bin = self.first_bin_start();
             self.value_buf = value;
+            self.bucket.push(value);
             self.is_initialized = true;
             return;
         }
 
-        // 若新的数据的时间戳大于buf位于的bin，则将buf放入到value_history中
-        if ts > self.last_ts_bin + self.frequency {
-            while self.last_ts_bin + self.frequency < ts {
-                self.pub_buf_record();
-            }
+        // 桶为左闭右开区间[last_ts_bin, last_ts_bin + frequency)，以右端点为记录的时间戳。
+        // 新数据落在之后的桶（包括恰好位于右端点）时，依次发布当前的桶与中间没有数据的桶
+        while self.last_ts_bin + self.frequency <= ts {
+            self.pub_buf_record();
         }
         self.value_buf = value;
+        self.bucket.push(value);
     }
 
+    /// 发布最后一个桶，只有一条数据时也会记录初始净值。没有任何数据时不产生记录
     fn end(&mut self) {
-        if self.is_end {
+        if self.is_end || !self.is_initialized {
             return;
         }
         self.is_end = true;
 
-        // 若value_history为空，则直接存入buf数据
-        if self.value_history.last().is_none() {
-            let new_ts_bin = self.last_ts_bin + self.frequency;
-            let record = Record::new(new_ts_bin, self.value_buf);
-            self.value_history.push(record);
-            return;
-        };
-
         self.pub_buf_record();
     }
 
+    /// BucketPolicy::Ohlc下写入开盘、最高、最低与收盘
     pub fn to_csv(&self, path: &Path) -> Result<()> {
         let mut writer = csv::Writer::from_path(path)?;
-        for record in &self.value_history {
-            writer.serialize(record)?;
+        if self.bucket_policy == BucketPolicy::Ohlc {
+            for record in &self.ohlc_history {
+                writer.serialize(record)?;
+            }
+        } else {
+            for record in &self.value_history {
+                writer.serialize(record)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// 每个报告周期写入净值，以及各产品的持仓数量、价格与市值。
+    /// 产品按首次出现的顺序排列，尚未有数据的周期留空
+    pub fn to_csv_detailed(&self, path: &Path) -> Result<()> {
+        let mut instruments = vec![];
+        for snapshot in self.inst_snapshot_history.iter().flatten() {
+            if !instruments.contains(&snapshot.instrument_id) {
+                instruments.push(snapshot.instrument_id);
+            }
+        }
+
+        let mut writer = csv::Writer::from_path(path)?;
+        let mut header = vec!["ts".to_string(), "value".to_string()];
+        for instrument_id in &instruments {
+            for field in ["size", "price", "value"] {
+                header.push(format!("{instrument_id:?}_{field}"));
+            }
+        }
+        writer.write_record(&header)?;
+
+        for (record, snapshots) in self.value_history.iter().zip(&self.inst_snapshot_history) {
+            let mut row = vec![record.ts.to_string(), record.value.to_string()];
+            for instrument_id in &instruments {
+                match snapshots
+                    .iter()
+                    .find(|snapshot| snapshot.instrument_id == *instrument_id)
+                {
+                    Some(snapshot) => row.extend([
+                        snapshot.size.to_string(),
+                        snapshot.price.to_string(),
+                        snapshot.value.to_string(),
+                    ]),
+                    None => row.extend(std::iter::repeat_n(String::new(), 3)),
+                }
+            }
+            writer.write_record(&row)?;
         }
         writer.flush()?;
         Ok(())
     }
 
+    /// 各报告周期末各产品的持仓快照，与净值记录一一对应
+    pub fn inst_snapshot_history(&self) -> &[Vec<InstrumentSnapshot>] {
+        &self.inst_snapshot_history
+    }
+
+    /// 各报告周期末的净值
+    pub fn value_history(&self) -> &[Record] {
+        &self.value_history
+    }
+
     pub fn last_value(&self) -> Option<f64> {
         self.value_history.last().map(|record| record.value)
     }
 
+    /// 各频率桶的开盘、最高、最低与收盘。仅在BucketPolicy::Ohlc下记录
+    pub fn ohlc_history(&self) -> &[OhlcRecord] {
+        &self.ohlc_history
+    }
+
+    /// 基准的净值记录。未设置基准时为空。
+    pub fn benchmark_history(&self) -> &[Record] {
+        &self.benchmark_history
+    }
+
+    /// 预热期结束后第一条记录的下标
+    fn live_start(&self) -> usize {
+        let warm_up_end = self.first_ts + self.warm_up;
+        self.value_history
+            .partition_point(|record| record.ts < warm_up_end)
+    }
+
+    /// 预热期结束后的净值记录
+    fn live_records(&self) -> &[Record] {
+        &self.value_history[self.live_start()..]
+    }
+
+    /// 预热期结束后每个周期的收益率
+    fn live_returns(&self) -> Vec<f64> {
+        Record::returns(self.live_records())
+    }
+
     pub fn sharpe_ratio(&self) -> f64 {
-        let returns: Vec<f64> = self
-            .value_history
-            .windows(2)
-            .map(|window| {
-                let prev_value = window[0].value;
-                let curr_value = window[1].value;
-                (curr_value - prev_value) / prev_value
-            })
-            .collect();
+        let returns = self.live_returns();
 
         let mean_return = returns.iter().mean();
         let std_dev = returns.iter().std_dev();
         mean_return / std_dev
     }
+
+    /// 以下行偏差（负收益率的均方根）代替标准差，不惩罚上涨的波动。没有负收益时为None
+    pub fn sortino_ratio(&self) -> Option<f64> {
+        let returns = self.live_returns();
+        let downside_squares: Vec<f64> =
+            returns.iter().filter(|r| **r < 0.).map(|r| r * r).collect();
+        if downside_squares.is_empty() {
+            return None;
+        }
+
+        let mean_return = returns.iter().mean();
+        let downside_dev = downside_squares.iter().mean().sqrt();
+        Some(mean_return / downside_dev)
+    }
+
+    /// 记录成交与其交易成本。持仓归零时完成一笔交易；持仓反向时，超出的部分开始新的交易
+    fn record_fill(&mut self, fill: &Fill, cost: f64, contract_type: ContractType) {
+        let value = |size| contract_type.value(size, fill.price);
+        let signed_size = if fill.side {
+            fill.filled_size
+        } else {
+            -fill.filled_size
+        };
+        let trade = self.open_trades.entry(fill.instrument_id).or_default();
+        let new_size = trade.size + signed_size;
+        let is_flat = new_size.abs() < 1e-12;
+        if trade.size == 0. || (trade.size * new_size > 0. && !is_flat) {
+            trade.size = new_size;
+            trade.cash_flow -= value(signed_size) + cost;
+            return;
+        }
+
+        // 平仓的部分按数量分摊交易成本
+        let closing_ratio = (trade.size / signed_size).abs();
+        let pnl = trade.cash_flow + value(trade.size) - cost * closing_ratio;
+        self.trade_pnls.push(pnl);
+        if is_flat {
+            self.open_trades.remove(&fill.instrument_id);
+        } else {
+            *trade = OpenTrade {
+                size: new_size,
+                cash_flow: -value(new_size) - cost * (1. - closing_ratio),
+            };
+        }
+    }
+
+    fn record_cost(&mut self, cost: &CostBreakdown) {
+        self.cost_breakdown.fee += cost.fee;
+        self.cost_breakdown.slippage += cost.slippage;
+        self.cost_breakdown.spread_cost += cost.spread_cost;
+    }
+
+    /// 全部成交的手续费、滑点与价差成本之和
+    pub fn cost_breakdown(&self) -> CostBreakdown {
+        self.cost_breakdown
+    }
+
+    /// 已平仓交易的盈亏，按平仓顺序排列
+    pub fn trade_pnls(&self) -> &[f64] {
+        &self.trade_pnls
+    }
+
+    /// 没有已平仓的交易时，各比率为NaN
+    pub fn trade_stats(&self) -> TradeStats {
+        let wins: Vec<f64> = self
+            .trade_pnls
+            .iter()
+            .copied()
+            .filter(|pnl| *pnl > 0.)
+            .collect();
+        let losses: Vec<f64> = self
+            .trade_pnls
+            .iter()
+            .copied()
+            .filter(|pnl| *pnl < 0.)
+            .collect();
+        let gross_profit: f64 = wins.iter().sum();
+        let gross_loss: f64 = -losses.iter().sum::<f64>();
+        let num_trades = self.trade_pnls.len();
+        TradeStats {
+            num_trades,
+            win_rate: wins.len() as f64 / num_trades as f64,
+            profit_factor: gross_profit / gross_loss,
+            avg_win: wins.iter().mean(),
+            avg_loss: losses.iter().mean(),
+            largest_loss: losses.iter().copied().fold(0., f64::min),
+        }
+    }
+
+    /// 策略收益减去基准收益的夏普比率。未设置基准时为NaN。
+    pub fn excess_sharpe(&self) -> f64 {
+        let start = self.live_start();
+        let Some(benchmark_records) = self.benchmark_history.get(start..) else {
+            return f64::NAN;
+        };
+        let excess_returns: Vec<f64> = Record::returns(&self.value_history[start..])
+            .into_iter()
+            .zip(Record::returns(benchmark_records))
+            .map(|(strategy_return, benchmark_return)| strategy_return - benchmark_return)
+            .collect();
+
+        let mean_return = excess_returns.iter().mean();
+        let std_dev = excess_returns.iter().std_dev();
+        mean_return / std_dev
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Record {
+    pub ts: Timestamp,
+    pub value: f64,
+}
+
+/// 某一时刻单个产品的持仓与市值
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct InstrumentSnapshot {
+    pub instrument_id: InstId,
+    /// 带方向的持仓数量
+    pub size: f64,
+    pub price: f64,
+    pub value: f64,
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize)]
-struct Record {
-    ts: Timestamp,
-    value: f64,
+pub struct OhlcRecord {
+    pub ts: Timestamp,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
 }
 
 impl Record {
     fn new(ts: Timestamp, value: f64) -> Self {
         Self { ts, value }
     }
+
+    /// 相邻记录之间的收益率
+    fn returns(records: &[Self]) -> Vec<f64> {
+        records
+            .windows(2)
+            .map(|window| {
+                let prev_value = window[0].value;
+                let curr_value = window[1].value;
+                (curr_value - prev_value) / prev_value
+            })
+            .collect()
+    }
+}
+
+/// 介于“价格穿越才成交”与排队模型之间的撮合方式：挂单位于最优价时，
+/// 以 scale * 挂单数量 / 最优价数量 的概率成交（不超过1）
+#[derive(Debug, Clone, Copy)]
+pub struct FillProbabilityModel {
+    scale: f64,
+}
+
+impl FillProbabilityModel {
+    pub fn new(scale: f64) -> Self {
+        Self { scale }
+    }
+
+    pub fn fill_probability(&self, order: &LimitOrder, touch_size: f64) -> f64 {
+        if touch_size <= 0. {
+            return 1.;
+        }
+        (self.scale * order.unfilled_size() / touch_size).min(1.)
+    }
+
+    fn is_filled(&self, order: &LimitOrder, touch_size: f64, rng: &mut StdRng) -> bool {
+        rng.random_bool(self.fill_probability(order, touch_size))
+    }
+}
+
+/// Taker成交的滑点，以成交价的比例表示
+#[derive(Debug, Clone, Copy)]
+pub enum Slippage {
+    /// 固定比例
+    Fixed(f64),
+    /// 成交时相对价差的倍数
+    SpreadMultiple(f64),
+}
+
+/// 一次成交的交易成本。手续费与滑点从现金中扣除；价差成本为成交价相对中间价的差，
+/// 已体现在成交价中。Maker以优于中间价的价格成交时，价差成本为负
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CostBreakdown {
+    pub fee: f64,
+    pub slippage: f64,
+    pub spread_cost: f64,
+}
+
+impl CostBreakdown {
+    /// 需要额外扣除的成本，即手续费与滑点
+    pub fn explicit(&self) -> f64 {
+        self.fee + self.slippage
+    }
+
+    pub fn total(&self) -> f64 {
+        self.fee + self.slippage + self.spread_cost
+    }
 }
 
+#[derive(Debug, Clone)]
 pub struct TransactionCostModel {
     maker_fee: f64,
     taker_fee: f64,
-    slippage: f64,
+    /// 各产品单独的(maker_fee, taker_fee)，未设置的产品使用maker_fee与taker_fee
+    instrument_fees: FxHashMap<InstId, (f64, f64)>,
+    slippage: Slippage,
 }
 
 impl TransactionCostModel {
@@ -435,30 +1925,78 @@ impl TransactionCostModel {
         Self {
             maker_fee,
             taker_fee,
-            slippage,
+            instrument_fees: Default::default(),
+            slippage: Slippage::Fixed(slippage),
         }
     }
 
     pub fn new_okx(slippage: f64) -> Self {
-        Self {
-            maker_fee: 0.0002,
-            taker_fee: 0.0005,
-            slippage,
-        }
+        Self::new(0.0002, 0.0005, slippage)
+    }
+
+    /// 滑点取成交时相对价差的spread_multiple倍
+    pub fn with_spread_slippage(mut self, spread_multiple: f64) -> Self {
+        self.slippage = Slippage::SpreadMultiple(spread_multiple);
+        self
     }
 
-    pub fn calculate_cost(&self, fill: &Fill) -> f64 {
+    /// 为该产品设置单独的手续费率，例如现货与永续合约的费率不同
+    pub fn with_instrument_fees(
+        mut self,
+        instrument_id: InstId,
+        maker_fee: f64,
+        taker_fee: f64,
+    ) -> Self {
+        self.instrument_fees
+            .insert(instrument_id, (maker_fee, taker_fee));
+        self
+    }
+
+    /// mid_price为成交时的中间价，缺失时价差成本为0；relative_spread为成交时的相对价差，仅用于按价差计算的滑点。
+    ///
+    /// 成本以contract_type的结算货币计，反向合约的名义价值为 size / price 个币
+    pub fn calculate_cost(
+        &self,
+        fill: &Fill,
+        mid_price: Option<f64>,
+        relative_spread: f64,
+        contract_type: ContractType,
+    ) -> CostBreakdown {
+        let (maker_fee, taker_fee) = self
+            .instrument_fees
+            .get(&fill.instrument_id)
+            .copied()
+            .unwrap_or((self.maker_fee, self.taker_fee));
         let (fee, slippage) = if fill.exec_type == ExecType::Taker {
-            (self.taker_fee, self.slippage)
+            let slippage = match self.slippage {
+                Slippage::Fixed(slippage) => slippage,
+                Slippage::SpreadMultiple(multiple) => multiple * relative_spread,
+            };
+            (taker_fee, slippage)
         } else {
-            (self.maker_fee, 0.)
+            (maker_fee, 0.)
         };
+        // 滑点使买入价更高、卖出价更低，对两个方向都是成本
         let price = if fill.side {
             fill.price * (1.0 + slippage)
         } else {
             fill.price * (1.0 - slippage)
         };
-        price * fill.filled_size * (1.0 + fee) - fill.price * fill.filled_size
+        let notional = |price| contract_type.value(fill.filled_size, price).abs();
+        let spread_cost = mid_price.map_or(0., |mid_price| {
+            let signed_size = if fill.side {
+                fill.filled_size
+            } else {
+                -fill.filled_size
+            };
+            contract_type.value(signed_size, fill.price)
+                - contract_type.value(signed_size, mid_price)
+        });
+        CostBreakdown {
+            fee: notional(price) * fee,
+            slippage: notional(fill.price) * slippage,
+            spread_cost,
+        }
     }
 }
 
@@ -466,85 +2004,290 @@ impl TransactionCostModel {
 mod tests {
     use float_cmp::assert_approx_eq;
 
-    use crate::AmendOrder;
+    use crate::{
+        AmendOrder,
+        data::{Level, VecDataProvider},
+    };
 
     use super::*;
 
     #[test]
     fn test_reporter_insert_same_bin() {
-        let mut reporter = Reporter::new(Duration::milliseconds(100));
-        reporter.insert(150, 10.0);
-        reporter.insert(180, 15.0);
+        let mut reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
+        reporter.insert(Timestamp::from_millis(150), 10.0);
+        reporter.insert(Timestamp::from_millis(180), 15.0);
 
         // Second value in same bin just updates buffer
         assert_eq!(reporter.value_buf, 15.0);
         assert_eq!(reporter.value_history.len(), 0);
     }
 
+    #[test]
+    fn test_reporter_bucket_policy() {
+        fn report(bucket_policy: BucketPolicy) -> Reporter {
+            let mut reporter = Reporter::new(Duration::milliseconds(100), bucket_policy);
+            for (ts, value) in [
+                (110, 10.0),
+                (130, 14.0),
+                (150, 8.0),
+                (180, 12.0),
+                (250, 20.0),
+            ] {
+                reporter.insert(Timestamp::from_millis(ts), value);
+            }
+            reporter.end();
+            reporter
+        }
+
+        let reporter = report(BucketPolicy::Last);
+        assert_eq!(reporter.value_history[0].value, 12.0);
+        assert!(reporter.ohlc_history().is_empty());
+
+        let reporter = report(BucketPolicy::Mean);
+        assert_eq!(reporter.value_history[0].value, 11.0);
+        assert_eq!(reporter.value_history[1].value, 20.0);
+
+        // 以收盘值作为净值，并记录开盘、最高、最低与收盘
+        let reporter = report(BucketPolicy::Ohlc);
+        assert_eq!(reporter.value_history[0].value, 12.0);
+        assert_eq!(
+            reporter.ohlc_history()[0],
+            OhlcRecord {
+                ts: Timestamp::from_millis(200),
+                open: 10.0,
+                high: 14.0,
+                low: 8.0,
+                close: 12.0,
+            }
+        );
+        assert_eq!(reporter.ohlc_history().len(), reporter.value_history.len());
+    }
+
     #[test]
     fn test_reporter_insert_multiple_bins() {
-        let mut reporter = Reporter::new(Duration::milliseconds(100));
-        reporter.insert(150, 10.0);
-        reporter.insert(450, 30.0);
+        let mut reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
+        reporter.insert(Timestamp::from_millis(150), 10.0);
+        reporter.insert(Timestamp::from_millis(450), 30.0);
 
         // Should create multiple history entries
         assert_eq!(reporter.value_history.len(), 3);
-        assert_eq!(reporter.value_history[0], Record::new(200, 10.0));
-        assert_eq!(reporter.value_history[1], Record::new(300, 10.0));
-        assert_eq!(reporter.value_history[2], Record::new(400, 10.0));
+        assert_eq!(
+            reporter.value_history[0],
+            Record::new(Timestamp::from_millis(200), 10.0)
+        );
+        assert_eq!(
+            reporter.value_history[1],
+            Record::new(Timestamp::from_millis(300), 10.0)
+        );
+        assert_eq!(
+            reporter.value_history[2],
+            Record::new(Timestamp::from_millis(400), 10.0)
+        );
+
+        reporter.end();
+        assert_eq!(
+            reporter.value_history[3],
+            Record::new(Timestamp::from_millis(500), 30.0)
+        );
+    }
+
+    #[test]
+    fn test_reporter_insert_at_bin_edges() {
+        let mut reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
+        reporter.insert(Timestamp::from_millis(100), 10.0);
+        reporter.insert(Timestamp::from_millis(199), 11.0);
+        // 恰好位于右端点的值属于下一个桶
+        reporter.insert(Timestamp::from_millis(200), 20.0);
+        assert_eq!(
+            reporter.value_history,
+            vec![Record::new(Timestamp::from_millis(200), 11.0)]
+        );
+
+        // 跳过整数个桶时，中间的桶沿用旧值，新值落在其所在的桶
+        reporter.insert(Timestamp::from_millis(500), 50.0);
+        assert_eq!(
+            reporter.value_history[1..],
+            [
+                Record::new(Timestamp::from_millis(300), 20.0),
+                Record::new(Timestamp::from_millis(400), 20.0),
+                Record::new(Timestamp::from_millis(500), 20.0),
+            ]
+        );
+        reporter.end();
+        assert_eq!(
+            reporter.value_history.last(),
+            Some(&Record::new(Timestamp::from_millis(600), 50.0))
+        );
+    }
+
+    #[test]
+    fn test_reporter_end() {
+        let mut reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
+        reporter.insert(Timestamp::from_millis(150), 10.0);
+        reporter.end();
+        reporter.end(); // End again does nothing
+
+        // End should push the final buffered value
+        assert_eq!(reporter.value_history.len(), 1);
+        assert_eq!(
+            reporter.value_history[0],
+            Record::new(Timestamp::from_millis(200), 10.0)
+        );
+    }
+
+    #[test]
+    fn test_reporter_initial_record() {
+        // 只有一条数据时，end后记录初始净值
+        let mut reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
+        reporter.insert(Timestamp::from_millis(1234), 10.0);
+        reporter.end();
+        assert_eq!(
+            reporter.value_history,
+            vec![Record::new(Timestamp::from_millis(1300), 10.0)]
+        );
+
+        // 以第一条数据的时间戳为起点划分桶
+        let mut reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
+        reporter.insert(Timestamp::from_millis(1234), 10.0);
+        reporter.set_bin_origin(BinOrigin::FirstTs);
+        reporter.insert(Timestamp::from_millis(1334), 20.0);
+        reporter.end();
+        assert_eq!(
+            reporter.value_history,
+            vec![
+                Record::new(Timestamp::from_millis(1334), 10.0),
+                Record::new(Timestamp::from_millis(1434), 20.0),
+            ]
+        );
 
+        // 没有数据时不产生记录
+        let mut reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
         reporter.end();
-        assert_eq!(reporter.value_history[3], Record::new(500, 30.0));
+        assert!(reporter.value_history.is_empty());
     }
 
     #[test]
-    fn test_reporter_end() {
-        let mut reporter = Reporter::new(Duration::milliseconds(100));
-        reporter.insert(150, 10.0);
+    fn test_reporter_warm_up_excluded_from_sharpe() {
+        let mut reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
+        reporter.set_warm_up(Duration::milliseconds(300));
+        reporter.insert(Timestamp::from_millis(0), 100.0);
+        // 预热期内的净值剧烈波动
+        reporter.insert(Timestamp::from_millis(150), 50.0);
+        reporter.insert(Timestamp::from_millis(250), 200.0);
+        reporter.insert(Timestamp::from_millis(350), 100.0);
+        reporter.insert(Timestamp::from_millis(450), 101.0);
+        reporter.insert(Timestamp::from_millis(550), 103.0);
+        reporter.insert(Timestamp::from_millis(650), 102.0);
         reporter.end();
-        reporter.end(); // End again does nothing
-
-        // End should push the final buffered value
-        assert_eq!(reporter.value_history.len(), 1);
-        assert_eq!(reporter.value_history[0], Record::new(200, 10.0));
-    }
 
-    // Mock DataProvider for testing
-    struct MockDataProvider {
-        data: Vec<Bbo>,
-        index: usize,
+        // 预热期内的记录仍保存在历史中
+        assert_eq!(
+            reporter.value_history[0],
+            Record::new(Timestamp::from_millis(100), 100.0)
+        );
+        assert_eq!(reporter.live_records()[0].ts, Timestamp::from_millis(300));
+
+        let mut live_reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
+        live_reporter.insert(Timestamp::from_millis(250), 200.0);
+        live_reporter.insert(Timestamp::from_millis(350), 100.0);
+        live_reporter.insert(Timestamp::from_millis(450), 101.0);
+        live_reporter.insert(Timestamp::from_millis(550), 103.0);
+        live_reporter.insert(Timestamp::from_millis(650), 102.0);
+        live_reporter.end();
+
+        assert_eq!(reporter.live_records(), &live_reporter.value_history[..]);
+        assert_approx_eq!(f64, reporter.sharpe_ratio(), live_reporter.sharpe_ratio());
     }
 
-    impl MockDataProvider {
-        fn new(data: Vec<Bbo>) -> Self {
-            Self { data, index: 0 }
+    #[test]
+    fn test_reporter_sortino_ratio() {
+        fn reporter_with_values(values: &[f64]) -> Reporter {
+            let mut reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
+            for (i, value) in values.iter().enumerate() {
+                reporter.insert(Timestamp::from_millis(100 * i as i64 + 50), *value);
+            }
+            reporter.end();
+            reporter
         }
-    }
 
-    impl Stream for MockDataProvider {
-        type Item = Bbo;
+        // 上涨幅度大、下跌幅度小，下行偏差小于标准差
+        let reporter = reporter_with_values(&[100., 110., 109., 120., 119., 130., 129.]);
+        let sharpe = reporter.sharpe_ratio();
+        let sortino = reporter.sortino_ratio().unwrap();
+        assert!(sharpe > 0.);
+        assert!(sortino > sharpe);
+
+        let returns = reporter.live_returns();
+        let negative: Vec<f64> = returns.iter().copied().filter(|r| *r < 0.).collect();
+        let downside_dev =
+            (negative.iter().map(|r| r * r).sum::<f64>() / negative.len() as f64).sqrt();
+        assert_approx_eq!(f64, sortino, returns.iter().mean() / downside_dev);
+
+        // 没有负收益时为None
+        let reporter = reporter_with_values(&[100., 101., 103., 106.]);
+        assert!(reporter.sharpe_ratio() > 0.);
+        assert_eq!(reporter.sortino_ratio(), None);
+    }
 
-        fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-            if self.index < self.data.len() {
-                let data = self.data[self.index];
-                self.index += 1;
-                Poll::Ready(Some(data))
-            } else {
-                Poll::Ready(None)
+    #[test]
+    fn test_reporter_trade_stats() {
+        fn fill(side: bool, filled_size: f64, price: f64) -> Fill {
+            Fill {
+                instrument_id: InstId::EthUsdtSwap,
+                side,
+                filled_size,
+                price,
+                ..Default::default()
             }
         }
-    }
 
-    impl Unpin for MockDataProvider {}
+        let mut reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
+        // 分两次开多，一次平仓：212 - 202 = 10
+        reporter.record_fill(&fill(true, 1., 100.), 0., ContractType::Linear);
+        reporter.record_fill(&fill(true, 1., 102.), 0., ContractType::Linear);
+        reporter.record_fill(&fill(false, 2., 106.), 0., ContractType::Linear);
+        // 空头：200 - 196 = 4
+        reporter.record_fill(&fill(false, 2., 100.), 0., ContractType::Linear);
+        reporter.record_fill(&fill(true, 2., 98.), 0., ContractType::Linear);
+        // 亏损并计入交易成本：-10 - 0.5 - 0.5 = -11
+        reporter.record_fill(&fill(true, 1., 100.), 0.5, ContractType::Linear);
+        reporter.record_fill(&fill(false, 1., 90.), 0.5, ContractType::Linear);
+
+        assert_eq!(reporter.trade_pnls(), &[10., 4., -11.]);
+        let stats = reporter.trade_stats();
+        assert_eq!(stats.num_trades, 3);
+        assert_approx_eq!(f64, stats.win_rate, 2. / 3.);
+        assert_approx_eq!(f64, stats.profit_factor, 14. / 11.);
+        assert_approx_eq!(f64, stats.avg_win, 7.);
+        assert_approx_eq!(f64, stats.avg_loss, -11.);
+        assert_approx_eq!(f64, stats.largest_loss, -11.);
+
+        // 持仓反向时，先完成原来的交易，超出的部分开始新的交易
+        let mut reporter = Reporter::new(Duration::milliseconds(100), BucketPolicy::Last);
+        reporter.record_fill(&fill(true, 1., 100.), 0., ContractType::Linear);
+        reporter.record_fill(&fill(false, 2., 110.), 2., ContractType::Linear);
+        reporter.record_fill(&fill(true, 1., 105.), 0., ContractType::Linear);
+        assert_eq!(reporter.trade_pnls(), &[9., 4.]);
+        let stats = reporter.trade_stats();
+        assert_eq!(stats.largest_loss, 0.);
+        assert_eq!(stats.profit_factor, f64::INFINITY);
+    }
 
-    fn create_mock_bbo(ts: u64, bid_price: f64, ask_price: f64) -> Bbo {
+    // Mock DataProvider for testing
+    fn create_mock_bbo(ts: i64, bid_price: f64, ask_price: f64) -> Bbo {
         Bbo {
-            ts,
+            ts: ts.into(),
             instrument_id: InstId::EthUsdtSwap,
-            bid_price,
-            ask_price,
-            bid_size: 1.,
-            ask_size: 1.,
+            best_bid: Level {
+                price: bid_price,
+                size: 1.,
+                order_count: 1,
+            },
+            best_ask: Level {
+                price: ask_price,
+                size: 1.,
+                order_count: 1,
+            },
         }
     }
 
@@ -554,6 +2297,7 @@ mod tests {
             instrument_id: InstId::EthUsdtSwap,
             size,
             side,
+            reduce_only: false,
         })
     }
 
@@ -565,10 +2309,11 @@ mod tests {
             size,
             side,
             filled_size: 0.,
+            reduce_only: false,
         })
     }
 
-    fn create_amend_order (order_id: u64, new_price: f64, new_size: f64) -> AmendOrder {
+    fn create_amend_order(order_id: u64, new_price: f64, new_size: f64) -> AmendOrder {
         AmendOrder {
             order_id,
             instrument_id: InstId::EthUsdtSwap,
@@ -581,12 +2326,13 @@ mod tests {
         ($inst_id:expr, $mock_data:expr) => {
             SandboxBroker::new(
                 vec![$inst_id],
-                MockDataProvider::new($mock_data),
+                VecDataProvider::new($mock_data),
                 100000.0,
                 TransactionCostModel::new(0.001, 0.002, 0.0001),
                 Duration::milliseconds(1000),
             )
             .await
+            .unwrap()
         };
         () => {};
     }
@@ -599,7 +2345,9 @@ mod tests {
 
         let market_order = create_market_order(1, 1.0, true);
 
-        broker.on_client_event(ClientEvent::PlaceOrder(market_order));
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(market_order))
+            .await;
 
         // Should have a fill event in buffer
         let event = broker.next_broker_event().await.unwrap();
@@ -618,11 +2366,590 @@ mod tests {
         assert!(broker.cash < 100000.0);
     }
 
+    #[tokio::test]
+    async fn test_spread_slippage_cost() {
+        // 同样大小的市价单，价差越宽，滑点成本越高
+        async fn slippage_cost(bid_price: f64, ask_price: f64) -> f64 {
+            let mut broker = SandboxBroker::new(
+                vec![InstId::EthUsdtSwap],
+                VecDataProvider::new(vec![create_mock_bbo(1000, bid_price, ask_price)]),
+                100000.0,
+                TransactionCostModel::new(0., 0., 0.).with_spread_slippage(0.5),
+                Duration::milliseconds(1000),
+            )
+            .await
+            .unwrap();
+            broker
+                .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 1.0, true)))
+                .await;
+            let Some(BrokerEvent::Fill(fill)) = broker.next_broker_event().await else {
+                panic!("Expected Fill event");
+            };
+            100000.0 - broker.cash - fill.price * fill.filled_size
+        }
+
+        let tight = slippage_cost(49999.0, 50001.0).await;
+        let wide = slippage_cost(49950.0, 50050.0).await;
+        assert!(tight > 0.);
+        assert!(wide > tight);
+        // 滑点为相对价差的一半：0.5 * 100 / 50000 * 50050
+        assert!((wide - 0.5 * 100. / 50000. * 50050.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_instrument_fees() {
+        let cost_model = TransactionCostModel::new(0.0002, 0.0005, 0.).with_instrument_fees(
+            InstId::BtcUsdtSwap,
+            0.0001,
+            0.0003,
+        );
+        let fill = |instrument_id, exec_type| Fill {
+            instrument_id,
+            exec_type,
+            price: 100.,
+            filled_size: 10.,
+            side: true,
+            ..Default::default()
+        };
+
+        // 未单独设置的产品使用全局费率
+        let cost = cost_model
+            .calculate_cost(
+                &fill(InstId::EthUsdtSwap, ExecType::Maker),
+                None,
+                0.,
+                ContractType::Linear,
+            )
+            .total();
+        assert_approx_eq!(f64, cost, 1000. * 0.0002);
+        let cost = cost_model
+            .calculate_cost(
+                &fill(InstId::EthUsdtSwap, ExecType::Taker),
+                None,
+                0.,
+                ContractType::Linear,
+            )
+            .total();
+        assert_approx_eq!(f64, cost, 1000. * 0.0005);
+
+        let cost = cost_model
+            .calculate_cost(
+                &fill(InstId::BtcUsdtSwap, ExecType::Maker),
+                None,
+                0.,
+                ContractType::Linear,
+            )
+            .total();
+        assert_approx_eq!(f64, cost, 1000. * 0.0001);
+        let cost = cost_model
+            .calculate_cost(
+                &fill(InstId::BtcUsdtSwap, ExecType::Taker),
+                None,
+                0.,
+                ContractType::Linear,
+            )
+            .total();
+        assert_approx_eq!(f64, cost, 1000. * 0.0003);
+    }
+
+    #[tokio::test]
+    async fn test_new_without_enough_data() {
+        let new_broker = |instruments, data| async move {
+            let broker = SandboxBroker::<_, Bbo, Bbo>::new(
+                instruments,
+                VecDataProvider::new(data),
+                100000.0,
+                TransactionCostModel::new(0., 0., 0.),
+                Duration::seconds(1),
+            );
+            tokio::time::timeout(std::time::Duration::from_secs(1), broker)
+                .await
+                .expect("SandboxBroker::new should not hang")
+        };
+
+        // 没有要交易的产品
+        let data = vec![create_mock_bbo(1000, 100.0, 101.0)];
+        let err = new_broker(vec![], data).await.err().unwrap();
+        assert!(err.to_string().contains("No instruments"));
+
+        // 数据耗尽前交易的产品没有行情
+        let err = new_broker(vec![InstId::BtcUsdtSwap], vec![])
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("exhausted"));
+        let data = vec![create_mock_bbo(1000, 100.0, 101.0)];
+        let err = new_broker(vec![InstId::BtcUsdtSwap], data)
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("exhausted"));
+    }
+
+    #[tokio::test]
+    async fn test_inverse_contract_pnl() {
+        // 以卖一价100买入100，价格涨到110后以买一价平仓
+        let data = vec![
+            create_mock_bbo(1000, 99.0, 100.0),
+            create_mock_bbo(2000, 110.0, 111.0),
+        ];
+        let mut pnls = vec![];
+        for (contract_type, taker_fee) in [
+            (ContractType::Linear, 0.),
+            (ContractType::Inverse, 0.),
+            (ContractType::Inverse, 0.0005),
+        ] {
+            let mut broker = SandboxBroker::new(
+                vec![InstId::EthUsdtSwap],
+                VecDataProvider::new(data.clone()),
+                100000.0,
+                TransactionCostModel::new(0., taker_fee, 0.),
+                Duration::seconds(1),
+            )
+            .await
+            .unwrap()
+            .with_contract_type(InstId::EthUsdtSwap, contract_type);
+            broker
+                .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 100.0, true)))
+                .await;
+            while let Some(event) = broker.next_broker_event().await {
+                if matches!(event, BrokerEvent::Data(_)) {
+                    break;
+                }
+            }
+            broker
+                .on_client_event(ClientEvent::PlaceOrder(create_market_order(
+                    2, 100.0, false,
+                )))
+                .await;
+            assert!(broker.portfolio.positions().is_empty());
+
+            let pnl = broker.get_total_value() - 100000.0;
+            assert_approx_eq!(f64, broker.reporter().trade_pnls()[0], pnl, epsilon = 1e-9);
+            pnls.push(pnl);
+        }
+
+        // 正向合约以计价货币计盈亏；反向合约以币计盈亏，面值100的持仓盈利 100 * (1/100 - 1/110)
+        assert_approx_eq!(f64, pnls[0], 100. * (110. - 100.), epsilon = 1e-9);
+        assert_approx_eq!(f64, pnls[1], 100. * (1. / 100. - 1. / 110.), epsilon = 1e-9);
+        // 反向合约的手续费同样以币计，按每次成交的名义价值 size / price 收取
+        let fee = 0.0005 * (100. / 100. + 100. / 110.);
+        assert_approx_eq!(f64, pnls[2], pnls[1] - fee, epsilon = 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_inverse_short_interest() {
+        const DAY: i64 = 24 * 3600 * 1000;
+        let mock_data = vec![
+            create_mock_bbo(1000, 100.0, 101.0),
+            create_mock_bbo(1000 + DAY, 100.0, 101.0),
+        ];
+        let mut broker = SandboxBroker::new(
+            vec![InstId::EthUsdtSwap],
+            VecDataProvider::new(mock_data),
+            0.5,
+            TransactionCostModel::new(0., 0., 0.),
+            Duration::milliseconds(1000),
+        )
+        .await
+        .unwrap()
+        .with_contract_type(InstId::EthUsdtSwap, ContractType::Inverse)
+        .with_interest_rate(0.1);
+
+        // 以0.5个币的保证金做空面值200的反向合约，现金记账为负，但并未借款
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(
+                1, 200.0, false,
+            )))
+            .await;
+        broker.next_broker_event().await.unwrap();
+        let cash = broker.cash;
+        assert_approx_eq!(f64, cash, 0.5 - 2., epsilon = 1e-9);
+
+        // 持有一天，不计利息
+        broker.next_broker_event().await.unwrap();
+        assert_eq!(broker.cash, cash);
+    }
+
+    #[tokio::test]
+    async fn test_cost_breakdown() {
+        let mut broker = SandboxBroker::new(
+            vec![InstId::EthUsdtSwap],
+            VecDataProvider::new(vec![create_mock_bbo(1000, 49990.0, 50010.0)]),
+            100000.0,
+            TransactionCostModel::new(0.0002, 0.0005, 0.0001),
+            Duration::milliseconds(1000),
+        )
+        .await
+        .unwrap();
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 1.0, true)))
+            .await;
+        let Some(BrokerEvent::Fill(fill)) = broker.next_broker_event().await else {
+            panic!("Expected Fill event");
+        };
+
+        // 以卖一价买入，付出半个价差
+        let cost = broker.reporter().cost_breakdown();
+        assert_approx_eq!(f64, cost.spread_cost, 10.);
+        assert_approx_eq!(f64, cost.slippage, 50010. * 0.0001);
+        assert_approx_eq!(f64, cost.fee, 50010. * 1.0001 * 0.0005);
+        assert_approx_eq!(
+            f64,
+            cost.total(),
+            cost.fee + cost.slippage + cost.spread_cost
+        );
+
+        // 现金只扣除手续费与滑点，价差成本已包含在成交价中
+        let cash_cost = 100000.0 - broker.cash - fill.price * fill.filled_size;
+        assert_approx_eq!(f64, cash_cost, cost.explicit(), epsilon = 1e-9);
+        assert_approx_eq!(
+            f64,
+            cash_cost + (fill.price - 50000.) * fill.filled_size,
+            cost.total(),
+            epsilon = 1e-9
+        );
+
+        // 没有中间价时不计价差成本，Maker以优于中间价的价格成交时价差成本为负
+        let cost_model = TransactionCostModel::new(0., 0., 0.);
+        let maker_fill = Fill {
+            price: 49990.,
+            filled_size: 2.,
+            side: true,
+            exec_type: ExecType::Maker,
+            ..Default::default()
+        };
+        let cost = cost_model.calculate_cost(&maker_fill, None, 0., ContractType::Linear);
+        assert_eq!(cost, CostBreakdown::default());
+        let cost = cost_model.calculate_cost(&maker_fill, Some(50000.), 0., ContractType::Linear);
+        assert_approx_eq!(f64, cost.spread_cost, -20.);
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_interest_accrual() {
+        const DAY: i64 = 24 * 3600 * 1000;
+        let mock_data = vec![
+            create_mock_bbo(1000, 50000.0, 50001.0),
+            create_mock_bbo(1000 + DAY, 50000.0, 50001.0),
+        ];
+        let mut broker = SandboxBroker::new(
+            vec![InstId::EthUsdtSwap],
+            VecDataProvider::new(mock_data),
+            1000.0,
+            TransactionCostModel::new(0., 0., 0.),
+            Duration::milliseconds(1000),
+        )
+        .await
+        .unwrap()
+        .with_interest_rate(0.1);
+
+        // 以1000的现金买入价值约50000的仓位，借款49001
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 1.0, true)))
+            .await;
+        broker.next_broker_event().await.unwrap();
+        assert_eq!(broker.cash, -49001.0);
+
+        // 持有一天
+        broker.next_broker_event().await.unwrap();
+        let expected_interest = 49001.0 * 0.1 / 365.;
+        assert!((broker.cash - (-49001.0 - expected_interest)).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_benchmark() {
+        // 价格在100与101之间来回波动
+        let mock_data: Vec<_> = (0..100)
+            .map(|i| {
+                let bid_price = if i % 2 == 0 { 99.5 } else { 100.5 };
+                create_mock_bbo(1000 * (i + 1), bid_price, bid_price + 1.)
+            })
+            .collect();
+        let mut broker = SandboxBroker::new(
+            vec![InstId::EthUsdtSwap],
+            VecDataProvider::new(mock_data),
+            10000.0,
+            TransactionCostModel::new(0., 0., 0.),
+            Duration::milliseconds(1000),
+        )
+        .await
+        .unwrap()
+        .with_benchmark(InstId::EthUsdtSwap);
+
+        // 策略从不交易
+        while broker.next_broker_event().await.is_some() {}
+
+        let reporter = broker.reporter();
+        let benchmark_history = reporter.benchmark_history();
+        assert_eq!(benchmark_history.len(), reporter.value_history.len());
+        // 基准以100买入100单位，净值随价格变动
+        for record in benchmark_history {
+            assert!(record.value == 10000. || record.value == 10100.);
+        }
+        assert!(
+            benchmark_history
+                .iter()
+                .any(|record| record.value == 10100.)
+        );
+        assert!(
+            reporter
+                .value_history
+                .iter()
+                .all(|record| record.value == 10000.)
+        );
+        assert!(reporter.excess_sharpe().abs() < 0.05);
+    }
+
+    #[tokio::test]
+    async fn test_sweep() {
+        use crate::strategy::{
+            ExecutorArgs, OrderUpdateMode, RePegMode, single_ticker::ofi_momentum::OfiMomentumArgs,
+        };
+
+        // 带有趋势与波动的合成数据
+        let mock_data: Vec<_> = (0..2000i64)
+            .map(|i| {
+                let price = 2000. + (i as f64 / 50.).sin() * 20. + i as f64 * 0.01;
+                create_mock_bbo(1000 * (i + 1), price, price + 0.01)
+            })
+            .collect();
+        let args = |theta| OfiMomentumArgs {
+            instrument_id: InstId::EthUsdtSwap,
+            window_ofi: Duration::seconds(10),
+            window_ema: Duration::seconds(100),
+            theta,
+            executor: ExecutorArgs {
+                holding_duration: Duration::seconds(20),
+                event_interval: Duration::seconds(1),
+                notional: 10_000.,
+                price_offset: 0.,
+                re_peg_mode: RePegMode::ChaseTouch,
+                order_update_mode: OrderUpdateMode::Amend,
+                order_ttl: Duration::zero(),
+                post_fill_cooldown: Duration::zero(),
+                max_position_notional: None,
+                max_distance_ticks: None,
+                passive_only: false,
+                order_id_offset: 0,
+            },
+        };
+
+        let results = sweep(
+            vec![args(0.5), args(2.)],
+            move || VecDataProvider::new(mock_data.clone()),
+            OfiMomentumArgs::into_strategy,
+            vec![InstId::EthUsdtSwap],
+            100_000.,
+            TransactionCostModel::new(0., 0., 0.),
+            Duration::seconds(10),
+        )
+        .await;
+
+        assert_eq!(results.len(), 2);
+        let mut thetas: Vec<_> = results.iter().map(|(args, _)| args.theta).collect();
+        thetas.sort_by(f64::total_cmp);
+        assert_eq!(thetas, vec![0.5, 2.]);
+        assert!(
+            results
+                .iter()
+                .all(|(_, summary)| summary.final_value.is_some())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_seeded_backtest_reproducible() {
+        async fn run(seed: u64) -> Vec<Record> {
+            let mock_data: Vec<_> = (0..100i64)
+                .map(|i| {
+                    let price = 50000. + (i % 7) as f64 * 3.;
+                    create_mock_bbo(1000 * (i + 1), price, price + 1.)
+                })
+                .collect();
+            let mut broker = SandboxBroker::new(
+                vec![InstId::EthUsdtSwap],
+                VecDataProvider::new(mock_data),
+                100000.0,
+                TransactionCostModel::new(0.001, 0.002, 0.0001),
+                Duration::milliseconds(5000),
+            )
+            .await
+            .unwrap()
+            .with_seed(seed);
+            broker
+                .on_client_event(ClientEvent::PlaceOrder(create_limit_order(
+                    1, 50003.0, 1.0, true,
+                )))
+                .await;
+            broker
+                .on_client_event(ClientEvent::PlaceOrder(create_limit_order(
+                    2, 50010.0, 0.5, false,
+                )))
+                .await;
+            while broker.next_broker_event().await.is_some() {}
+            broker.reporter().value_history.clone()
+        }
+
+        let first = run(42).await;
+        let second = run(42).await;
+        assert!(!first.is_empty());
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_order_before_first_data_of_instrument() {
+        let mut btc_bbo = create_mock_bbo(3000, 30000.0, 30001.0);
+        btc_bbo.instrument_id = InstId::BtcUsdtSwap;
+        let mock_data = vec![
+            create_mock_bbo(1000, 100.0, 101.0),
+            create_mock_bbo(2000, 100.0, 101.0),
+            btc_bbo,
+            create_mock_bbo(4000, 100.0, 101.0),
+        ];
+        let mut broker = SandboxBroker::new(
+            vec![InstId::EthUsdtSwap, InstId::BtcUsdtSwap],
+            VecDataProvider::new(mock_data),
+            100000.0,
+            TransactionCostModel::new(0., 0., 0.),
+            Duration::milliseconds(1000),
+        )
+        .await
+        .unwrap();
+        // BTC的第一条数据尚未到达
+        assert!(!broker.inst_matcher.contains_key(&InstId::BtcUsdtSwap));
+
+        let btc_market_order = |order_id| {
+            Order::Market(MarketOrder {
+                order_id,
+                instrument_id: InstId::BtcUsdtSwap,
+                size: 0.1,
+                side: true,
+                reduce_only: false,
+            })
+        };
+        let btc_limit_order = Order::Limit(LimitOrder {
+            order_id: 2,
+            instrument_id: InstId::BtcUsdtSwap,
+            price: 30000.0,
+            size: 0.1,
+            filled_size: 0.,
+            side: true,
+            reduce_only: false,
+        });
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(btc_market_order(1)))
+            .await;
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(btc_limit_order))
+            .await;
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(3, 1.0, true)))
+            .await;
+        for order_id in [1, 2] {
+            assert!(matches!(
+                broker.next_broker_event().await,
+                Some(BrokerEvent::Rejected { order_id: id, reason: RejectReason::NoMarketData })
+                    if id == order_id
+            ));
+        }
+        // ETH的订单正常成交
+        assert!(matches!(
+            broker.next_broker_event().await,
+            Some(BrokerEvent::Fill(Fill { order_id: 3, .. }))
+        ));
+
+        // BTC的数据到达后可以成交
+        while let Some(event) = broker.next_broker_event().await {
+            if matches!(event, BrokerEvent::Data(bbo) if bbo.instrument_id == InstId::BtcUsdtSwap) {
+                break;
+            }
+        }
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(btc_market_order(4)))
+            .await;
+        let Some(BrokerEvent::Fill(fill)) = broker.next_broker_event().await else {
+            panic!("Expected a fill of the BTC order");
+        };
+        assert_eq!(fill.order_id, 4);
+        assert_eq!(fill.price, 30001.0);
+        // 两个产品都按中间价估值
+        assert_approx_eq!(
+            f64,
+            broker.get_total_value(),
+            100000.0 + (100.5 - 101.0) + 0.1 * (30000.5 - 30001.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_data_skipped() {
+        let mock_data = vec![
+            create_mock_bbo(1000, 100.0, 101.0),
+            create_mock_bbo(3000, 100.0, 101.0),
+            // 时间戳倒退的数据
+            create_mock_bbo(1500, 90.0, 91.0),
+            create_mock_bbo(4000, 100.0, 101.0),
+        ];
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data);
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 1.0, true)))
+            .await;
+
+        let mut data_ts = vec![];
+        while let Some(event) = broker.next_broker_event().await {
+            if let BrokerEvent::Data(bbo) = event {
+                data_ts.push(bbo.ts);
+                // 乱序数据未更新撮合价格
+                assert_eq!(
+                    broker.inst_matcher[&InstId::EthUsdtSwap].best_bid.price,
+                    100.0
+                );
+            }
+        }
+        assert_eq!(data_ts, [3000, 4000].map(Timestamp::from_millis));
+        assert_eq!(broker.clock.now(), Timestamp::from_millis(4000));
+
+        let history = &broker.reporter.value_history;
+        assert!(history.windows(2).all(|w| w[0].ts < w[1].ts));
+        assert!(history.iter().all(|record| record.value.is_finite()));
+    }
+
+    #[tokio::test]
+    async fn test_fill_probability_model() {
+        const ORDER_COUNT: u64 = 2000;
+        let mock_data = vec![
+            create_mock_bbo(1000, 50000.0, 50001.0),
+            create_mock_bbo(2000, 50000.0, 50001.0),
+        ];
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data)
+            .with_seed(7)
+            .with_fill_probability_model(FillProbabilityModel::new(1.));
+
+        // 每个挂单占最优买价数量的10%
+        for order_id in 0..ORDER_COUNT {
+            broker
+                .on_client_event(ClientEvent::PlaceOrder(create_limit_order(
+                    order_id, 50000.0, 0.1, true,
+                )))
+                .await;
+        }
+        let mut fill_count = 0;
+        while let Some(event) = broker.next_broker_event().await {
+            if let BrokerEvent::Fill(fill) = event {
+                assert_eq!(fill.price, 50000.0);
+                assert_eq!(fill.exec_type, ExecType::Maker);
+                fill_count += 1;
+            }
+        }
+
+        let fill_rate = fill_count as f64 / ORDER_COUNT as f64;
+        // 标准差约为0.0067
+        assert!((fill_rate - 0.1).abs() < 0.02, "fill rate: {fill_rate}");
+    }
+
     #[tokio::test]
     async fn test_sandbox_broker_limit_order_immediate_fill() {
         let mock_data = vec![create_mock_bbo(1000, 50000.0, 50001.0)];
 
-        let data_provider = MockDataProvider::new(mock_data);
+        let data_provider = VecDataProvider::new(mock_data);
         let transaction_cost_model = TransactionCostModel::new(0.001, 0.002, 0.0001);
 
         let mut broker = SandboxBroker::new(
@@ -632,12 +2959,15 @@ mod tests {
             transaction_cost_model,
             Duration::milliseconds(1000),
         )
-        .await;
+        .await
+        .unwrap();
 
         // Place a limit buy order at ask price (should fill immediately)
         let limit_order = create_limit_order(2, 50001.0, 0.5, true);
 
-        broker.on_client_event(ClientEvent::PlaceOrder(limit_order));
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(limit_order))
+            .await;
 
         // Should have a fill event
         let event = broker.next_broker_event().await.unwrap();
@@ -662,12 +2992,12 @@ mod tests {
 
         let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data);
 
-        dbg!(&broker.data_provider.index);
-
         // Place a limit buy order below current bid (should not fill)
         let limit_order = create_limit_order(3, 49999.0, 1.0, true);
 
-        broker.on_client_event(ClientEvent::PlaceOrder(limit_order));
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(limit_order))
+            .await;
         let event = broker.next_broker_event().await.unwrap();
         assert!(matches!(event, BrokerEvent::Placed(_)));
 
@@ -698,7 +3028,9 @@ mod tests {
         // Place a limit buy order
         let limit_order = create_limit_order(4, 49999.0, 1.0, true);
 
-        broker.on_client_event(ClientEvent::PlaceOrder(limit_order));
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(limit_order))
+            .await;
 
         // First event should be order placed
         let event = broker.next_broker_event().await.unwrap();
@@ -744,25 +3076,140 @@ mod tests {
 
         let limit_order = create_limit_order(5, 49999.0, 1.0, true);
 
-        broker.on_client_event(ClientEvent::PlaceOrder(limit_order));
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(limit_order))
+            .await;
         let event = broker.next_broker_event().await.unwrap();
         assert!(matches!(event, BrokerEvent::Placed(_)));
 
         // Amend the order
-        let amended_order = create_amend_order(5, 50001.0, 0.8);
+        let amended_order = create_amend_order(5, 50000.0, 0.8);
 
-        broker.on_client_event(ClientEvent::AmendOrder(amended_order));
+        broker
+            .on_client_event(ClientEvent::AmendOrder(amended_order))
+            .await;
         let event = broker.next_broker_event().await.unwrap();
         assert!(matches!(event, BrokerEvent::Amended(_)));
 
-        // Check that order was amended
-        let order = broker.limit_orders.get(&5).unwrap();
-        assert_eq!(order.price, 50001.0);
-        assert_eq!(order.size, 0.8);
+        // Check that order was amended
+        let order = broker.limit_orders.get(&5).unwrap();
+        assert_eq!(order.price, 50000.0);
+        assert_eq!(order.size, 0.8);
+
+        // Get data event
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Data(_)));
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_below_min_size_rejected() {
+        let mock_data = vec![create_mock_bbo(1000, 50000.0, 50001.0)];
+
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data);
+        let initial_cash = broker.cash;
+
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 0.005, true)))
+            .await;
+        let event = broker.next_broker_event().await.unwrap();
+        match event {
+            BrokerEvent::Rejected { order_id, reason } => {
+                assert_eq!(order_id, 1);
+                assert_eq!(reason, RejectReason::BelowMinSize);
+            }
+            _ => panic!("Expected Rejected event: {event:#?}"),
+        }
+        assert_eq!(broker.cash, initial_cash);
+        assert!(broker.portfolio().positions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_lot_size_rounding() {
+        let mock_data = vec![create_mock_bbo(1000, 50000.0, 50001.0)];
+
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data);
+
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_limit_order(
+                1, 49999.0, 0.257, true,
+            )))
+            .await;
+        let event = broker.next_broker_event().await.unwrap();
+        match event {
+            BrokerEvent::Placed(order) => assert_eq!(order.size(), 0.25),
+            _ => panic!("Expected Placed event: {event:#?}"),
+        }
+
+        broker
+            .on_client_event(ClientEvent::AmendOrder(create_amend_order(
+                1, 49999.0, 0.327,
+            )))
+            .await;
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Amended(_)));
+        assert_eq!(broker.limit_orders[&1].size, 0.32);
+
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(2, 0.129, true)))
+            .await;
+        let event = broker.next_broker_event().await.unwrap();
+        match event {
+            BrokerEvent::Fill(fill) => assert_eq!(fill.filled_size, 0.12),
+            _ => panic!("Expected Fill event: {event:#?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_amend_crosses_book() {
+        let mock_data = vec![create_mock_bbo(1000, 50000.0, 50001.0)];
+
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data);
+
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_limit_order(
+                1, 49999.0, 1.0, true,
+            )))
+            .await;
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Placed(_)));
+
+        // 改价到最优卖价，应立即以挂单价成交
+        broker
+            .on_client_event(ClientEvent::AmendOrder(create_amend_order(1, 50001.0, 1.0)))
+            .await;
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Amended(_)));
+        let event = broker.next_broker_event().await.unwrap();
+        match event {
+            BrokerEvent::Fill(fill) => {
+                assert_eq!(fill.order_id, 1);
+                assert_eq!(fill.price, 50001.0);
+                assert_eq!(fill.exec_type, ExecType::Maker);
+            }
+            _ => panic!("Expected Fill event: {event:#?}"),
+        }
+        assert!(broker.limit_orders.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_amend_unknown_order() {
+        let mock_data = vec![create_mock_bbo(1000, 50000.0, 50001.0)];
+
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data);
 
-        // Get data event
+        broker
+            .on_client_event(ClientEvent::AmendOrder(create_amend_order(
+                99, 49999.0, 1.0,
+            )))
+            .await;
         let event = broker.next_broker_event().await.unwrap();
-        assert!(matches!(event, BrokerEvent::Data(_)));
+        assert!(matches!(
+            event,
+            BrokerEvent::Rejected {
+                order_id: 99,
+                reason: RejectReason::UnknownOrder
+            }
+        ));
     }
 
     #[tokio::test]
@@ -774,17 +3221,371 @@ mod tests {
         // Place a limit order
         let limit_order = create_limit_order(6, 49999.0, 1.0, true);
 
-        broker.on_client_event(ClientEvent::PlaceOrder(limit_order));
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(limit_order))
+            .await;
         assert!(broker.limit_orders.contains_key(&6));
 
         // Cancel the order
-        broker.on_client_event(ClientEvent::CancelOrder(InstId::EthUsdtSwap, 6));
+        broker
+            .on_client_event(ClientEvent::CancelOrder(InstId::EthUsdtSwap, 6))
+            .await;
 
         // Order should be removed
         assert!(!broker.limit_orders.contains_key(&6));
         assert_eq!(broker.limit_orders.len(), 0);
     }
 
+    #[tokio::test]
+    async fn test_deferred_market_order_fills_at_next_trade() {
+        let trade = |ts, price, side| Trade {
+            ts,
+            instrument_id: InstId::EthUsdtSwap,
+            price,
+            size: 1.,
+            side,
+        };
+        let trades = vec![
+            trade(1000, 100., true),
+            trade(2000, 102., true),
+            trade(3000, 99., false),
+        ];
+        let mut broker = SandboxBroker::new(
+            vec![InstId::EthUsdtSwap],
+            VecDataProvider::new(trades),
+            10000.,
+            TransactionCostModel::new(0., 0., 0.),
+            Duration::seconds(1),
+        )
+        .await
+        .unwrap()
+        .with_deferred_market_orders();
+
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 1.0, true)))
+            .await;
+        // 挂单价与卖方主动成交的价格相同时成交
+        let limit_order = create_limit_order(2, 99., 1.0, true);
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(limit_order))
+            .await;
+        assert!(matches!(
+            broker.broker_events_buf.pop_front(),
+            Some(BrokerEvent::Placed(_))
+        ));
+        // 市价单不以已有的成交价立即成交
+        assert!(broker.broker_events_buf.is_empty());
+
+        let Some(BrokerEvent::Fill(fill)) = broker.next_broker_event().await else {
+            panic!("Expected the market order to fill at the next trade");
+        };
+        assert_eq!(fill.order_id, 1);
+        assert_eq!(fill.price, 102.);
+        assert_eq!(fill.exec_type, ExecType::Taker);
+        assert!(matches!(
+            broker.next_broker_event().await,
+            Some(BrokerEvent::Data(_))
+        ));
+
+        let Some(BrokerEvent::Fill(fill)) = broker.next_broker_event().await else {
+            panic!("Expected the limit order to fill");
+        };
+        assert_eq!(fill.order_id, 2);
+        assert_eq!(fill.price, 99.);
+        assert_eq!(fill.exec_type, ExecType::Maker);
+    }
+
+    /// 收到第一条数据时买入size
+    struct BuyOnce {
+        size: f64,
+        is_bought: bool,
+    }
+
+    impl Strategy<Bbo> for BuyOnce {
+        fn on_event(&mut self, broker_event: &BrokerEvent<Bbo>) -> Vec<ClientEvent> {
+            if self.is_bought || !matches!(broker_event, BrokerEvent::Data(_)) {
+                return vec![];
+            }
+            self.is_bought = true;
+            vec![ClientEvent::PlaceOrder(create_market_order(
+                1, self.size, true,
+            ))]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fan_out() {
+        let mock_data: Vec<_> = (0..100i64)
+            .map(|i| {
+                let price = 2000. + i as f64;
+                create_mock_bbo(1000 * (i + 1), price, price + 1.)
+            })
+            .collect();
+        let strategies = [1., 2.]
+            .map(|size| BuyOnce {
+                size,
+                is_bought: false,
+            })
+            .into();
+        let reporters = fan_out(
+            VecDataProvider::new(mock_data),
+            strategies,
+            vec![InstId::EthUsdtSwap],
+            100_000.,
+            TransactionCostModel::new(0., 0., 0.),
+            Duration::seconds(10),
+        )
+        .await
+        .unwrap();
+
+        // 两个策略收到同样的数据，但持仓互不影响：买入两倍数量的策略盈亏也是两倍
+        assert_eq!(reporters.len(), 2);
+        let (single, double) = (reporters[0].value_history(), reporters[1].value_history());
+        assert_eq!(single.len(), double.len());
+        assert!(single.len() > 5);
+        for (single, double) in single.iter().zip(double) {
+            assert_eq!(single.ts, double.ts);
+            assert_approx_eq!(
+                f64,
+                double.value - 100_000.,
+                2. * (single.value - 100_000.),
+                epsilon = 1e-6
+            );
+        }
+        assert!(reporters[0].last_value().unwrap() > 100_000.);
+    }
+
+    /// 记录收到的Bbo与逐笔成交的时间戳
+    #[derive(Default)]
+    struct RecordingStrategy {
+        bbo_ts: Vec<i64>,
+        trade_ts: Vec<i64>,
+    }
+
+    impl Strategy<data::MarketData> for RecordingStrategy {
+        fn on_event(&mut self, broker_event: &BrokerEvent<data::MarketData>) -> Vec<ClientEvent> {
+            match broker_event {
+                BrokerEvent::Data(data::MarketData::Bbo(bbo)) => {
+                    self.bbo_ts.push(bbo.ts.as_millis())
+                }
+                BrokerEvent::Data(data::MarketData::Trade(trade)) => self.trade_ts.push(trade.ts),
+                _ => {}
+            }
+            vec![]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mixed_market_data() {
+        use crate::strategy::{
+            ExecutorArgs, OrderUpdateMode, RePegMode, single_ticker::ofi_momentum::OfiMomentumArgs,
+        };
+
+        let trade = |ts, price| Trade {
+            ts,
+            instrument_id: InstId::EthUsdtSwap,
+            price,
+            size: 1.,
+            side: true,
+        };
+        let mixed_data = || {
+            (0..10)
+                .flat_map(|i| {
+                    let bid_price = 100. + (i % 3) as f64;
+                    [
+                        data::MarketData::Bbo(create_mock_bbo(1000 * i, bid_price, bid_price + 1.)),
+                        data::MarketData::Trade(trade(1000 * i + 500, bid_price + 1.)),
+                    ]
+                })
+                .collect::<Vec<_>>()
+        };
+        let create_broker = || async {
+            SandboxBroker::new(
+                vec![InstId::EthUsdtSwap],
+                VecDataProvider::new(mixed_data()),
+                10000.,
+                TransactionCostModel::new(0., 0., 0.),
+                Duration::seconds(1),
+            )
+            .await
+            .unwrap()
+        };
+
+        // 第一条Bbo用于初始化，其余的Bbo与全部逐笔成交都推送给策略
+        let mut engine = Engine::new(create_broker().await, RecordingStrategy::default());
+        engine.run().await;
+        let strategy = &engine.strategy;
+        assert_eq!(
+            strategy.bbo_ts,
+            (1..10).map(|i| 1000 * i).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            strategy.trade_ts,
+            (0..10).map(|i| 1000 * i + 500).collect::<Vec<_>>()
+        );
+
+        // 只处理Bbo的策略也能运行在混合的行情上
+        let args = OfiMomentumArgs {
+            instrument_id: InstId::EthUsdtSwap,
+            window_ofi: Duration::seconds(2),
+            window_ema: Duration::seconds(5),
+            theta: 0.1,
+            executor: ExecutorArgs {
+                holding_duration: Duration::seconds(3),
+                event_interval: Duration::zero(),
+                notional: 1000.,
+                price_offset: 0.,
+                re_peg_mode: RePegMode::HoldOriginal,
+                order_update_mode: OrderUpdateMode::Amend,
+                order_ttl: Duration::zero(),
+                post_fill_cooldown: Duration::zero(),
+                max_position_notional: None,
+                max_distance_ticks: None,
+                passive_only: false,
+                order_id_offset: 1,
+            },
+        };
+        let mut engine = Engine::new(create_broker().await, args.into_market_data_strategy());
+        engine.run().await;
+    }
+
+    #[tokio::test]
+    async fn test_run_with_progress() {
+        let mock_data = (0..10)
+            .map(|i| data::MarketData::Bbo(create_mock_bbo(1000 * i, 100., 101.)))
+            .collect();
+        let broker = SandboxBroker::new(
+            vec![InstId::EthUsdtSwap],
+            VecDataProvider::new(mock_data),
+            10000.,
+            TransactionCostModel::new(0., 0., 0.),
+            Duration::seconds(1),
+        )
+        .await
+        .unwrap();
+        let mut engine = Engine::new(broker, RecordingStrategy::default());
+
+        let mut calls = vec![];
+        engine
+            .run_with_progress(
+                3,
+                std::time::Duration::from_secs(3600),
+                |count, ts, equity| calls.push((count, ts, equity)),
+            )
+            .await;
+
+        // 第一条Bbo用于初始化，其余9条每3条调用一次，结束时再调用一次
+        let counts: Vec<_> = calls.iter().map(|(count, ..)| *count).collect();
+        assert_eq!(counts, vec![3, 6, 9, 9]);
+        assert!(calls.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert_eq!(calls.last().unwrap().1, Timestamp::from_millis(9000));
+        assert!(calls.iter().all(|(.., equity)| *equity == 10000.));
+        assert_eq!(engine.strategy.bbo_ts.len(), 9);
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_cancel_all() {
+        let mock_data = vec![create_mock_bbo(1000, 50000.0, 50001.0)];
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data);
+        for (order_id, price, side) in [(1, 49990.0, true), (2, 49980.0, true), (3, 50010.0, false)]
+        {
+            let limit_order = create_limit_order(order_id, price, 1.0, side);
+            broker
+                .on_client_event(ClientEvent::PlaceOrder(limit_order))
+                .await;
+        }
+        assert_eq!(broker.limit_orders.len(), 3);
+        broker.broker_events_buf.clear();
+
+        // 其他产品的全部撤单不影响挂单
+        broker
+            .on_client_event(ClientEvent::CancelAll(InstId::BtcUsdtSwap))
+            .await;
+        assert_eq!(broker.limit_orders.len(), 3);
+
+        broker
+            .on_client_event(ClientEvent::CancelAll(InstId::EthUsdtSwap))
+            .await;
+        assert!(broker.limit_orders.is_empty());
+        assert!(broker.queue_ahead.is_empty());
+        let canceled: Vec<_> = broker
+            .broker_events_buf
+            .iter()
+            .map(|event| match event {
+                BrokerEvent::Canceled(order_id) => *order_id,
+                _ => panic!("Expected only Canceled events"),
+            })
+            .collect();
+        assert_eq!(canceled, vec![1, 2, 3]);
+    }
+
+    fn reduce_only(order: Order) -> Order {
+        match order {
+            Order::Market(order) => Order::Market(MarketOrder {
+                reduce_only: true,
+                ..order
+            }),
+            Order::Limit(order) => Order::Limit(order.with_reduce_only(true)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_reduce_only() {
+        let mock_data = vec![create_mock_bbo(1000, 50000.0, 50001.0)];
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data);
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 1.0, true)))
+            .await;
+        assert!(matches!(
+            broker.next_broker_event().await,
+            Some(BrokerEvent::Fill(_))
+        ));
+
+        // 超出多头持仓的只减仓卖单只成交持仓的数量，不会反向开空
+        let order = reduce_only(create_market_order(2, 3.0, false));
+        broker.on_client_event(ClientEvent::PlaceOrder(order)).await;
+        let Some(BrokerEvent::Fill(fill)) = broker.next_broker_event().await else {
+            panic!("Expected a fill");
+        };
+        assert_eq!(fill.filled_size, 1.0);
+        assert_eq!(broker.portfolio().position_size(InstId::EthUsdtSwap), 0.);
+
+        // 没有持仓时只减仓订单被拒绝
+        let order = reduce_only(create_market_order(3, 1.0, false));
+        broker.on_client_event(ClientEvent::PlaceOrder(order)).await;
+        assert!(matches!(
+            broker.next_broker_event().await,
+            Some(BrokerEvent::Rejected {
+                order_id: 3,
+                reason: RejectReason::ReduceOnly
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_reduce_only_resting_order() {
+        let mock_data = vec![create_mock_bbo(1000, 50000.0, 50001.0)];
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data);
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 1.0, true)))
+            .await;
+        let order = reduce_only(create_limit_order(2, 60000.0, 2.0, false));
+        broker.on_client_event(ClientEvent::PlaceOrder(order)).await;
+        // 挂单数量被限制为持仓的数量
+        assert_eq!(broker.limit_orders[&2].size, 1.0);
+
+        // 持仓被其他订单平掉后，只减仓挂单被撤销
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(3, 1.0, false)))
+            .await;
+        assert!(!broker.limit_orders.contains_key(&2));
+        let mut events = vec![];
+        while let Some(event) = broker.next_broker_event().await {
+            events.push(event);
+        }
+        assert!(matches!(events[2], BrokerEvent::Canceled(2)));
+    }
+
     #[tokio::test]
     async fn test_sandbox_broker_multiple_orders_complex_scenario() {
         let mock_data = vec![
@@ -804,7 +3605,7 @@ mod tests {
             ClientEvent::PlaceOrder(create_market_order(12, 0.1, true)),
         ];
 
-        broker.on_client_events(orders.into_iter());
+        broker.on_client_events(orders.into_iter()).await;
 
         // Should have 2 limit orders placed and 1 market order filled
         assert_eq!(broker.limit_orders.len(), 2);
@@ -859,9 +3660,9 @@ mod tests {
             }
         }
 
-        let position = &broker.portfolio.positions[&InstId::EthUsdtSwap];
-        assert_approx_eq!(f64, position.size, 0.5 - 1. + 0.1); // 0.5 bought, 1 sold, 0.1 bought
-        assert!(position.size < 0.); // Net position should be short
+        let position = &broker.portfolio().positions()[&InstId::EthUsdtSwap];
+        assert_approx_eq!(f64, position.size(), 0.5 - 1. + 0.1); // 0.5 bought, 1 sold, 0.1 bought
+        assert!(position.size() < 0.); // Net position should be short
 
         // Should have processed several fills and data events
         assert_eq!(data_count, 3); // All market data events
@@ -870,11 +3671,442 @@ mod tests {
         assert_ne!(broker.cash, initial_cash);
 
         // Portfolio should have positions
-        assert!(!broker.portfolio.positions.is_empty());
+        assert!(!broker.portfolio().positions().is_empty());
 
         dbg!(&broker.reporter.value_history);
     }
 
+    #[tokio::test]
+    async fn test_sandbox_broker_latency() {
+        let mock_data = vec![
+            create_mock_bbo(1000, 50000.0, 50001.0),
+            create_mock_bbo(1200, 50010.0, 50011.0),
+            create_mock_bbo(1500, 50020.0, 50021.0),
+        ];
+
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data)
+            .with_latency(Duration::milliseconds(500));
+
+        // 在ts=1000发出的市价单，要到ts=1500才抵达
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 1.0, true)))
+            .await;
+
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Data(bbo) if bbo.ts == Timestamp::from_millis(1200)));
+        assert!(broker.portfolio().positions().is_empty());
+
+        // 以较后一期的价格成交
+        let event = broker.next_broker_event().await.unwrap();
+        match event {
+            BrokerEvent::Fill(fill) => {
+                assert_eq!(fill.order_id, 1);
+                assert_eq!(fill.price, 50021.0);
+                assert_eq!(fill.exec_type, ExecType::Taker);
+            }
+            _ => panic!("Expected Fill event: {event:#?}"),
+        }
+
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Data(bbo) if bbo.ts == Timestamp::from_millis(1500)));
+    }
+
+    #[tokio::test]
+    async fn test_look_ahead_check() {
+        let mock_data = vec![
+            create_mock_bbo(1000, 50000.0, 50001.0),
+            create_mock_bbo(1200, 50010.0, 50011.0),
+            create_mock_bbo(1500, 49990.0, 49991.0),
+        ];
+        let mut broker =
+            create_sandbox_broker!(InstId::EthUsdtSwap, mock_data).with_look_ahead_check();
+
+        // 正常的撮合不触发检查
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_limit_order(
+                1, 49995.0, 1.0, true,
+            )))
+            .await;
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Placed(_)));
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Data(bbo) if bbo.ts == Timestamp::from_millis(1200)));
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Fill(fill) if fill.order_id == 1));
+        assert!(broker.submitted_ts.is_empty());
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "look-ahead bias")]
+    async fn test_look_ahead_check_fires_on_stale_data() {
+        let mock_data = vec![
+            create_mock_bbo(1000, 50000.0, 50001.0),
+            create_mock_bbo(1200, 50010.0, 50011.0),
+        ];
+        let mut broker =
+            create_sandbox_broker!(InstId::EthUsdtSwap, mock_data).with_look_ahead_check();
+        broker.next_broker_event().await.unwrap();
+
+        // 在ts=1200发出的挂单
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_limit_order(
+                1, 50005.0, 1.0, true,
+            )))
+            .await;
+        assert!(matches!(
+            broker.next_broker_event().await.unwrap(),
+            BrokerEvent::Placed(_)
+        ));
+
+        // 故意以ts=1000的过时数据撮合
+        let stale_bbo = create_mock_bbo(1000, 50000.0, 50001.0);
+        broker
+            .inst_matcher
+            .insert(InstId::EthUsdtSwap, stale_bbo.draw_matcher().unwrap());
+        broker.try_fill_placed_orders();
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_push_latency() {
+        let mock_data = vec![
+            create_mock_bbo(1000, 50000.0, 50001.0),
+            create_mock_bbo(1200, 50010.0, 50011.0),
+            create_mock_bbo(1500, 50020.0, 50021.0),
+        ];
+
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data)
+            .with_push_latency(Duration::milliseconds(300));
+
+        // 在ts=1000立即成交，但成交推送要到ts=1300之后的数据到达时才推送
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 1.0, true)))
+            .await;
+        assert_eq!(
+            broker.portfolio().positions()[&InstId::EthUsdtSwap].size(),
+            1.0
+        );
+
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Data(bbo) if bbo.ts == Timestamp::from_millis(1200)));
+
+        let event = broker.next_broker_event().await.unwrap();
+        match event {
+            BrokerEvent::Fill(fill) => {
+                assert_eq!(fill.order_id, 1);
+                assert_eq!(fill.price, 50001.0);
+            }
+            _ => panic!("Expected Fill event: {event:#?}"),
+        }
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Data(bbo) if bbo.ts == Timestamp::from_millis(1500)));
+    }
+
+    fn create_mock_bbo_with_size(ts: i64, bid_size: f64, ask_size: f64) -> Bbo {
+        let mut bbo = create_mock_bbo(ts, 100.0, 101.0);
+        bbo.best_bid.size = bid_size;
+        bbo.best_ask.size = ask_size;
+        bbo
+    }
+
+    #[test]
+    fn test_bbo_queue_ahead() {
+        let order = match create_limit_order(1, 100.0, 1.0, true) {
+            Order::Limit(order) => order,
+            _ => unreachable!(),
+        };
+
+        let bbo = create_mock_bbo_with_size(1000, 3.0, 1.0);
+        assert_eq!(bbo.queue_ahead(&order), 3.0);
+        // 挂单价低于最优买价，排队数量未知
+        let lower_bbo = create_mock_bbo(1000, 100.5, 101.0);
+        assert_eq!(lower_bbo.queue_ahead(&order), f64::INFINITY);
+
+        // 同价位数量减少，排在之前的数量相应减少
+        let next_bbo = create_mock_bbo_with_size(2000, 2.0, 1.0);
+        assert_eq!(next_bbo.update_queue_ahead(&bbo, &order, 3.0), 2.0);
+        // 同价位数量增加，新增的数量排在之后
+        let next_bbo = create_mock_bbo_with_size(2000, 5.0, 1.0);
+        assert_eq!(next_bbo.update_queue_ahead(&bbo, &order, 3.0), 3.0);
+        // 最优买价到达挂单价，排队数量不超过该价位的数量
+        let next_bbo = create_mock_bbo_with_size(2000, 2.0, 1.0);
+        assert_eq!(
+            next_bbo.update_queue_ahead(&lower_bbo, &order, f64::INFINITY),
+            2.0
+        );
+        // 最优买价低于挂单价，挂单价位已被消耗
+        let next_bbo = create_mock_bbo(2000, 99.0, 101.0);
+        assert_eq!(next_bbo.update_queue_ahead(&bbo, &order, 3.0), 0.0);
+    }
+
+    #[test]
+    fn test_top_levels_sweep() {
+        let level = |price, size| Level {
+            price,
+            size,
+            order_count: 1,
+        };
+        // 卖方各档：100 × 1, 101 × 2, 102 × 3, 103 × 4, 104 × 5，第6档被截去
+        let order_book = data_center::OrderBook {
+            ts: Timestamp::from_millis(1000),
+            instrument_id: InstId::EthUsdtSwap,
+            bids: (0..6).map(|i| level(99. - i as f64, 1.)).collect(),
+            asks: (0..6)
+                .map(|i| level(100. + i as f64, 1. + i as f64))
+                .collect(),
+        };
+        let levels = TopLevels::<5>::from(order_book);
+        assert_eq!(levels.asks.len(), 5);
+        assert_eq!(levels.best_bid().unwrap().price, 99.);
+        let inst_levels = FxHashMap::from_iter([(InstId::EthUsdtSwap, levels)]);
+        let market_fill = |size| {
+            let Order::Market(order) = create_market_order(1, size, true) else {
+                unreachable!()
+            };
+            TopLevels::fill_market_order(&inst_levels, &order)
+                .unwrap()
+                .price
+        };
+        let limit_fill = |price, size, exec_type| {
+            let Order::Limit(order) = create_limit_order(1, price, size, true) else {
+                unreachable!()
+            };
+            TopLevels::try_fill_limit_order(&inst_levels, &order, exec_type).map(|fill| fill.price)
+        };
+
+        // 市价单依次吃掉前三档：(100 × 1 + 101 × 2 + 102 × 1) / 4
+        assert_approx_eq!(f64, market_fill(4.), 101.);
+        // 超出可见深度的5个以最差一档104成交：(1540 + 104 × 5) / 20
+        assert_approx_eq!(f64, market_fill(20.), 103.);
+        // 可立即成交的限价单只吃不劣于限价的档位，其余以限价成交：(100 + 202 + 101.5) / 4
+        assert_approx_eq!(
+            f64,
+            limit_fill(101.5, 4., ExecType::Taker).unwrap(),
+            100.875
+        );
+        assert_eq!(limit_fill(99.5, 4., ExecType::Taker), None);
+        assert_eq!(limit_fill(101.5, 4., ExecType::Maker), Some(101.5));
+
+        // 只有一档时与Bbo的撮合一致
+        let bbo = create_mock_bbo(1000, 50000.0, 50001.0);
+        let inst_bbo = FxHashMap::from_iter([(InstId::EthUsdtSwap, bbo)]);
+        let inst_levels = FxHashMap::from_iter([(InstId::EthUsdtSwap, TopLevels::<1>::from(bbo))]);
+        let Order::Market(order) = create_market_order(1, 3., false) else {
+            unreachable!()
+        };
+        assert_eq!(
+            TopLevels::fill_market_order(&inst_levels, &order),
+            Bbo::fill_market_order(&inst_bbo, &order)
+        );
+        let Order::Limit(order) = create_limit_order(1, 50000.0, 1.0, true) else {
+            unreachable!()
+        };
+        assert_eq!(
+            inst_levels[&InstId::EthUsdtSwap].queue_ahead(&order),
+            bbo.queue_ahead(&order)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_top_levels_empty_side() {
+        // 卖方没有报价
+        let order_book = data_center::OrderBook {
+            ts: Timestamp::from_millis(1000),
+            instrument_id: InstId::EthUsdtSwap,
+            bids: vec![Level {
+                price: 99.,
+                size: 1.,
+                order_count: 1,
+            }],
+            asks: vec![],
+        };
+        let levels = TopLevels::<5>::from(order_book);
+        let inst_levels = FxHashMap::from_iter([(InstId::EthUsdtSwap, levels.clone())]);
+        let market_order = |side| {
+            let Order::Market(order) = create_market_order(1, 1., side) else {
+                unreachable!()
+            };
+            order
+        };
+        assert!(TopLevels::fill_market_order(&inst_levels, &market_order(true)).is_none());
+        assert!(TopLevels::fill_market_order(&inst_levels, &market_order(false)).is_some());
+
+        // Broker拒绝无法成交的市价单
+        let mut broker = SandboxBroker::new(
+            vec![InstId::EthUsdtSwap],
+            VecDataProvider::new(vec![levels]),
+            10000.0,
+            TransactionCostModel::new(0., 0., 0.),
+            Duration::seconds(1),
+        )
+        .await
+        .unwrap();
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 1., true)))
+            .await;
+        assert!(matches!(
+            broker.next_broker_event().await,
+            Some(BrokerEvent::Rejected {
+                order_id: 1,
+                reason: RejectReason::NoMarketData
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_queue_drain() {
+        let mock_data = vec![
+            create_mock_bbo_with_size(1000, 3.0, 1.0),
+            create_mock_bbo_with_size(2000, 1.0, 1.0),
+            create_mock_bbo_with_size(3000, 5.0, 1.0),
+            create_mock_bbo_with_size(4000, 4.0, 1.0),
+        ];
+
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data).with_queue_model();
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_limit_order(
+                1, 100.0, 1.0, true,
+            )))
+            .await;
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Placed(_)));
+        assert_eq!(broker.queue_ahead[&1], 3.0);
+
+        // 排在之前的数量由3减少到1
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Data(_)));
+        assert_eq!(broker.queue_ahead[&1], 1.0);
+
+        // 新增的数量排在之后
+        let event = broker.next_broker_event().await.unwrap();
+        assert!(matches!(event, BrokerEvent::Data(_)));
+        assert_eq!(broker.queue_ahead[&1], 1.0);
+
+        // 排在之前的数量耗尽，未穿越价格也成交
+        let event = broker.next_broker_event().await.unwrap();
+        match event {
+            BrokerEvent::Fill(fill) => {
+                assert_eq!(fill.order_id, 1);
+                assert_eq!(fill.price, 100.0);
+                assert_eq!(fill.exec_type, ExecType::Maker);
+            }
+            _ => panic!("Expected Fill event: {event:#?}"),
+        }
+        assert!(broker.limit_orders.is_empty());
+        assert!(broker.queue_ahead.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_broker_without_queue_model() {
+        let mock_data = vec![
+            create_mock_bbo_with_size(1000, 3.0, 1.0),
+            create_mock_bbo_with_size(2000, 1.0, 1.0),
+            create_mock_bbo_with_size(3000, 0.5, 1.0),
+        ];
+
+        let mut broker = create_sandbox_broker!(InstId::EthUsdtSwap, mock_data);
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_limit_order(
+                1, 100.0, 1.0, true,
+            )))
+            .await;
+
+        // 未启用排队模型时，价格未穿越则不成交
+        while let Some(event) = broker.next_broker_event().await {
+            assert!(!matches!(event, BrokerEvent::Fill(_)));
+        }
+        assert!(broker.limit_orders.contains_key(&1));
+    }
+
+    #[tokio::test]
+    async fn test_to_csv_detailed() {
+        let btc_bbo = |ts, bid_price, ask_price| Bbo {
+            instrument_id: InstId::BtcUsdtSwap,
+            ..create_mock_bbo(ts, bid_price, ask_price)
+        };
+        let mock_data = vec![
+            create_mock_bbo(0, 100.0, 101.0),
+            btc_bbo(100, 1000.0, 1002.0),
+            create_mock_bbo(1500, 110.0, 111.0),
+        ];
+        let mut broker = SandboxBroker::new(
+            vec![InstId::EthUsdtSwap, InstId::BtcUsdtSwap],
+            VecDataProvider::new(mock_data),
+            10000.0,
+            TransactionCostModel::new(0.0, 0.0, 0.0),
+            Duration::milliseconds(1000),
+        )
+        .await
+        .unwrap();
+
+        // 以101买入2 ETH，以1000卖出1 BTC
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 2.0, true)))
+            .await;
+        broker.next_broker_event().await.unwrap();
+        broker.next_broker_event().await.unwrap();
+        let mut order = create_market_order(2, 1.0, false);
+        if let Order::Market(order) = &mut order {
+            order.instrument_id = InstId::BtcUsdtSwap;
+        }
+        broker.on_client_event(ClientEvent::PlaceOrder(order)).await;
+        while broker.next_broker_event().await.is_some() {}
+
+        let path = std::env::temp_dir().join("ac_test_to_csv_detailed.csv");
+        broker.reporter().to_csv_detailed(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "ts,value,EthUsdtSwap_size,EthUsdtSwap_price,EthUsdtSwap_value,\
+                 BtcUsdtSwap_size,BtcUsdtSwap_price,BtcUsdtSwap_value",
+                // 现金 10000 - 202 + 1000，ETH按中间价100.5、BTC按1001估值
+                "1000,9998,2,100.5,201,-1,1001,-1001",
+                "2000,10018,2,110.5,221,-1,1001,-1001",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_to_csv_detailed_inverse() {
+        let mock_data = vec![
+            create_mock_bbo(0, 100.0, 100.0),
+            create_mock_bbo(1500, 125.0, 125.0),
+        ];
+        let mut broker = SandboxBroker::new(
+            vec![InstId::EthUsdtSwap],
+            VecDataProvider::new(mock_data),
+            10.0,
+            TransactionCostModel::new(0.0, 0.0, 0.0),
+            Duration::milliseconds(1000),
+        )
+        .await
+        .unwrap()
+        .with_contract_type(InstId::EthUsdtSwap, ContractType::Inverse);
+
+        // 以100买入面值100的反向合约，现金以币计增加 100 / 100
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 100.0, true)))
+            .await;
+        while broker.next_broker_event().await.is_some() {}
+
+        let path = std::env::temp_dir().join("ac_test_to_csv_detailed_inverse.csv");
+        broker.reporter().to_csv_detailed(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "ts,value,EthUsdtSwap_size,EthUsdtSwap_price,EthUsdtSwap_value",
+                // 持仓的价值为 -size / price，与现金11相加即为净值
+                "1000,10,100,100,-1",
+                "2000,10.2,100,125,-0.8",
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn test_sandbox_broker_reporter() {
         // Create market data with clear price changes
@@ -885,7 +4117,7 @@ mod tests {
             create_mock_bbo(3999, 51999.0, 52000.0), // Price up again
         ];
 
-        let data_provider = MockDataProvider::new(mock_data);
+        let data_provider = VecDataProvider::new(mock_data);
 
         // Zero transaction costs
         let transaction_cost_model = TransactionCostModel::new(0.0, 0.0, 0.0);
@@ -899,10 +4131,13 @@ mod tests {
             transaction_cost_model,
             Duration::milliseconds(1000),
         )
-        .await;
+        .await
+        .unwrap();
 
         // 1. Buy 0.1 BTC at 50,000
-        broker.on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 0.1, true)));
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(1, 0.1, true)))
+            .await;
 
         // Get first event (fill)
         let event = broker.next_broker_event().await.unwrap();
@@ -923,7 +4158,9 @@ mod tests {
         }
 
         // 3. Sell 0.05 BTC at 51,000
-        broker.on_client_event(ClientEvent::PlaceOrder(create_market_order(2, 0.05, false)));
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(2, 0.05, false)))
+            .await;
 
         // Get sell fill event
         let event = broker.next_broker_event().await.unwrap();
@@ -944,7 +4181,9 @@ mod tests {
         }
 
         // 5. Buy 0.1 BTC at 49,000
-        broker.on_client_event(ClientEvent::PlaceOrder(create_market_order(3, 0.1, true)));
+        broker
+            .on_client_event(ClientEvent::PlaceOrder(create_market_order(3, 0.1, true)))
+            .await;
 
         // Get buy fill event
         let event = broker.next_broker_event().await.unwrap();
@@ -1022,17 +4261,29 @@ mod tests {
         );
 
         // Verify timestamps
-        assert_eq!(broker.reporter.value_history[0].ts, 1000);
-        assert_eq!(broker.reporter.value_history[1].ts, 2000);
-        assert_eq!(broker.reporter.value_history[2].ts, 3000);
-        assert_eq!(broker.reporter.value_history[3].ts, 4000);
+        assert_eq!(
+            broker.reporter.value_history[0].ts,
+            Timestamp::from_millis(1000)
+        );
+        assert_eq!(
+            broker.reporter.value_history[1].ts,
+            Timestamp::from_millis(2000)
+        );
+        assert_eq!(
+            broker.reporter.value_history[2].ts,
+            Timestamp::from_millis(3000)
+        );
+        assert_eq!(
+            broker.reporter.value_history[3].ts,
+            Timestamp::from_millis(4000)
+        );
 
         // Verify final portfolio state
         assert_approx_eq!(f64, broker.cash, cash, epsilon = 1e-6);
-        assert!(broker.portfolio.positions[&InstId::EthUsdtSwap].size > 0.); // Long
+        assert!(broker.portfolio().positions()[&InstId::EthUsdtSwap].size() > 0.); // Long
         assert_approx_eq!(
             f64,
-            broker.portfolio.positions[&InstId::EthUsdtSwap].size,
+            broker.portfolio().positions()[&InstId::EthUsdtSwap].size(),
             0.15,
             epsilon = 1e-6
         );
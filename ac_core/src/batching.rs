@@ -0,0 +1,129 @@
+use rustc_hash::FxHashMap;
+
+use crate::{Broker, BrokerEvent, ClientEvent, InstId};
+
+/// 将同一tick内策略发出的全部ClientEvent合并为一批，以on_client_batch交给内部的Broker。
+///
+/// Engine每处理一个BrokerEvent调用一次on_client_events，此时发送缓存的全部事件；
+/// 单独调用on_client_event的事件在下一次on_client_events或next_broker_event前发送。
+pub struct BatchingSink<B> {
+    inner: B,
+    buffer: Vec<ClientEvent>,
+}
+
+impl<B> BatchingSink<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            buffer: vec![],
+        }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    async fn flush<D>(&mut self)
+    where
+        B: Broker<D>,
+    {
+        if !self.buffer.is_empty() {
+            let client_events = std::mem::take(&mut self.buffer);
+            self.inner.on_client_batch(client_events).await;
+        }
+    }
+}
+
+impl<D, B: Broker<D>> Broker<D> for BatchingSink<B> {
+    async fn on_client_event(&mut self, client_event: ClientEvent) {
+        self.buffer.push(client_event);
+    }
+
+    async fn on_client_events(&mut self, client_events: impl Iterator<Item = ClientEvent>) {
+        self.buffer.extend(client_events);
+        self.flush().await;
+    }
+
+    async fn next_broker_event(&mut self) -> Option<BrokerEvent<D>> {
+        self.flush().await;
+        self.inner.next_broker_event().await
+    }
+
+    async fn request_open_orders(&mut self) {
+        self.flush().await;
+        self.inner.request_open_orders().await;
+    }
+
+    fn positions_snapshot(&self) -> Option<FxHashMap<InstId, f64>> {
+        self.inner.positions_snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LimitOrder, data::Bbo};
+
+    /// 记录收到的每一批事件
+    #[derive(Default)]
+    struct RecordingBroker {
+        batches: Vec<Vec<ClientEvent>>,
+    }
+
+    impl Broker<Bbo> for RecordingBroker {
+        async fn on_client_event(&mut self, client_event: ClientEvent) {
+            self.batches.push(vec![client_event]);
+        }
+
+        async fn on_client_batch(&mut self, client_events: Vec<ClientEvent>) {
+            self.batches.push(client_events);
+        }
+
+        async fn next_broker_event(&mut self) -> Option<BrokerEvent<Bbo>> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batching_sink() {
+        let mut broker = BatchingSink::new(RecordingBroker::default());
+        let place = || {
+            ClientEvent::place_limit_order(LimitOrder::from_raw_size(
+                1.,
+                2,
+                InstId::EthUsdtSwap,
+                100.,
+            ))
+        };
+
+        // 撤单与下单作为一批交给内部的Broker
+        let client_events = vec![ClientEvent::CancelOrder(InstId::EthUsdtSwap, 1), place()];
+        broker.on_client_events(client_events.into_iter()).await;
+        let batches = &broker.inner().batches;
+        assert_eq!(batches.len(), 1);
+        assert!(matches!(
+            batches[0][..],
+            [ClientEvent::CancelOrder(_, 1), ClientEvent::PlaceOrder(_)]
+        ));
+
+        // 单独发出的事件缓存到下一次取BrokerEvent前
+        broker
+            .on_client_event(ClientEvent::CancelAll(InstId::EthUsdtSwap))
+            .await;
+        broker.on_client_event(place()).await;
+        assert_eq!(broker.inner().batches.len(), 1);
+        assert!(broker.next_broker_event().await.is_none());
+        let batches = &broker.inner().batches;
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[1].len(), 2);
+
+        // 没有事件时不发送空的一批
+        broker.on_client_events(std::iter::empty()).await;
+        assert!(broker.next_broker_event().await.is_none());
+        assert_eq!(broker.into_inner().batches.len(), 2);
+    }
+}
@@ -1,35 +1,24 @@
 use std::path::Path;
 
-use ac_core::InstId;
 use ac_core::{
     Engine,
     backtest::{SandboxBroker, TransactionCostModel},
     data::okx::get_bbo_history_provider,
-    strategy::single_ticker::ofi_momentum::OfiMomentumArgs,
+    strategy::config::{self, StrategyConfig, build_strategy},
 };
 use chrono::Duration;
 
 #[tokio::main]
 async fn main() {
-    let _guard = utils::init_tracing();
+    let _guard = utils::init_tracing(utils::TracingConfig::default());
 
-    let instrument_id = InstId::EthUsdtSwap;
-    let instruments = vec![instrument_id];
+    let strategy_config = StrategyConfig::load(config::config_path()).unwrap();
+    let instruments = vec![strategy_config.instrument_id()];
 
     let data_provider = get_bbo_history_provider(instruments.clone(), Duration::days(300));
 
-    let strategy_args = OfiMomentumArgs {
-        instrument_id,
-        window_ofi: Duration::minutes(8),
-        window_ema: Duration::minutes(240),
-        holding_duration: Duration::seconds(200),
-        event_interval: Duration::seconds(1),
-        theta: 5.,
-        notional: 100_000.,
-        price_offset: 0.,
-        order_id_offset: 0,
-    };
-    let strategy = strategy_args.into_strategy();
+    let warm_up = strategy_config.warm_up();
+    let strategy = build_strategy(strategy_config);
 
     let transaction_cost_model = TransactionCostModel::new_okx(0.);
     let broker = SandboxBroker::new(
@@ -39,7 +28,9 @@ async fn main() {
         transaction_cost_model,
         Duration::minutes(1),
     )
-    .await;
+    .await
+    .unwrap()
+    .with_warm_up(warm_up);
 
     let mut engine = Engine::new(broker, strategy);
     engine.run().await;
@@ -10,7 +10,8 @@ async fn main() {
     let instrument_id = InstId::EthUsdtSwap;
     let instruments = vec![instrument_id];
     let data_provider = get_bbo_history_provider(instruments.clone(), Duration::hours(2400));
-    let spread_stream = data_provider.map(|bbo| OrderedFloat(bbo.ask_price - bbo.bid_price));
+    let spread_stream =
+        data_provider.map(|bbo| OrderedFloat(bbo.best_ask.price - bbo.best_bid.price));
     let top_spreads = top_100(spread_stream).await;
     dbg!(top_spreads);
 }
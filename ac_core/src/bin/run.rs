@@ -1,29 +1,39 @@
-use ac_core::InstId;
 use ac_core::okx::OkxBroker;
-use ac_core::{Engine, strategy::single_ticker::ofi_momentum::OfiMomentumArgs};
+use ac_core::{
+    Engine,
+    strategy::config::{self, StrategyConfig, build_live_strategy},
+};
 use chrono::Duration;
 
+/// 策略状态的保存路径
+const CHECKPOINT_PATH: &str = "checkpoints/strategy.json";
+
 #[tokio::main]
 async fn main() {
-    let _guard = utils::init_tracing();
+    let _guard = utils::init_tracing(utils::TracingConfig::default());
+
+    if let Some(port) = data_center::metrics::configured_port() {
+        tokio::spawn(data_center::metrics::serve(port));
+    }
 
-    let instrument_id = InstId::EthUsdtSwap;
+    let strategy_config = StrategyConfig::load(config::config_path()).unwrap();
+    let instrument_id = strategy_config.instrument_id();
+    let strategy = build_live_strategy(strategy_config);
 
-    let strategy_args = OfiMomentumArgs {
-        instrument_id,
-        window_ofi: Duration::minutes(8),
-        window_ema: Duration::minutes(240),
-        holding_duration: Duration::seconds(200),
-        event_interval: Duration::seconds(1),
-        theta: 5.,
-        notional: 100_000.,
-        price_offset: 0.,
-        order_id_offset: 0,
-    };
-    let strategy = strategy_args.into_strategy();
+    let broker = OkxBroker::new_bbo(instrument_id, Duration::minutes(240), false).await;
 
-    let broker = OkxBroker::new_bbo(instrument_id, Duration::minutes(240)).await;
+    let mut engine = Engine::new(broker, strategy)
+        .with_checkpoint(CHECKPOINT_PATH, std::time::Duration::from_secs(60));
+    let checkpoint_path = std::path::Path::new(CHECKPOINT_PATH);
+    if checkpoint_path.exists() {
+        engine.load_checkpoint(checkpoint_path).unwrap();
+    }
 
-    let mut engine = Engine::new(broker, strategy);
-    engine.run().await;
+    tokio::select! {
+        _ = engine.run() => {}
+        _ = tokio::signal::ctrl_c() => {
+            tracing::info!("Shutting down");
+            engine.save_configured_checkpoint();
+        }
+    }
 }
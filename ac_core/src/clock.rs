@@ -0,0 +1,69 @@
+use crate::Timestamp;
+
+/// 当前时刻的来源。回测中为最新数据的时间戳，实盘中为系统时间，
+/// 使挂单存活时间、下单间隔等依赖时间的逻辑在两种模式下一致
+pub trait Clock {
+    fn now(&self) -> Timestamp;
+
+    /// 收到带时间戳的事件时调用。只由数据驱动的时钟需要推进
+    fn advance(&mut self, _ts: Timestamp) {}
+}
+
+/// 由最新事件的时间戳驱动的时钟，用于回测。时间不会倒退
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DataClock {
+    ts: Timestamp,
+}
+
+impl DataClock {
+    pub fn new(ts: Timestamp) -> Self {
+        Self { ts }
+    }
+}
+
+impl Clock for DataClock {
+    fn now(&self) -> Timestamp {
+        self.ts
+    }
+
+    fn advance(&mut self, ts: Timestamp) {
+        self.ts = self.ts.max(ts);
+    }
+}
+
+/// 系统时间，用于实盘
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WallClock;
+
+impl Clock for WallClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::now()
+    }
+}
+
+impl Default for Box<dyn Clock + Send> {
+    fn default() -> Self {
+        Box::new(DataClock::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_clock() {
+        let mut clock = DataClock::default();
+        clock.advance(Timestamp::from_millis(2000));
+        assert_eq!(clock.now(), Timestamp::from_millis(2000));
+
+        // 乱序的时间戳不会使时钟倒退
+        clock.advance(Timestamp::from_millis(1000));
+        assert_eq!(clock.now(), Timestamp::from_millis(2000));
+
+        // 系统时间不受事件影响
+        let mut clock = WallClock;
+        clock.advance(Timestamp::from_millis(1000));
+        assert!(clock.now() > Timestamp::from_millis(1000));
+    }
+}
@@ -1,10 +1,26 @@
 pub mod okx;
+pub mod synthetic;
 
-use data_center::types::{Action, OrdType, OrderPushType};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
 
-use crate::{BrokerEvent, ClientEvent, ExecType, Fill, FillState, InstId, LimitOrder, Order};
+use arrayvec::ArrayVec;
+use data_center::types::{Action, OrdType, OrderOp, OrderPushType};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use utils::Timestamped;
 
-#[derive(Debug, Clone)]
+pub use data_center::types::Level;
+
+use crate::{
+    BrokerEvent, ClientEvent, ExecType, Fill, FillState, InstId, LimitOrder, Order, RejectReason,
+    Timestamp,
+    backtest::{self, MatchOrder},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
     /// Unix millis timestamp
     pub ts: i64,
@@ -14,33 +30,40 @@ pub struct Trade {
     pub side: bool,
 }
 
-#[derive(Debug, Clone)]
-pub struct Level {
-    pub price: f64,
-    pub size: f64,
-    pub order_count: i32,
-}
-
 /// "Best bid and offer"
-#[derive(Debug, Clone, Default, Copy)]
+#[derive(Debug, Clone, Default, Copy, Serialize, Deserialize)]
 pub struct Bbo {
-    /// Unix millis timestamp
-    pub ts: u64,
+    pub ts: Timestamp,
     pub instrument_id: InstId,
-    pub bid_price: f64,
-    pub bid_size: f64,
-    pub ask_price: f64,
-    pub ask_size: f64,
+    pub best_bid: Level,
+    pub best_ask: Level,
 }
 
 impl Bbo {
     pub fn get_unbiased_price(&self) -> f64 {
-        (self.bid_price * self.ask_size + self.ask_price * self.bid_size)
-            / (self.bid_size + self.ask_size)
+        (self.best_bid.price * self.best_ask.size + self.best_ask.price * self.best_bid.size)
+            / (self.best_bid.size + self.best_ask.size)
+    }
+
+    /// 以 挂单量 * 挂单数 作为流动性权重的微观价格。挂单数多说明流动性来自更多参与者，更难被一次吃掉。
+    ///
+    /// 任一侧的挂单数缺失（为0，例如币安的数据）时，退化为只以挂单量加权的get_unbiased_price。
+    pub fn get_microprice_with_counts(&self) -> f64 {
+        if self.best_bid.order_count <= 0 || self.best_ask.order_count <= 0 {
+            return self.get_unbiased_price();
+        }
+        let bid_weight = self.best_bid.size * self.best_bid.order_count as f64;
+        let ask_weight = self.best_ask.size * self.best_ask.order_count as f64;
+        (self.best_bid.price * ask_weight + self.best_ask.price * bid_weight)
+            / (bid_weight + ask_weight)
+    }
+
+    pub fn get_mid_price(&self) -> f64 {
+        (self.best_bid.price + self.best_ask.price) / 2.
     }
 
     pub fn get_spread(&self) -> f64 {
-        self.ask_price - self.bid_price
+        self.best_ask.price - self.best_bid.price
     }
 
     pub fn get_relevent_spread(&self) -> f64 {
@@ -48,25 +71,146 @@ impl Bbo {
     }
 }
 
+impl Timestamped for Bbo {
+    fn get_ts(&self) -> i64 {
+        self.ts.as_millis()
+    }
+}
+
 impl From<data_center::types::Bbo> for Bbo {
     fn from(bbo: data_center::types::Bbo) -> Self {
         Self {
-            ts: bbo.ts as u64,
+            ts: bbo.ts,
             instrument_id: bbo.instrument_id,
-            bid_price: bbo.bid_price,
-            bid_size: bbo.bid_size,
-            ask_price: bbo.ask_price,
-            ask_size: bbo.ask_size,
+            best_bid: bbo.best_bid,
+            best_ask: bbo.best_ask,
+        }
+    }
+}
+
+/// 最优的N档买卖盘，供需要深度的策略与撮合使用。N为1时与Bbo等价
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopLevels<const N: usize> {
+    pub ts: Timestamp,
+    pub instrument_id: InstId,
+    /// 按价格从高到低排列，可能不足N档
+    pub bids: ArrayVec<Level, N>,
+    /// 按价格从低到高排列，可能不足N档
+    pub asks: ArrayVec<Level, N>,
+}
+
+impl<const N: usize> TopLevels<N> {
+    pub fn best_bid(&self) -> Option<&Level> {
+        self.bids.first()
+    }
+
+    pub fn best_ask(&self) -> Option<&Level> {
+        self.asks.first()
+    }
+
+    /// 由最优一档组成的Bbo。任一侧没有挂单时为None
+    pub fn to_bbo(&self) -> Option<Bbo> {
+        Some(Bbo {
+            ts: self.ts,
+            instrument_id: self.instrument_id,
+            best_bid: *self.best_bid()?,
+            best_ask: *self.best_ask()?,
+        })
+    }
+}
+
+impl<const N: usize> Timestamped for TopLevels<N> {
+    fn get_ts(&self) -> i64 {
+        self.ts.as_millis()
+    }
+}
+
+/// 只保留前N档
+impl<const N: usize> From<data_center::OrderBook> for TopLevels<N> {
+    fn from(order_book: data_center::OrderBook) -> Self {
+        Self {
+            ts: order_book.ts,
+            instrument_id: order_book.instrument_id,
+            bids: order_book.bids.into_iter().take(N).collect(),
+            asks: order_book.asks.into_iter().take(N).collect(),
+        }
+    }
+}
+
+impl<const N: usize> From<Bbo> for TopLevels<N> {
+    fn from(bbo: Bbo) -> Self {
+        Self {
+            ts: bbo.ts,
+            instrument_id: bbo.instrument_id,
+            bids: [bbo.best_bid].into_iter().take(N).collect(),
+            asks: [bbo.best_ask].into_iter().take(N).collect(),
+        }
+    }
+}
+
+/// 同时包含Bbo与逐笔成交的行情，用于需要两者的策略
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MarketData {
+    Bbo(Bbo),
+    Trade(Trade),
+}
+
+impl MarketData {
+    pub fn ts(&self) -> Timestamp {
+        match self {
+            MarketData::Bbo(bbo) => bbo.ts,
+            MarketData::Trade(trade) => Timestamp::from_millis(trade.ts),
+        }
+    }
+
+    pub fn instrument_id(&self) -> InstId {
+        match self {
+            MarketData::Bbo(bbo) => bbo.instrument_id,
+            MarketData::Trade(trade) => trade.instrument_id,
+        }
+    }
+
+    pub fn to_bbo(&self) -> Option<&Bbo> {
+        match self {
+            MarketData::Bbo(bbo) => Some(bbo),
+            MarketData::Trade(_) => None,
         }
     }
 }
 
+impl Timestamped for Trade {
+    fn get_ts(&self) -> i64 {
+        self.ts
+    }
+}
+
+impl Timestamped for MarketData {
+    fn get_ts(&self) -> i64 {
+        self.ts().as_millis()
+    }
+}
+
 pub struct Level1 {
     bbo: Bbo,
     last_price: f64,
     volume: f64,
 }
 
+/// 查询得到的挂单，filled_size为累计成交量
+impl From<&data_center::OrderPush> for LimitOrder {
+    fn from(order_push: &data_center::OrderPush) -> Self {
+        LimitOrder {
+            order_id: order_push.order_id,
+            instrument_id: order_push.inst_id,
+            price: order_push.price,
+            size: order_push.size,
+            filled_size: order_push.acc_filled_size,
+            side: order_push.side,
+            reduce_only: order_push.reduce_only,
+        }
+    }
+}
+
 impl<T> From<data_center::OrderPush> for BrokerEvent<T> {
     fn from(order_push: data_center::OrderPush) -> Self {
         let order = match order_push.ord_type {
@@ -77,6 +221,7 @@ impl<T> From<data_center::OrderPush> for BrokerEvent<T> {
                 size: order_push.size,
                 filled_size: order_push.filled_size,
                 side: order_push.side,
+                reduce_only: order_push.reduce_only,
             }),
             OrdType::Market => unimplemented!(),
         };
@@ -117,7 +262,36 @@ impl BrokerEvent<Bbo> {
         match data {
             data_center::Data::Bbo(bbo) => Some(BrokerEvent::Data(bbo.into())),
             data_center::Data::Order(order_push) => Some(order_push.into()),
-            data_center::Data::Trade(_) => None,
+            data_center::Data::OrderError {
+                order_id,
+                op: OrderOp::Place,
+                code,
+                msg,
+                ..
+            } => Some(BrokerEvent::Rejected {
+                order_id,
+                reason: RejectReason::Other(format!("{code}: {msg}")),
+            }),
+            // 改单或撤单失败时订单仍保持原状态，例如要撤销的订单已经成交，只记录日志
+            data_center::Data::OrderError {
+                order_id,
+                op,
+                code,
+                msg,
+                ..
+            } => {
+                tracing::warn!("{op:?} of order {order_id} failed: {code}: {msg}");
+                None
+            }
+            data_center::Data::ConnectionState(state) => Some(BrokerEvent::Connection(state)),
+            // 持仓与标记价格推送由Broker单独处理
+            data_center::Data::Trade(_)
+            | data_center::Data::OrderAck { .. }
+            | data_center::Data::Position(_)
+            | data_center::Data::FundingRate(_)
+            | data_center::Data::OpenInterest(_)
+            | data_center::Data::MarkPrice(_)
+            | data_center::Data::OrderBook(_) => None,
         }
     }
 }
@@ -126,4 +300,159 @@ impl ClientEvent {
     fn try_into_action(client_event: ClientEvent) -> Action {
         todo!()
     }
-}
\ No newline at end of file
+}
+
+/// 按顺序推送Vec中数据的DataProvider，用于测试与确定性的回测。
+///
+/// ```
+/// use ac_core::{
+///     Broker, BrokerEvent, ClientEvent, InstId, MarketOrder, Order, Timestamp,
+///     backtest::{SandboxBroker, TransactionCostModel},
+///     data::{Bbo, Level, VecDataProvider},
+/// };
+/// use chrono::Duration;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let bbo = |ts, bid_price| Bbo {
+///     ts: Timestamp::from_millis(ts),
+///     instrument_id: InstId::EthUsdtSwap,
+///     best_bid: Level { price: bid_price, size: 1., order_count: 1 },
+///     best_ask: Level { price: bid_price + 1., size: 1., order_count: 1 },
+/// };
+/// let data_provider = VecDataProvider::new(vec![bbo(1000, 100.), bbo(2000, 101.)]);
+/// let instruments = data_provider.instruments();
+/// let mut broker = SandboxBroker::new(
+///     instruments,
+///     data_provider,
+///     10_000.,
+///     TransactionCostModel::new(0., 0., 0.),
+///     Duration::seconds(1),
+/// )
+/// .await
+/// .unwrap();
+///
+/// // 第一条Bbo用于初始化，市价单以其卖一价成交
+/// let order = MarketOrder {
+///     order_id: 1,
+///     instrument_id: InstId::EthUsdtSwap,
+///     size: 1.,
+///     side: true,
+///     reduce_only: false,
+/// };
+/// broker.on_client_event(ClientEvent::PlaceOrder(Order::Market(order))).await;
+/// let Some(BrokerEvent::Fill(fill)) = broker.next_broker_event().await else {
+///     panic!("Expected a fill");
+/// };
+/// assert_eq!(fill.price, 101.);
+///
+/// let Some(BrokerEvent::Data(bbo)) = broker.next_broker_event().await else {
+///     panic!("Expected the second bbo");
+/// };
+/// assert_eq!(bbo.ts, Timestamp::from_millis(2000));
+/// assert!(broker.next_broker_event().await.is_none());
+/// # }
+/// ```
+pub struct VecDataProvider<D> {
+    data: std::vec::IntoIter<D>,
+}
+
+impl<D> VecDataProvider<D> {
+    pub fn new(data: Vec<D>) -> Self {
+        Self {
+            data: data.into_iter(),
+        }
+    }
+
+    /// 尚未推送的数据中出现的所有产品，按首次出现的顺序排列
+    pub fn instruments<M>(&self) -> Vec<InstId>
+    where
+        D: backtest::MarketData<M>,
+        M: MatchOrder,
+    {
+        let mut instruments = vec![];
+        for data in self.data.as_slice() {
+            let Some(matcher) = data.clone().draw_matcher() else {
+                continue;
+            };
+            let instrument_id = matcher.instrument_id();
+            if !instruments.contains(&instrument_id) {
+                instruments.push(instrument_id);
+            }
+        }
+        instruments
+    }
+}
+
+// 不会对内部的数据做pin projection
+impl<D> Unpin for VecDataProvider<D> {}
+
+impl<D> Stream for VecDataProvider<D> {
+    type Item = D;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<D>> {
+        Poll::Ready(self.data.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.data.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_bbo(bid_order_count: i32, ask_order_count: i32) -> Bbo {
+        Bbo {
+            ts: Timestamp::default(),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: Level {
+                price: 100.,
+                size: 2.,
+                order_count: bid_order_count,
+            },
+            best_ask: Level {
+                price: 101.,
+                size: 2.,
+                order_count: ask_order_count,
+            },
+        }
+    }
+
+    #[test]
+    fn test_microprice_with_counts() {
+        // 挂单量相同时，只以挂单量加权的价格为中间价
+        let bbo = create_bbo(9, 1);
+        assert_eq!(bbo.get_unbiased_price(), 100.5);
+        // 买方挂单数更多，价格偏向卖价: (100 * 2 + 101 * 18) / 20
+        assert_eq!(bbo.get_microprice_with_counts(), 100.9);
+
+        let bbo = create_bbo(1, 3);
+        assert_eq!(bbo.get_microprice_with_counts(), 100.25);
+
+        // 挂单数相同，或缺失挂单数时与get_unbiased_price一致
+        let bbo = create_bbo(4, 4);
+        assert_eq!(bbo.get_microprice_with_counts(), bbo.get_unbiased_price());
+        let bbo = create_bbo(0, 5);
+        assert_eq!(bbo.get_microprice_with_counts(), bbo.get_unbiased_price());
+    }
+
+    #[test]
+    fn test_order_error_into_broker_event() {
+        let order_error = |op| data_center::Data::OrderError {
+            request_id: "1".into(),
+            order_id: 1,
+            op,
+            code: "51400".into(),
+            msg: "Order cancellation failed".into(),
+        };
+        // 只有下单失败使订单被拒绝
+        assert!(matches!(
+            BrokerEvent::try_from_data(order_error(OrderOp::Place)),
+            Some(BrokerEvent::Rejected { order_id: 1, .. })
+        ));
+        assert!(BrokerEvent::try_from_data(order_error(OrderOp::Amend)).is_none());
+        assert!(BrokerEvent::try_from_data(order_error(OrderOp::Cancel)).is_none());
+    }
+}
@@ -0,0 +1,105 @@
+use data_center::OrderBook;
+
+use super::{Bbo, Level};
+
+/// 由Bbo外推更深档位的模型。第i档（最优档为第0档）的价格离最优价i * tick_size，
+/// 挂单量为最优档的size_growth^i倍
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthModel {
+    /// 包含最优档在内的档数
+    pub levels: usize,
+    pub tick_size: f64,
+    pub size_growth: f64,
+}
+
+impl DepthModel {
+    pub fn new(levels: usize, tick_size: f64, size_growth: f64) -> Self {
+        assert!(levels > 0, "levels must be positive");
+        Self {
+            levels,
+            tick_size,
+            size_growth,
+        }
+    }
+
+    fn extrapolate(&self, best: &Level, price_step: f64) -> Vec<Level> {
+        let mut levels = vec![*best];
+        let mut size = best.size;
+        for i in 1..self.levels {
+            size *= self.size_growth;
+            levels.push(Level {
+                price: best.price + i as f64 * price_step,
+                size,
+                // 挂单数未知
+                order_count: 0,
+            });
+        }
+        levels
+    }
+}
+
+/// 只记录了Bbo的历史上近似的订单簿，供需要深度的撮合使用。深档的价格与挂单量都是外推的，
+/// 不代表真实的流动性
+pub struct SyntheticBook;
+
+impl SyntheticBook {
+    /// 最优档与bbo一致，更深的档位按model外推
+    pub fn from_bbo(bbo: &Bbo, model: &DepthModel) -> OrderBook {
+        OrderBook {
+            ts: bbo.ts,
+            instrument_id: bbo.instrument_id,
+            bids: model.extrapolate(&bbo.best_bid, -model.tick_size),
+            asks: model.extrapolate(&bbo.best_ask, model.tick_size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::assert_approx_eq;
+
+    use super::*;
+    use crate::{InstId, Timestamp};
+
+    #[test]
+    fn test_synthetic_book() {
+        let bbo = Bbo {
+            ts: Timestamp::from_millis(1000),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: Level {
+                price: 100.,
+                size: 2.,
+                order_count: 3,
+            },
+            best_ask: Level {
+                price: 100.01,
+                size: 1.,
+                order_count: 1,
+            },
+        };
+        let book = SyntheticBook::from_bbo(&bbo, &DepthModel::new(5, 0.01, 1.5));
+        assert_eq!(book.ts, bbo.ts);
+        assert_eq!(book.instrument_id, bbo.instrument_id);
+        assert_eq!(book.best_bid(), Some(&bbo.best_bid));
+        assert_eq!(book.best_ask(), Some(&bbo.best_ask));
+        assert_eq!(book.bids.len(), 5);
+        assert_eq!(book.asks.len(), 5);
+
+        // 每深一档，价格离最优价远一个tick，挂单量乘以1.5
+        for i in 1..5 {
+            let (bid, ask) = (&book.bids[i], &book.asks[i]);
+            assert_approx_eq!(f64, bid.price, 100. - 0.01 * i as f64, epsilon = 1e-9);
+            assert_approx_eq!(f64, ask.price, 100.01 + 0.01 * i as f64, epsilon = 1e-9);
+            assert_approx_eq!(f64, bid.size, 2. * 1.5f64.powi(i as i32));
+            assert_approx_eq!(f64, ask.size, 1.5f64.powi(i as i32));
+            assert_eq!(bid.order_count, 0);
+            assert!(book.bids[i - 1].price > bid.price);
+            assert!(book.asks[i - 1].price < ask.price);
+        }
+
+        // 只有一档时即为Bbo
+        let book = SyntheticBook::from_bbo(&bbo, &DepthModel::new(1, 0.01, 1.5));
+        assert_eq!(book.bids, vec![bbo.best_bid]);
+        assert_eq!(book.asks, vec![bbo.best_ask]);
+    }
+}
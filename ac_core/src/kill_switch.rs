@@ -0,0 +1,223 @@
+use rustc_hash::FxHashMap;
+
+use crate::{
+    BrokerEvent, ClientEvent, InstId, MarketOrder, Order, OrderId, Portfolio, backtest::MatchOrder,
+    strategy::ORDER_ID_OFFSET_BITS,
+};
+
+/// 平仓单使用的order_id_offset，策略不应使用该值
+pub const KILL_SWITCH_ORDER_ID_OFFSET: u64 = (1 << ORDER_ID_OFFSET_BITS) - 1;
+
+/// 回撤保护。根据成交与行情估算权益，记录本次运行中的最高权益。
+///
+/// 权益从最高点回撤超过max_drawdown_pct后，撤销全部挂单、以只减仓的市价单平仓，
+/// 之后不再转发策略的ClientEvent，直到手动reset。手续费不计入权益。
+pub struct KillSwitch<D> {
+    /// 初始权益与成交带来的现金变动
+    cash: f64,
+    portfolio: Portfolio,
+    /// 各产品最新的价格，用于对持仓估值
+    marks: FxHashMap<InstId, f64>,
+    session_high: f64,
+    /// 允许的最大回撤，以百分比表示，例如5.0表示5%
+    max_drawdown_pct: f64,
+    is_halted: bool,
+    next_order_id_body: u64,
+    mark_price: fn(&D) -> (InstId, f64),
+}
+
+impl<D: MatchOrder> KillSwitch<D> {
+    pub fn new(initial_equity: f64, max_drawdown_pct: f64) -> Self {
+        Self {
+            cash: initial_equity,
+            portfolio: Portfolio::new(),
+            marks: Default::default(),
+            session_high: initial_equity,
+            max_drawdown_pct,
+            is_halted: false,
+            next_order_id_body: 0,
+            mark_price: |data| (data.instrument_id(), data.market_price()),
+        }
+    }
+}
+
+impl<D> KillSwitch<D> {
+    pub fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+
+    /// 以持仓的最新价格估算的权益
+    pub fn equity(&self) -> f64 {
+        self.cash + self.portfolio.get_value(&self.marks)
+    }
+
+    /// 恢复转发策略的ClientEvent，并以当前权益作为新的最高点
+    pub fn reset(&mut self) {
+        self.is_halted = false;
+        self.session_high = self.equity();
+    }
+
+    /// 根据BrokerEvent更新权益，返回实际应发出的ClientEvent。
+    ///
+    /// 回撤超限时返回撤单与平仓事件；停止交易期间丢弃策略的事件。
+    pub fn guard(
+        &mut self,
+        broker_event: &BrokerEvent<D>,
+        client_events: Vec<ClientEvent>,
+    ) -> Vec<ClientEvent> {
+        self.update(broker_event);
+        if self.is_halted {
+            return vec![];
+        }
+        let equity = self.equity();
+        self.session_high = self.session_high.max(equity);
+        let drawdown_pct = (1. - equity / self.session_high) * 100.;
+        if drawdown_pct <= self.max_drawdown_pct {
+            return client_events;
+        }
+        tracing::error!(
+            "Drawdown {drawdown_pct:.2}% from the session high {} exceeds {}%, flattening and halting",
+            self.session_high,
+            self.max_drawdown_pct
+        );
+        self.is_halted = true;
+        self.flatten()
+    }
+
+    fn update(&mut self, broker_event: &BrokerEvent<D>) {
+        match broker_event {
+            BrokerEvent::Data(data) => {
+                let (instrument_id, price) = (self.mark_price)(data);
+                self.marks.insert(instrument_id, price);
+            }
+            BrokerEvent::Fill(fill) => {
+                let signed_size = if fill.side {
+                    fill.filled_size
+                } else {
+                    -fill.filled_size
+                };
+                let contract_type = self.portfolio.contract_type(fill.instrument_id);
+                self.cash -= contract_type.value(signed_size, fill.price);
+                self.portfolio.update(fill);
+                self.marks.entry(fill.instrument_id).or_insert(fill.price);
+            }
+            _ => {}
+        }
+    }
+
+    /// 撤销所有产品的挂单，并以只减仓的市价单平掉全部持仓
+    fn flatten(&mut self) -> Vec<ClientEvent> {
+        let mut instruments: Vec<_> = self.marks.keys().copied().collect();
+        instruments.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+        let mut events: Vec<_> = instruments
+            .iter()
+            .map(|instrument_id| ClientEvent::CancelAll(*instrument_id))
+            .collect();
+        for instrument_id in instruments {
+            let size = self.portfolio.position_size(instrument_id);
+            if size == 0. {
+                continue;
+            }
+            let order = MarketOrder {
+                order_id: self.get_next_order_id(),
+                instrument_id,
+                size: size.abs(),
+                side: size < 0.,
+                reduce_only: true,
+            };
+            events.push(ClientEvent::PlaceOrder(Order::Market(order)));
+        }
+        events
+    }
+
+    fn get_next_order_id(&mut self) -> OrderId {
+        let order_id_body = self.next_order_id_body;
+        self.next_order_id_body += 1;
+        (order_id_body << ORDER_ID_OFFSET_BITS) | KILL_SWITCH_ORDER_ID_OFFSET
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ExecType, Fill, FillState, LimitOrder, Timestamp,
+        data::{Bbo, Level},
+    };
+
+    fn create_bbo(ts: i64, bid_price: f64) -> Bbo {
+        Bbo {
+            ts: Timestamp::from_millis(ts),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: Level {
+                price: bid_price,
+                size: 1.,
+                order_count: 1,
+            },
+            best_ask: Level {
+                price: bid_price + 2.,
+                size: 1.,
+                order_count: 1,
+            },
+        }
+    }
+
+    fn place_order() -> Vec<ClientEvent> {
+        let order = LimitOrder::from_raw_size(1., 1, InstId::EthUsdtSwap, 90.);
+        vec![ClientEvent::place_limit_order(order)]
+    }
+
+    #[test]
+    fn test_kill_switch() {
+        let mut kill_switch = KillSwitch::new(1000., 5.);
+        kill_switch.guard(&BrokerEvent::Data(create_bbo(1000, 99.)), vec![]);
+        let fill = Fill {
+            order_id: 1,
+            instrument_id: InstId::EthUsdtSwap,
+            filled_size: 5.,
+            acc_filled_size: 5.,
+            price: 100.,
+            side: true,
+            exec_type: ExecType::Maker,
+            state: FillState::Filled,
+        };
+        kill_switch.guard(&BrokerEvent::Fill(fill), vec![]);
+        assert_eq!(kill_switch.equity(), 1000.);
+
+        // 价格上涨，最高权益为1000 + 5 * 20 = 1100
+        let events = kill_switch.guard(&BrokerEvent::Data(create_bbo(2000, 119.)), place_order());
+        assert_eq!(events.len(), 1);
+        assert_eq!(kill_switch.equity(), 1100.);
+
+        // 回撤 (1100 - 1050) / 1100 < 5%，继续转发
+        let events = kill_switch.guard(&BrokerEvent::Data(create_bbo(3000, 109.)), place_order());
+        assert_eq!(events.len(), 1);
+        assert!(!kill_switch.is_halted());
+
+        // 回撤 (1100 - 1040) / 1100 > 5%，撤单并平仓
+        let events = kill_switch.guard(&BrokerEvent::Data(create_bbo(4000, 107.)), place_order());
+        assert!(kill_switch.is_halted());
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            events[0],
+            ClientEvent::CancelAll(InstId::EthUsdtSwap)
+        ));
+        let ClientEvent::PlaceOrder(Order::Market(order)) = &events[1] else {
+            panic!("Expected a flattening market order");
+        };
+        assert!(!order.side);
+        assert!(order.reduce_only);
+        assert_eq!(order.size, 5.);
+        assert_eq!(order.order_id, KILL_SWITCH_ORDER_ID_OFFSET);
+
+        // 停止交易期间丢弃策略的事件，即使价格回升
+        let events = kill_switch.guard(&BrokerEvent::Data(create_bbo(5000, 130.)), place_order());
+        assert!(events.is_empty());
+
+        // 手动恢复后以当前权益为最高点
+        kill_switch.reset();
+        assert!(!kill_switch.is_halted());
+        let events = kill_switch.guard(&BrokerEvent::Data(create_bbo(6000, 130.)), place_order());
+        assert_eq!(events.len(), 1);
+    }
+}
@@ -1,27 +1,39 @@
 pub mod backtest;
+pub mod batching;
+pub mod clock;
 pub mod data;
+pub mod kill_switch;
 pub mod okx;
+pub mod recorder;
 pub mod strategy;
 mod utils;
 
-use std::marker::PhantomData;
+use std::{
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 use ::utils::Duplex;
-use float_cmp::approx_eq;
+use data_center::instruments_profile::INSTRUMENT_PROFILES;
 use futures::{Stream, StreamExt};
 use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
 
-use crate::strategy::Strategy;
+use crate::{kill_switch::KillSwitch, recorder::EventRecorder, strategy::Strategy};
 
-pub use data_center::types::InstId;
+pub use ::utils::Timestamp;
+pub use data_center::{
+    instruments_profile::ContractType,
+    types::{ConnState, InstId},
+};
 
 pub trait DataProvider<D>: Stream<Item = D> + Unpin + Send {}
 impl<D, S> DataProvider<D> for S where S: Stream<Item = D> + Unpin + Send {}
 
 type OrderId = u64;
-type Timestamp = u64;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Order {
     Market(MarketOrder),
     Limit(LimitOrder),
@@ -49,6 +61,13 @@ impl Order {
         }
     }
 
+    pub fn reduce_only(&self) -> bool {
+        match self {
+            Order::Market(order) => order.reduce_only,
+            Order::Limit(order) => order.reduce_only,
+        }
+    }
+
     /// 不含有方向信息的size。严格为正。
     pub fn size(&self) -> f64 {
         match self {
@@ -65,17 +84,35 @@ impl Order {
             -self.size()
         }
     }
+
+    /// 按产品的交易规则调整订单规模：向下取整为下单单位的整数倍，低于最小下单量则拒绝。
+    /// 没有产品信息时不作调整。
+    pub fn conform_to_profile(&mut self) -> Result<(), RejectReason> {
+        let Some(profile) = INSTRUMENT_PROFILES.get(&self.instrument_id()) else {
+            return Ok(());
+        };
+        let size = profile
+            .conform_size(self.size())
+            .ok_or(RejectReason::BelowMinSize)?;
+        match self {
+            Order::Market(order) => order.size = size,
+            Order::Limit(order) => order.size = size,
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct MarketOrder {
     pub order_id: OrderId,
     pub instrument_id: InstId,
     pub size: f64,
     pub side: bool,
+    /// 只减仓：成交不会使持仓反向，超出持仓的部分不会成交
+    pub reduce_only: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct LimitOrder {
     pub order_id: OrderId,
     pub instrument_id: InstId,
@@ -84,6 +121,9 @@ pub struct LimitOrder {
     /// filled_size 根据传回的fill信息进行更新
     pub filled_size: f64,
     pub side: bool,
+    /// 只减仓：成交不会使持仓反向，超出持仓的部分不会成交
+    #[serde(default)]
+    pub reduce_only: bool,
 }
 
 impl LimitOrder {
@@ -106,9 +146,15 @@ impl LimitOrder {
             size,
             side,
             filled_size: 0.,
+            reduce_only: false,
         }
     }
 
+    pub fn with_reduce_only(mut self, reduce_only: bool) -> Self {
+        self.reduce_only = reduce_only;
+        self
+    }
+
     pub fn amended(&mut self, new_size: f64, new_price: f64) -> AmendOrder {
         self.size = self.filled_size + new_size;
         self.price = new_price;
@@ -137,7 +183,7 @@ impl LimitOrder {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AmendOrder {
     pub order_id: u64,
     pub instrument_id: InstId,
@@ -145,14 +191,23 @@ pub struct AmendOrder {
     pub new_price: f64,
 }
 
-#[derive(Debug, PartialEq, Default)]
+impl AmendOrder {
+    /// 将新的订单规模向下取整为下单单位的整数倍。改单失败时原订单仍然有效，因此不检查最小下单量。
+    pub fn conform_to_profile(&mut self) {
+        if let Some(profile) = INSTRUMENT_PROFILES.get(&self.instrument_id) {
+            self.new_size = profile.round_size(self.new_size);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
 pub enum ExecType {
     #[default]
     Taker,
     Maker,
 }
 
-#[derive(Debug, PartialEq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
 pub enum FillState {
     Live,
     Partially,
@@ -160,7 +215,7 @@ pub enum FillState {
     Filled,
 }
 
-#[derive(Debug, PartialEq, Default)]
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct Fill {
     pub order_id: OrderId,
     pub instrument_id: InstId,
@@ -174,13 +229,57 @@ pub struct Fill {
     pub state: FillState,
 }
 
-#[derive(Debug)]
+impl Fill {
+    /// 限价单以给定价格全部成交
+    pub fn full(order: &LimitOrder, price: f64, exec_type: ExecType) -> Self {
+        Self {
+            order_id: order.order_id,
+            instrument_id: order.instrument_id,
+            filled_size: order.size,
+            acc_filled_size: order.size,
+            price,
+            side: order.side,
+            exec_type,
+            state: FillState::Filled,
+        }
+    }
+}
+
+/// 订单被拒绝的原因
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RejectReason {
+    InsufficientCash,
+    /// Post-only订单会立即成交
+    PostOnlyWouldCross,
+    /// FOK订单无法全部成交
+    FokUnfillable,
+    /// 订单规模低于产品的最小下单量
+    BelowMinSize,
+    /// 该产品尚无行情数据，无法撮合
+    NoMarketData,
+    /// 改单或撤单的订单不存在，例如已成交或已撤销
+    UnknownOrder,
+    /// 只减仓订单没有可以减少的持仓
+    ReduceOnly,
+    Other(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub enum BrokerEvent<D> {
     Data(D),
     Fill(Fill),
     Placed(Order),
     Amended(Order),
     Canceled(OrderId),
+    Rejected {
+        order_id: OrderId,
+        reason: RejectReason,
+    },
+    /// 实盘连接的状态变化。重连后策略可重置指标、核对持仓
+    Connection(ConnState),
+    /// 交易所当前全部尚未完全成交的限价单，由[`Broker::request_open_orders`]请求。
+    /// 策略以此为准核对本地记录的挂单
+    OpenOrders(Vec<LimitOrder>),
 }
 
 impl<D> BrokerEvent<D> {
@@ -190,13 +289,33 @@ impl<D> BrokerEvent<D> {
             _ => None,
         }
     }
+
+    /// 转换行情的类型，其余事件原样复制。f返回None时丢弃该行情
+    pub fn filter_map_data<E>(&self, f: impl FnOnce(&D) -> Option<E>) -> Option<BrokerEvent<E>> {
+        let event = match self {
+            BrokerEvent::Data(data) => BrokerEvent::Data(f(data)?),
+            BrokerEvent::Fill(fill) => BrokerEvent::Fill(fill.clone()),
+            BrokerEvent::Placed(order) => BrokerEvent::Placed(order.clone()),
+            BrokerEvent::Amended(order) => BrokerEvent::Amended(order.clone()),
+            BrokerEvent::Canceled(order_id) => BrokerEvent::Canceled(*order_id),
+            BrokerEvent::Rejected { order_id, reason } => BrokerEvent::Rejected {
+                order_id: *order_id,
+                reason: reason.clone(),
+            },
+            BrokerEvent::Connection(state) => BrokerEvent::Connection(state.clone()),
+            BrokerEvent::OpenOrders(orders) => BrokerEvent::OpenOrders(orders.clone()),
+        };
+        Some(event)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClientEvent {
     PlaceOrder(Order),
     AmendOrder(AmendOrder),
     CancelOrder(InstId, OrderId),
+    /// 撤销该产品的全部挂单，用于停止运行或紧急情况
+    CancelAll(InstId),
 }
 
 impl ClientEvent {
@@ -208,7 +327,8 @@ impl ClientEvent {
         match self {
             ClientEvent::PlaceOrder(_)
             | &ClientEvent::AmendOrder(_)
-            | &ClientEvent::CancelOrder(_, _) => true,
+            | &ClientEvent::CancelOrder(_, _)
+            | &ClientEvent::CancelAll(_) => true,
             // _ => false
         }
     }
@@ -222,17 +342,39 @@ pub trait Broker<D> {
             self.on_client_event(event).await;
         }
     }
+    /// 同一时刻产生的一批事件。支持批量请求的Broker可以合并发送，默认逐个处理
+    async fn on_client_batch(&mut self, client_events: Vec<ClientEvent>) {
+        self.on_client_events(client_events.into_iter()).await;
+    }
     async fn next_broker_event(&mut self) -> Option<BrokerEvent<D>>;
+
+    /// 请求交易所当前的挂单，之后以BrokerEvent::OpenOrders推送。默认不推送
+    async fn request_open_orders(&mut self) {}
+
+    /// 交易所当前的持仓，单向持仓模式下带有方向。尚未得到交易所的持仓时返回None。
+    fn positions_snapshot(&self) -> Option<FxHashMap<InstId, f64>> {
+        None
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// 单个产品的持仓。
+///
+/// 单向持仓模式：size为正表示多头，为负表示空头，为0表示空仓。
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
 pub struct Position {
     size: f64,
+    /// 当前持仓的平均开仓价，空仓或未知时为0
+    #[serde(default)]
+    avg_entry_price: f64,
 }
 
 impl Position {
+    /// 开仓价未知的持仓，例如从交易所同步的持仓
     pub fn new(size: f64) -> Self {
-        Self { size }
+        Self {
+            size,
+            avg_entry_price: 0.,
+        }
     }
 
     pub fn new_from_fill(fill: &Fill) -> Self {
@@ -241,30 +383,61 @@ impl Position {
         } else {
             -fill.filled_size
         };
-        Self { size }
+        Self {
+            size,
+            avg_entry_price: fill.price,
+        }
     }
 
+    /// 加仓时按成交量加权更新平均开仓价；减仓时开仓价不变；反手后以成交价为开仓价
     pub fn update(&mut self, fill: &Fill) {
-        if fill.side {
-            self.size += fill.filled_size;
+        let delta = if fill.side {
+            fill.filled_size
         } else {
-            self.size -= fill.filled_size;
+            -fill.filled_size
+        };
+        let new_size = self.size + delta;
+        if self.size * delta >= 0. {
+            if new_size != 0. {
+                self.avg_entry_price =
+                    (self.avg_entry_price * self.size + fill.price * delta) / new_size;
+            }
+        } else if self.size * new_size < 0. {
+            self.avg_entry_price = fill.price;
+        } else if new_size.abs() < 1e-12 {
+            self.avg_entry_price = 0.;
         }
+        self.size = new_size;
     }
 
-    pub fn is_clear(&self, size_digits: i32) -> bool {
-        let eps = 10f64.powi(-size_digits);
-        approx_eq!(f64, 0., self.size, epsilon = eps)
+    /// 持仓规模小于eps时视为空仓。eps通常取产品的最小下单量，低于该值的残余仓位无法单独平掉
+    pub fn is_clear(&self, eps: f64) -> bool {
+        self.size.abs() < eps
     }
 
+    /// 带方向的持仓规模，多头为正，空头为负
     pub fn size(&self) -> f64 {
         self.size
     }
+
+    pub fn avg_entry_price(&self) -> f64 {
+        self.avg_entry_price
+    }
+
+    /// 以给定价格计算的市值，空头为负
+    pub fn market_value(&self, price: f64) -> f64 {
+        self.size * price
+    }
 }
 
-#[derive(Default)]
+/// 各产品的持仓。持仓归零的产品会被移除
+#[derive(Debug, Default)]
 pub struct Portfolio {
     positions: FxHashMap<InstId, Position>,
+    /// 各产品最近一次已知的价格，来自行情或成交。估值时缺少当前价格的产品以此估值
+    last_prices: FxHashMap<InstId, f64>,
+    /// 手动指定的合约类型，优先于产品信息
+    contract_types: FxHashMap<InstId, ContractType>,
 }
 
 impl Portfolio {
@@ -274,8 +447,54 @@ impl Portfolio {
         }
     }
 
+    /// 指定产品的合约类型，用于产品信息中没有的产品
+    pub fn with_contract_type(
+        mut self,
+        instrument_id: InstId,
+        contract_type: ContractType,
+    ) -> Self {
+        self.contract_types.insert(instrument_id, contract_type);
+        self
+    }
+
+    pub fn positions(&self) -> &FxHashMap<InstId, Position> {
+        &self.positions
+    }
+
+    /// 该产品的合约类型。未指定时按产品信息，没有产品信息时视为正向合约
+    pub fn contract_type(&self, instrument_id: InstId) -> ContractType {
+        self.contract_types
+            .get(&instrument_id)
+            .copied()
+            .or_else(|| {
+                INSTRUMENT_PROFILES
+                    .get(&instrument_id)
+                    .map(|p| p.contract_type)
+            })
+            .unwrap_or_default()
+    }
+
+    /// 持仓在给定价格下以结算货币计的价值，见[`ContractType::value`]
+    fn position_value(&self, instrument_id: InstId, position: &Position, price: f64) -> f64 {
+        self.contract_type(instrument_id)
+            .value(position.size, price)
+    }
+
+    /// 该产品带方向的持仓数量，没有持仓时为0
+    pub fn position_size(&self, instrument_id: InstId) -> f64 {
+        self.positions
+            .get(&instrument_id)
+            .map_or(0., |position| position.size())
+    }
+
+    /// 记录该产品的最新价格
+    pub fn update_price(&mut self, instrument_id: InstId, price: f64) {
+        self.last_prices.insert(instrument_id, price);
+    }
+
     pub fn update(&mut self, new_fill: &Fill) {
         let instrument_id = new_fill.instrument_id;
+        self.last_prices.insert(instrument_id, new_fill.price);
 
         if let Some(position) = self.positions.get_mut(&instrument_id) {
             position.update(new_fill);
@@ -289,22 +508,99 @@ impl Portfolio {
         }
     }
 
+    /// 持仓的市值。inst_price中没有的产品以最近一次已知的价格估值，从未有过价格时以开仓均价估值
     pub fn get_value(&self, inst_price: &FxHashMap<InstId, f64>) -> f64 {
+        self.positions
+            .iter()
+            .map(|(instrument_id, position)| {
+                let price = self.price_of(inst_price, *instrument_id, position);
+                self.position_value(*instrument_id, position, price)
+            })
+            .sum()
+    }
+
+    /// 优先以标记价格估值，没有标记价格的产品以Bbo的中间价估值。
+    ///
+    /// 任一持仓的产品两者都没有时返回None
+    pub fn get_marked_value(
+        &self,
+        mark_prices: &FxHashMap<InstId, f64>,
+        inst_bbo: &FxHashMap<InstId, data::Bbo>,
+    ) -> Option<f64> {
         let mut value = 0.0;
         for (instrument_id, position) in &self.positions {
-            let price = inst_price.get(instrument_id).unwrap();
-            value += position.size * price;
+            let price = match mark_prices.get(instrument_id) {
+                Some(mark_price) => *mark_price,
+                None => inst_bbo.get(instrument_id)?.get_mid_price(),
+            };
+            value += self.position_value(*instrument_id, position, price);
         }
-        value
+        Some(value)
+    }
+
+    /// 所有空头持仓的市值之和，为非负数
+    pub fn get_short_value(&self, inst_price: &FxHashMap<InstId, f64>) -> f64 {
+        self.positions
+            .iter()
+            .filter(|(_, position)| position.size < 0.)
+            .map(|(instrument_id, position)| {
+                let price = self.price_of(inst_price, *instrument_id, position);
+                self.position_value(*instrument_id, position, price).abs()
+            })
+            .sum()
+    }
+
+    /// 反向合约持仓以币计的价值之和。反向合约开仓时现金的变化只是记账，并非借款
+    pub fn get_inverse_value(&self, inst_price: &FxHashMap<InstId, f64>) -> f64 {
+        self.positions
+            .iter()
+            .filter(|(instrument_id, _)| {
+                self.contract_type(**instrument_id) == ContractType::Inverse
+            })
+            .map(|(instrument_id, position)| {
+                let price = self.price_of(inst_price, *instrument_id, position);
+                self.position_value(*instrument_id, position, price)
+            })
+            .sum()
+    }
+
+    fn price_of(
+        &self,
+        inst_price: &FxHashMap<InstId, f64>,
+        instrument_id: InstId,
+        position: &Position,
+    ) -> f64 {
+        inst_price
+            .get(&instrument_id)
+            .or_else(|| self.last_prices.get(&instrument_id))
+            .copied()
+            .unwrap_or(position.avg_entry_price)
     }
 }
 
 pub struct Engine<B, S, D> {
     broker: B,
     strategy: S,
+    /// 定期保存checkpoint的路径与间隔
+    checkpoint: Option<(PathBuf, std::time::Duration)>,
+    last_checkpoint_at: Instant,
+    /// 载入checkpoint后，等待交易所的持仓以校正策略的持仓
+    is_reconcile_pending: bool,
+    /// 回撤超限后平仓并停止转发策略的事件
+    kill_switch: Option<KillSwitch<D>>,
+    /// 记录收发的全部事件，用于重放
+    recorder: Option<EventRecorder<D>>,
     _phantom_data: PhantomData<D>,
 }
 
+/// 写入文件的checkpoint
+#[derive(Serialize, Deserialize)]
+struct EngineCheckpoint {
+    /// 保存时的Unix millis timestamp
+    saved_at: i64,
+    strategy: serde_json::Value,
+}
+
 impl<B, S, D> Engine<B, S, D>
 where
     B: Broker<D>,
@@ -314,17 +610,146 @@ where
         Self {
             broker,
             strategy,
+            checkpoint: None,
+            last_checkpoint_at: Instant::now(),
+            is_reconcile_pending: false,
+            kill_switch: None,
+            recorder: None,
             _phantom_data: PhantomData,
         }
     }
 
+    /// 运行时每隔interval将策略的状态保存到path，结束时再保存一次
+    pub fn with_checkpoint(
+        mut self,
+        path: impl Into<PathBuf>,
+        interval: std::time::Duration,
+    ) -> Self {
+        self.checkpoint = Some((path.into(), interval));
+        self
+    }
+
+    /// 设置回撤保护，见[`KillSwitch`]
+    pub fn with_kill_switch(mut self, kill_switch: KillSwitch<D>) -> Self {
+        self.kill_switch = Some(kill_switch);
+        self
+    }
+
+    pub fn kill_switch(&self) -> Option<&KillSwitch<D>> {
+        self.kill_switch.as_ref()
+    }
+
+    /// 回撤保护触发后，手动恢复交易
+    pub fn reset_kill_switch(&mut self) {
+        if let Some(kill_switch) = &mut self.kill_switch {
+            kill_switch.reset();
+        }
+    }
+
+    /// 记录策略收到的BrokerEvent与实际发出的ClientEvent，见[`recorder::replay`]
+    pub fn with_recorder(mut self, recorder: EventRecorder<D>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
     pub async fn run(&mut self) {
-        loop {
-            let Some(broker_event) = self.broker.next_broker_event().await else {
-                break;
-            };
-            let client_events = self.strategy.on_event(&broker_event);
-            self.broker.on_client_events(client_events.into_iter()).await;
+        while let Some(broker_event) = self.broker.next_broker_event().await {
+            self.handle_broker_event(broker_event).await;
+        }
+        self.finish();
+    }
+
+    /// 将一个BrokerEvent交给策略，并把策略的ClientEvent发给Broker
+    async fn handle_broker_event(&mut self, broker_event: BrokerEvent<D>) {
+        self.try_reconcile_positions();
+        let mut client_events = self.strategy.on_event(&broker_event);
+        if let Some(kill_switch) = &mut self.kill_switch {
+            client_events = kill_switch.guard(&broker_event, client_events);
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_broker_event(&broker_event);
+            recorder.record_client_events(&client_events);
+        }
+        self.broker
+            .on_client_events(client_events.into_iter())
+            .await;
+        // 断线期间挂单可能已成交或被撤销，重连后以交易所的挂单为准
+        if matches!(
+            broker_event,
+            BrokerEvent::Connection(ConnState::Reconnected)
+        ) {
+            self.broker.request_open_orders().await;
+        }
+        if self.last_checkpoint_at.elapsed() >= self.checkpoint_interval() {
+            self.save_configured_checkpoint();
+        }
+    }
+
+    /// 数据结束后保存checkpoint并写出记录的事件
+    fn finish(&mut self) {
+        self.save_configured_checkpoint();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.flush();
+        }
+    }
+
+    fn checkpoint_interval(&self) -> std::time::Duration {
+        self.checkpoint
+            .as_ref()
+            .map_or(std::time::Duration::MAX, |(_, interval)| *interval)
+    }
+
+    /// 保存到with_checkpoint设置的路径，失败时只记录日志
+    pub fn save_configured_checkpoint(&mut self) {
+        let Some((path, _)) = &self.checkpoint else {
+            return;
+        };
+        if let Err(e) = self.save_checkpoint(path) {
+            tracing::error!("Failed to save checkpoint to {}: {e}", path.display());
+        }
+        self.last_checkpoint_at = Instant::now();
+    }
+
+    /// 将策略的状态写入path。先写入临时文件再重命名，避免进程崩溃时留下不完整的文件。
+    pub fn save_checkpoint(&self, path: &Path) -> anyhow::Result<()> {
+        let strategy = self
+            .strategy
+            .checkpoint()
+            .ok_or_else(|| anyhow::anyhow!("The strategy does not support checkpoints"))?;
+        let checkpoint = EngineCheckpoint {
+            saved_at: chrono::Utc::now().timestamp_millis(),
+            strategy,
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(&checkpoint)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// 从path恢复策略的状态。之后得到交易所的持仓时，以交易所的持仓为准校正策略的持仓。
+    pub fn load_checkpoint(&mut self, path: &Path) -> anyhow::Result<()> {
+        let checkpoint: EngineCheckpoint = serde_json::from_slice(&std::fs::read(path)?)?;
+        self.strategy.restore(checkpoint.strategy)?;
+        tracing::info!(
+            "Checkpoint saved at {} loaded from {}",
+            checkpoint.saved_at,
+            path.display()
+        );
+        self.is_reconcile_pending = true;
+        self.try_reconcile_positions();
+        Ok(())
+    }
+
+    fn try_reconcile_positions(&mut self) {
+        if !self.is_reconcile_pending {
+            return;
+        }
+        if let Some(positions) = self.broker.positions_snapshot() {
+            self.strategy.reconcile_positions(&positions);
+            self.is_reconcile_pending = false;
         }
     }
 
@@ -332,6 +757,10 @@ where
         &self.broker
     }
 
+    pub fn into_broker(self) -> B {
+        self.broker
+    }
+
     pub fn strategy(&self) -> &S {
         &self.strategy
     }
@@ -347,6 +776,7 @@ mod tests {
             Fill {
                 side,
                 filled_size,
+                price: 100.,
                 ..Default::default()
             }
         }
@@ -369,6 +799,47 @@ mod tests {
         assert_eq!(position.size(), 5.0);
     }
 
+    #[test]
+    fn test_position_avg_entry_price() {
+        fn gen_fill(side: bool, filled_size: f64, price: f64) -> Fill {
+            Fill {
+                side,
+                filled_size,
+                price,
+                ..Default::default()
+            }
+        }
+
+        // 加仓时按成交量加权
+        let mut position = Position::new_from_fill(&gen_fill(true, 1.0, 100.0));
+        position.update(&gen_fill(true, 3.0, 120.0));
+        assert_eq!(position.avg_entry_price(), 115.0);
+        assert_eq!(position.market_value(130.0), 520.0);
+
+        // 减仓不改变开仓价
+        position.update(&gen_fill(false, 2.0, 150.0));
+        assert_eq!(position.size(), 2.0);
+        assert_eq!(position.avg_entry_price(), 115.0);
+
+        // 反手后以成交价为开仓价，空头的市值为负
+        position.update(&gen_fill(false, 5.0, 140.0));
+        assert_eq!(position.size(), -3.0);
+        assert_eq!(position.avg_entry_price(), 140.0);
+        assert_eq!(position.market_value(100.0), -300.0);
+
+        position.update(&gen_fill(true, 3.0, 90.0));
+        assert!(position.is_clear(1e-8));
+        assert_eq!(position.avg_entry_price(), 0.0);
+    }
+
+    #[test]
+    fn test_position_dust_is_clear() {
+        // 低于最小下单量的残余仓位视为空仓
+        let dust = Position::new(0.004);
+        assert!(dust.is_clear(0.01));
+        assert!(!Position::new(0.01).is_clear(0.01));
+    }
+
     #[test]
     fn test_portfolio() {
         let mut portfolio = Portfolio::new();
@@ -383,7 +854,7 @@ mod tests {
             state: FillState::Filled,
         };
         portfolio.update(&fill1);
-        assert_eq!(portfolio.positions.len(), 1);
+        assert_eq!(portfolio.positions().len(), 1);
 
         let fill2 = Fill {
             order_id: 2,
@@ -396,7 +867,7 @@ mod tests {
             state: FillState::Filled,
         };
         portfolio.update(&fill2);
-        assert_eq!(portfolio.positions.len(), 1);
+        assert_eq!(portfolio.positions().len(), 1);
 
         let fill3 = Fill {
             order_id: 3,
@@ -409,7 +880,14 @@ mod tests {
             state: FillState::Filled,
         };
         portfolio.update(&fill3);
-        assert_eq!(portfolio.positions.len(), 2);
+        assert_eq!(portfolio.positions().len(), 2);
+        let btc_position = portfolio.positions()[&InstId::BtcUsdtSwap];
+        assert_eq!(btc_position.size(), 5.0);
+        assert_eq!(btc_position.avg_entry_price(), 150.0);
+        assert_eq!(
+            portfolio.positions()[&InstId::EthUsdtSwap].avg_entry_price(),
+            2800.0
+        );
 
         let mut inst_price = FxHashMap::default();
         inst_price.insert(InstId::BtcUsdtSwap, 160.0);
@@ -417,4 +895,302 @@ mod tests {
         let value = portfolio.get_value(&FxHashMap::from(inst_price));
         assert_eq!(value, 5.0 * 160.0 + 2.0 * 2900.0);
     }
+
+    #[test]
+    fn test_portfolio_value_with_missing_prices() {
+        let mut portfolio = Portfolio::new();
+        portfolio.update(&Fill {
+            instrument_id: InstId::BtcUsdtSwap,
+            side: true,
+            price: 100.,
+            filled_size: 2.,
+            ..Default::default()
+        });
+        portfolio.update(&Fill {
+            instrument_id: InstId::EthUsdtSwap,
+            side: false,
+            price: 50.,
+            filled_size: 4.,
+            ..Default::default()
+        });
+        let mut inst_price = FxHashMap::default();
+        inst_price.insert(InstId::BtcUsdtSwap, 110.);
+
+        // 没有当前价格的产品以最近的成交价估值
+        assert_eq!(portfolio.get_value(&inst_price), 2. * 110. - 4. * 50.);
+        assert_eq!(portfolio.get_short_value(&inst_price), 4. * 50.);
+
+        // 有过行情价格时以最近的行情价格估值
+        portfolio.update_price(InstId::EthUsdtSwap, 45.);
+        assert_eq!(portfolio.get_value(&inst_price), 2. * 110. - 4. * 45.);
+        assert_eq!(
+            portfolio.get_value(&FxHashMap::default()),
+            2. * 100. - 4. * 45.
+        );
+
+        // 从未有过价格时以开仓均价估值
+        portfolio.last_prices.clear();
+        assert_eq!(
+            portfolio.get_value(&FxHashMap::default()),
+            2. * 100. - 4. * 50.
+        );
+    }
+
+    #[test]
+    fn test_portfolio_marked_value() {
+        let mut portfolio = Portfolio::new();
+        for (instrument_id, side) in [(InstId::BtcUsdtSwap, true), (InstId::EthUsdtSwap, false)] {
+            portfolio.update(&Fill {
+                instrument_id,
+                side,
+                price: 100.,
+                filled_size: 2.,
+                ..Default::default()
+            });
+        }
+        let bbo = |instrument_id, bid_price| data::Bbo {
+            instrument_id,
+            best_bid: data::Level {
+                price: bid_price,
+                size: 5.,
+                order_count: 1,
+            },
+            best_ask: data::Level {
+                price: bid_price + 2.,
+                size: 1.,
+                order_count: 1,
+            },
+            ..Default::default()
+        };
+        let mut inst_bbo = FxHashMap::default();
+        inst_bbo.insert(InstId::BtcUsdtSwap, bbo(InstId::BtcUsdtSwap, 109.));
+        inst_bbo.insert(InstId::EthUsdtSwap, bbo(InstId::EthUsdtSwap, 89.));
+        let mut mark_prices = FxHashMap::default();
+
+        // 没有标记价格时以中间价估值
+        let value = portfolio.get_marked_value(&mark_prices, &inst_bbo);
+        assert_eq!(value, Some(2. * 110. - 2. * 90.));
+
+        // 有标记价格的产品以标记价格估值
+        mark_prices.insert(InstId::BtcUsdtSwap, 120.);
+        let value = portfolio.get_marked_value(&mark_prices, &inst_bbo);
+        assert_eq!(value, Some(2. * 120. - 2. * 90.));
+
+        // 缺少价格时无法估值
+        inst_bbo.remove(&InstId::EthUsdtSwap);
+        assert_eq!(portfolio.get_marked_value(&mark_prices, &inst_bbo), None);
+    }
+
+    async fn checkpoint_test_engine()
+    -> Engine<impl Broker<data::Bbo>, impl Strategy<data::Bbo>, data::Bbo> {
+        use chrono::Duration;
+
+        use crate::strategy::{
+            ExecutorArgs, OrderUpdateMode, RePegMode, single_ticker::ofi_momentum::OfiMomentumArgs,
+        };
+
+        let args = OfiMomentumArgs {
+            instrument_id: InstId::EthUsdtSwap,
+            window_ofi: Duration::seconds(5),
+            window_ema: Duration::seconds(20),
+            theta: 1.,
+            executor: ExecutorArgs {
+                holding_duration: Duration::seconds(10),
+                event_interval: Duration::zero(),
+                notional: 1000.,
+                price_offset: 0.,
+                re_peg_mode: RePegMode::HoldOriginal,
+                order_update_mode: OrderUpdateMode::Amend,
+                order_ttl: Duration::zero(),
+                post_fill_cooldown: Duration::zero(),
+                max_position_notional: None,
+                max_distance_ticks: None,
+                passive_only: false,
+                order_id_offset: 0,
+            },
+        };
+        let bbos: Vec<_> = (0..30)
+            .map(|i| data::Bbo {
+                ts: Timestamp::from_millis(1000 * i),
+                instrument_id: InstId::EthUsdtSwap,
+                best_bid: data::Level {
+                    price: 100. + (i % 7) as f64,
+                    size: 1. + (i % 3) as f64,
+                    order_count: 1,
+                },
+                best_ask: data::Level {
+                    price: 101. + (i % 7) as f64,
+                    size: 1. + (i % 5) as f64,
+                    order_count: 1,
+                },
+            })
+            .collect();
+        let broker = backtest::SandboxBroker::new(
+            vec![InstId::EthUsdtSwap],
+            data::VecDataProvider::new(bbos),
+            100_000.,
+            backtest::TransactionCostModel::new(0., 0., 0.),
+            Duration::seconds(1),
+        )
+        .await
+        .unwrap();
+        Engine::new(broker, args.into_strategy())
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_round_trip() {
+        let mut engine = checkpoint_test_engine().await;
+        // 直接向策略推送行情与成交，使指标与持仓都有状态
+        let mut broker_events = vec![];
+        while let Some(event) = engine.broker.next_broker_event().await {
+            broker_events.push(event);
+        }
+        for event in &broker_events {
+            engine.strategy.on_event(event);
+        }
+        engine.strategy.on_event(&BrokerEvent::Fill(Fill {
+            instrument_id: InstId::EthUsdtSwap,
+            side: true,
+            filled_size: 2.,
+            acc_filled_size: 2.,
+            price: 100.,
+            ..Default::default()
+        }));
+        let checkpoint = engine.strategy.checkpoint().unwrap();
+        assert_eq!(checkpoint["executor"]["position"]["size"], 2.);
+        assert!(!checkpoint["signaler"]["variables"].is_null());
+
+        let path = std::env::temp_dir().join(format!("ac_checkpoint_{}.json", std::process::id()));
+        engine.save_checkpoint(&path).unwrap();
+
+        // 恢复后的状态与保存时一致
+        let mut restored = checkpoint_test_engine().await;
+        restored.strategy.restore(checkpoint.clone()).unwrap();
+        assert_eq!(restored.strategy.checkpoint().unwrap(), checkpoint);
+
+        // 从文件恢复后，以交易所（此处为空仓的SandboxBroker）的持仓为准
+        let mut restored = checkpoint_test_engine().await;
+        restored.load_checkpoint(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let restored_checkpoint = restored.strategy.checkpoint().unwrap();
+        assert_eq!(restored_checkpoint["executor"]["position"]["size"], 0.);
+        assert_eq!(restored_checkpoint["signaler"], checkpoint["signaler"]);
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let path = std::env::temp_dir().join(format!("ac_events_{}.jsonl", std::process::id()));
+        let recorder = recorder::EventRecorder::create(&path).unwrap();
+        let mut engine = checkpoint_test_engine().await.with_recorder(recorder);
+        engine.run().await;
+
+        let records: Vec<recorder::Record<data::Bbo>> = recorder::load_records(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let recorded: Vec<_> = records
+            .iter()
+            .filter_map(|record| match &record.event {
+                recorder::RecordedEvent::Client(client_event) => Some(client_event),
+                recorder::RecordedEvent::Broker(_) => None,
+            })
+            .collect();
+        assert!(!recorded.is_empty());
+        // 非行情事件沿用最新行情的时间戳
+        assert!(records.is_sorted_by_key(|record| record.ts));
+
+        // 不经Broker重放，新的策略发出相同的ClientEvent
+        let mut strategy = checkpoint_test_engine().await.strategy;
+        let replayed = recorder::replay(&mut strategy, &records);
+        assert_eq!(
+            serde_json::to_value(&replayed).unwrap(),
+            serde_json::to_value(&recorded).unwrap()
+        );
+    }
+
+    /// 依次推送预设的事件，被请求挂单时推送预设的交易所挂单，并记录收到的ClientEvent
+    struct ReconnectingBroker {
+        broker_events: std::collections::VecDeque<BrokerEvent<data::Bbo>>,
+        open_orders: Vec<LimitOrder>,
+        client_events: Vec<ClientEvent>,
+    }
+
+    impl Broker<data::Bbo> for ReconnectingBroker {
+        async fn on_client_event(&mut self, client_event: ClientEvent) {
+            self.client_events.push(client_event);
+        }
+
+        async fn next_broker_event(&mut self) -> Option<BrokerEvent<data::Bbo>> {
+            self.broker_events.pop_front()
+        }
+
+        async fn request_open_orders(&mut self) {
+            let open_orders = std::mem::take(&mut self.open_orders);
+            self.broker_events
+                .push_back(BrokerEvent::OpenOrders(open_orders));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_orders_after_reconnect() {
+        use crate::strategy::single_ticker::market_maker::MarketMaker;
+
+        let bbo = data::Bbo {
+            ts: Timestamp::from_millis(1000),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: data::Level {
+                price: 100.,
+                size: 1.,
+                order_count: 1,
+            },
+            best_ask: data::Level {
+                price: 101.,
+                size: 1.,
+                order_count: 1,
+            },
+        };
+        let order = |order_id, raw_size, price| {
+            LimitOrder::from_raw_size(raw_size, order_id, InstId::EthUsdtSwap, price)
+        };
+        // order_id_offset为1：买单为 0 << 16 | 1，卖单为 1 << 16 | 1
+        let bid_order_id = 1;
+        let (lost_ask_id, forgotten_ask_id, extra_ask_id) = (1 << 16 | 1, 5 << 16 | 1, 6 << 16 | 1);
+        let mut exchange_bid = order(bid_order_id, 10., 99.5);
+        exchange_bid.filled_size = 2.;
+        let broker = ReconnectingBroker {
+            broker_events: [
+                BrokerEvent::Data(bbo),
+                BrokerEvent::Connection(ConnState::Reconnecting),
+                BrokerEvent::Connection(ConnState::Reconnected),
+            ]
+            .into(),
+            // 本地的卖单在断线期间丢失，交易所上有两个本地不知道的卖单，以及其他策略的挂单
+            open_orders: vec![
+                exchange_bid,
+                order(forgotten_ask_id, -10., 101.5),
+                order(extra_ask_id, -10., 102.),
+                order(2 << 16 | 2, 1., 90.),
+            ],
+            client_events: vec![],
+        };
+        let strategy = MarketMaker::new(InstId::EthUsdtSwap, 1000., 2, 2, 0.5, 0., 1);
+        let mut engine = Engine::new(broker, strategy);
+        engine.run().await;
+
+        let client_events = &engine.broker.client_events;
+        assert_eq!(client_events.len(), 3);
+        assert!(matches!(
+            &client_events[1],
+            ClientEvent::PlaceOrder(Order::Limit(order)) if order.order_id == lost_ask_id
+        ));
+        // 只撤销多余的自有挂单
+        assert!(matches!(
+            client_events[2],
+            ClientEvent::CancelOrder(InstId::EthUsdtSwap, order_id) if order_id == extra_ask_id
+        ));
+
+        // 仍在交易所上的买单以交易所的记录为准，卖单接管交易所上的挂单
+        let checkpoint = engine.strategy.checkpoint().unwrap();
+        assert_eq!(checkpoint["bid_order"]["order_id"], bid_order_id);
+        assert_eq!(checkpoint["bid_order"]["filled_size"], 2.);
+        assert_eq!(checkpoint["ask_order"]["order_id"], forgotten_ask_id);
+    }
 }
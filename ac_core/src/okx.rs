@@ -1,98 +1,1032 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Instant,
+};
+
 use chrono::Duration;
 use data_center::{
-    Action, Terminal,
-    types::{InstId, Side},
+    Action, Data, Exchange, OrderPush, Terminal,
+    metrics::METRICS,
+    okx_api::Okx,
+    types::{ConnState, InstId, MAX_BATCH_SIZE, OrdType, OrderPushType, OrderState, Side, TdMode},
 };
 use futures::{SinkExt, StreamExt};
+use rustc_hash::FxHashMap;
 
-use crate::{Broker, ClientEvent, Order, data::Bbo};
+use crate::{
+    Broker, BrokerEvent, ClientEvent, ExecType, LimitOrder, Order, OrderId, Portfolio,
+    RejectReason, backtest::MatchOrder, data::Bbo,
+};
 
-pub struct OkxBroker {
+/// 连接交易所进行实盘交易的Broker
+pub struct LiveBroker<E> {
     terminal: Terminal,
+    /// 本地产生、尚未推送的BrokerEvent，例如被拒绝的订单
+    broker_events_buf: VecDeque<BrokerEvent<Bbo>>,
+    /// 根据成交累计的持仓，用于监控
+    position: f64,
+    /// 根据成交累计的各产品持仓，用于估值
+    portfolio: Portfolio,
+    /// 交易所推送的各产品最新标记价格
+    mark_prices: FxHashMap<InstId, f64>,
+    /// 为真时不向交易所发送订单，而是记录日志并以最新的Bbo在本地模拟成交
+    dry_run: bool,
+    /// 各产品最新的Bbo
+    inst_bbo: FxHashMap<InstId, Bbo>,
+    /// dry run模式下本地挂着的限价单
+    dry_run_orders: FxHashMap<OrderId, LimitOrder>,
+    /// 下一个请求的id，单调递增
+    next_request_id: RequestId,
+    /// 已发送、尚未得到交易所确认的请求
+    pending_requests: BTreeMap<RequestId, PendingRequest>,
+    /// 交易所推送的持仓。收到第一条持仓推送前为None
+    exchange_positions: Option<FxHashMap<InstId, f64>>,
+    /// 各产品下单使用的交易模式，未设置的产品使用全仓
+    td_modes: FxHashMap<InstId, TdMode>,
+    /// 用于查询挂单等请求
+    exchange: E,
+    /// 交易的产品，重连后查询这些产品的挂单
+    instruments: Vec<InstId>,
+    /// 各订单已推送的累计成交量，用于识别重连后重复推送的成交。订单撤销或完全成交后移除
+    acc_filled_sizes: FxHashMap<OrderId, f64>,
 }
 
-impl OkxBroker {
-    pub async fn new_bbo(instrument_id: InstId, history_duration: Duration) -> Self {
-        let subscribe_actions = vec![
+pub type OkxBroker = LiveBroker<Okx>;
+
+pub type RequestId = u64;
+
+/// 已发送、尚未得到确认的请求
+#[derive(Debug, Clone, Copy)]
+pub struct PendingRequest {
+    pub order_id: OrderId,
+    pub sent_at: Instant,
+}
+
+/// 准备发送的请求
+struct OutgoingRequest {
+    request_id: RequestId,
+    order_id: OrderId,
+    is_new_order: bool,
+    action: Action,
+}
+
+impl<E> LiveBroker<E>
+where
+    E: Exchange<Action = Action, Data = Data> + Clone + 'static,
+{
+    /// dry_run为真时只订阅行情，订单在本地模拟成交
+    pub async fn new_bbo_with(
+        exchange: &E,
+        instrument_id: InstId,
+        history_duration: Duration,
+        dry_run: bool,
+    ) -> Self {
+        let mut subscribe_actions = vec![
             Action::SubscribeBboTbt(instrument_id),
-            Action::SubscribeOrders(instrument_id),
+            Action::SubscribeMarkPrice(instrument_id),
         ];
-        let terminal = Terminal::new_okx(true, subscribe_actions, history_duration)
+        if !dry_run {
+            subscribe_actions.push(Action::SubscribeOrders(instrument_id));
+            subscribe_actions.push(Action::SubscribePositions(instrument_id));
+        }
+        let terminal = Terminal::new(exchange, subscribe_actions, history_duration)
             .await
             .unwrap();
-        Self { terminal }
+        Self {
+            terminal,
+            broker_events_buf: Default::default(),
+            position: 0.,
+            portfolio: Portfolio::new(),
+            mark_prices: Default::default(),
+            dry_run,
+            inst_bbo: Default::default(),
+            dry_run_orders: Default::default(),
+            next_request_id: 1,
+            pending_requests: Default::default(),
+            exchange_positions: None,
+            td_modes: Default::default(),
+            exchange: exchange.clone(),
+            instruments: vec![instrument_id],
+            acc_filled_sizes: Default::default(),
+        }
     }
 }
 
-impl Broker<Bbo> for OkxBroker {
-    async fn on_client_event(&mut self, client_event: ClientEvent) {
-        let action = match client_event {
-            ClientEvent::PlaceOrder(order) => match order {
-                Order::Market(order) => {
-                    let request_id = "".into();
-                    let side = if order.side { Side::Buy } else { Side::Sell };
-                    let inst_id = order.instrument_id;
-                    let client_order_id = order.order_id.to_string().into();
-                    let size = order.size.to_string().into();
-                    Action::MarketOrder {
-                        request_id,
-                        side,
-                        inst_id,
-                        client_order_id,
-                        size,
-                    }
+impl<E> LiveBroker<E> {
+    /// 设置该产品下单使用的交易模式
+    pub fn with_td_mode(mut self, instrument_id: InstId, td_mode: TdMode) -> Self {
+        self.td_modes.insert(instrument_id, td_mode);
+        self
+    }
+
+    fn td_mode(&self, instrument_id: InstId) -> TdMode {
+        self.td_modes
+            .get(&instrument_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// 尚未得到交易所确认的请求。长时间未确认的请求可能已丢失。
+    pub fn outstanding_requests(&self) -> &BTreeMap<RequestId, PendingRequest> {
+        &self.pending_requests
+    }
+
+    /// 以标记价格估算的持仓市值，没有标记价格时使用Bbo的中间价
+    pub fn portfolio_value(&self) -> Option<f64> {
+        self.portfolio
+            .get_marked_value(&self.mark_prices, &self.inst_bbo)
+    }
+
+    /// 将ClientEvent转换为发给交易所的请求。订单被本地拒绝，或dry run时返回None
+    fn prepare_request(&mut self, client_event: ClientEvent) -> Option<OutgoingRequest> {
+        let is_new_order = matches!(client_event, ClientEvent::PlaceOrder(_));
+        let dry_run_event = self.dry_run.then(|| client_event.clone());
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        let (order_id, action) = match client_event {
+            ClientEvent::PlaceOrder(mut order) => {
+                // 不符合交易规则的订单会被交易所拒绝，因此在本地直接拒绝
+                if let Err(reason) = order.conform_to_profile() {
+                    let order_id = order.order_id();
+                    tracing::warn!("Order {order_id} rejected locally: {reason:?}");
+                    self.broker_events_buf
+                        .push_back(BrokerEvent::Rejected { order_id, reason });
+                    return None;
                 }
-                Order::Limit(order) => {
-                    let request_id = "".into();
-                    let side = if order.side { Side::Buy } else { Side::Sell };
-                    let inst_id = order.instrument_id;
-                    let client_order_id = order.order_id.to_string().into();
-                    let size = order.size.to_string().into();
-                    let price = order.price.to_string().into();
-                    Action::LimitOrder {
-                        request_id,
-                        side,
-                        inst_id,
-                        client_order_id,
-                        size,
-                        price,
+                let action = match order {
+                    Order::Market(order) => {
+                        let request_id = request_id.to_string().into();
+                        let side = if order.side { Side::Buy } else { Side::Sell };
+                        let inst_id = order.instrument_id;
+                        let client_order_id = order.order_id.to_string().into();
+                        let td_mode = self.td_mode(inst_id);
+                        let size = order.size.to_string().into();
+                        Action::MarketOrder {
+                            request_id,
+                            side,
+                            inst_id,
+                            client_order_id,
+                            td_mode,
+                            size,
+                            reduce_only: order.reduce_only,
+                        }
                     }
-                }
-            },
-            ClientEvent::AmendOrder(amend) => {
-                let request_id = "".into();
+                    Order::Limit(order) => {
+                        let request_id = request_id.to_string().into();
+                        let side = if order.side { Side::Buy } else { Side::Sell };
+                        let inst_id = order.instrument_id;
+                        let client_order_id = order.order_id.to_string().into();
+                        let td_mode = self.td_mode(inst_id);
+                        let size = order.size.to_string().into();
+                        let price = order.price.to_string().into();
+                        Action::LimitOrder {
+                            request_id,
+                            side,
+                            inst_id,
+                            client_order_id,
+                            td_mode,
+                            size,
+                            price,
+                            reduce_only: order.reduce_only,
+                        }
+                    }
+                };
+                (order.order_id(), action)
+            }
+            ClientEvent::AmendOrder(mut amend) => {
+                amend.conform_to_profile();
+                let request_id = request_id.to_string().into();
                 let inst_id = amend.instrument_id;
                 let client_order_id = amend.order_id.to_string().into();
                 let new_size = amend.new_size.to_string().into();
                 let new_price = amend.new_price.to_string().into();
-                Action::AmendOrder {
+                let action = Action::AmendOrder {
                     request_id,
                     inst_id,
                     client_order_id,
                     new_size,
                     new_price,
-                }
+                };
+                (amend.order_id, action)
             }
             ClientEvent::CancelOrder(inst_id, order_id) => {
-                let request_id = "".into();
+                let request_id = request_id.to_string().into();
                 let client_order_id = order_id.to_string().into();
-                Action::CancelOrder {
+                let action = Action::CancelOrder {
                     request_id,
                     inst_id,
                     client_order_id,
+                };
+                (order_id, action)
+            }
+            // 实盘的全部撤单在发送前已展开为逐个撤单，只有dry run会到达这里
+            ClientEvent::CancelAll(inst_id) => {
+                if let Some(client_event) = dry_run_event {
+                    self.simulate_client_event(client_event);
+                } else {
+                    tracing::error!(
+                        "Cancel-all of {inst_id:?} was not expanded into cancellations"
+                    );
                 }
+                return None;
             }
         };
+        if let Some(client_event) = dry_run_event {
+            tracing::info!("Dry run, action not sent: {action:?}");
+            self.simulate_client_event(client_event);
+            return None;
+        }
+        Some(OutgoingRequest {
+            request_id,
+            order_id,
+            is_new_order,
+            action,
+        })
+    }
+
+    /// 只有一个请求时直接发送，否则以一个批量请求发送。发送成功后记录为待确认的请求
+    async fn send_requests(&mut self, requests: Vec<OutgoingRequest>) {
+        let action = if requests.len() == 1 {
+            requests[0].action.clone()
+        } else {
+            Action::BatchOrders(
+                requests
+                    .iter()
+                    .map(|request| request.action.clone())
+                    .collect(),
+            )
+        };
         tracing::info!("Sending action: {action:?}");
         if let Err(e) = self.terminal.send(action).await {
             tracing::error!("Error sending action: {}", e);
+            return;
+        }
+        for request in requests {
+            self.pending_requests.insert(
+                request.request_id,
+                PendingRequest {
+                    order_id: request.order_id,
+                    sent_at: Instant::now(),
+                },
+            );
+            if request.is_new_order {
+                METRICS.inc_orders_sent();
+            }
+        }
+    }
+
+    /// 由交易所的回复或订单推送确认请求
+    fn confirm_request(&mut self, data: &Data) {
+        match data {
+            // 批量请求中各订单的回复都带有批量请求的id，即第一个请求的id，因此从该id起按订单查找
+            Data::OrderAck {
+                request_id,
+                order_id,
+            }
+            | Data::OrderError {
+                request_id,
+                order_id,
+                ..
+            } => {
+                let Ok(batch_request_id) = request_id.parse::<RequestId>() else {
+                    return;
+                };
+                let request_id = self
+                    .pending_requests
+                    .range(batch_request_id..)
+                    .find(|(_, request)| request.order_id == *order_id)
+                    .map(|(request_id, _)| *request_id);
+                if let Some(request_id) = request_id {
+                    self.pending_requests.remove(&request_id);
+                }
+            }
+            // 订单推送说明该订单最早的请求已被交易所收到
+            Data::Order(order_push) => {
+                let request_id = self
+                    .pending_requests
+                    .iter()
+                    .find(|(_, request)| request.order_id == order_push.order_id)
+                    .map(|(request_id, _)| *request_id);
+                if let Some(request_id) = request_id {
+                    self.pending_requests.remove(&request_id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 重连后交易所会重新推送订单的最新状态。累计成交量没有增加的成交推送是已处理过的，应丢弃以免重复计入持仓
+    fn is_duplicate_fill(&mut self, order_push: &OrderPush) -> bool {
+        match order_push.push_type {
+            OrderPushType::Fill => {}
+            OrderPushType::Canceled => {
+                self.acc_filled_sizes.remove(&order_push.order_id);
+                return false;
+            }
+            OrderPushType::Placed | OrderPushType::Amended => return false,
+        }
+        let last_acc_filled_size = self
+            .acc_filled_sizes
+            .entry(order_push.order_id)
+            .or_default();
+        if order_push.acc_filled_size <= *last_acc_filled_size {
+            return true;
+        }
+        *last_acc_filled_size = order_push.acc_filled_size;
+        // 完全成交的订单不会再有成交推送
+        if matches!(order_push.state, OrderState::Filled) {
+            self.acc_filled_sizes.remove(&order_push.order_id);
+        }
+        false
+    }
+
+    fn record_metrics(&mut self, broker_event: &BrokerEvent<Bbo>) {
+        match broker_event {
+            BrokerEvent::Data(bbo) => METRICS.set_last_bbo_ts(bbo.ts.as_millis()),
+            BrokerEvent::Fill(fill) => {
+                let signed_size = if fill.side {
+                    fill.filled_size
+                } else {
+                    -fill.filled_size
+                };
+                self.position += signed_size;
+                self.portfolio.update(fill);
+                METRICS.inc_fills_received();
+                METRICS.set_position(self.position);
+            }
+            _ => {}
+        }
+    }
+
+    /// 以最新的Bbo模拟订单事件，产生的BrokerEvent放入缓冲区
+    fn simulate_client_event(&mut self, client_event: ClientEvent) {
+        match client_event {
+            ClientEvent::PlaceOrder(Order::Market(order)) => {
+                if let Some(fill) = Bbo::fill_market_order(&self.inst_bbo, &order) {
+                    self.broker_events_buf.push_back(BrokerEvent::Fill(fill));
+                } else {
+                    self.broker_events_buf.push_back(BrokerEvent::Rejected {
+                        order_id: order.order_id,
+                        reason: RejectReason::NoMarketData,
+                    });
+                }
+            }
+            ClientEvent::PlaceOrder(Order::Limit(order)) => {
+                self.broker_events_buf
+                    .push_back(BrokerEvent::Placed(Order::Limit(order)));
+                self.dry_run_orders.insert(order.order_id, order);
+                self.try_fill_dry_run_order(order.order_id, ExecType::Taker);
+            }
+            ClientEvent::AmendOrder(amend) => {
+                let Some(order) = self.dry_run_orders.get_mut(&amend.order_id) else {
+                    return;
+                };
+                order.size = amend.new_size;
+                order.price = amend.new_price;
+                self.broker_events_buf
+                    .push_back(BrokerEvent::Amended(Order::Limit(*order)));
+                self.try_fill_dry_run_order(amend.order_id, ExecType::Taker);
+            }
+            ClientEvent::CancelOrder(_, order_id) => {
+                if self.dry_run_orders.remove(&order_id).is_some() {
+                    self.broker_events_buf
+                        .push_back(BrokerEvent::Canceled(order_id));
+                }
+            }
+            ClientEvent::CancelAll(instrument_id) => {
+                let mut order_ids: Vec<_> = self
+                    .dry_run_orders
+                    .values()
+                    .filter(|order| order.instrument_id == instrument_id)
+                    .map(|order| order.order_id)
+                    .collect();
+                order_ids.sort_unstable();
+                for order_id in order_ids {
+                    self.dry_run_orders.remove(&order_id);
+                    self.broker_events_buf
+                        .push_back(BrokerEvent::Canceled(order_id));
+                }
+            }
+        }
+    }
+
+    fn try_fill_dry_run_order(&mut self, order_id: OrderId, exec_type: ExecType) {
+        let Some(order) = self.dry_run_orders.get(&order_id) else {
+            return;
+        };
+        if !self.inst_bbo.contains_key(&order.instrument_id) {
+            return;
+        }
+        if let Some(fill) = Bbo::try_fill_limit_order(&self.inst_bbo, order, exec_type) {
+            self.dry_run_orders.remove(&order_id);
+            self.broker_events_buf.push_back(BrokerEvent::Fill(fill));
+        }
+    }
+
+    fn on_bbo(&mut self, bbo: Bbo) {
+        self.inst_bbo.insert(bbo.instrument_id, bbo);
+        let order_ids: Vec<_> = self.dry_run_orders.keys().copied().collect();
+        for order_id in order_ids {
+            self.try_fill_dry_run_order(order_id, ExecType::Maker);
+        }
+    }
+}
+
+impl<E> LiveBroker<E>
+where
+    E: Exchange<Action = Action, Data = Data>,
+{
+    /// 通过REST查询该产品的全部挂单，逐个撤销。
+    ///
+    /// 查询失败时无法撤单，以ConnState::RequestFailed通知策略，由策略决定是否重试
+    async fn open_order_cancellations(&mut self, instrument_id: InstId) -> Vec<ClientEvent> {
+        match self.exchange.open_orders(instrument_id).await {
+            Ok(order_pushes) => order_pushes
+                .iter()
+                .map(|order_push| ClientEvent::CancelOrder(instrument_id, order_push.order_id))
+                .collect(),
+            Err(e) => {
+                tracing::error!("Failed to query open orders of {instrument_id:?} to cancel: {e}");
+                let reason = format!("Failed to cancel all orders of {instrument_id:?}: {e}");
+                self.broker_events_buf.push_back(BrokerEvent::Connection(
+                    ConnState::RequestFailed(reason.into()),
+                ));
+                vec![]
+            }
+        }
+    }
+}
+
+impl OkxBroker {
+    pub async fn new_bbo(instrument_id: InstId, history_duration: Duration, dry_run: bool) -> Self {
+        Self::new_bbo_with(&Okx::new(true), instrument_id, history_duration, dry_run).await
+    }
+}
+
+impl<E> Broker<Bbo> for LiveBroker<E>
+where
+    E: Exchange<Action = Action, Data = Data>,
+{
+    async fn on_client_event(&mut self, client_event: ClientEvent) {
+        self.on_client_batch(vec![client_event]).await;
+    }
+
+    /// 相邻的同种下单、改单或撤单合并为一个批量请求。全部撤单展开为对各挂单的撤单，同样按批发送
+    async fn on_client_batch(&mut self, client_events: Vec<ClientEvent>) {
+        let mut expanded = vec![];
+        for client_event in client_events {
+            match client_event {
+                ClientEvent::CancelAll(instrument_id) if !self.dry_run => {
+                    expanded.extend(self.open_order_cancellations(instrument_id).await)
+                }
+                client_event => expanded.push(client_event),
+            }
+        }
+
+        let mut batches: Vec<Vec<OutgoingRequest>> = vec![];
+        for client_event in expanded {
+            let Some(request) = self.prepare_request(client_event) else {
+                continue;
+            };
+            let batch_op = request.action.batch_op();
+            match batches.last_mut() {
+                Some(batch)
+                    if batch_op.is_some()
+                        && batch[0].action.batch_op() == batch_op
+                        && batch.len() < MAX_BATCH_SIZE =>
+                {
+                    batch.push(request)
+                }
+                _ => batches.push(vec![request]),
+            }
+        }
+        for batch in batches {
+            self.send_requests(batch).await;
         }
     }
 
     async fn next_broker_event(&mut self) -> Option<crate::BrokerEvent<Bbo>> {
-        self.terminal
-            .next()
-            .await
-            .and_then(|data| crate::BrokerEvent::try_from_data(data))
+        if let Some(event) = self.broker_events_buf.pop_front() {
+            self.record_metrics(&event);
+            return Some(event);
+        }
+        loop {
+            let data = self.terminal.next().await?;
+            self.confirm_request(&data);
+            if let Data::Position(position) = &data {
+                self.exchange_positions
+                    .get_or_insert_default()
+                    .insert(position.inst_id, position.size);
+            }
+            if let Data::MarkPrice(mark_price) = &data {
+                self.mark_prices
+                    .insert(mark_price.inst_id, mark_price.mark_px);
+            }
+            if let Data::Order(order_push) = &data
+                && self.is_duplicate_fill(order_push)
+            {
+                tracing::info!(
+                    "Drop duplicate fill of order {} at acc_filled_size {}",
+                    order_push.order_id,
+                    order_push.acc_filled_size
+                );
+                continue;
+            }
+            // 请求确认等数据没有对应的BrokerEvent
+            let Some(broker_event) = crate::BrokerEvent::try_from_data(data) else {
+                continue;
+            };
+            self.record_metrics(&broker_event);
+            if let BrokerEvent::Data(bbo) = &broker_event {
+                self.on_bbo(*bbo);
+            }
+            return Some(broker_event);
+        }
+    }
+
+    async fn request_open_orders(&mut self) {
+        if self.dry_run {
+            let open_orders = self.dry_run_orders.values().copied().collect();
+            self.broker_events_buf
+                .push_back(BrokerEvent::OpenOrders(open_orders));
+            return;
+        }
+        let mut open_orders = vec![];
+        for &instrument_id in &self.instruments {
+            match self.exchange.open_orders(instrument_id).await {
+                Ok(order_pushes) => open_orders.extend(
+                    order_pushes
+                        .iter()
+                        .filter(|order_push| matches!(order_push.ord_type, OrdType::Limit))
+                        .map(LimitOrder::from),
+                ),
+                // 不完整的挂单列表会使策略误撤挂单，因此不推送
+                Err(e) => {
+                    tracing::error!("Failed to query open orders of {instrument_id:?}: {e}");
+                    return;
+                }
+            }
+        }
+        self.broker_events_buf
+            .push_back(BrokerEvent::OpenOrders(open_orders));
+    }
+
+    fn positions_snapshot(&self) -> Option<FxHashMap<InstId, f64>> {
+        self.exchange_positions.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    };
+
+    use anyhow::Result;
+    use futures::{Sink, Stream};
+    use utils::Duplex;
+
+    use super::*;
+    use crate::{Engine, batching::BatchingSink, strategy::Strategy};
+
+    /// 内存中的交易所连接：依次推送预设的数据，并记录收到的Action
+    struct MockConnection {
+        data: VecDeque<Data>,
+        sent: Arc<Mutex<Vec<Action>>>,
+    }
+
+    impl Stream for MockConnection {
+        type Item = Data;
+
+        fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Data>> {
+            Poll::Ready(self.data.pop_front())
+        }
+    }
+
+    impl Sink<Action> for MockConnection {
+        type Error = anyhow::Error;
+
+        fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(self: Pin<&mut Self>, item: Action) -> Result<()> {
+            self.sent.lock().unwrap().push(item);
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct MockExchange {
+        data: Arc<Mutex<Vec<Data>>>,
+        sent: Arc<Mutex<Vec<Action>>>,
+        /// 以REST查询到的挂单
+        open_orders: Vec<OrderPush>,
+        /// 为真时REST查询挂单失败
+        is_open_orders_failing: bool,
+    }
+
+    impl Exchange for MockExchange {
+        type Action = Action;
+        type Data = Data;
+
+        async fn connect(
+            &self,
+            subscriptions: Vec<Action>,
+        ) -> Result<impl Duplex<Action, anyhow::Error, Data> + use<>> {
+            self.sent.lock().unwrap().extend(subscriptions);
+            Ok(MockConnection {
+                data: std::mem::take(&mut *self.data.lock().unwrap()).into(),
+                sent: self.sent.clone(),
+            })
+        }
+
+        fn sign_login(&self, _timestamp: i64) -> String {
+            String::new()
+        }
+
+        async fn open_orders(&self, _inst_id: InstId) -> Result<Vec<data_center::OrderPush>> {
+            if self.is_open_orders_failing {
+                anyhow::bail!("REST request timed out");
+            }
+            Ok(self.open_orders.clone())
+        }
+    }
+
+    /// 每收到一条行情就撤销一次订单
+    struct CancelOnData;
+
+    impl Strategy<Bbo> for CancelOnData {
+        fn on_event(&mut self, broker_event: &BrokerEvent<Bbo>) -> Vec<ClientEvent> {
+            match broker_event {
+                BrokerEvent::Data(_) => vec![ClientEvent::CancelOrder(InstId::EthUsdtSwap, 1)],
+                _ => vec![],
+            }
+        }
+    }
+
+    /// 收到第一条行情时下一个可立即成交的买单和一个不会成交的卖单，并记录收到的成交
+    struct PlaceOnFirstData {
+        is_placed: bool,
+        fills: Arc<Mutex<Vec<OrderId>>>,
+    }
+
+    impl Strategy<Bbo> for PlaceOnFirstData {
+        fn on_event(&mut self, broker_event: &BrokerEvent<Bbo>) -> Vec<ClientEvent> {
+            match broker_event {
+                BrokerEvent::Data(_) if !self.is_placed => {
+                    self.is_placed = true;
+                    vec![
+                        ClientEvent::place_limit_order(LimitOrder::from_raw_size(
+                            1.,
+                            1,
+                            InstId::EthUsdtSwap,
+                            101.,
+                        )),
+                        ClientEvent::place_limit_order(LimitOrder::from_raw_size(
+                            -1.,
+                            2,
+                            InstId::EthUsdtSwap,
+                            200.,
+                        )),
+                    ]
+                }
+                BrokerEvent::Fill(fill) => {
+                    self.fills.lock().unwrap().push(fill.order_id);
+                    vec![]
+                }
+                _ => vec![],
+            }
+        }
+    }
+
+    fn create_bbo(ts: i64) -> data_center::types::Bbo {
+        data_center::types::Bbo {
+            ts: ts.into(),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: data_center::types::Level {
+                price: 100.0,
+                size: 1.0,
+                order_count: 1,
+            },
+            best_ask: data_center::types::Level {
+                price: 101.0,
+                size: 1.0,
+                order_count: 1,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_exchange_drives_engine() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let exchange = MockExchange {
+            data: Arc::new(Mutex::new(vec![
+                Data::Bbo(create_bbo(1)),
+                Data::Bbo(create_bbo(2)),
+            ])),
+            sent: sent.clone(),
+            ..Default::default()
+        };
+        let broker =
+            LiveBroker::new_bbo_with(&exchange, InstId::EthUsdtSwap, Duration::zero(), false).await;
+        let mut engine = Engine::new(broker, CancelOnData);
+        engine.run().await;
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 6);
+        assert!(matches!(
+            sent[0],
+            Action::SubscribeBboTbt(InstId::EthUsdtSwap)
+        ));
+        assert!(matches!(
+            sent[1],
+            Action::SubscribeMarkPrice(InstId::EthUsdtSwap)
+        ));
+        assert!(matches!(
+            sent[2],
+            Action::SubscribeOrders(InstId::EthUsdtSwap)
+        ));
+        assert!(matches!(
+            sent[3],
+            Action::SubscribePositions(InstId::EthUsdtSwap)
+        ));
+        assert!(
+            sent[4..]
+                .iter()
+                .all(|action| matches!(action, Action::CancelOrder { .. }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_sends_no_order() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let exchange = MockExchange {
+            data: Arc::new(Mutex::new(vec![
+                Data::Bbo(create_bbo(1)),
+                Data::Bbo(create_bbo(2)),
+            ])),
+            sent: sent.clone(),
+            ..Default::default()
+        };
+        let broker =
+            LiveBroker::new_bbo_with(&exchange, InstId::EthUsdtSwap, Duration::zero(), true).await;
+        let fills = Arc::new(Mutex::new(vec![]));
+        let strategy = PlaceOnFirstData {
+            is_placed: false,
+            fills: fills.clone(),
+        };
+        let mut engine = Engine::new(broker, strategy);
+        engine.run().await;
+
+        // 只发送了行情订阅
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert!(matches!(
+            sent[0],
+            Action::SubscribeBboTbt(InstId::EthUsdtSwap)
+        ));
+        assert!(matches!(
+            sent[1],
+            Action::SubscribeMarkPrice(InstId::EthUsdtSwap)
+        ));
+        // 买单以最新的Bbo在本地成交，卖单仍挂着
+        assert_eq!(*fills.lock().unwrap(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_orders() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let exchange = MockExchange {
+            data: Arc::new(Mutex::new(vec![Data::Bbo(create_bbo(1))])),
+            sent: sent.clone(),
+            ..Default::default()
+        };
+        let broker =
+            LiveBroker::new_bbo_with(&exchange, InstId::EthUsdtSwap, Duration::zero(), false).await;
+        let strategy = PlaceOnFirstData {
+            is_placed: false,
+            fills: Default::default(),
+        };
+        let mut engine = Engine::new(BatchingSink::new(broker), strategy);
+        engine.run().await;
+
+        // 同一tick内的两个下单合并为一个批量请求，两个订单都等待确认
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 5);
+        let Action::BatchOrders(actions) = &sent[4] else {
+            panic!("Expected a batch, got {:?}", sent[4]);
+        };
+        assert_eq!(actions.len(), 2);
+        assert!(
+            actions
+                .iter()
+                .all(|action| matches!(action, Action::LimitOrder { .. }))
+        );
+        let outstanding = engine.broker.inner().outstanding_requests();
+        let order_ids: Vec<_> = outstanding
+            .values()
+            .map(|request| request.order_id)
+            .collect();
+        assert_eq!(order_ids, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_fill_push() {
+        let fill_push = |acc_filled_size, state| {
+            Data::Order(OrderPush {
+                ts: 2,
+                order_id: 1,
+                inst_id: InstId::EthUsdtSwap,
+                state,
+                size: 2.,
+                filled_size: 1.,
+                acc_filled_size,
+                price: 101.,
+                side: true,
+                ord_type: OrdType::Limit,
+                exec_type: Some(data_center::types::ExecType::T),
+                push_type: OrderPushType::Fill,
+                reduce_only: false,
+            })
+        };
+        let partially_filled = || fill_push(1., data_center::types::OrderState::PartiallyFilled);
+        let exchange = MockExchange {
+            data: Arc::new(Mutex::new(vec![
+                Data::Bbo(create_bbo(1)),
+                partially_filled(),
+                // 重连后重新推送的同一状态
+                partially_filled(),
+                fill_push(2., data_center::types::OrderState::Filled),
+            ])),
+            ..Default::default()
+        };
+        let broker =
+            LiveBroker::new_bbo_with(&exchange, InstId::EthUsdtSwap, Duration::zero(), false).await;
+        let fills = Arc::new(Mutex::new(vec![]));
+        let strategy = PlaceOnFirstData {
+            is_placed: false,
+            fills: fills.clone(),
+        };
+        let mut engine = Engine::new(broker, strategy);
+        engine.run().await;
+
+        // 重复的推送被丢弃，持仓只增加一次
+        assert_eq!(*fills.lock().unwrap(), vec![1, 1]);
+        assert_eq!(engine.broker().position, 2.);
+        // 完全成交后不再记录该订单的累计成交量
+        assert!(engine.broker().acc_filled_sizes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_outstanding_requests() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let exchange = MockExchange {
+            data: Arc::new(Mutex::new(vec![
+                Data::Bbo(create_bbo(1)),
+                Data::OrderAck {
+                    request_id: "1".into(),
+                    order_id: 1,
+                },
+            ])),
+            sent: sent.clone(),
+            ..Default::default()
+        };
+        let mut broker =
+            LiveBroker::new_bbo_with(&exchange, InstId::EthUsdtSwap, Duration::zero(), false).await;
+        for (order_id, raw_size) in [(1, 1.), (2, -1.)] {
+            let order = LimitOrder::from_raw_size(raw_size, order_id, InstId::EthUsdtSwap, 100.);
+            broker
+                .on_client_event(ClientEvent::place_limit_order(order))
+                .await;
+        }
+        while broker.next_broker_event().await.is_some() {}
+
+        let outstanding = broker.outstanding_requests();
+        assert_eq!(outstanding.len(), 1);
+        assert_eq!(outstanding[&2].order_id, 2);
+    }
+
+    #[tokio::test]
+    async fn test_batch_responses_confirm_each_request() {
+        let exchange = MockExchange {
+            data: Arc::new(Mutex::new(vec![
+                Data::Bbo(create_bbo(1)),
+                // 批量请求的回复中，各订单都带有第一个请求的id
+                Data::OrderAck {
+                    request_id: "1".into(),
+                    order_id: 1,
+                },
+                Data::OrderError {
+                    request_id: "1".into(),
+                    order_id: 2,
+                    op: data_center::types::OrderOp::Place,
+                    code: "51008".into(),
+                    msg: "Insufficient balance".into(),
+                },
+            ])),
+            ..Default::default()
+        };
+        let mut broker =
+            LiveBroker::new_bbo_with(&exchange, InstId::EthUsdtSwap, Duration::zero(), false).await;
+        let orders = [(1, 1.), (2, -1.)].map(|(order_id, raw_size)| {
+            ClientEvent::place_limit_order(LimitOrder::from_raw_size(
+                raw_size,
+                order_id,
+                InstId::EthUsdtSwap,
+                100.,
+            ))
+        });
+        broker.on_client_batch(orders.to_vec()).await;
+        assert_eq!(broker.outstanding_requests().len(), 2);
+        while broker.next_broker_event().await.is_some() {}
+
+        assert!(broker.outstanding_requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all() {
+        let open_order = |order_id| OrderPush {
+            ts: 1,
+            order_id,
+            inst_id: InstId::EthUsdtSwap,
+            state: data_center::types::OrderState::Live,
+            size: 1.,
+            filled_size: 0.,
+            acc_filled_size: 0.,
+            price: 100.,
+            side: true,
+            ord_type: OrdType::Limit,
+            exec_type: None,
+            push_type: OrderPushType::Placed,
+            reduce_only: false,
+        };
+        let sent = Arc::new(Mutex::new(vec![]));
+        let exchange = MockExchange {
+            sent: sent.clone(),
+            open_orders: (1..=25).map(open_order).collect(),
+            ..Default::default()
+        };
+        let mut broker =
+            LiveBroker::new_bbo_with(&exchange, InstId::EthUsdtSwap, Duration::zero(), false).await;
+        broker
+            .on_client_event(ClientEvent::CancelAll(InstId::EthUsdtSwap))
+            .await;
+
+        // 查询到的挂单被逐个撤销，每批不超过MAX_BATCH_SIZE个
+        let sent = sent.lock().unwrap();
+        let batch_sizes: Vec<_> = sent[4..]
+            .iter()
+            .map(|action| match action {
+                Action::BatchOrders(actions) => {
+                    assert!(
+                        actions
+                            .iter()
+                            .all(|action| matches!(action, Action::CancelOrder { .. }))
+                    );
+                    actions.len()
+                }
+                _ => panic!("Expected a batch, got {action:?}"),
+            })
+            .collect();
+        assert_eq!(batch_sizes, vec![MAX_BATCH_SIZE, 5]);
+        assert_eq!(broker.outstanding_requests().len(), 25);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all_query_failed() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let exchange = MockExchange {
+            sent: sent.clone(),
+            is_open_orders_failing: true,
+            ..Default::default()
+        };
+        let mut broker =
+            LiveBroker::new_bbo_with(&exchange, InstId::EthUsdtSwap, Duration::zero(), false).await;
+        let sent_before = sent.lock().unwrap().len();
+        broker
+            .on_client_event(ClientEvent::CancelAll(InstId::EthUsdtSwap))
+            .await;
+
+        // 无法撤单时不发送请求，并通知策略
+        assert_eq!(sent.lock().unwrap().len(), sent_before);
+        let event = broker.next_broker_event().await;
+        assert!(
+            matches!(
+                event,
+                Some(BrokerEvent::Connection(ConnState::RequestFailed(_)))
+            ),
+            "Expected a failed request, got {event:?}"
+        );
     }
 }
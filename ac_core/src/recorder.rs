@@ -0,0 +1,125 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use utils::Timestamped;
+
+use crate::{BrokerEvent, ClientEvent, Timestamp, strategy::Strategy};
+
+/// 记录的一条事件。ts为记录时最新行情的时间戳
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record<D> {
+    pub ts: Timestamp,
+    pub event: RecordedEvent<D>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RecordedEvent<D> {
+    /// 策略收到的事件
+    Broker(BrokerEvent<D>),
+    /// 策略发出的事件
+    Client(ClientEvent),
+}
+
+/// 与Record的序列化格式相同，避免写入时复制事件
+#[derive(Serialize)]
+#[serde(rename = "Record")]
+struct RecordRef<'a, D> {
+    ts: Timestamp,
+    event: RecordedEventRef<'a, D>,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "RecordedEvent")]
+enum RecordedEventRef<'a, D> {
+    Broker(&'a BrokerEvent<D>),
+    Client(&'a ClientEvent),
+}
+
+/// 将Engine收发的全部事件以JSON lines写入文件，用于不经Broker重放策略的输入
+pub struct EventRecorder<D> {
+    writer: BufWriter<File>,
+    /// 最新行情的时间戳，非行情事件沿用该时间戳
+    ts: Timestamp,
+    data_ts: fn(&D) -> i64,
+    serialize: fn(&RecordRef<'_, D>) -> serde_json::Result<String>,
+}
+
+impl<D> EventRecorder<D>
+where
+    D: Serialize + Timestamped,
+{
+    /// 创建或清空path处的文件
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            ts: Timestamp::default(),
+            data_ts: |data| data.get_ts(),
+            serialize: |record| serde_json::to_string(record),
+        })
+    }
+}
+
+impl<D> EventRecorder<D> {
+    pub fn record_broker_event(&mut self, broker_event: &BrokerEvent<D>) {
+        if let BrokerEvent::Data(data) = broker_event {
+            self.ts = Timestamp::from_millis((self.data_ts)(data));
+        }
+        self.write(RecordedEventRef::Broker(broker_event));
+    }
+
+    pub fn record_client_events(&mut self, client_events: &[ClientEvent]) {
+        for client_event in client_events {
+            self.write(RecordedEventRef::Client(client_event));
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            tracing::error!("Failed to flush recorded events: {e}");
+        }
+    }
+
+    /// 写入失败时只记录日志，不影响运行
+    fn write(&mut self, event: RecordedEventRef<'_, D>) {
+        let record = RecordRef { ts: self.ts, event };
+        let result = (self.serialize)(&record)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| Ok(writeln!(self.writer, "{line}")?));
+        if let Err(e) = result {
+            tracing::error!("Failed to record event: {e}");
+        }
+    }
+}
+
+/// 读取EventRecorder写入的文件
+pub fn load_records<D: DeserializeOwned>(path: impl AsRef<Path>) -> Result<Vec<Record<D>>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = vec![];
+    for line in reader.lines() {
+        records.push(serde_json::from_str(&line?)?);
+    }
+    Ok(records)
+}
+
+/// 按顺序将记录的BrokerEvent推送给策略，返回策略发出的全部ClientEvent。记录的ClientEvent被忽略
+pub fn replay<D, S>(strategy: &mut S, records: &[Record<D>]) -> Vec<ClientEvent>
+where
+    S: Strategy<D>,
+{
+    let mut client_events = vec![];
+    for record in records {
+        if let RecordedEvent::Broker(broker_event) = &record.event {
+            client_events.extend(strategy.on_event(broker_event));
+        }
+    }
+    client_events
+}
@@ -1,13 +1,25 @@
-use std::marker::PhantomData;
+use std::{fs::File, marker::PhantomData, path::Path};
 
+use anyhow::{Result, bail};
 use chrono::Duration;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utils::Timestamped;
 
-use crate::{BrokerEvent, ClientEvent, Timestamp};
+use crate::{
+    BrokerEvent, ClientEvent, InstId, LimitOrder, Timestamp,
+    data::{Bbo, MarketData},
+};
 
-mod calc;
+pub mod calc;
+pub mod config;
 mod executors;
 pub mod single_ticker;
 
+pub(crate) use executors::ORDER_ID_OFFSET_BITS;
+pub use executors::{ExecutorArgs, OrderUpdateMode, RePegMode};
+
 /// D: type for the data
 ///
 /// Strategy内部不维护订单信息。每次下单后，等待服务器返回订单信息。假设服务器的订单信息可以在cooling_duration内返回。在每次Client Event后，在cooling_duraton，不做出任何行动。
@@ -17,6 +29,19 @@ pub mod single_ticker;
 pub trait Strategy<D> {
     fn on_event(&mut self, broker_event: &BrokerEvent<D>) -> Vec<ClientEvent>;
 
+    /// 可恢复的内部状态，用于实盘进程重启后恢复。不支持checkpoint的策略返回None。
+    fn checkpoint(&self) -> Option<Value> {
+        None
+    }
+
+    /// 从checkpoint恢复内部状态
+    fn restore(&mut self, _checkpoint: Value) -> Result<()> {
+        bail!("The strategy does not support checkpoints")
+    }
+
+    /// 以交易所的持仓为准，校正策略记录的持仓。positions中没有的产品视为空仓。
+    fn reconcile_positions(&mut self, _positions: &FxHashMap<InstId, f64>) {}
+
     // fn on_events<'a, I>(&mut self, market_evnets: I, now: Timestamp) -> Vec<ClientEvent>
     // where
     //     D: 'a,
@@ -30,7 +55,28 @@ pub trait Strategy<D> {
     // }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl<D, S> Strategy<D> for Box<S>
+where
+    S: Strategy<D> + ?Sized,
+{
+    fn on_event(&mut self, broker_event: &BrokerEvent<D>) -> Vec<ClientEvent> {
+        (**self).on_event(broker_event)
+    }
+
+    fn checkpoint(&self) -> Option<Value> {
+        (**self).checkpoint()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        (**self).restore(checkpoint)
+    }
+
+    fn reconcile_positions(&mut self, positions: &FxHashMap<InstId, f64>) {
+        (**self).reconcile_positions(positions)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Signal {
     Long,
     Short,
@@ -46,13 +92,57 @@ impl Signal {
     }
 }
 
+/// Signaler最近一次on_data时的中间结果，用于诊断信号的产生。尚未算出的值为None
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct SignalDiagnostics {
+    pub z_score: Option<f64>,
+    pub ofi: Option<f64>,
+    pub variance: Option<f64>,
+}
+
 pub trait Signaler<D> {
     fn on_data(&mut self, data: &D) -> Option<Signal>;
+
+    /// 不支持诊断的Signaler返回None
+    fn diagnostics(&self) -> Option<SignalDiagnostics> {
+        None
+    }
+
+    /// 指标等内部状态，见[`Strategy::checkpoint`]
+    fn checkpoint(&self) -> Option<Value> {
+        None
+    }
+
+    fn restore(&mut self, _checkpoint: Value) -> Result<()> {
+        bail!("The signaler does not support checkpoints")
+    }
 }
 
 pub trait Executor<D> {
     fn update(&mut self, broker_event: &BrokerEvent<D>);
     fn on_signal(&mut self, signal: Option<Signal>) -> Vec<ClientEvent>;
+
+    /// 持仓、挂单等内部状态，见[`Strategy::checkpoint`]
+    fn checkpoint(&self) -> Option<Value> {
+        None
+    }
+
+    fn restore(&mut self, _checkpoint: Value) -> Result<()> {
+        bail!("The executor does not support checkpoints")
+    }
+
+    fn reconcile_positions(&mut self, _positions: &FxHashMap<InstId, f64>) {}
+
+    /// 以交易所的挂单为准核对本地记录的挂单，返回撤销多余挂单的事件
+    fn reconcile_orders(&mut self, _open_orders: &[LimitOrder]) -> Vec<ClientEvent> {
+        vec![]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignalExecuteCheckpoint {
+    signaler: Value,
+    executor: Value,
 }
 
 pub struct SignalExecuteStrategy<Sg, Ex, D> {
@@ -67,6 +157,9 @@ where
     Ex: Executor<D>,
 {
     fn on_event(&mut self, broker_event: &BrokerEvent<D>) -> Vec<ClientEvent> {
+        if let BrokerEvent::OpenOrders(open_orders) = broker_event {
+            return self.executor.reconcile_orders(open_orders);
+        }
         self.executor.update(broker_event);
         if let Some(data) = broker_event.to_data() {
             let signal = self.signaler.on_data(data);
@@ -75,6 +168,90 @@ where
             vec![]
         }
     }
+
+    fn checkpoint(&self) -> Option<Value> {
+        let checkpoint = SignalExecuteCheckpoint {
+            signaler: self.signaler.checkpoint()?,
+            executor: self.executor.checkpoint()?,
+        };
+        serde_json::to_value(checkpoint).ok()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        let checkpoint: SignalExecuteCheckpoint = serde_json::from_value(checkpoint)?;
+        self.signaler.restore(checkpoint.signaler)?;
+        self.executor.restore(checkpoint.executor)
+    }
+
+    fn reconcile_positions(&mut self, positions: &FxHashMap<InstId, f64>) {
+        self.executor.reconcile_positions(positions)
+    }
+}
+
+/// 使只处理Bbo的Signaler或Executor能用于同时包含Bbo与逐笔成交的行情。
+///
+/// 逐笔成交不传给内部的Signaler与Executor；成交到达时沿用最近一次由Bbo得到的信号。
+pub struct BboOnly<T> {
+    inner: T,
+    last_signal: Option<Signal>,
+}
+
+impl<T> BboOnly<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            last_signal: None,
+        }
+    }
+}
+
+impl<S: Signaler<Bbo>> Signaler<MarketData> for BboOnly<S> {
+    fn on_data(&mut self, data: &MarketData) -> Option<Signal> {
+        if let MarketData::Bbo(bbo) = data {
+            self.last_signal = self.inner.on_data(bbo);
+        }
+        self.last_signal
+    }
+
+    fn diagnostics(&self) -> Option<SignalDiagnostics> {
+        self.inner.diagnostics()
+    }
+
+    fn checkpoint(&self) -> Option<Value> {
+        self.inner.checkpoint()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        self.inner.restore(checkpoint)
+    }
+}
+
+impl<E: Executor<Bbo>> Executor<MarketData> for BboOnly<E> {
+    fn update(&mut self, broker_event: &BrokerEvent<MarketData>) {
+        if let Some(broker_event) = broker_event.filter_map_data(|data| data.to_bbo().copied()) {
+            self.inner.update(&broker_event);
+        }
+    }
+
+    fn on_signal(&mut self, signal: Option<Signal>) -> Vec<ClientEvent> {
+        self.inner.on_signal(signal)
+    }
+
+    fn checkpoint(&self) -> Option<Value> {
+        self.inner.checkpoint()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        self.inner.restore(checkpoint)
+    }
+
+    fn reconcile_positions(&mut self, positions: &FxHashMap<InstId, f64>) {
+        self.inner.reconcile_positions(positions)
+    }
+
+    fn reconcile_orders(&mut self, open_orders: &[LimitOrder]) -> Vec<ClientEvent> {
+        self.inner.reconcile_orders(open_orders)
+    }
 }
 
 impl<Sg, Ex, D> SignalExecuteStrategy<Sg, Ex, D>
@@ -90,3 +267,220 @@ where
         }
     }
 }
+
+/// CSV中的一行
+#[derive(Serialize)]
+struct DiagnosticsRecord {
+    ts: i64,
+    z_score: Option<f64>,
+    ofi: Option<f64>,
+    variance: Option<f64>,
+    signal: Option<Signal>,
+}
+
+/// 将内部Signaler每期的时间戳、中间结果与信号写入CSV，用于诊断信号为何产生或未产生。
+///
+/// 写入有缓冲，drop时写出剩余的行
+pub struct LoggingSignaler<S> {
+    inner: S,
+    writer: csv::Writer<File>,
+}
+
+impl<S> LoggingSignaler<S> {
+    /// 创建或清空path处的文件
+    pub fn create(inner: S, path: impl AsRef<Path>) -> Result<Self> {
+        let writer = csv::Writer::from_path(path)?;
+        Ok(Self { inner, writer })
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<D, S> Signaler<D> for LoggingSignaler<S>
+where
+    D: Timestamped,
+    S: Signaler<D>,
+{
+    fn on_data(&mut self, data: &D) -> Option<Signal> {
+        let signal = self.inner.on_data(data);
+        let diagnostics = self.inner.diagnostics().unwrap_or_default();
+        let record = DiagnosticsRecord {
+            ts: data.get_ts(),
+            z_score: diagnostics.z_score,
+            ofi: diagnostics.ofi,
+            variance: diagnostics.variance,
+            signal,
+        };
+        if let Err(e) = self.writer.serialize(record) {
+            tracing::error!("Failed to write signal diagnostics: {e}");
+        }
+        signal
+    }
+
+    fn diagnostics(&self) -> Option<SignalDiagnostics> {
+        self.inner.diagnostics()
+    }
+
+    fn checkpoint(&self) -> Option<Value> {
+        self.inner.checkpoint()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        self.inner.restore(checkpoint)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PairCheckpoint {
+    a: Value,
+    b: Value,
+}
+
+/// 两个Signaler都给出相同方向时才发出信号，用于信号确认。
+///
+/// 每期两个Signaler都会收到数据，使各自的指标保持更新
+pub struct CombinedSignaler<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> CombinedSignaler<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self { a, b }
+    }
+}
+
+impl<D, A, B> Signaler<D> for CombinedSignaler<A, B>
+where
+    A: Signaler<D>,
+    B: Signaler<D>,
+{
+    fn on_data(&mut self, data: &D) -> Option<Signal> {
+        let a = self.a.on_data(data);
+        let b = self.b.on_data(data);
+        if a == b { a } else { None }
+    }
+
+    fn checkpoint(&self) -> Option<Value> {
+        let checkpoint = PairCheckpoint {
+            a: self.a.checkpoint()?,
+            b: self.b.checkpoint()?,
+        };
+        serde_json::to_value(checkpoint).ok()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        let checkpoint: PairCheckpoint = serde_json::from_value(checkpoint)?;
+        self.a.restore(checkpoint.a)?;
+        self.b.restore(checkpoint.b)
+    }
+}
+
+/// 任一Signaler给出信号时即发出该信号。两者方向相反时不发出信号
+pub struct AnySignaler<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> AnySignaler<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self { a, b }
+    }
+}
+
+impl<D, A, B> Signaler<D> for AnySignaler<A, B>
+where
+    A: Signaler<D>,
+    B: Signaler<D>,
+{
+    fn on_data(&mut self, data: &D) -> Option<Signal> {
+        let a = self.a.on_data(data);
+        let b = self.b.on_data(data);
+        match (a, b) {
+            (Some(a), Some(b)) if a != b => None,
+            _ => a.or(b),
+        }
+    }
+
+    fn checkpoint(&self) -> Option<Value> {
+        let checkpoint = PairCheckpoint {
+            a: self.a.checkpoint()?,
+            b: self.b.checkpoint()?,
+        };
+        serde_json::to_value(checkpoint).ok()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        let checkpoint: PairCheckpoint = serde_json::from_value(checkpoint)?;
+        self.a.restore(checkpoint.a)?;
+        self.b.restore(checkpoint.b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 每期返回固定信号，并记录收到的数据条数
+    struct Fixed {
+        signal: Option<Signal>,
+        count: usize,
+    }
+
+    impl Fixed {
+        fn new(signal: Option<Signal>) -> Self {
+            Self { signal, count: 0 }
+        }
+    }
+
+    impl Signaler<()> for Fixed {
+        fn on_data(&mut self, _data: &()) -> Option<Signal> {
+            self.count += 1;
+            self.signal
+        }
+    }
+
+    #[test]
+    fn test_combined_signaler() {
+        use Signal::*;
+        let cases = [
+            (None, None, None),
+            (None, Some(Long), None),
+            (None, Some(Short), None),
+            (Some(Long), None, None),
+            (Some(Long), Some(Long), Some(Long)),
+            (Some(Long), Some(Short), None),
+            (Some(Short), None, None),
+            (Some(Short), Some(Long), None),
+            (Some(Short), Some(Short), Some(Short)),
+        ];
+        for (a, b, expected) in cases {
+            let mut signaler = CombinedSignaler::new(Fixed::new(a), Fixed::new(b));
+            assert_eq!(signaler.on_data(&()), expected, "{a:?} & {b:?}");
+            assert_eq!((signaler.a.count, signaler.b.count), (1, 1));
+        }
+    }
+
+    #[test]
+    fn test_any_signaler() {
+        use Signal::*;
+        let cases = [
+            (None, None, None),
+            (None, Some(Long), Some(Long)),
+            (None, Some(Short), Some(Short)),
+            (Some(Long), None, Some(Long)),
+            (Some(Long), Some(Long), Some(Long)),
+            (Some(Long), Some(Short), None),
+            (Some(Short), None, Some(Short)),
+            (Some(Short), Some(Long), None),
+            (Some(Short), Some(Short), Some(Short)),
+        ];
+        for (a, b, expected) in cases {
+            let mut signaler = AnySignaler::new(Fixed::new(a), Fixed::new(b));
+            assert_eq!(signaler.on_data(&()), expected, "{a:?} | {b:?}");
+            assert_eq!((signaler.a.count, signaler.b.count), (1, 1));
+        }
+    }
+}
@@ -1,3 +1,11 @@
+use std::collections::VecDeque;
+
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::Timestamp;
+
+#[derive(Serialize, Deserialize)]
 pub struct Ema {
     /// The smoothing time constant (tau).
     tau: f64,
@@ -34,6 +42,7 @@ impl Ema {
 }
 
 /// Expoential moving average and variance
+#[derive(Serialize, Deserialize)]
 pub struct Emav {
     /// The smoothing time constant (tau).
     tau: f64,
@@ -89,3 +98,197 @@ impl Emav {
         }
     }
 }
+
+/// 成交量加权平均价（VWAP）。累计VWAP从第一笔成交开始计算；设置窗口后，另外计算最近window时长内的VWAP。
+#[derive(Default)]
+pub struct Vwap {
+    notional: f64,
+    volume: f64,
+
+    /// 窗口长度，单位为毫秒
+    window: Option<i64>,
+    /// 窗口内的成交：(ts, price, size)
+    window_trades: VecDeque<(Timestamp, f64, f64)>,
+    window_notional: f64,
+    window_volume: f64,
+}
+
+impl Vwap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 同时计算最近window时长内的VWAP
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = Some(window.num_milliseconds());
+        self
+    }
+
+    /// 加入一笔成交，返回累计VWAP
+    pub fn update(&mut self, ts: Timestamp, price: f64, size: f64) -> f64 {
+        self.notional += price * size;
+        self.volume += size;
+
+        if let Some(window) = self.window {
+            self.window_trades.push_back((ts, price, size));
+            self.window_notional += price * size;
+            self.window_volume += size;
+            while let Some(&(old_ts, old_price, old_size)) = self.window_trades.front() {
+                if old_ts + window > ts {
+                    break;
+                }
+                self.window_trades.pop_front();
+                self.window_notional -= old_price * old_size;
+                self.window_volume -= old_size;
+            }
+        }
+
+        self.notional / self.volume
+    }
+
+    /// 累计VWAP。尚无成交时为None。
+    pub fn vwap(&self) -> Option<f64> {
+        (self.volume > 0.).then(|| self.notional / self.volume)
+    }
+
+    /// 窗口内的VWAP。未设置窗口或窗口内无成交时为None。
+    pub fn windowed_vwap(&self) -> Option<f64> {
+        (self.window.is_some() && !self.window_trades.is_empty())
+            .then(|| self.window_notional / self.window_volume)
+    }
+}
+
+/// 相对强弱指数（RSI），平均涨幅与平均跌幅使用Wilder平滑
+pub struct Rsi {
+    period: usize,
+    last_price: Option<f64>,
+    /// 已收到的价格变动数
+    count: usize,
+    avg_gain: f64,
+    avg_loss: f64,
+}
+
+impl Rsi {
+    pub fn new(period: usize) -> Self {
+        assert!(period > 0, "period must be positive");
+        Self {
+            period,
+            last_price: None,
+            count: 0,
+            avg_gain: 0.,
+            avg_loss: 0.,
+        }
+    }
+
+    /// 加入一个价格。收到period个价格变动（即period + 1个价格）后，返回0到100之间的RSI。
+    pub fn update(&mut self, price: f64) -> Option<f64> {
+        let last_price = self.last_price.replace(price)?;
+        let change = price - last_price;
+        let (gain, loss) = (change.max(0.), (-change).max(0.));
+
+        self.count += 1;
+        let period = self.period as f64;
+        if self.count <= self.period {
+            // 前period个变动取简单平均
+            self.avg_gain += gain / period;
+            self.avg_loss += loss / period;
+            if self.count < self.period {
+                return None;
+            }
+        } else {
+            self.avg_gain = (self.avg_gain * (period - 1.) + gain) / period;
+            self.avg_loss = (self.avg_loss * (period - 1.) + loss) / period;
+        }
+
+        if self.avg_loss == 0. {
+            return Some(100.);
+        }
+        let rs = self.avg_gain / self.avg_loss;
+        Some(100. - 100. / (1. + rs))
+    }
+}
+
+/// 变动率（ROC）：当前价格相对n个样本之前价格的变化比例
+pub struct Roc {
+    n: usize,
+    prices: VecDeque<f64>,
+}
+
+impl Roc {
+    pub fn new(n: usize) -> Self {
+        assert!(n > 0, "n must be positive");
+        Self {
+            n,
+            prices: VecDeque::with_capacity(n + 1),
+        }
+    }
+
+    /// 加入一个价格。收到n + 1个价格后返回变动率。
+    pub fn update(&mut self, price: f64) -> Option<f64> {
+        self.prices.push_back(price);
+        if self.prices.len() <= self.n {
+            return None;
+        }
+        let old_price = self.prices.pop_front().unwrap();
+        Some((price - old_price) / old_price)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use float_cmp::approx_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_rsi() {
+        // Wilder的14期RSI示例数据
+        let prices = [
+            44.34, 44.09, 44.15, 43.61, 44.33, 44.83, 45.10, 45.42, 45.84, 46.08, 45.89, 46.03,
+            45.61, 46.28, 46.28, 46.00,
+        ];
+        let mut rsi = Rsi::new(14);
+        let values: Vec<_> = prices.iter().map(|price| rsi.update(*price)).collect();
+
+        assert!(values[..14].iter().all(Option::is_none));
+        assert!((values[14].unwrap() - 70.4641).abs() < 1e-4);
+        assert!((values[15].unwrap() - 66.2496).abs() < 1e-4);
+
+        // 只涨不跌时为100
+        let mut rsi = Rsi::new(2);
+        rsi.update(1.);
+        rsi.update(2.);
+        assert_eq!(rsi.update(3.), Some(100.));
+    }
+
+    #[test]
+    fn test_roc() {
+        let mut roc = Roc::new(2);
+        assert_eq!(roc.update(100.), None);
+        assert_eq!(roc.update(105.), None);
+        assert_eq!(roc.update(110.), Some(0.1));
+        assert_eq!(roc.update(84.), Some(-0.2));
+    }
+
+    #[test]
+    fn test_vwap() {
+        let trades = [(1000, 100., 1.), (2000, 102., 3.), (3000, 101., 2.)];
+        let mut vwap = Vwap::new().with_window(Duration::milliseconds(1500));
+        let mut cumulative = 0.;
+        for (ts, price, size) in trades {
+            cumulative = vwap.update(Timestamp::from_millis(ts), price, size);
+        }
+
+        // (100*1 + 102*3 + 101*2) / 6
+        assert!(approx_eq!(f64, cumulative, 608. / 6., ulps = 4));
+        assert_eq!(vwap.vwap(), Some(cumulative));
+        // 窗口内只剩后两笔成交：(102*3 + 101*2) / 5
+        assert!(approx_eq!(
+            f64,
+            vwap.windowed_vwap().unwrap(),
+            508. / 5.,
+            ulps = 4
+        ));
+        assert_eq!(Vwap::new().windowed_vwap(), None);
+    }
+}
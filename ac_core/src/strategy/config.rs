@@ -0,0 +1,176 @@
+//! 从TOML或JSON文件读取策略参数，避免调参时重新编译。
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, bail};
+use chrono::Duration;
+use serde::{Deserialize, Deserializer};
+
+use super::{
+    Strategy,
+    single_ticker::{
+        ema_crossover::EmaCrossoverArgs, market_maker::MarketMakerArgs,
+        ofi_momentum::OfiMomentumArgs, order_book_imbalance::OrderBookImbalanceArgs,
+    },
+};
+use crate::{InstId, data::Bbo};
+
+/// 指定策略参数文件路径的环境变量，命令行的第一个参数优先
+pub const STRATEGY_CONFIG_ENV: &str = "STRATEGY_CONFIG";
+
+/// 策略参数文件的路径：命令行的第一个参数、STRATEGY_CONFIG环境变量，或默认的strategies/ofi_momentum.toml
+pub fn config_path() -> PathBuf {
+    std::env::args()
+        .nth(1)
+        .or_else(|| std::env::var(STRATEGY_CONFIG_ENV).ok())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("strategies/ofi_momentum.toml")
+        })
+}
+
+/// 由strategy字段区分策略种类
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "strategy", rename_all = "kebab-case")]
+pub enum StrategyConfig {
+    OfiMomentum(OfiMomentumArgs),
+    OrderBookImbalance(OrderBookImbalanceArgs),
+    MarketMaker(MarketMakerArgs),
+    EmaCrossover(EmaCrossoverArgs),
+}
+
+impl StrategyConfig {
+    /// 按扩展名读取TOML或JSON文件
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let config_str = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Ok(toml::from_str(&config_str)?),
+            Some("json") => Ok(serde_json::from_str(&config_str)?),
+            _ => bail!("Unsupported strategy config file: {path:?}"),
+        }
+    }
+
+    pub fn instrument_id(&self) -> InstId {
+        match self {
+            Self::OfiMomentum(args) => args.instrument_id,
+            Self::OrderBookImbalance(args) => args.instrument_id,
+            Self::MarketMaker(args) => args.instrument_id,
+            Self::EmaCrossover(args) => args.instrument_id,
+        }
+    }
+
+    /// 策略指标的预热期
+    pub fn warm_up(&self) -> Duration {
+        match self {
+            Self::OfiMomentum(args) => args.window_ema,
+            Self::OrderBookImbalance(args) => args.window,
+            Self::MarketMaker(_) => Duration::zero(),
+            Self::EmaCrossover(args) => args.slow_window,
+        }
+    }
+}
+
+pub fn build_strategy(config: StrategyConfig) -> Box<dyn Strategy<Bbo>> {
+    match config {
+        StrategyConfig::OfiMomentum(args) => Box::new(args.into_strategy()),
+        StrategyConfig::OrderBookImbalance(args) => Box::new(args.into_strategy()),
+        StrategyConfig::MarketMaker(args) => Box::new(args.into_strategy()),
+        StrategyConfig::EmaCrossover(args) => Box::new(args.into_strategy()),
+    }
+}
+
+/// 实盘使用的策略，时间相关的逻辑以系统时间而非行情的时间戳计算
+pub fn build_live_strategy(config: StrategyConfig) -> Box<dyn Strategy<Bbo>> {
+    match config {
+        StrategyConfig::OfiMomentum(args) => Box::new(args.into_live_strategy()),
+        StrategyConfig::OrderBookImbalance(args) => Box::new(args.into_live_strategy()),
+        StrategyConfig::MarketMaker(args) => Box::new(args.into_strategy()),
+        StrategyConfig::EmaCrossover(args) => Box::new(args.into_live_strategy()),
+    }
+}
+
+/// 将以秒为单位的数字反序列化为Duration
+pub(crate) fn deserialize_secs<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let secs = f64::deserialize(deserializer)?;
+    Ok(Duration::milliseconds((secs * 1000.) as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::strategy::{ExecutorArgs, OrderUpdateMode, RePegMode};
+
+    #[test]
+    fn test_load_strategy_config() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("strategies/ofi_momentum.toml");
+        let config = StrategyConfig::load(path).unwrap();
+        let expected = OfiMomentumArgs {
+            instrument_id: InstId::EthUsdtSwap,
+            window_ofi: Duration::minutes(8),
+            window_ema: Duration::minutes(240),
+            theta: 5.,
+            executor: ExecutorArgs {
+                holding_duration: Duration::seconds(200),
+                event_interval: Duration::seconds(1),
+                notional: 100_000.,
+                price_offset: 0.,
+                re_peg_mode: RePegMode::ChaseTouch,
+                order_update_mode: OrderUpdateMode::Amend,
+                order_ttl: Duration::zero(),
+                post_fill_cooldown: Duration::zero(),
+                max_position_notional: None,
+                max_distance_ticks: None,
+                passive_only: false,
+                order_id_offset: 0,
+            },
+        };
+        assert_eq!(config, StrategyConfig::OfiMomentum(expected));
+        assert_eq!(config.warm_up(), Duration::minutes(240));
+
+        let json = r#"{"strategy":"ofi-momentum","instrument_id":"BTC-USDT-SWAP","window_ofi":60,
+            "window_ema":600,"theta":3.0,"holding_duration":10,"event_interval":0.5,
+            "notional":1000.0,"price_offset":0.1,"order_ttl":5,"order_id_offset":1}"#;
+        let StrategyConfig::OfiMomentum(args) = serde_json::from_str(json).unwrap() else {
+            panic!("Expected ofi-momentum");
+        };
+        assert_eq!(args.executor.event_interval, Duration::milliseconds(500));
+        assert_eq!(args.executor.re_peg_mode, RePegMode::HoldOriginal);
+        assert_eq!(args.executor.order_ttl, Duration::seconds(5));
+
+        let path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("strategies/order_book_imbalance.toml");
+        let config = StrategyConfig::load(path).unwrap();
+        let StrategyConfig::OrderBookImbalance(args) = &config else {
+            panic!("Expected order-book-imbalance");
+        };
+        assert_eq!(args.window, Duration::minutes(1));
+        assert_eq!(args.threshold, 0.6);
+        assert_eq!(config.warm_up(), Duration::minutes(1));
+
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("strategies/market_maker.toml");
+        let config = StrategyConfig::load(path).unwrap();
+        let StrategyConfig::MarketMaker(args) = &config else {
+            panic!("Expected market-maker");
+        };
+        assert_eq!(args.offset, 0.5);
+        assert_eq!(args.max_position_notional, Some(50_000.));
+        assert_eq!(config.warm_up(), Duration::zero());
+
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("strategies/ema_crossover.toml");
+        let config = StrategyConfig::load(path).unwrap();
+        let StrategyConfig::EmaCrossover(args) = &config else {
+            panic!("Expected ema-crossover");
+        };
+        assert_eq!(args.fast_window, Duration::minutes(1));
+        assert_eq!(args.hysteresis, 0.0005);
+        assert_eq!(config.warm_up(), Duration::minutes(10));
+    }
+}
@@ -1,16 +1,95 @@
+use anyhow::Result;
 use chrono::Duration;
+use data_center::instruments_profile::INSTRUMENT_PROFILES;
 use float_cmp::approx_eq;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::{
-    BrokerEvent, ClientEvent, InstId, LimitOrder, Order, Position, Timestamp,
+    BrokerEvent, ClientEvent, InstId, LimitOrder, Order, OrderId, Position, Timestamp,
+    clock::Clock,
     data::Bbo,
     utils::{round_f64, truncate_f64},
 };
 
-use super::{Executor, Signal};
+use super::{Executor, Signal, config::deserialize_secs};
+
+/// Order id的低位用于存放order_id_offset，高位存放递增的序号
+pub(crate) const ORDER_ID_OFFSET_BITS: u32 = 16;
+/// 序号可用的位数
+const ORDER_ID_BODY_BITS: u32 = u64::BITS - ORDER_ID_OFFSET_BITS;
+
+/// 该订单是否由order_id_offset对应的策略发出
+pub(super) fn is_own_order(order_id: OrderId, order_id_offset: u64) -> bool {
+    order_id & ((1 << ORDER_ID_OFFSET_BITS) - 1) == order_id_offset
+}
+
+/// 以交易所的挂单为准核对本地记录的一个挂单。candidates为交易所上属于该挂单位置的挂单。
+///
+/// 本地挂单仍在交易所上时以交易所的记录更新；已不在时视为丢失。本地没有挂单时接管第一个候选挂单。
+/// 返回其余需要撤销的挂单。
+pub(super) fn reconcile_order_slot(
+    slot: &mut Option<LimitOrder>,
+    mut candidates: Vec<LimitOrder>,
+) -> Vec<LimitOrder> {
+    if let Some(order) = *slot {
+        match candidates.iter().position(|c| c.order_id == order.order_id) {
+            Some(i) => *slot = Some(candidates.remove(i)),
+            None => {
+                tracing::warn!("Order {} is no longer on the exchange", order.order_id);
+                *slot = None;
+            }
+        }
+    }
+    if slot.is_none() && !candidates.is_empty() {
+        let order = candidates.remove(0);
+        tracing::warn!("Adopting order {} found on the exchange", order.order_id);
+        *slot = Some(order);
+    }
+    candidates
+}
 
 // 生成订单的逻辑：先计算期望的持仓，再与当前的持仓相减，得到所需的订单。与当前的挂单进行对比，判断维持/改单/取消
 
+/// 信号不变时，挂单价格如何随BBO调整
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RePegMode {
+    /// 在信号改变前维持最初的挂单价格，只在规模不匹配时改单
+    #[default]
+    HoldOriginal,
+    /// 锚定最激进的限价，BBO变动后持续改单到 最优价 ± price_offset
+    ChaseTouch,
+}
+
+/// 同方向的挂单需要调整规模或价格时，如何发出订单事件
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrderUpdateMode {
+    /// 原地改单，保留排队位置
+    #[default]
+    Amend,
+    /// 撤单后重新下单，用于改单频率受限的交易所
+    CancelReplace,
+}
+
+/// NaiveLimitExecutor重启后需要恢复的状态
+#[derive(Serialize, Deserialize)]
+struct NaiveLimitCheckpoint {
+    position: Position,
+    placed_order: Option<LimitOrder>,
+    placed_order_ts: Timestamp,
+    last_signal: Option<Signal>,
+    last_signal_ts: Timestamp,
+    next_order_id_body: u64,
+    /// 以下字段缺省时按默认值恢复，兼容旧的检查点
+    #[serde(default)]
+    last_fill_ts: Option<Timestamp>,
+    #[serde(default)]
+    is_replacement_pending: bool,
+}
+
 /// A naive limit order executor based on bbo. 根据信号尝试建仓。若为多头信号，则在 最优买价 + price_offset 挂限价单。若在给定时间内未成交，则取消订单。
 /// 若在成交前信号转为空头，则取消并反向挂空单。若距离最后一次信号的时长到达给定值，挂单平仓。
 #[derive(Default)]
@@ -19,27 +98,48 @@ pub struct NaiveLimitExecutor {
     notional: f64,
     /// The digits of the size
     size_digits: i32,
+    /// 小于该值的规模视为0，取size精度与最小下单量中的较大者
     size_eps: f64,
     price_digits: i32,
     /// 下单的名义金额门槛
     notional_threshold: f64,
     /// 挂单价格朝激进方向的偏移量
     price_offset: f64,
+    /// 挂单价格不越过最优价，买单不高于最优买价，卖单不低于最优卖价，确保以maker成交
+    is_passive_only: bool,
+    re_peg_mode: RePegMode,
+    order_update_mode: OrderUpdateMode,
+    /// 持仓名义金额的上限
+    max_position_notional: Option<f64>,
+    /// 挂单价格与目标价格相差超过该tick数时视为无法成交，即使在HoldOriginal模式下也改单到目标价格
+    max_distance_ticks: Option<u32>,
 
     bbo: Bbo,
+    /// 挂单存活时间、事件间隔与持仓时限所用的时钟，默认由行情的时间戳驱动
+    clock: Box<dyn Clock + Send>,
 
     last_signal: Option<Signal>,
     /// 最后一个非None的Signal抵达的ts
     last_signal_ts: Timestamp,
     /// 信号消失后继续持有的时长
-    holding_duration: Timestamp,
+    holding_duration: i64,
 
     last_event_ts: Timestamp,
     /// 发出事件的最小时间间隔，避免频繁发出事件
-    event_interval: Timestamp,
+    event_interval: i64,
+    /// 最近一次成交的ts
+    last_fill_ts: Option<Timestamp>,
+    /// 成交后不再下单或改单的时长，只允许撤单。为0时不限制
+    post_fill_cooldown: i64,
 
     position: Position,
     placed_order: Option<LimitOrder>,
+    /// 挂单最近一次下单、改单或部分成交的ts
+    placed_order_ts: Timestamp,
+    /// 撤单重下时，placed_order记为尚未确认的新挂单。确认前不再对其发出事件
+    is_replacement_pending: bool,
+    /// 挂单无进展的最长时间，超时则撤单。为0时不撤单。
+    order_ttl: i64,
 
     next_order_id_body: u64,
     /// 小于2^16，用于作为每个策略的Order id的末位唯一标识符
@@ -57,6 +157,10 @@ impl NaiveLimitExecutor {
         event_interval: Duration,
         order_id_offset: u64,
     ) -> Self {
+        assert!(
+            order_id_offset < 1 << ORDER_ID_OFFSET_BITS,
+            "order_id_offset must be less than 2^{ORDER_ID_OFFSET_BITS}, got {order_id_offset}"
+        );
         Self {
             instrument_id,
             notional,
@@ -65,21 +169,124 @@ impl NaiveLimitExecutor {
             notional_threshold: 0.05 * notional,
             price_offset,
             price_digits,
-            holding_duration: holding_duration.num_milliseconds() as u64,
-            event_interval: event_interval.num_milliseconds() as u64,
+            holding_duration: holding_duration.num_milliseconds(),
+            event_interval: event_interval.num_milliseconds(),
             order_id_offset,
             ..Default::default()
         }
     }
 
+    /// 设置产品的最小下单量。持仓低于该值时视为空仓，规模差低于该值时不下单
+    pub fn with_min_size(mut self, min_size: f64) -> Self {
+        self.size_eps = self.size_eps.max(min_size);
+        self
+    }
+
+    /// 设置时钟，实盘中使用系统时间
+    pub fn with_clock(mut self, clock: impl Clock + Send + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// 设置挂单的存活时间。挂单在该时长内既未成交也未改单，则撤单，之后按信号重新挂单。
+    pub fn with_order_ttl(mut self, order_ttl: Duration) -> Self {
+        self.order_ttl = order_ttl.num_milliseconds();
+        self
+    }
+
+    /// 设置成交后的冷却时间。成交后该时长内不下新单也不改单，只撤单，避免信号来回变动时过度交易
+    pub fn with_post_fill_cooldown(mut self, post_fill_cooldown: Duration) -> Self {
+        self.post_fill_cooldown = post_fill_cooldown.num_milliseconds();
+        self
+    }
+
+    /// 只以maker挂单。price_offset为正时，挂单价格限制在最优价上；盘口交叉时不下单也不改单
+    pub fn with_passive_only(mut self) -> Self {
+        self.is_passive_only = true;
+        self
+    }
+
+    /// 设置信号不变时挂单价格随BBO调整的方式，默认维持最初的挂单价格
+    pub fn with_re_peg_mode(mut self, re_peg_mode: RePegMode) -> Self {
+        self.re_peg_mode = re_peg_mode;
+        self
+    }
+
+    /// 设置同方向挂单的更新方式，默认为原地改单
+    pub fn with_order_update_mode(mut self, order_update_mode: OrderUpdateMode) -> Self {
+        self.order_update_mode = order_update_mode;
+        self
+    }
+
+    /// 设置持仓名义金额的上限，目标仓位的 |size| * price 不会超过该值
+    pub fn with_max_position_notional(mut self, max_position_notional: f64) -> Self {
+        self.max_position_notional = Some(max_position_notional);
+        self
+    }
+
+    /// 设置挂单的刷新距离。挂单价格与 最优价 ± price_offset 相差超过max_distance_ticks个tick时，
+    /// 按order_update_mode将挂单移到目标价格
+    pub fn with_max_distance_ticks(mut self, max_distance_ticks: u32) -> Self {
+        self.max_distance_ticks = Some(max_distance_ticks);
+        self
+    }
+
+    /// 挂单价格是否已离目标价格过远
+    fn is_too_far(&self, order_price: f64, target_price: f64) -> bool {
+        let Some(max_distance_ticks) = self.max_distance_ticks else {
+            return false;
+        };
+        let distance_ticks = (order_price - target_price).abs() * 10f64.powi(self.price_digits);
+        round_f64(distance_ticks, 0) > max_distance_ticks as f64
+    }
+
+    /// 将目标仓位限制在持仓名义金额上限之内，多头按最优买价、空头按最优卖价计算
+    fn clamp_position(&self, position: Position) -> Position {
+        let Some(max_notional) = self.max_position_notional else {
+            return position;
+        };
+        let price = if position.size > 0. {
+            self.bbo.best_bid.price
+        } else {
+            self.bbo.best_ask.price
+        };
+        if position.size.abs() * price <= max_notional {
+            return position;
+        }
+        let max_size = truncate_f64(max_notional / price, self.size_digits);
+        Position::new(max_size.copysign(position.size))
+    }
+
+    /// 是否处于成交后的冷却时间内
+    fn is_cooling_down(&self) -> bool {
+        self.last_fill_ts
+            .is_some_and(|ts| self.clock.now() - ts < self.post_fill_cooldown)
+    }
+
+    /// 该价格的订单是否会立即与对手方最优价成交
+    fn is_crossing(&self, raw_size: f64, price: f64) -> bool {
+        if raw_size > 0. {
+            price >= self.bbo.best_ask.price
+        } else {
+            price <= self.bbo.best_bid.price
+        }
+    }
+
+    /// 挂单是否已超过存活时间
+    fn is_placed_order_expired(&self) -> bool {
+        self.order_ttl > 0
+            && self.placed_order.is_some()
+            && self.clock.now() - self.placed_order_ts >= self.order_ttl
+    }
+
     fn get_ideal_position(&self, signal: Option<Signal>) -> Position {
         let Some(signal) = signal else {
-            if self.position.is_clear(self.size_digits) {
+            if self.position.is_clear(self.size_eps) {
                 // 无信号且无仓位，维持空仓
                 return self.position;
             } else {
                 // 无信号但有仓位，检测持仓是否超过时限，若是则平仓，若不是则维持仓位
-                if self.bbo.ts - self.last_signal_ts >= self.holding_duration {
+                if self.clock.now() - self.last_signal_ts >= self.holding_duration {
                     return Position::new(0.);
                 } else {
                     return self.position;
@@ -89,23 +296,28 @@ impl NaiveLimitExecutor {
 
         match signal {
             Signal::Long => {
-                let size = self.notional / self.bbo.bid_price;
+                let size = self.notional / self.bbo.best_bid.price;
                 let size = truncate_f64(size, self.size_digits);
                 Position::new(size)
             }
             Signal::Short => {
-                let size = -self.notional / self.bbo.ask_price;
+                let size = -self.notional / self.bbo.best_ask.price;
                 let size = truncate_f64(size, self.size_digits);
                 Position::new(size)
             }
         }
     }
 
+    /// 序号用尽2^48后从0重新开始，不会溢出到offset的位上
     fn get_next_order_id(&mut self) -> u64 {
         let order_id_body = self.next_order_id_body;
         self.next_order_id_body += 1;
+        if self.next_order_id_body >> ORDER_ID_BODY_BITS != 0 {
+            tracing::warn!("Order id body exhausted, wrapping around to 0");
+            self.next_order_id_body = 0;
+        }
 
-        (order_id_body << 16) | self.order_id_offset
+        (order_id_body << ORDER_ID_OFFSET_BITS) | self.order_id_offset
     }
 
     fn gen_order(&mut self, raw_size: f64, price: f64) -> Option<LimitOrder> {
@@ -124,10 +336,25 @@ impl NaiveLimitExecutor {
         Some(order)
     }
 
-    // 将应有的挂单规模与实际挂单规模对比，并按需发出事件
-    fn get_event_from_target_order(&mut self, raw_size: f64, price: f64) -> Vec<ClientEvent> {
+    // 将应有的挂单规模与实际挂单规模对比，并按需发出事件。is_cancel_only为真时只撤单
+    fn get_event_from_target_order(
+        &mut self,
+        raw_size: f64,
+        price: f64,
+        is_cancel_only: bool,
+    ) -> Vec<ClientEvent> {
+        let is_too_far = self
+            .placed_order
+            .is_some_and(|order| self.is_too_far(order.price, price));
+        // 撤单重下的新挂单尚未确认时，不重复发出事件
+        if self.is_replacement_pending {
+            return vec![];
+        }
         // 若不存在挂单，则直接下单
         let Some(ref mut old_order) = self.placed_order else {
+            if is_cancel_only {
+                return vec![];
+            }
             let order = self.gen_order(raw_size, price);
             let event = order.map(ClientEvent::place_limit_order);
             return event.into_iter().collect();
@@ -141,39 +368,67 @@ impl NaiveLimitExecutor {
 
         let (new_side, new_size) = crate::utils::get_side_size_from_raw_size(raw_size);
         if new_side == old_order.side {
-            // 方向匹配，订单规模或价格不匹配，则进行改单
-            if !approx_eq!(
+            // 方向匹配，订单规模不匹配，或在ChaseTouch模式下价格不匹配，或挂单离目标价格过远，则进行改单
+            let is_size_changed = !approx_eq!(
                 f64,
                 old_order.unfilled_size(),
                 new_size,
                 epsilon = self.size_eps
-            ) || old_order.price != price
-            {
-                let modified_order = old_order.amended(new_size, price);
-                return vec![ClientEvent::AmendOrder(modified_order)];
+            );
+            let price = match self.re_peg_mode {
+                RePegMode::HoldOriginal if !is_too_far => old_order.price,
+                _ => price,
+            };
+            if !is_size_changed && old_order.price == price {
+                return vec![];
+            }
+            match self.order_update_mode {
+                OrderUpdateMode::Amend if is_cancel_only => vec![],
+                OrderUpdateMode::Amend => {
+                    let modified_order = old_order.amended(new_size, price);
+                    vec![ClientEvent::AmendOrder(modified_order)]
+                }
+                OrderUpdateMode::CancelReplace => {
+                    let old_order_id = old_order.order_id;
+                    self.cancel_replace(old_order_id, raw_size, price, is_cancel_only)
+                }
             }
-
-            // 两个思路：1：在收到信号后，在信号改变前，维持最初的挂单，不改单；
-            // 2：锚定最激进的限价单，持续改单。
-            // 上面被注释掉的代码是第2种思路。目前先试试第一种思路。也许可以写进参数。
-            vec![]
         } else {
             // 方向不匹配，则取消订单并重新下单
-            let mut events = vec![];
             let old_order_id = old_order.order_id;
-            events.push(ClientEvent::CancelOrder(self.instrument_id, old_order_id));
-            let new_order = self.gen_order(raw_size, price);
-            events.extend(new_order.map(ClientEvent::place_limit_order));
-            events
+            self.cancel_replace(old_order_id, raw_size, price, is_cancel_only)
         }
     }
 
+    /// 撤销旧挂单并下新单。与改单一样立即以新挂单更新placed_order，旧挂单的撤单回报不再影响placed_order
+    fn cancel_replace(
+        &mut self,
+        old_order_id: OrderId,
+        raw_size: f64,
+        price: f64,
+        is_cancel_only: bool,
+    ) -> Vec<ClientEvent> {
+        let mut events = vec![ClientEvent::CancelOrder(self.instrument_id, old_order_id)];
+        if is_cancel_only {
+            return events;
+        }
+        let Some(new_order) = self.gen_order(raw_size, price) else {
+            return events;
+        };
+        self.placed_order = Some(new_order);
+        self.placed_order_ts = self.clock.now();
+        self.is_replacement_pending = true;
+        events.push(ClientEvent::place_limit_order(new_order));
+        events
+    }
+
     fn calc_target_order_arg(&self, target_position: Position) -> (f64, f64) {
         let target_order_size = target_position.size - self.position.size;
-        let price = if target_order_size > 0. {
-            self.bbo.bid_price + self.price_offset
-        } else {
-            self.bbo.ask_price - self.price_offset
+        let price = match (target_order_size > 0., self.is_passive_only) {
+            (true, false) => self.bbo.best_bid.price + self.price_offset,
+            (true, true) => self.bbo.best_bid.price + self.price_offset.min(0.),
+            (false, false) => self.bbo.best_ask.price - self.price_offset,
+            (false, true) => self.bbo.best_ask.price - self.price_offset.min(0.),
         };
         let price = round_f64(price, self.price_digits);
         (target_order_size, price)
@@ -183,20 +438,39 @@ impl NaiveLimitExecutor {
 impl Executor<Bbo> for NaiveLimitExecutor {
     fn update(&mut self, broker_event: &BrokerEvent<Bbo>) {
         match broker_event {
-            BrokerEvent::Data(bbo) => self.bbo = *bbo,
+            BrokerEvent::Data(bbo) => {
+                self.bbo = *bbo;
+                self.clock.advance(bbo.ts);
+            }
             BrokerEvent::Fill(fill) => {
-                self.placed_order = self.placed_order.and_then(|order| order.fill(fill));
+                // 撤单重下时，旧挂单在撤单生效前仍可能成交，只计入持仓
+                if let Some(order) = self.placed_order
+                    && order.order_id == fill.order_id
+                {
+                    self.placed_order = order.fill(fill);
+                    // 新挂单越过盘口时可能直接成交而没有下单回报，成交即视为已确认
+                    self.is_replacement_pending = false;
+                }
+                self.placed_order_ts = self.clock.now();
+                self.last_fill_ts = Some(self.clock.now());
                 self.position.update(fill);
             }
-            BrokerEvent::Placed(Order::Limit(order)) => self.placed_order = Some(*order),
-            BrokerEvent::Amended(Order::Limit(order)) => self.placed_order = Some(*order),
-            BrokerEvent::Canceled(order_id) => {
-                if let Some(order) = self.placed_order {
-                    if order.order_id == *order_id {
-                        self.placed_order = None
-                    }
+            BrokerEvent::Placed(Order::Limit(order))
+            | BrokerEvent::Amended(Order::Limit(order)) => {
+                self.placed_order = Some(*order);
+                self.placed_order_ts = self.clock.now();
+                self.is_replacement_pending = false;
+            }
+            BrokerEvent::Canceled(order_id) | BrokerEvent::Rejected { order_id, .. } => {
+                if let Some(order) = self.placed_order
+                    && order.order_id == *order_id
+                {
+                    self.placed_order = None;
+                    self.is_replacement_pending = false;
                 }
             }
+            // 挂单的核对见reconcile_orders
+            BrokerEvent::Connection(_) | BrokerEvent::OpenOrders(_) => {}
             _ => unreachable!(),
         }
     }
@@ -207,35 +481,187 @@ impl Executor<Bbo> for NaiveLimitExecutor {
         //     return vec![];
         // }
 
-        if self.bbo.ts - self.last_event_ts < self.event_interval {
+        let now = self.clock.now();
+        if now - self.last_event_ts < self.event_interval {
             return vec![];
         }
 
-        // 根据信号，获取目标仓位
-        let ideal_position: Position = self.get_ideal_position(signal);
+        // 挂单超时未成交，则撤单。撤单确认后再按信号重新挂单
+        if self.is_placed_order_expired() {
+            let order_id = self.placed_order.unwrap().order_id;
+            self.last_event_ts = now;
+            return vec![ClientEvent::CancelOrder(self.instrument_id, order_id)];
+        }
+
+        // 根据信号，获取目标仓位，并限制在持仓上限之内
+        let ideal_position: Position = self.clamp_position(self.get_ideal_position(signal));
         // 根据目标仓位，获取目标挂单
         let (ideal_order_size, price) = self.calc_target_order_arg(ideal_position);
+        // 成交后的冷却期内，或挂单会越过盘口时，只撤单
+        let is_crossing = self.is_passive_only && self.is_crossing(ideal_order_size, price);
+        let is_cancel_only = self.is_cooling_down() || is_crossing;
         // 根据目标挂单，获取操作
-        let events = self.get_event_from_target_order(ideal_order_size, price);
+        let events = self.get_event_from_target_order(ideal_order_size, price, is_cancel_only);
 
         // 更新signal相关状态
         self.last_signal = signal;
         if signal.is_some() {
-            self.last_signal_ts = self.bbo.ts
+            self.last_signal_ts = now
         }
 
         if !events.is_empty() {
-            self.last_event_ts = self.bbo.ts
+            self.last_event_ts = now
         }
 
         events
     }
+
+    fn checkpoint(&self) -> Option<Value> {
+        let checkpoint = NaiveLimitCheckpoint {
+            position: self.position,
+            placed_order: self.placed_order,
+            placed_order_ts: self.placed_order_ts,
+            last_signal: self.last_signal,
+            last_signal_ts: self.last_signal_ts,
+            next_order_id_body: self.next_order_id_body,
+            last_fill_ts: self.last_fill_ts,
+            is_replacement_pending: self.is_replacement_pending,
+        };
+        serde_json::to_value(checkpoint).ok()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        let checkpoint: NaiveLimitCheckpoint = serde_json::from_value(checkpoint)?;
+        self.position = checkpoint.position;
+        self.placed_order = checkpoint.placed_order;
+        self.placed_order_ts = checkpoint.placed_order_ts;
+        self.last_signal = checkpoint.last_signal;
+        self.last_signal_ts = checkpoint.last_signal_ts;
+        self.next_order_id_body = checkpoint.next_order_id_body;
+        self.last_fill_ts = checkpoint.last_fill_ts;
+        self.is_replacement_pending = checkpoint.is_replacement_pending;
+        Ok(())
+    }
+
+    fn reconcile_positions(&mut self, positions: &FxHashMap<InstId, f64>) {
+        let exchange_size = positions
+            .get(&self.instrument_id)
+            .copied()
+            .unwrap_or_default();
+        if !approx_eq!(
+            f64,
+            self.position.size(),
+            exchange_size,
+            epsilon = self.size_eps
+        ) {
+            tracing::warn!(
+                "Position of {:?} drifted: recorded {}, exchange {exchange_size}",
+                self.instrument_id,
+                self.position.size()
+            );
+        }
+        self.position = Position::new(exchange_size);
+    }
+
+    fn reconcile_orders(&mut self, open_orders: &[LimitOrder]) -> Vec<ClientEvent> {
+        let candidates = open_orders
+            .iter()
+            .filter(|order| {
+                order.instrument_id == self.instrument_id
+                    && is_own_order(order.order_id, self.order_id_offset)
+            })
+            .copied()
+            .collect();
+        let old_order_id = self.placed_order.map(|order| order.order_id);
+        let extra_orders = reconcile_order_slot(&mut self.placed_order, candidates);
+        if self.placed_order.map(|order| order.order_id) != old_order_id {
+            self.placed_order_ts = self.clock.now();
+        }
+        extra_orders
+            .into_iter()
+            .map(|order| ClientEvent::CancelOrder(self.instrument_id, order.order_id))
+            .collect()
+    }
+}
+
+/// 各策略共用的下单执行参数。配置文件中的时长以秒为单位
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ExecutorArgs {
+    /// 信号消失后的持仓时间
+    #[serde(deserialize_with = "deserialize_secs")]
+    pub holding_duration: Duration,
+    #[serde(deserialize_with = "deserialize_secs")]
+    pub event_interval: Duration,
+
+    pub notional: f64,
+    pub price_offset: f64,
+    #[serde(default)]
+    pub re_peg_mode: RePegMode,
+    #[serde(default)]
+    pub order_update_mode: OrderUpdateMode,
+    /// 挂单无进展的最长时间，为0时不因超时撤单
+    #[serde(default, deserialize_with = "deserialize_secs")]
+    pub order_ttl: Duration,
+    /// 成交后只撤单、不下单的冷却时间，缺省为0
+    #[serde(default, deserialize_with = "deserialize_secs")]
+    pub post_fill_cooldown: Duration,
+    /// 持仓名义金额的上限，缺省时不设上限
+    #[serde(default)]
+    pub max_position_notional: Option<f64>,
+    /// 挂单离目标价格超过该tick数时改单，缺省时不因距离改单
+    #[serde(default)]
+    pub max_distance_ticks: Option<u32>,
+    /// 挂单价格不越过最优价，只以maker成交
+    #[serde(default)]
+    pub passive_only: bool,
+    /// 策略实例的全局唯一标识符，小于2^16
+    pub order_id_offset: u64,
+}
+
+impl ExecutorArgs {
+    /// 按产品的下单精度创建执行器
+    pub fn build_executor(&self, instrument_id: InstId) -> NaiveLimitExecutor {
+        let profile = INSTRUMENT_PROFILES.get(&instrument_id).unwrap();
+        let executor = NaiveLimitExecutor::new(
+            instrument_id,
+            self.notional,
+            profile.size_digits,
+            profile.price_digits,
+            self.price_offset,
+            self.holding_duration,
+            self.event_interval,
+            self.order_id_offset,
+        )
+        .with_min_size(profile.min_size)
+        .with_re_peg_mode(self.re_peg_mode)
+        .with_order_ttl(self.order_ttl)
+        .with_post_fill_cooldown(self.post_fill_cooldown)
+        .with_order_update_mode(self.order_update_mode);
+        let executor = match self.max_position_notional {
+            Some(max_position_notional) => {
+                executor.with_max_position_notional(max_position_notional)
+            }
+            None => executor,
+        };
+        let executor = match self.max_distance_ticks {
+            Some(max_distance_ticks) => executor.with_max_distance_ticks(max_distance_ticks),
+            None => executor,
+        };
+        if self.passive_only {
+            executor.with_passive_only()
+        } else {
+            executor
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{BrokerEvent, ClientEvent, ExecType, Fill, FillState, Order};
+    use crate::{
+        BrokerEvent, ClientEvent, ExecType, Fill, FillState, Order, RejectReason, clock::DataClock,
+        data::Level,
+    };
 
     fn create_test_executor() -> NaiveLimitExecutor {
         NaiveLimitExecutor::new(
@@ -250,14 +676,20 @@ mod tests {
         )
     }
 
-    fn create_test_bbo(ts: u64, bid_price: f64, ask_price: f64) -> Bbo {
+    fn create_test_bbo(ts: i64, bid_price: f64, ask_price: f64) -> Bbo {
         Bbo {
-            ts,
+            ts: ts.into(),
             instrument_id: InstId::EthUsdtSwap,
-            bid_price,
-            bid_size: 10.0,
-            ask_price,
-            ask_size: 10.0,
+            best_bid: Level {
+                price: bid_price,
+                size: 10.0,
+                order_count: 1,
+            },
+            best_ask: Level {
+                price: ask_price,
+                size: 10.0,
+                order_count: 1,
+            },
         }
     }
 
@@ -271,6 +703,45 @@ mod tests {
         assert_eq!(executor.order_id_offset, 123);
     }
 
+    #[test]
+    #[should_panic(expected = "order_id_offset")]
+    fn test_order_id_offset_out_of_range() {
+        NaiveLimitExecutor::new(
+            InstId::EthUsdtSwap,
+            1000.0,
+            2,
+            2,
+            0.,
+            Duration::milliseconds(10000),
+            Duration::seconds(0),
+            1 << 16,
+        );
+    }
+
+    #[test]
+    fn test_order_id_unique() {
+        let mut executor = create_test_executor();
+        let mut other_executor = create_test_executor();
+        other_executor.order_id_offset = 124;
+
+        let ids: Vec<_> = (0..100)
+            .flat_map(|_| {
+                [
+                    executor.get_next_order_id(),
+                    other_executor.get_next_order_id(),
+                ]
+            })
+            .collect();
+        let unique_ids: std::collections::HashSet<_> = ids.iter().collect();
+        assert_eq!(unique_ids.len(), ids.len());
+
+        // 序号用尽时从0重新开始，offset的位保持不变
+        executor.next_order_id_body = (1 << 48) - 1;
+        assert_eq!(executor.get_next_order_id(), u64::MAX << 16 | 123);
+        assert_eq!(executor.get_next_order_id(), 123);
+        assert_eq!(executor.get_next_order_id(), 1 << 16 | 123);
+    }
+
     #[test]
     fn test_long_signal() {
         let mut executor = create_test_executor();
@@ -440,6 +911,387 @@ mod tests {
         assert!(matches!(events[0], ClientEvent::AmendOrder(_)));
     }
 
+    /// 发出Long信号并确认挂单，返回挂单价
+    fn place_long_order(executor: &mut NaiveLimitExecutor) -> f64 {
+        let events = executor.on_signal(Some(Signal::Long));
+        let ClientEvent::PlaceOrder(Order::Limit(order)) = &events[0] else {
+            panic!("Expected PlaceOrder event");
+        };
+        executor.update(&BrokerEvent::Placed(Order::Limit(*order)));
+        order.price
+    }
+
+    #[test]
+    fn test_hold_original_price() {
+        let mut executor = create_test_executor();
+        executor.update(&BrokerEvent::Data(create_test_bbo(1000, 100.0, 101.0)));
+        assert_eq!(place_long_order(&mut executor), 100.0);
+
+        // 最优买价上移，但规模不变（1000 / 100.01 截断后仍为9.99），维持原挂单
+        executor.update(&BrokerEvent::Data(create_test_bbo(2000, 100.01, 101.0)));
+        let events = executor.on_signal(Some(Signal::Long));
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_chase_touch() {
+        let mut executor = create_test_executor().with_re_peg_mode(RePegMode::ChaseTouch);
+        executor.price_offset = 0.01;
+        executor.update(&BrokerEvent::Data(create_test_bbo(1000, 100.0, 101.0)));
+        assert_eq!(place_long_order(&mut executor), 100.01);
+
+        // BBO多次变动，每次都改单到 最优买价 + price_offset
+        for (ts, bid_price) in [(2000, 100.05), (3000, 100.1), (4000, 100.08)] {
+            executor.update(&BrokerEvent::Data(create_test_bbo(ts, bid_price, 101.0)));
+            let events = executor.on_signal(Some(Signal::Long));
+            assert_eq!(events.len(), 1);
+            let ClientEvent::AmendOrder(amended) = &events[0] else {
+                panic!("Expected AmendOrder event");
+            };
+            assert_eq!(amended.new_price, round_f64(bid_price + 0.01, 2));
+            executor.update(&BrokerEvent::Amended(Order::Limit(LimitOrder {
+                price: amended.new_price,
+                size: amended.new_size,
+                ..executor.placed_order.unwrap()
+            })));
+        }
+
+        // BBO不变时不再改单
+        executor.update(&BrokerEvent::Data(create_test_bbo(5000, 100.08, 101.0)));
+        assert!(executor.on_signal(Some(Signal::Long)).is_empty());
+    }
+
+    #[test]
+    fn test_passive_only() {
+        let place = |executor: NaiveLimitExecutor, signal| {
+            let mut executor = executor;
+            executor.price_offset = 0.5;
+            executor.update(&BrokerEvent::Data(create_test_bbo(1000, 100.0, 101.0)));
+            match &executor.on_signal(Some(signal))[..] {
+                [ClientEvent::PlaceOrder(Order::Limit(order))] => order.price,
+                events => panic!("Expected PlaceOrder event, got {events:?}"),
+            }
+        };
+        // 正的price_offset会使挂单越过最优价，限制在最优价上
+        assert_eq!(place(create_test_executor(), Signal::Long), 100.5);
+        assert_eq!(
+            place(create_test_executor().with_passive_only(), Signal::Long),
+            100.0
+        );
+        assert_eq!(place(create_test_executor(), Signal::Short), 100.5);
+        assert_eq!(
+            place(create_test_executor().with_passive_only(), Signal::Short),
+            101.0
+        );
+
+        // 负的price_offset本就不越过最优价，不作调整
+        let mut executor = create_test_executor().with_passive_only();
+        executor.price_offset = -0.2;
+        executor.update(&BrokerEvent::Data(create_test_bbo(1000, 100.0, 101.0)));
+        assert_eq!(place_long_order(&mut executor), 99.8);
+
+        // 盘口交叉时挂在最优价也会立即成交，只撤单不下单
+        executor.update(&BrokerEvent::Data(create_test_bbo(2000, 100.5, 100.3)));
+        let events = executor.on_signal(Some(Signal::Short));
+        let [ClientEvent::CancelOrder(..)] = events[..] else {
+            panic!("Expected CancelOrder event, got {events:?}");
+        };
+        executor.update(&BrokerEvent::Canceled(
+            executor.placed_order.unwrap().order_id,
+        ));
+        assert!(executor.on_signal(Some(Signal::Short)).is_empty());
+    }
+
+    #[test]
+    fn test_max_distance_ticks() {
+        let mut executor = create_test_executor().with_max_distance_ticks(100);
+        executor.price_offset = -0.5;
+        executor.update(&BrokerEvent::Data(create_test_bbo(1000, 100.0, 101.0)));
+        assert_eq!(place_long_order(&mut executor), 99.5);
+
+        // 最优买价上移80个tick，规模改变但维持原挂单价格
+        executor.update(&BrokerEvent::Data(create_test_bbo(2000, 100.8, 101.8)));
+        let events = executor.on_signal(Some(Signal::Long));
+        let [ClientEvent::AmendOrder(amended)] = &events[..] else {
+            panic!("Expected AmendOrder event, got {events:?}");
+        };
+        assert_eq!(amended.new_price, 99.5);
+        assert_eq!(amended.new_size, 9.92);
+        executor.update(&BrokerEvent::Amended(Order::Limit(LimitOrder {
+            size: amended.new_size,
+            ..executor.placed_order.unwrap()
+        })));
+        executor.update(&BrokerEvent::Data(create_test_bbo(3000, 100.8, 101.8)));
+        assert!(executor.on_signal(Some(Signal::Long)).is_empty());
+
+        // 价格大幅远离挂单，超过100个tick后改单到 最优买价 + price_offset
+        executor.update(&BrokerEvent::Data(create_test_bbo(4000, 103.0, 104.0)));
+        let events = executor.on_signal(Some(Signal::Long));
+        let [ClientEvent::AmendOrder(amended)] = &events[..] else {
+            panic!("Expected AmendOrder event, got {events:?}");
+        };
+        assert_eq!(amended.new_price, 102.5);
+        assert_eq!(amended.new_size, 9.7);
+    }
+
+    #[test]
+    fn test_cancel_replace() {
+        let mut executor =
+            create_test_executor().with_order_update_mode(OrderUpdateMode::CancelReplace);
+        executor.update(&BrokerEvent::Data(create_test_bbo(1000, 100.0, 101.0)));
+        let events = executor.on_signal(Some(Signal::Long));
+        let ClientEvent::PlaceOrder(Order::Limit(old_order)) = events[0] else {
+            panic!("Expected PlaceOrder event");
+        };
+        executor.update(&BrokerEvent::Placed(Order::Limit(old_order)));
+
+        // 规模改变时，先撤单再以新的规模下单，而不是改单
+        executor.update(&BrokerEvent::Data(create_test_bbo(2000, 102.0, 103.0)));
+        let events = executor.on_signal(Some(Signal::Long));
+        let [
+            ClientEvent::CancelOrder(_, canceled_id),
+            ClientEvent::PlaceOrder(Order::Limit(new_order)),
+        ] = &events[..]
+        else {
+            panic!("Expected CancelOrder and PlaceOrder events, got {events:?}");
+        };
+        assert_eq!(*canceled_id, old_order.order_id);
+        assert_ne!(new_order.order_id, old_order.order_id);
+        assert!(new_order.side);
+        assert_eq!(new_order.size, 9.8);
+        assert_eq!(new_order.price, old_order.price);
+        assert_eq!(executor.placed_order.unwrap().order_id, new_order.order_id);
+
+        // 新挂单确认前，即使目标规模再次改变也不重复撤单重下
+        executor.update(&BrokerEvent::Data(create_test_bbo(3000, 104.0, 105.0)));
+        assert!(executor.on_signal(Some(Signal::Long)).is_empty());
+
+        // 新挂单确认后，旧挂单的撤单回报不影响新挂单
+        executor.update(&BrokerEvent::Placed(Order::Limit(*new_order)));
+        executor.update(&BrokerEvent::Canceled(old_order.order_id));
+        assert_eq!(executor.placed_order.unwrap().order_id, new_order.order_id);
+        // 确认后按最新的目标规模继续撤单重下
+        assert_eq!(executor.on_signal(Some(Signal::Long)).len(), 2);
+    }
+
+    #[test]
+    fn test_cancel_replace_filled_immediately() {
+        let mut executor =
+            create_test_executor().with_order_update_mode(OrderUpdateMode::CancelReplace);
+        executor.update(&BrokerEvent::Data(create_test_bbo(1000, 100.0, 101.0)));
+        let events = executor.on_signal(Some(Signal::Long));
+        let ClientEvent::PlaceOrder(Order::Limit(old_order)) = events[0] else {
+            panic!("Expected PlaceOrder event");
+        };
+        executor.update(&BrokerEvent::Placed(Order::Limit(old_order)));
+
+        executor.update(&BrokerEvent::Data(create_test_bbo(2000, 102.0, 103.0)));
+        let events = executor.on_signal(Some(Signal::Long));
+        let [_, ClientEvent::PlaceOrder(Order::Limit(new_order))] = &events[..] else {
+            panic!("Expected CancelOrder and PlaceOrder events, got {events:?}");
+        };
+
+        // 新挂单越过盘口立即全部成交，没有下单回报
+        executor.update(&BrokerEvent::Fill(Fill {
+            order_id: new_order.order_id,
+            instrument_id: InstId::EthUsdtSwap,
+            filled_size: new_order.size,
+            acc_filled_size: new_order.size,
+            price: new_order.price,
+            side: true,
+            exec_type: ExecType::Taker,
+            state: FillState::Filled,
+        }));
+        executor.update(&BrokerEvent::Canceled(old_order.order_id));
+        assert!(executor.placed_order.is_none());
+        assert!(!executor.is_replacement_pending);
+
+        // 之后仍按信号继续下单
+        executor.update(&BrokerEvent::Data(create_test_bbo(3000, 104.0, 105.0)));
+        let events = executor.on_signal(Some(Signal::Short));
+        assert!(
+            matches!(&events[..], [ClientEvent::PlaceOrder(Order::Limit(order))] if !order.side),
+            "Expected a sell order, got {events:?}"
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_restores_cooldown_and_pending_replacement() {
+        let mut executor = create_test_executor()
+            .with_order_update_mode(OrderUpdateMode::CancelReplace)
+            .with_post_fill_cooldown(Duration::seconds(10));
+        executor.last_fill_ts = Some(Timestamp::from_millis(1000));
+        executor.is_replacement_pending = true;
+        let checkpoint = executor.checkpoint().unwrap();
+
+        let mut restored = create_test_executor()
+            .with_order_update_mode(OrderUpdateMode::CancelReplace)
+            .with_post_fill_cooldown(Duration::seconds(10));
+        restored.restore(checkpoint).unwrap();
+        assert_eq!(restored.last_fill_ts, Some(Timestamp::from_millis(1000)));
+        assert!(restored.is_replacement_pending);
+        restored.update(&BrokerEvent::Data(create_test_bbo(2000, 100.0, 101.0)));
+        assert!(restored.is_cooling_down());
+    }
+
+    #[test]
+    fn test_max_position_notional() {
+        let mut executor = create_test_executor().with_max_position_notional(500.);
+
+        // 价格不断下跌，重复的多头信号会使目标规模不断增大，但持仓停在上限
+        for (i, bid_price) in [100.0, 80.0, 60.0, 50.0].into_iter().enumerate() {
+            let ts = 1000 * (i as i64 + 1);
+            executor.update(&BrokerEvent::Data(create_test_bbo(
+                ts,
+                bid_price,
+                bid_price + 1.,
+            )));
+            let events = executor.on_signal(Some(Signal::Long));
+            let target_size = truncate_f64(500. / bid_price, 2);
+            match &events[..] {
+                [ClientEvent::PlaceOrder(Order::Limit(order))] => {
+                    assert_eq!(
+                        order.size,
+                        round_f64(target_size - executor.position.size, 2)
+                    );
+                    // 挂单全部成交
+                    executor.update(&BrokerEvent::Fill(Fill {
+                        order_id: order.order_id,
+                        instrument_id: InstId::EthUsdtSwap,
+                        filled_size: order.size,
+                        acc_filled_size: order.size,
+                        price: order.price,
+                        side: true,
+                        exec_type: ExecType::Maker,
+                        state: FillState::Filled,
+                    }));
+                }
+                _ => panic!("Expected PlaceOrder event, got {events:?}"),
+            }
+            assert_eq!(round_f64(executor.position.size, 2), target_size);
+            assert!(executor.position.size * bid_price <= 500.);
+        }
+
+        // 价格不变时，重复信号不再加仓
+        executor.update(&BrokerEvent::Data(create_test_bbo(5000, 50.0, 51.0)));
+        assert!(executor.on_signal(Some(Signal::Long)).is_empty());
+    }
+
+    #[test]
+    fn test_max_position_notional_short() {
+        let mut executor = create_test_executor().with_max_position_notional(500.);
+        executor.update(&BrokerEvent::Data(create_test_bbo(1000, 99.0, 100.0)));
+        let events = executor.on_signal(Some(Signal::Short));
+        let [ClientEvent::PlaceOrder(Order::Limit(order))] = &events[..] else {
+            panic!("Expected PlaceOrder event");
+        };
+        assert!(!order.side);
+        assert_eq!(order.size, 5.0);
+    }
+
+    #[test]
+    fn test_order_ttl() {
+        let mut executor = create_test_executor().with_order_ttl(Duration::seconds(5));
+        executor.update(&BrokerEvent::Data(create_test_bbo(1000, 100.0, 101.0)));
+        place_long_order(&mut executor);
+        let order_id = executor.placed_order.unwrap().order_id;
+
+        // 4秒后仍未超时
+        executor.update(&BrokerEvent::Data(create_test_bbo(5000, 100.0, 101.0)));
+        assert!(executor.on_signal(Some(Signal::Long)).is_empty());
+
+        // 6秒后未成交，撤单
+        executor.update(&BrokerEvent::Data(create_test_bbo(7000, 100.0, 101.0)));
+        let events = executor.on_signal(Some(Signal::Long));
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            events[0],
+            ClientEvent::CancelOrder(InstId::EthUsdtSwap, id) if id == order_id
+        ));
+
+        // 撤单确认后重新挂单
+        executor.update(&BrokerEvent::Canceled(order_id));
+        executor.update(&BrokerEvent::Data(create_test_bbo(8000, 100.0, 101.0)));
+        let events = executor.on_signal(Some(Signal::Long));
+        assert!(matches!(events[0], ClientEvent::PlaceOrder(_)));
+    }
+
+    #[test]
+    fn test_order_ttl_with_data_clock() {
+        let mut executor = create_test_executor().with_order_ttl(Duration::seconds(5));
+        let mut clocked_executor = create_test_executor()
+            .with_order_ttl(Duration::seconds(5))
+            .with_clock(DataClock::new(Timestamp::from_millis(0)));
+        for executor in [&mut executor, &mut clocked_executor] {
+            executor.update(&BrokerEvent::Data(create_test_bbo(1000, 100.0, 101.0)));
+            place_long_order(executor);
+        }
+
+        // 与以行情时间戳计算的结果一致：挂单满5秒后撤单，撤单确认前持续撤单
+        for ts in (2000..=8000).step_by(500) {
+            let bbo = create_test_bbo(ts, 100.0, 101.0);
+            let events: Vec<_> = [&mut executor, &mut clocked_executor]
+                .map(|executor| {
+                    executor.update(&BrokerEvent::Data(bbo));
+                    format!("{:?}", executor.on_signal(Some(Signal::Long)))
+                })
+                .into();
+            assert_eq!(events[0], events[1]);
+            assert_eq!(events[0].contains("CancelOrder"), ts >= 6000);
+        }
+    }
+
+    #[test]
+    fn test_post_fill_cooldown() {
+        let mut executor = create_test_executor().with_post_fill_cooldown(Duration::seconds(5));
+        let fill = |order: &LimitOrder, filled_size: f64, state: FillState| {
+            BrokerEvent::Fill(Fill {
+                order_id: order.order_id,
+                instrument_id: InstId::EthUsdtSwap,
+                filled_size,
+                acc_filled_size: filled_size,
+                price: order.price,
+                side: order.side,
+                exec_type: ExecType::Maker,
+                state,
+            })
+        };
+
+        // 买单部分成交
+        executor.update(&BrokerEvent::Data(create_test_bbo(1000, 100.0, 101.0)));
+        place_long_order(&mut executor);
+        let buy_order = executor.placed_order.unwrap();
+        executor.update(&fill(&buy_order, 4.0, FillState::Partially));
+
+        // 冷却期内信号反转，只撤单，不反向挂单
+        executor.update(&BrokerEvent::Data(create_test_bbo(2000, 100.0, 101.0)));
+        let events = executor.on_signal(Some(Signal::Short));
+        let [ClientEvent::CancelOrder(_, order_id)] = events[..] else {
+            panic!("Expected CancelOrder event, got {events:?}");
+        };
+        assert_eq!(order_id, buy_order.order_id);
+        executor.update(&BrokerEvent::Canceled(order_id));
+        executor.update(&BrokerEvent::Data(create_test_bbo(5500, 100.0, 101.0)));
+        assert!(executor.on_signal(Some(Signal::Short)).is_empty());
+
+        // 冷却结束后挂卖单，卖单全部成交
+        executor.update(&BrokerEvent::Data(create_test_bbo(6000, 100.0, 101.0)));
+        let events = executor.on_signal(Some(Signal::Short));
+        let [ClientEvent::PlaceOrder(Order::Limit(sell_order))] = events[..] else {
+            panic!("Expected PlaceOrder event, got {events:?}");
+        };
+        assert_eq!(sell_order.size, 13.9);
+        executor.update(&BrokerEvent::Placed(Order::Limit(sell_order)));
+        executor.update(&fill(&sell_order, 13.9, FillState::Filled));
+
+        // 第二次成交重新开始冷却
+        executor.update(&BrokerEvent::Data(create_test_bbo(7000, 100.0, 101.0)));
+        assert!(executor.on_signal(Some(Signal::Long)).is_empty());
+        executor.update(&BrokerEvent::Data(create_test_bbo(11000, 100.0, 101.0)));
+        let events = executor.on_signal(Some(Signal::Long));
+        assert!(matches!(events[..], [ClientEvent::PlaceOrder(_)]));
+    }
+
     #[test]
     fn test_position_timeout() {
         let mut executor = create_test_executor();
@@ -493,6 +1345,93 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dust_position_is_clear() {
+        let mut executor = create_test_executor().with_min_size(0.1);
+        assert_eq!(executor.size_eps, 0.1);
+        // 最小下单量小于size精度时不放宽
+        assert_eq!(create_test_executor().with_min_size(0.001).size_eps, 0.01);
+
+        let bbo = create_test_bbo(1000, 100.0, 101.0);
+        executor.update(&BrokerEvent::Data(bbo));
+        let fill = Fill {
+            order_id: 1,
+            instrument_id: InstId::EthUsdtSwap,
+            filled_size: 0.05,
+            acc_filled_size: 0.05,
+            price: 100.0,
+            side: true,
+            exec_type: ExecType::Maker,
+            state: FillState::Filled,
+        };
+        executor.update(&BrokerEvent::Fill(fill));
+
+        // 低于最小下单量的残余仓位视为空仓，超过持仓时限后也不尝试平仓
+        let bbo = create_test_bbo(20000, 100.0, 101.0);
+        executor.update(&BrokerEvent::Data(bbo));
+        assert_eq!(executor.get_ideal_position(None), executor.position);
+        assert!(executor.on_signal(None).is_empty());
+    }
+
+    #[test]
+    fn test_rejection_recovery() {
+        let mut executor = create_test_executor();
+
+        let bbo = create_test_bbo(1000, 100.0, 101.0);
+        executor.update(&BrokerEvent::Data(bbo));
+
+        let events = executor.on_signal(Some(Signal::Long));
+        let order = match &events[0] {
+            ClientEvent::PlaceOrder(Order::Limit(order)) => *order,
+            _ => panic!("Expected PlaceOrder event"),
+        };
+        executor.update(&BrokerEvent::Placed(Order::Limit(order)));
+        assert!(executor.placed_order.is_some());
+
+        // 订单被拒绝后清除挂单
+        executor.update(&BrokerEvent::Rejected {
+            order_id: order.order_id,
+            reason: RejectReason::InsufficientCash,
+        });
+        assert!(executor.placed_order.is_none());
+
+        // 下一个信号重新下单
+        let bbo = create_test_bbo(2000, 100.0, 101.0);
+        executor.update(&BrokerEvent::Data(bbo));
+        let events = executor.on_signal(Some(Signal::Long));
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            ClientEvent::PlaceOrder(Order::Limit(new_order)) => {
+                assert!(new_order.side);
+                assert_eq!(new_order.size, 10.0);
+                assert_ne!(new_order.order_id, order.order_id);
+            }
+            _ => panic!("Expected PlaceOrder event"),
+        }
+    }
+
+    #[test]
+    fn test_rejection_of_other_order() {
+        let mut executor = create_test_executor();
+
+        let bbo = create_test_bbo(1000, 100.0, 101.0);
+        executor.update(&BrokerEvent::Data(bbo));
+
+        let events = executor.on_signal(Some(Signal::Long));
+        let order = match &events[0] {
+            ClientEvent::PlaceOrder(Order::Limit(order)) => *order,
+            _ => panic!("Expected PlaceOrder event"),
+        };
+        executor.update(&BrokerEvent::Placed(Order::Limit(order)));
+
+        // 其他订单被拒绝，不影响现有挂单
+        executor.update(&BrokerEvent::Rejected {
+            order_id: order.order_id + 1,
+            reason: RejectReason::Other("unknown".into()),
+        });
+        assert_eq!(executor.placed_order.unwrap().order_id, order.order_id);
+    }
+
     #[test]
     fn test_complex_scenario() {
         let mut executor = create_test_executor();
@@ -1 +1,4 @@
+pub mod ema_crossover;
+pub mod market_maker;
 pub mod ofi_momentum;
+pub mod order_book_imbalance;
@@ -0,0 +1,247 @@
+use anyhow::Result;
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    InstId, Timestamp,
+    clock::WallClock,
+    data::Bbo,
+    strategy::{
+        Signal, SignalExecuteStrategy, Signaler, Strategy,
+        calc::Ema,
+        config::deserialize_secs,
+        executors::{ExecutorArgs, NaiveLimitExecutor},
+    },
+};
+
+/// 快慢EMA交叉，作为检验回测与执行的基线策略。以unbiased price计算两条EMA。
+///
+/// 相对差 $d_t = (fast - slow) / slow$。为避免在两线附近反复切换，带有滞回：
+/// - 多头：$d_t > h$，即快线上穿慢线
+/// - 空头：$d_t < -h$，即快线下穿慢线
+/// - $|d_t| \le h$ 时保持原来的方向
+///
+/// 预热期后持续发出当前的方向，尚未发生过交叉时不发出信号。
+pub struct EmaCrossover {
+    /// 慢线的时间常数，同时作为预热期的长度
+    slow_window: i64,
+    /// 切换方向所需的相对差
+    hysteresis: f64,
+
+    first_ts: Option<Timestamp>,
+    last_ts: Timestamp,
+    fast: Ema,
+    slow: Ema,
+    /// 最近一次交叉后的方向
+    trend: Option<Signal>,
+}
+
+impl EmaCrossover {
+    pub fn new(fast_window: Duration, slow_window: Duration, hysteresis: f64) -> Self {
+        assert!(
+            fast_window < slow_window,
+            "fast_window must be shorter than slow_window"
+        );
+        let slow_window = slow_window.num_milliseconds();
+        Self {
+            slow_window,
+            hysteresis,
+            first_ts: None,
+            last_ts: Timestamp::default(),
+            fast: Ema::new(fast_window.num_milliseconds() as f64),
+            slow: Ema::new(slow_window as f64),
+            trend: None,
+        }
+    }
+
+    /// 快线相对慢线的偏离
+    fn spread(fast: f64, slow: f64) -> f64 {
+        (fast - slow) / slow
+    }
+}
+
+/// 预热进度与指标的状态
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    first_ts: Option<Timestamp>,
+    last_ts: Timestamp,
+    fast: &'a Ema,
+    slow: &'a Ema,
+    trend: Option<Signal>,
+}
+
+#[derive(Deserialize)]
+struct Checkpoint {
+    first_ts: Option<Timestamp>,
+    last_ts: Timestamp,
+    fast: Ema,
+    slow: Ema,
+    trend: Option<Signal>,
+}
+
+impl Signaler<Bbo> for EmaCrossover {
+    #[inline]
+    fn on_data(&mut self, bbo: &Bbo) -> Option<Signal> {
+        let first_ts = *self.first_ts.get_or_insert(bbo.ts);
+        let dt = (bbo.ts - self.last_ts).max(0) as f64;
+        self.last_ts = bbo.ts;
+        let price = bbo.get_unbiased_price();
+        let fast = self.fast.update(price, dt);
+        let slow = self.slow.update(price, dt);
+
+        let spread = Self::spread(fast, slow);
+        if spread > self.hysteresis {
+            self.trend = Some(Signal::Long);
+        } else if spread < -self.hysteresis {
+            self.trend = Some(Signal::Short);
+        }
+
+        if bbo.ts - first_ts <= self.slow_window {
+            return None;
+        }
+        self.trend
+    }
+
+    fn checkpoint(&self) -> Option<Value> {
+        let checkpoint = CheckpointRef {
+            first_ts: self.first_ts,
+            last_ts: self.last_ts,
+            fast: &self.fast,
+            slow: &self.slow,
+            trend: self.trend,
+        };
+        serde_json::to_value(checkpoint).ok()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        let checkpoint: Checkpoint = serde_json::from_value(checkpoint)?;
+        self.first_ts = checkpoint.first_ts;
+        self.last_ts = checkpoint.last_ts;
+        self.fast = checkpoint.fast;
+        self.slow = checkpoint.slow;
+        self.trend = checkpoint.trend;
+        Ok(())
+    }
+}
+
+/// 配置文件中的时长以秒为单位
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EmaCrossoverArgs {
+    pub instrument_id: InstId,
+    #[serde(deserialize_with = "deserialize_secs")]
+    pub fast_window: Duration,
+    #[serde(deserialize_with = "deserialize_secs")]
+    pub slow_window: Duration,
+    /// 切换方向所需的快慢线相对差
+    #[serde(default)]
+    pub hysteresis: f64,
+    /// 下单执行的参数，在配置文件中与策略参数位于同一层
+    #[serde(flatten)]
+    pub executor: ExecutorArgs,
+}
+
+impl EmaCrossoverArgs {
+    pub fn into_strategy(self) -> impl Strategy<Bbo> {
+        let (signaler, executor) = self.into_parts();
+        SignalExecuteStrategy::new(signaler, executor)
+    }
+
+    /// 实盘中以系统时间计算挂单存活时间、事件间隔与持仓时限
+    pub fn into_live_strategy(self) -> impl Strategy<Bbo> {
+        let (signaler, executor) = self.into_parts();
+        SignalExecuteStrategy::new(signaler, executor.with_clock(WallClock))
+    }
+
+    fn into_parts(self) -> (EmaCrossover, NaiveLimitExecutor) {
+        let signaler = EmaCrossover::new(self.fast_window, self.slow_window, self.hysteresis);
+        let executor = self.executor.build_executor(self.instrument_id);
+        (signaler, executor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Level;
+
+    fn create_bbo(ts: i64, price: f64) -> Bbo {
+        Bbo {
+            ts: ts.into(),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: Level {
+                price: price - 0.5,
+                size: 1.,
+                order_count: 1,
+            },
+            best_ask: Level {
+                price: price + 0.5,
+                size: 1.,
+                order_count: 1,
+            },
+        }
+    }
+
+    fn new_signaler(hysteresis: f64) -> EmaCrossover {
+        EmaCrossover::new(Duration::seconds(5), Duration::seconds(20), hysteresis)
+    }
+
+    #[test]
+    fn test_ema_crossover_signal() {
+        let mut signaler = new_signaler(0.001);
+
+        // 预热期内价格上涨也不发出信号
+        for i in 0..=20 {
+            assert_eq!(
+                signaler.on_data(&create_bbo(i * 1000, 100. + i as f64)),
+                None
+            );
+        }
+        // 预热期后，快线在慢线之上
+        assert_eq!(
+            signaler.on_data(&create_bbo(21_000, 121.)),
+            Some(Signal::Long)
+        );
+
+        // 价格转跌后，快线下穿慢线之前仍为多头，之后一直为空头
+        let signals: Vec<_> = (22..80)
+            .map(|i| signaler.on_data(&create_bbo(i * 1000, 121. - 2. * (i - 21) as f64)))
+            .collect();
+        let first_short = signals
+            .iter()
+            .position(|signal| *signal == Some(Signal::Short))
+            .unwrap();
+        assert!(first_short > 0);
+        assert!(
+            signals[..first_short]
+                .iter()
+                .all(|signal| *signal == Some(Signal::Long))
+        );
+        assert!(
+            signals[first_short..]
+                .iter()
+                .all(|signal| *signal == Some(Signal::Short))
+        );
+    }
+
+    #[test]
+    fn test_ema_crossover_hysteresis() {
+        // 在100附近小幅振荡的价格
+        let prices: Vec<_> = (0..200)
+            .map(|i| 100. + if (i / 3) % 2 == 0 { 0.05 } else { -0.05 })
+            .collect();
+        let count_switches = |hysteresis| {
+            let mut signaler = new_signaler(hysteresis);
+            let signals: Vec<_> = prices
+                .iter()
+                .enumerate()
+                .map(|(i, price)| signaler.on_data(&create_bbo(i as i64 * 1000, *price)))
+                .collect();
+            signals.windows(2).filter(|pair| pair[0] != pair[1]).count()
+        };
+
+        // 没有滞回时随振荡反复切换，滞回大于振荡幅度时不发出信号
+        assert!(count_switches(0.) > 10);
+        assert_eq!(count_switches(0.001), 0);
+    }
+}
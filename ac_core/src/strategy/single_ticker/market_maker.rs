@@ -0,0 +1,422 @@
+use anyhow::Result;
+use chrono::Duration;
+use data_center::instruments_profile::INSTRUMENT_PROFILES;
+use float_cmp::approx_eq;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    BrokerEvent, ClientEvent, InstId, LimitOrder, Order, OrderId, Position, Timestamp,
+    data::Bbo,
+    strategy::{
+        Strategy,
+        config::deserialize_secs,
+        executors::{ORDER_ID_OFFSET_BITS, is_own_order, reconcile_order_slot},
+    },
+    utils::{round_f64, truncate_f64},
+};
+
+/// 被动做市。在 最优买价 - offset 与 最优卖价 + offset 同时挂买卖单，BBO变动后改单跟随。
+///
+/// 根据库存调整报价：持有多头时两侧报价同时下移，卖单更接近市场、买单更远离市场，反之亦然。
+/// 偏移量为 inventory_skew * 库存名义金额 / notional。
+#[derive(Default)]
+pub struct MarketMaker {
+    instrument_id: InstId,
+    /// 每侧挂单的名义金额
+    notional: f64,
+    size_digits: i32,
+    size_eps: f64,
+    price_digits: i32,
+    /// 报价相对最优价向外的偏移量
+    offset: f64,
+    /// 库存为一份notional时，报价的偏移量
+    inventory_skew: f64,
+    /// 持仓名义金额的上限，达到后不再挂增加持仓的一侧
+    max_position_notional: Option<f64>,
+
+    bbo: Bbo,
+    last_event_ts: Timestamp,
+    /// 发出事件的最小时间间隔，避免频繁改单
+    event_interval: i64,
+
+    position: Position,
+    /// 买卖两侧的挂单。下单、改单与撤单发出后即更新，不等待交易所确认
+    bid_order: Option<LimitOrder>,
+    ask_order: Option<LimitOrder>,
+
+    next_order_id_body: u64,
+    /// 小于2^16，用于作为每个策略的Order id的末位唯一标识符
+    order_id_offset: u64,
+}
+
+impl MarketMaker {
+    pub fn new(
+        instrument_id: InstId,
+        notional: f64,
+        size_digits: i32,
+        price_digits: i32,
+        offset: f64,
+        inventory_skew: f64,
+        order_id_offset: u64,
+    ) -> Self {
+        assert!(
+            order_id_offset < 1 << ORDER_ID_OFFSET_BITS,
+            "order_id_offset must be less than 2^{ORDER_ID_OFFSET_BITS}, got {order_id_offset}"
+        );
+        Self {
+            instrument_id,
+            notional,
+            size_digits,
+            size_eps: 10f64.powi(-size_digits),
+            price_digits,
+            offset,
+            inventory_skew,
+            order_id_offset,
+            ..Default::default()
+        }
+    }
+
+    /// 设置发出事件的最小时间间隔，默认为0
+    pub fn with_event_interval(mut self, event_interval: Duration) -> Self {
+        self.event_interval = event_interval.num_milliseconds();
+        self
+    }
+
+    /// 设置持仓名义金额的上限
+    pub fn with_max_position_notional(mut self, max_position_notional: f64) -> Self {
+        self.max_position_notional = Some(max_position_notional);
+        self
+    }
+
+    fn get_next_order_id(&mut self) -> OrderId {
+        let order_id_body = self.next_order_id_body;
+        self.next_order_id_body += 1;
+        (order_id_body << ORDER_ID_OFFSET_BITS) | self.order_id_offset
+    }
+
+    /// 库存带来的报价偏移量，多头为正
+    fn get_skew(&self) -> f64 {
+        let mid_price = (self.bbo.best_bid.price + self.bbo.best_ask.price) / 2.;
+        self.inventory_skew * self.position.size() * mid_price / self.notional
+    }
+
+    /// 持仓是否已达到该方向的上限
+    fn is_saturated(&self, side: bool) -> bool {
+        let Some(max_notional) = self.max_position_notional else {
+            return false;
+        };
+        let size = self.position.size();
+        let is_increasing = if side { size > 0. } else { size < 0. };
+        is_increasing && size.abs() * self.bbo.best_bid.price >= max_notional
+    }
+
+    /// 某一侧的目标挂单：(价格, 规模)。不应挂单时返回None
+    fn get_target_quote(&self, side: bool) -> Option<(f64, f64)> {
+        if self.is_saturated(side) {
+            return None;
+        }
+        let skew = self.get_skew();
+        let price = if side {
+            self.bbo.best_bid.price - self.offset - skew
+        } else {
+            self.bbo.best_ask.price + self.offset - skew
+        };
+        let price = round_f64(price, self.price_digits);
+        let size = truncate_f64(self.notional / price, self.size_digits);
+        (size > self.size_eps / 2.).then_some((price, size))
+    }
+
+    fn placed_order_mut(&mut self, side: bool) -> &mut Option<LimitOrder> {
+        if side {
+            &mut self.bid_order
+        } else {
+            &mut self.ask_order
+        }
+    }
+
+    /// 对比某一侧的目标挂单与现有挂单，产生下单、改单或撤单事件
+    fn requote(&mut self, side: bool) -> Option<ClientEvent> {
+        let instrument_id = self.instrument_id;
+        let Some((price, size)) = self.get_target_quote(side) else {
+            let order = self.placed_order_mut(side).take()?;
+            return Some(ClientEvent::CancelOrder(instrument_id, order.order_id));
+        };
+
+        let size_eps = self.size_eps;
+        if let Some(order) = self.placed_order_mut(side) {
+            let is_size_changed = !approx_eq!(f64, order.unfilled_size(), size, epsilon = size_eps);
+            return (is_size_changed || order.price != price)
+                .then(|| ClientEvent::AmendOrder(order.amended(size, price)));
+        }
+
+        let raw_size = if side { size } else { -size };
+        let order_id = self.get_next_order_id();
+        let order = LimitOrder::from_raw_size(raw_size, order_id, instrument_id, price);
+        *self.placed_order_mut(side) = Some(order);
+        Some(ClientEvent::place_limit_order(order))
+    }
+
+    /// 挂单不再有效时，从对应的一侧移除
+    fn remove_order(&mut self, order_id: OrderId) {
+        for placed_order in [&mut self.bid_order, &mut self.ask_order] {
+            if placed_order.is_some_and(|order| order.order_id == order_id) {
+                *placed_order = None;
+            }
+        }
+    }
+
+    /// 以交易所的挂单为准核对两侧的挂单，撤销每侧多余的挂单
+    fn reconcile_orders(&mut self, open_orders: &[LimitOrder]) -> Vec<ClientEvent> {
+        let instrument_id = self.instrument_id;
+        let order_id_offset = self.order_id_offset;
+        let mut events = vec![];
+        for side in [true, false] {
+            let candidates = open_orders
+                .iter()
+                .filter(|order| {
+                    order.instrument_id == instrument_id
+                        && order.side == side
+                        && is_own_order(order.order_id, order_id_offset)
+                })
+                .copied()
+                .collect();
+            let extra_orders = reconcile_order_slot(self.placed_order_mut(side), candidates);
+            events.extend(
+                extra_orders
+                    .into_iter()
+                    .map(|order| ClientEvent::CancelOrder(instrument_id, order.order_id)),
+            );
+        }
+        events
+    }
+
+    fn sync_order(&mut self, order: &LimitOrder) {
+        let placed_order = self.placed_order_mut(order.side);
+        if placed_order.is_some_and(|placed| placed.order_id == order.order_id) {
+            *placed_order = Some(*order);
+        }
+    }
+}
+
+/// MarketMaker重启后需要恢复的状态
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    position: Position,
+    bid_order: Option<LimitOrder>,
+    ask_order: Option<LimitOrder>,
+    next_order_id_body: u64,
+}
+
+impl Strategy<Bbo> for MarketMaker {
+    fn on_event(&mut self, broker_event: &BrokerEvent<Bbo>) -> Vec<ClientEvent> {
+        match broker_event {
+            BrokerEvent::Data(bbo) => {
+                self.bbo = *bbo;
+                if bbo.ts - self.last_event_ts < self.event_interval {
+                    return vec![];
+                }
+                let events: Vec<_> = [true, false]
+                    .into_iter()
+                    .filter_map(|side| self.requote(side))
+                    .collect();
+                if !events.is_empty() {
+                    self.last_event_ts = bbo.ts;
+                }
+                events
+            }
+            BrokerEvent::Fill(fill) => {
+                self.position.update(fill);
+                let placed_order = self.placed_order_mut(fill.side);
+                if let Some(order) = placed_order
+                    && order.order_id == fill.order_id
+                {
+                    *placed_order = order.fill(fill);
+                }
+                vec![]
+            }
+            BrokerEvent::Placed(Order::Limit(order))
+            | BrokerEvent::Amended(Order::Limit(order)) => {
+                self.sync_order(order);
+                vec![]
+            }
+            BrokerEvent::Canceled(order_id) | BrokerEvent::Rejected { order_id, .. } => {
+                self.remove_order(*order_id);
+                vec![]
+            }
+            BrokerEvent::OpenOrders(open_orders) => self.reconcile_orders(open_orders),
+            _ => vec![],
+        }
+    }
+
+    fn checkpoint(&self) -> Option<Value> {
+        let checkpoint = Checkpoint {
+            position: self.position,
+            bid_order: self.bid_order,
+            ask_order: self.ask_order,
+            next_order_id_body: self.next_order_id_body,
+        };
+        serde_json::to_value(checkpoint).ok()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        let checkpoint: Checkpoint = serde_json::from_value(checkpoint)?;
+        self.position = checkpoint.position;
+        self.bid_order = checkpoint.bid_order;
+        self.ask_order = checkpoint.ask_order;
+        self.next_order_id_body = checkpoint.next_order_id_body;
+        Ok(())
+    }
+
+    fn reconcile_positions(&mut self, positions: &FxHashMap<InstId, f64>) {
+        let exchange_size = positions
+            .get(&self.instrument_id)
+            .copied()
+            .unwrap_or_default();
+        self.position = Position::new(exchange_size);
+    }
+}
+
+/// 配置文件中的时长以秒为单位
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MarketMakerArgs {
+    pub instrument_id: InstId,
+    pub notional: f64,
+    pub offset: f64,
+    #[serde(default)]
+    pub inventory_skew: f64,
+    #[serde(deserialize_with = "deserialize_secs")]
+    pub event_interval: Duration,
+    /// 持仓名义金额的上限，缺省时不设上限
+    #[serde(default)]
+    pub max_position_notional: Option<f64>,
+    /// 策略实例的全局唯一标识符，小于2^16
+    pub order_id_offset: u64,
+}
+
+impl MarketMakerArgs {
+    pub fn into_strategy(self) -> MarketMaker {
+        let profile = INSTRUMENT_PROFILES.get(&self.instrument_id).unwrap();
+        let market_maker = MarketMaker::new(
+            self.instrument_id,
+            self.notional,
+            profile.size_digits,
+            profile.price_digits,
+            self.offset,
+            self.inventory_skew,
+            self.order_id_offset,
+        )
+        .with_event_interval(self.event_interval);
+        match self.max_position_notional {
+            Some(max_position_notional) => {
+                market_maker.with_max_position_notional(max_position_notional)
+            }
+            None => market_maker,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Broker, Engine,
+        backtest::{SandboxBroker, TransactionCostModel},
+        data::{Level, VecDataProvider},
+    };
+
+    fn create_bbo(ts: i64, mid_price: f64) -> Bbo {
+        Bbo {
+            ts: ts.into(),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: Level {
+                price: mid_price - 0.1,
+                size: 10.,
+                order_count: 1,
+            },
+            best_ask: Level {
+                price: mid_price + 0.1,
+                size: 10.,
+                order_count: 1,
+            },
+        }
+    }
+
+    fn create_market_maker(inventory_skew: f64) -> MarketMaker {
+        MarketMaker::new(InstId::EthUsdtSwap, 1000., 2, 2, 0.5, inventory_skew, 1)
+    }
+
+    #[test]
+    fn test_quote_both_sides() {
+        let mut market_maker = create_market_maker(0.);
+        let events = market_maker.on_event(&BrokerEvent::Data(create_bbo(1000, 100.)));
+        let [
+            ClientEvent::PlaceOrder(Order::Limit(bid)),
+            ClientEvent::PlaceOrder(Order::Limit(ask)),
+        ] = &events[..]
+        else {
+            panic!("Expected two PlaceOrder events, got {events:?}");
+        };
+        assert!(bid.side && !ask.side);
+        assert_eq!(bid.price, 99.4);
+        assert_eq!(ask.price, 100.6);
+        assert_ne!(bid.order_id, ask.order_id);
+
+        // BBO变动后两侧都改单
+        let events = market_maker.on_event(&BrokerEvent::Data(create_bbo(2000, 101.)));
+        let [ClientEvent::AmendOrder(bid), ClientEvent::AmendOrder(ask)] = &events[..] else {
+            panic!("Expected two AmendOrder events, got {events:?}");
+        };
+        assert_eq!(bid.new_price, 100.4);
+        assert_eq!(ask.new_price, 101.6);
+
+        // BBO不变时不改单
+        let events = market_maker.on_event(&BrokerEvent::Data(create_bbo(3000, 101.)));
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_inventory_skew() {
+        let mut market_maker = create_market_maker(0.2);
+        market_maker.position = Position::new(10.);
+        market_maker.on_event(&BrokerEvent::Data(create_bbo(1000, 100.)));
+        // 持有一份notional的多头，报价整体下移0.2，卖单更靠近市场
+        assert_eq!(market_maker.bid_order.unwrap().price, 99.2);
+        assert_eq!(market_maker.ask_order.unwrap().price, 100.4);
+
+        // 达到持仓上限后，撤掉增加持仓的买单
+        market_maker.max_position_notional = Some(500.);
+        let events = market_maker.on_event(&BrokerEvent::Data(create_bbo(2000, 100.)));
+        assert!(matches!(events[0], ClientEvent::CancelOrder(..)));
+        assert!(market_maker.bid_order.is_none());
+        assert!(market_maker.ask_order.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_capture_spread_in_mean_reverting_market() {
+        // 中间价在99与101之间来回波动
+        let mid_prices = [100., 99., 100., 101.];
+        let bbos: Vec<_> = (0..400)
+            .map(|i| create_bbo(1000 * (i as i64 + 1), mid_prices[i % 4]))
+            .collect();
+        let broker = SandboxBroker::new(
+            vec![InstId::EthUsdtSwap],
+            VecDataProvider::new(bbos),
+            100_000.,
+            TransactionCostModel::new(0., 0., 0.),
+            Duration::seconds(1),
+        )
+        .await
+        .unwrap();
+        let mut engine = Engine::new(broker, create_market_maker(0.1));
+        engine.run().await;
+
+        let final_value = engine.broker().reporter().last_value().unwrap();
+        assert!(final_value > 100_000. + 100., "final value: {final_value}");
+        // 单边连续两步的行情会使同一侧连续成交两次，但库存不会持续累积
+        let positions = engine.broker().positions_snapshot().unwrap();
+        let size = positions[&InstId::EthUsdtSwap];
+        assert!(size.abs() < 21., "position: {size}");
+    }
+}
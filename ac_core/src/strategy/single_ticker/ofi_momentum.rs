@@ -1,13 +1,20 @@
+use std::path::Path;
+
+use anyhow::Result;
 use chrono::Duration;
-use data_center::instruments_profile::INSTRUMENT_PROFILES;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::{
     InstId, Timestamp,
-    data::Bbo,
+    clock::WallClock,
+    data::{Bbo, MarketData},
     strategy::{
-        Signal, SignalExecuteStrategy, Signaler, Strategy,
+        BboOnly, LoggingSignaler, Signal, SignalDiagnostics, SignalExecuteStrategy, Signaler,
+        Strategy,
         calc::{Ema, Emav},
-        executors::NaiveLimitExecutor,
+        config::deserialize_secs,
+        executors::{ExecutorArgs, NaiveLimitExecutor},
     },
 };
 
@@ -19,18 +26,19 @@ use crate::{
 #[derive(Default)]
 pub struct OfiMomentum {
     /// 策略的窗口长度
-    window_ofi: u64,
-    window_ema: u64,
+    window_ofi: i64,
+    window_ema: i64,
     /// 入场的标准化OFI阈值
     theta: f64,
 
     /// 策略预热期的长度
-    warm_up_duration: u64,
+    warm_up_duration: i64,
     first_ts: Option<Timestamp>,
 
     variables: Option<Variables>,
 }
 
+#[derive(Serialize, Deserialize)]
 struct Variables {
     bbo: Bbo,
     /// EMA of ofi
@@ -40,7 +48,7 @@ struct Variables {
 }
 
 impl Variables {
-    fn new(bbo: Bbo, window_ofi: u64, window_ema_ofi: u64) -> Self {
+    fn new(bbo: Bbo, window_ofi: i64, window_ema_ofi: i64) -> Self {
         Self {
             bbo,
             ofi: Ema::new(window_ofi as f64),
@@ -52,17 +60,17 @@ impl Variables {
     fn update(&mut self, bbo: &Bbo) {
         let mut ofi_segment = 0.;
         let old_bbo = &self.bbo;
-        if bbo.bid_price >= old_bbo.bid_price {
-            ofi_segment += bbo.bid_size
+        if bbo.best_bid.price >= old_bbo.best_bid.price {
+            ofi_segment += bbo.best_bid.size
         }
-        if bbo.bid_price <= old_bbo.bid_price {
-            ofi_segment -= old_bbo.bid_size
+        if bbo.best_bid.price <= old_bbo.best_bid.price {
+            ofi_segment -= old_bbo.best_bid.size
         }
-        if bbo.ask_price <= old_bbo.ask_price {
-            ofi_segment -= bbo.ask_size
+        if bbo.best_ask.price <= old_bbo.best_ask.price {
+            ofi_segment -= bbo.best_ask.size
         }
-        if bbo.ask_price >= old_bbo.ask_price {
-            ofi_segment += old_bbo.ask_size
+        if bbo.best_ask.price >= old_bbo.best_ask.price {
+            ofi_segment += old_bbo.best_ask.size
         }
 
         let dt = bbo.ts - old_bbo.ts;
@@ -72,14 +80,19 @@ impl Variables {
         self.bbo = *bbo;
     }
 
-    /// 计算ema_ofi的z-score
+    /// ema_ofi的z-score
     #[inline]
-    fn get_signal(&self, theta: f64) -> Option<Signal> {
+    fn z_score(&self) -> Option<f64> {
         let ofi = self.ofi.mean()?;
         let mean_ofi = self.eam_ofi.mean()?;
         let var_ofi = self.eam_ofi.variance()?;
 
-        let z_score = (ofi - mean_ofi) / var_ofi.sqrt();
+        Some((ofi - mean_ofi) / var_ofi.sqrt())
+    }
+
+    #[inline]
+    fn get_signal(&self, theta: f64) -> Option<Signal> {
+        let z_score = self.z_score()?;
         if z_score > theta {
             Some(Signal::Short)
         } else if z_score < -theta {
@@ -92,8 +105,8 @@ impl Variables {
 
 impl OfiMomentum {
     pub fn new(window_ofi: Duration, window_ema: Duration, theta: f64) -> Self {
-        let window_ofi = window_ofi.num_milliseconds() as u64;
-        let window_ema = window_ema.num_milliseconds() as u64;
+        let window_ofi = window_ofi.num_milliseconds();
+        let window_ema = window_ema.num_milliseconds();
         Self {
             window_ofi,
             window_ema,
@@ -104,6 +117,19 @@ impl OfiMomentum {
     }
 }
 
+/// 预热进度与指标的状态
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    first_ts: Option<Timestamp>,
+    variables: &'a Option<Variables>,
+}
+
+#[derive(Deserialize)]
+struct Checkpoint {
+    first_ts: Option<Timestamp>,
+    variables: Option<Variables>,
+}
+
 impl Signaler<Bbo> for OfiMomentum {
     #[inline]
     fn on_data(&mut self, bbo: &Bbo) -> Option<Signal> {
@@ -130,37 +156,153 @@ impl Signaler<Bbo> for OfiMomentum {
             None
         }
     }
+
+    fn diagnostics(&self) -> Option<SignalDiagnostics> {
+        let variables = self.variables.as_ref()?;
+        Some(SignalDiagnostics {
+            z_score: variables.z_score(),
+            ofi: variables.ofi.mean(),
+            variance: variables.eam_ofi.variance(),
+        })
+    }
+
+    fn checkpoint(&self) -> Option<Value> {
+        let checkpoint = CheckpointRef {
+            first_ts: self.first_ts,
+            variables: &self.variables,
+        };
+        serde_json::to_value(checkpoint).ok()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        let checkpoint: Checkpoint = serde_json::from_value(checkpoint)?;
+        self.first_ts = checkpoint.first_ts;
+        self.variables = checkpoint.variables;
+        Ok(())
+    }
 }
 
+/// 配置文件中的时长以秒为单位
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct OfiMomentumArgs {
     pub instrument_id: InstId,
+    #[serde(deserialize_with = "deserialize_secs")]
     pub window_ofi: Duration,
+    #[serde(deserialize_with = "deserialize_secs")]
     pub window_ema: Duration,
     pub theta: f64,
-    /// 信号消失后的持仓时间
-    pub holding_duration: Duration,
-    pub event_interval: Duration,
-
-    pub notional: f64,
-    pub price_offset: f64,
-    /// 策略实例的全局唯一标识符，小于2^16
-    pub order_id_offset: u64,
+    /// 下单执行的参数，在配置文件中与策略参数位于同一层
+    #[serde(flatten)]
+    pub executor: ExecutorArgs,
 }
 
 impl OfiMomentumArgs {
     pub fn into_strategy(self) -> impl Strategy<Bbo> {
-        let profile = &INSTRUMENT_PROFILES[&self.instrument_id];
+        let (signaler, executor) = self.into_parts();
+        SignalExecuteStrategy::new(signaler, executor)
+    }
+
+    /// 实盘中以系统时间计算挂单存活时间、事件间隔与持仓时限
+    pub fn into_live_strategy(self) -> impl Strategy<Bbo> {
+        let (signaler, executor) = self.into_parts();
+        SignalExecuteStrategy::new(signaler, executor.with_clock(WallClock))
+    }
+
+    /// 用于同时包含Bbo与逐笔成交的行情，逐笔成交被忽略
+    pub fn into_market_data_strategy(self) -> impl Strategy<MarketData> {
+        let (signaler, executor) = self.into_parts();
+        SignalExecuteStrategy::new(BboOnly::new(signaler), BboOnly::new(executor))
+    }
+
+    /// 将每期的z-score等中间结果与信号写入path处的CSV
+    pub fn into_logging_strategy(self, path: impl AsRef<Path>) -> Result<impl Strategy<Bbo>> {
+        let (signaler, executor) = self.into_parts();
+        let signaler = LoggingSignaler::create(signaler, path)?;
+        Ok(SignalExecuteStrategy::new(signaler, executor))
+    }
+
+    fn into_parts(self) -> (OfiMomentum, NaiveLimitExecutor) {
         let ofi_momentum_signaler = OfiMomentum::new(self.window_ofi, self.window_ema, self.theta);
-        let executor = NaiveLimitExecutor::new(
-            self.instrument_id,
-            self.notional,
-            profile.size_digits,
-            profile.price_digits,
-            self.price_offset,
-            self.holding_duration,
-            self.event_interval,
-            self.order_id_offset,
+        let executor = self.executor.build_executor(self.instrument_id);
+        (ofi_momentum_signaler, executor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Level;
+
+    #[test]
+    fn test_logging_signaler() {
+        let bbos: Vec<_> = (0..40)
+            .map(|i| Bbo {
+                ts: Timestamp::from_millis(1000 * i),
+                instrument_id: InstId::EthUsdtSwap,
+                best_bid: Level {
+                    price: 100. + (i % 7) as f64,
+                    size: 1. + (i % 3) as f64,
+                    order_count: 1,
+                },
+                best_ask: Level {
+                    price: 101. + (i % 5) as f64,
+                    size: 1. + (i % 4) as f64,
+                    order_count: 1,
+                },
+            })
+            .collect();
+        let new_signaler = || OfiMomentum::new(Duration::seconds(5), Duration::seconds(20), 1.);
+
+        let path = std::env::temp_dir().join(format!("ac_signals_{}.csv", std::process::id()));
+        let mut signaler = LoggingSignaler::create(new_signaler(), &path).unwrap();
+        let mut reference = new_signaler();
+        let mut expected = vec![];
+        for bbo in &bbos {
+            let signal = signaler.on_data(bbo);
+            assert_eq!(signal, reference.on_data(bbo));
+            // 直接由指标计算z-score
+            let z_score = reference.variables.as_ref().and_then(|variables| {
+                let ofi = variables.ofi.mean()?;
+                let mean_ofi = variables.eam_ofi.mean()?;
+                let var_ofi = variables.eam_ofi.variance()?;
+                Some((ofi - mean_ofi) / var_ofi.sqrt())
+            });
+            expected.push((bbo.ts.as_millis(), z_score, signal));
+        }
+        drop(signaler);
+
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        let headers = reader.headers().unwrap().clone();
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            ["ts", "z_score", "ofi", "variance", "signal"]
+        );
+        let rows: Vec<csv::StringRecord> = reader.records().map(|row| row.unwrap()).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rows.len(), bbos.len());
+        for (row, (ts, z_score, signal)) in rows.iter().zip(&expected) {
+            assert_eq!(row[0].parse::<i64>().unwrap(), *ts);
+            // 方差为0时z-score为NaN
+            let logged_z_score = row[1].parse::<f64>().ok();
+            assert!(match (logged_z_score, z_score) {
+                (Some(logged), Some(z_score)) => {
+                    logged == *z_score || logged.is_nan() && z_score.is_nan()
+                }
+                (logged, z_score) => logged.is_none() && z_score.is_none(),
+            });
+            let logged_signal = match &row[4] {
+                "Long" => Some(Signal::Long),
+                "Short" => Some(Signal::Short),
+                _ => None,
+            };
+            assert_eq!(logged_signal, *signal);
+        }
+        // 第一条数据只用于初始化，之后逐渐算出z-score
+        assert_eq!(expected[0].1, None);
+        assert!(
+            expected
+                .iter()
+                .any(|(_, z_score, _)| z_score.is_some_and(|z_score| z_score.is_finite()))
         );
-        SignalExecuteStrategy::new(ofi_momentum_signaler, executor)
     }
 }
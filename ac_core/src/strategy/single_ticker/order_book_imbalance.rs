@@ -0,0 +1,208 @@
+use anyhow::Result;
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    InstId, Timestamp,
+    clock::WallClock,
+    data::Bbo,
+    strategy::{
+        Signal, SignalExecuteStrategy, Signaler, Strategy,
+        calc::Ema,
+        config::deserialize_secs,
+        executors::{ExecutorArgs, NaiveLimitExecutor},
+    },
+};
+
+/// 订单簿失衡。
+///
+/// 失衡度 $I_t = (bid\_size - ask\_size) / (bid\_size + ask\_size)$，取值于 $[-1, 1]$，以EMA平滑。
+///
+/// 入场条件：
+/// - 多头：平滑后的 $I_t > \theta$
+/// - 空头：平滑后的 $I_t < -\theta$
+pub struct OrderBookImbalance {
+    /// EMA的时间常数，同时作为预热期的长度
+    window: i64,
+    /// 入场的失衡度阈值
+    threshold: f64,
+
+    first_ts: Option<Timestamp>,
+    last_ts: Timestamp,
+    imbalance: Ema,
+}
+
+impl OrderBookImbalance {
+    pub fn new(window: Duration, threshold: f64) -> Self {
+        let window = window.num_milliseconds();
+        Self {
+            window,
+            threshold,
+            first_ts: None,
+            last_ts: Timestamp::default(),
+            imbalance: Ema::new(window as f64),
+        }
+    }
+
+    fn get_imbalance(bbo: &Bbo) -> f64 {
+        let total_size = bbo.best_bid.size + bbo.best_ask.size;
+        if total_size <= 0. {
+            return 0.;
+        }
+        (bbo.best_bid.size - bbo.best_ask.size) / total_size
+    }
+}
+
+/// 预热进度与指标的状态
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    first_ts: Option<Timestamp>,
+    last_ts: Timestamp,
+    imbalance: &'a Ema,
+}
+
+#[derive(Deserialize)]
+struct Checkpoint {
+    first_ts: Option<Timestamp>,
+    last_ts: Timestamp,
+    imbalance: Ema,
+}
+
+impl Signaler<Bbo> for OrderBookImbalance {
+    #[inline]
+    fn on_data(&mut self, bbo: &Bbo) -> Option<Signal> {
+        let first_ts = *self.first_ts.get_or_insert(bbo.ts);
+        let dt = (bbo.ts - self.last_ts).max(0);
+        self.last_ts = bbo.ts;
+        let imbalance = self.imbalance.update(Self::get_imbalance(bbo), dt as f64);
+
+        if bbo.ts - first_ts <= self.window {
+            return None;
+        }
+        if imbalance > self.threshold {
+            Some(Signal::Long)
+        } else if imbalance < -self.threshold {
+            Some(Signal::Short)
+        } else {
+            None
+        }
+    }
+
+    fn checkpoint(&self) -> Option<Value> {
+        let checkpoint = CheckpointRef {
+            first_ts: self.first_ts,
+            last_ts: self.last_ts,
+            imbalance: &self.imbalance,
+        };
+        serde_json::to_value(checkpoint).ok()
+    }
+
+    fn restore(&mut self, checkpoint: Value) -> Result<()> {
+        let checkpoint: Checkpoint = serde_json::from_value(checkpoint)?;
+        self.first_ts = checkpoint.first_ts;
+        self.last_ts = checkpoint.last_ts;
+        self.imbalance = checkpoint.imbalance;
+        Ok(())
+    }
+}
+
+/// 配置文件中的时长以秒为单位
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct OrderBookImbalanceArgs {
+    pub instrument_id: InstId,
+    #[serde(deserialize_with = "deserialize_secs")]
+    pub window: Duration,
+    pub threshold: f64,
+    /// 下单执行的参数，在配置文件中与策略参数位于同一层
+    #[serde(flatten)]
+    pub executor: ExecutorArgs,
+}
+
+impl OrderBookImbalanceArgs {
+    pub fn into_strategy(self) -> impl Strategy<Bbo> {
+        let (signaler, executor) = self.into_parts();
+        SignalExecuteStrategy::new(signaler, executor)
+    }
+
+    /// 实盘中以系统时间计算挂单存活时间、事件间隔与持仓时限
+    pub fn into_live_strategy(self) -> impl Strategy<Bbo> {
+        let (signaler, executor) = self.into_parts();
+        SignalExecuteStrategy::new(signaler, executor.with_clock(WallClock))
+    }
+
+    fn into_parts(self) -> (OrderBookImbalance, NaiveLimitExecutor) {
+        let signaler = OrderBookImbalance::new(self.window, self.threshold);
+        let executor = self.executor.build_executor(self.instrument_id);
+        (signaler, executor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Level;
+
+    fn create_bbo(ts: i64, bid_size: f64, ask_size: f64) -> Bbo {
+        Bbo {
+            ts: ts.into(),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: Level {
+                price: 100.,
+                size: bid_size,
+                order_count: 1,
+            },
+            best_ask: Level {
+                price: 101.,
+                size: ask_size,
+                order_count: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn test_order_book_imbalance_signal() {
+        let mut signaler = OrderBookImbalance::new(Duration::seconds(10), 0.5);
+
+        // 预热期内，即使失衡很大也不发出信号
+        for ts in (0..=10_000).step_by(1000) {
+            assert_eq!(signaler.on_data(&create_bbo(ts, 9., 1.)), None);
+        }
+        // 预热期后，买方挂单量占优，平滑后的失衡度为0.8
+        assert_eq!(
+            signaler.on_data(&create_bbo(11_000, 9., 1.)),
+            Some(Signal::Long)
+        );
+
+        // 失衡反转后，平滑值逐渐下降，先回到阈值之内，再跌破-threshold
+        let signals: Vec<_> = (12..60)
+            .map(|i| signaler.on_data(&create_bbo(i * 1000, 1., 9.)))
+            .collect();
+        let first_none = signals.iter().position(Option::is_none).unwrap();
+        let first_short = signals
+            .iter()
+            .position(|signal| *signal == Some(Signal::Short))
+            .unwrap();
+        assert!(first_none < first_short);
+        assert!(
+            signals[first_short..]
+                .iter()
+                .all(|signal| *signal == Some(Signal::Short))
+        );
+
+        // 挂单量平衡时，失衡度回到0附近，不再发出信号
+        let signal = (60..200)
+            .map(|i| signaler.on_data(&create_bbo(i * 1000, 5., 5.)))
+            .last()
+            .unwrap();
+        assert_eq!(signal, None);
+    }
+
+    #[test]
+    fn test_empty_book_imbalance() {
+        assert_eq!(
+            OrderBookImbalance::get_imbalance(&create_bbo(0, 0., 0.)),
+            0.
+        );
+    }
+}
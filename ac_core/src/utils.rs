@@ -1,5 +1,3 @@
-use chrono::Utc;
-
 use crate::Timestamp;
 
 /// 将f64阶段到小数点后第digits位
@@ -22,5 +20,5 @@ pub fn get_side_size_from_raw_size(raw_size: f64) -> (bool, f64) {
 }
 
 pub fn get_ts_now() -> Timestamp {
-    Utc::now().timestamp_millis() as u64
+    Timestamp::now()
 }
@@ -24,6 +24,41 @@ CREATE TABLE IF NOT EXISTS okx_bbo (
 CREATE INDEX IF NOT EXISTS idx_okx_bbo ON okx_bbo (ts, instrument_id);
 CREATE INDEX IF NOT EXISTS idx_okx_bbo_ts ON okx_bbo (ts);
 
+CREATE TABLE IF NOT EXISTS okx_funding_rate (
+    ts BIGINT NOT NULL,
+    instrument_id TEXT NOT NULL,
+    funding_rate DOUBLE PRECISION NOT NULL,
+    funding_time BIGINT NOT NULL,
+    PRIMARY KEY (ts, instrument_id)
+);
+
+CREATE TABLE IF NOT EXISTS okx_open_interest (
+    ts BIGINT NOT NULL,
+    instrument_id TEXT NOT NULL,
+    open_interest DOUBLE PRECISION NOT NULL,
+    open_interest_ccy DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY (ts, instrument_id)
+);
+
+-- order_id为u64按位转换的BIGINT，枚举字段为serde的字符串形式
+CREATE TABLE IF NOT EXISTS okx_orders (
+    ts BIGINT NOT NULL,
+    order_id BIGINT NOT NULL,
+    instrument_id TEXT NOT NULL,
+    state TEXT NOT NULL,
+    size DOUBLE PRECISION NOT NULL,
+    filled_size DOUBLE PRECISION NOT NULL,
+    acc_filled_size DOUBLE PRECISION NOT NULL,
+    price DOUBLE PRECISION NOT NULL,
+    side BOOLEAN NOT NULL,
+    ord_type TEXT NOT NULL,
+    exec_type TEXT,
+    push_type TEXT NOT NULL,
+    reduce_only BOOLEAN NOT NULL,
+    PRIMARY KEY (order_id, ts, push_type, acc_filled_size)
+);
+CREATE INDEX IF NOT EXISTS idx_okx_orders_ts ON okx_orders (ts);
+
 /* CREATE OR REPLACE FUNCTION prevent_out_of_order_insert()
 RETURNS TRIGGER AS $$
 DECLARE
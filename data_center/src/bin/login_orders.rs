@@ -2,13 +2,13 @@ use std::vec;
 
 use data_center::{
     okx_api::{self, OkxWsEndpoint},
-    types::{Action, InstId, Side},
+    types::{Action, InstId, Side, TdMode},
 };
 use futures::{SinkExt, StreamExt};
 
 #[tokio::main]
 async fn main() {
-    let _guard = utils::init_tracing();
+    let _guard = utils::init_tracing(utils::TracingConfig::default());
     let subscribe_actions = vec![Action::SubscribeOrders(InstId::EthUsdtSwap)];
     let mut okx_ws = okx_api::connect(OkxWsEndpoint::PrivateSimu, subscribe_actions)
         .await
@@ -20,8 +20,10 @@ async fn main() {
             side: Side::Buy,
             inst_id: InstId::EthUsdtSwap,
             client_order_id: "123".into(),
+            td_mode: TdMode::Cross,
             size: "0.1".into(),
             price: "100".into(),
+            reduce_only: false,
         })
         .await
         .unwrap();
@@ -4,26 +4,68 @@ use anyhow::Result;
 use data_center::{
     okx_api::{self, OkxWsEndpoint},
     sql,
-    types::{Action, Data, InstId},
+    types::{Action, ConnState, Data, InstId},
 };
-use futures_util::StreamExt;
+use futures_util::{Stream, StreamExt};
+use utils::DedupConsecutive;
 
 static INSTRUMENTS: [InstId; 1] = [InstId::EthUsdtSwap];
 
 #[tokio::main]
 async fn main() {
-    let _guard = utils::init_tracing();
+    let _guard = utils::init_tracing(utils::TracingConfig::default());
     let handle = utils::spawn_with_retry(main_task, Duration::from_millis(0));
     let _ = handle.await;
 }
 
+#[derive(PartialEq)]
+enum DedupKey {
+    Bbo(InstId, f64, f64, f64, f64),
+    Trade(InstId, String),
+    FundingRate(InstId, i64),
+    OpenInterest(InstId, i64),
+    Other,
+}
+
+/// BBO按最优价与数量去重；成交的trade_id各不相同，不会被去重；
+/// 资金费率与持仓总量按合约与时间戳去重，只丢弃重复推送的同一条数据
+fn dedup_key(data: &Data) -> DedupKey {
+    match data {
+        Data::Bbo(bbo) => DedupKey::Bbo(
+            bbo.instrument_id,
+            bbo.best_bid.price,
+            bbo.best_bid.size,
+            bbo.best_ask.price,
+            bbo.best_ask.size,
+        ),
+        Data::Trade(trade) => DedupKey::Trade(trade.instrument_id, trade.trade_id.to_string()),
+        Data::FundingRate(funding_rate) => {
+            DedupKey::FundingRate(funding_rate.inst_id, funding_rate.ts)
+        }
+        Data::OpenInterest(open_interest) => {
+            DedupKey::OpenInterest(open_interest.inst_id, open_interest.ts)
+        }
+        _ => DedupKey::Other,
+    }
+}
+
 async fn main_task() -> Result<()> {
     let mut subscribe_actions = vec![];
     for inst_id in INSTRUMENTS {
         subscribe_actions.push(Action::SubscribeTrades(inst_id));
         subscribe_actions.push(Action::SubscribeBboTbt(inst_id));
+        subscribe_actions.push(Action::SubscribeFundingRate(inst_id));
+        subscribe_actions.push(Action::SubscribeOpenInterest(inst_id));
     }
-    let mut okx_ws = okx_api::connect(OkxWsEndpoint::Public, subscribe_actions).await?;
+    let okx_ws = okx_api::connect(OkxWsEndpoint::Public, subscribe_actions).await?;
+    store_data(okx_ws).await
+}
+
+/// 将行情数据写入数据库，连接状态变化仅记录日志
+async fn store_data(stream: impl Stream<Item = Data>) -> Result<()> {
+    // 最优价与数量都未变化的BBO不必重复存储
+    let okx_ws = DedupConsecutive::new(stream, dedup_key);
+    tokio::pin!(okx_ws);
 
     while let Some(data) = okx_ws.next().await {
         match data {
@@ -37,9 +79,66 @@ async fn main_task() -> Result<()> {
                     tracing::error!("Failed to insert bbo data: {e}");
                 }
             }
-            _ => unreachable!(),
+            Data::FundingRate(funding_rate) => {
+                if let Err(e) = sql::insert_funding_rate(&funding_rate).await {
+                    tracing::error!("Failed to insert funding rate: {e}");
+                }
+            }
+            Data::OpenInterest(open_interest) => {
+                if let Err(e) = sql::insert_open_interest(&open_interest).await {
+                    tracing::error!("Failed to insert open interest: {e}");
+                }
+            }
+            Data::ConnectionState(ConnState::Disconnected(reason)) => {
+                tracing::warn!("Connection lost: {reason}")
+            }
+            Data::ConnectionState(state) => tracing::info!("Connection state changed: {state:?}"),
+            other => tracing::warn!("Skipping unexpected data: {other:?}"),
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use data_center::types::{FundingRate, OpenInterest};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_store_connection_state() {
+        let stream = futures_util::stream::iter([
+            Data::ConnectionState(ConnState::Connected),
+            Data::ConnectionState(ConnState::Disconnected("reset".into())),
+            Data::ConnectionState(ConnState::Reconnecting),
+            Data::ConnectionState(ConnState::Reconnected),
+        ]);
+        store_data(stream).await.unwrap();
+    }
+
+    #[test]
+    fn test_dedup_key() {
+        let funding_rate = |ts| {
+            Data::FundingRate(FundingRate {
+                ts,
+                inst_id: InstId::EthUsdtSwap,
+                funding_rate: 0.0001,
+                funding_time: 0,
+            })
+        };
+        let open_interest = |ts| {
+            Data::OpenInterest(OpenInterest {
+                ts,
+                inst_id: InstId::EthUsdtSwap,
+                open_interest: 1.,
+                open_interest_ccy: 0.1,
+            })
+        };
+
+        assert!(dedup_key(&funding_rate(1)) == dedup_key(&funding_rate(1)));
+        assert!(dedup_key(&funding_rate(1)) != dedup_key(&funding_rate(2)));
+        assert!(dedup_key(&open_interest(1)) != dedup_key(&open_interest(2)));
+        assert!(dedup_key(&funding_rate(1)) != dedup_key(&open_interest(1)));
+    }
+}
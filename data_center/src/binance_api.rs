@@ -0,0 +1,233 @@
+pub(crate) mod pushes;
+
+use core::{pin::Pin, task::Poll};
+use std::task::Context;
+
+use anyhow::{Result, anyhow, bail};
+use futures::{Sink, Stream, ready};
+use futures_util::SinkExt;
+use hmac::{Hmac, Mac};
+use pin_project::pin_project;
+use pushes::Push;
+use sha2::Sha256;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{self, Message},
+};
+use utils::Duplex;
+
+use crate::{
+    CONFIG,
+    exchange::Exchange,
+    okx_api::with_heartbeat,
+    types::{Action, Data, InstId, OrderPush},
+    utils::AutoReconnect,
+};
+
+/// U本位合约的组合流地址，连接后通过SUBSCRIBE请求订阅
+const FUTURES_STREAM_URL: &str = "wss://fstream.binance.com/stream";
+
+/// 币安U本位合约交易所，目前只支持行情数据（BBO与成交）
+#[derive(Clone, Copy, Default)]
+pub struct Binance;
+
+impl Exchange for Binance {
+    type Action = Action;
+    type Data = Data;
+
+    async fn connect(
+        &self,
+        subscriptions: Vec<Action>,
+    ) -> Result<impl Duplex<Action, anyhow::Error, Data> + use<>> {
+        connect(subscriptions).await
+    }
+
+    /// 目前只支持行情数据，没有挂单
+    async fn open_orders(&self, _inst_id: InstId) -> Result<Vec<OrderPush>> {
+        bail!("Binance does not support trading yet")
+    }
+
+    /// 生成签名：Hex( HMAC-SHA256( "timestamp={timestamp}", SECRET_KEY ) )
+    fn sign_login(&self, timestamp: i64) -> String {
+        let payload = format!("timestamp={timestamp}");
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(CONFIG.binance_secret_key.as_bytes()).unwrap();
+        mac.update(payload.as_bytes());
+        let result = mac.finalize().into_bytes();
+
+        result.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+}
+
+impl InstId {
+    /// 币安的交易对名称，例如"ETHUSDT"
+    #[inline]
+    pub fn binance_symbol(&self) -> &str {
+        match self {
+            Self::EthUsdtSwap => "ETHUSDT",
+            Self::BtcUsdtSwap => "BTCUSDT",
+        }
+    }
+
+    pub fn try_from_binance_symbol(symbol: &str) -> Result<Self> {
+        match symbol {
+            "ETHUSDT" => Ok(Self::EthUsdtSwap),
+            "BTCUSDT" => Ok(Self::BtcUsdtSwap),
+            _ => bail!("Unknown binance symbol: {symbol}"),
+        }
+    }
+}
+
+impl Action {
+    /// 币安的订阅频道名，例如"ethusdt@bookTicker"。只有行情订阅有对应的频道。
+    fn binance_stream(&self) -> Result<std::string::String> {
+        match self {
+            Action::SubscribeTrades(inst_id) => Ok(format!(
+                "{}@aggTrade",
+                inst_id.binance_symbol().to_lowercase()
+            )),
+            Action::SubscribeBboTbt(inst_id) => Ok(format!(
+                "{}@bookTicker",
+                inst_id.binance_symbol().to_lowercase()
+            )),
+            _ => bail!("Action not supported by binance: {self:?}"),
+        }
+    }
+}
+
+#[pin_project]
+pub struct BinanceWsStream<S>
+where
+    S: Duplex<Message, tungstenite::Error, Result<Message, tungstenite::Error>>,
+{
+    #[pin]
+    inner: S,
+    /// 订阅请求的id，每次发送后递增
+    next_request_id: u64,
+}
+
+pub async fn connect(
+    subscribe_actions: Vec<Action>,
+) -> Result<impl Duplex<Action, anyhow::Error, Data>> {
+    let make_connection = move || {
+        let subscribe_actions = subscribe_actions.clone();
+        async move {
+            let (ws_stream, _) = connect_async(FUTURES_STREAM_URL).await?;
+            // 币安不识别文本"ping"，使用协议层的Ping帧
+            let ws_stream =
+                with_heartbeat(ws_stream).with_ping_message(Message::Ping(Default::default()));
+            let mut ws_stream = BinanceWsStream {
+                inner: ws_stream,
+                next_request_id: 1,
+            };
+            for request in subscribe_actions {
+                ws_stream.send(request).await?
+            }
+
+            Ok(ws_stream)
+        }
+    };
+
+    let ws_stream = AutoReconnect::new(make_connection).await?;
+    let ws_stream = Box::pin(ws_stream);
+    Ok(ws_stream)
+}
+
+impl<S> Sink<Action> for BinanceWsStream<S>
+where
+    S: Duplex<Message, tungstenite::Error, Result<Message, tungstenite::Error>>,
+{
+    type Error = anyhow::Error;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::result::Result<(), Self::Error>> {
+        let mut this = self.project();
+        ready!(this.inner.as_mut().poll_ready(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Action) -> std::result::Result<(), Self::Error> {
+        let mut this = self.project();
+        let request = serde_json::json!({
+            "method": "SUBSCRIBE",
+            "params": [item.binance_stream()?],
+            "id": *this.next_request_id,
+        });
+        *this.next_request_id += 1;
+        let message = Message::text(request.to_string());
+        tracing::debug!("Send message: {message:?}");
+        this.inner
+            .as_mut()
+            .start_send(message)
+            .map_err(|e| anyhow!("Failed to send message: {e}"))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::result::Result<(), Self::Error>> {
+        let mut this = self.project();
+        ready!(this.inner.as_mut().poll_flush(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::result::Result<(), Self::Error>> {
+        let mut this = self.project();
+        ready!(this.inner.as_mut().poll_close(cx))?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> Stream for BinanceWsStream<S>
+where
+    S: Duplex<Message, tungstenite::Error, Result<Message, tungstenite::Error>>,
+{
+    type Item = Data;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            let Some(msg) = ready!(this.inner.as_mut().poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+
+            let msg = match msg {
+                Ok(m) => m,
+                Err(e) => {
+                    tracing::error!("WebSocket error: {e}");
+                    return Poll::Ready(None);
+                }
+            };
+
+            // 只关心文本消息，Ping/Pong由tungstenite处理
+            let Message::Text(text) = msg else {
+                continue;
+            };
+
+            tracing::debug!("Receive message: {text}");
+
+            // 订阅回复形如{"result":null,"id":1}，不是数据帧
+            let push: Push = match serde_json::from_str(&text) {
+                Ok(p) => p,
+                Err(_) => {
+                    tracing::info!("Unidentified message: {text}");
+                    continue;
+                }
+            };
+
+            match Data::try_from_binance_push(push) {
+                Ok(data) => return Poll::Ready(Some(data)),
+                Err(e) => {
+                    tracing::info!("Fail to convert push to data: {e}");
+                    continue;
+                }
+            }
+        }
+    }
+}
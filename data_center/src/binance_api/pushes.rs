@@ -0,0 +1,240 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use smartstring::alias::String;
+
+use crate::{
+    instruments_profile::INSTRUMENT_PROFILES,
+    types::{Bbo, Data, InstId, Level, Trade},
+};
+
+/// 组合流的数据帧：{"stream":"ethusdt@bookTicker","data":{...}}
+#[derive(Debug, Deserialize)]
+pub struct Push<'a> {
+    pub stream: String,
+    #[serde(borrow)]
+    pub data: &'a RawValue,
+}
+
+/// 仅用于读取事件类型
+#[derive(Deserialize)]
+struct EventType {
+    #[serde(rename = "e")]
+    event_type: String,
+}
+
+pub enum BinanceData {
+    BookTicker(BookTickerData),
+    AggTrade(AggTradeData),
+    Trade(TradeData),
+}
+
+impl BinanceData {
+    pub fn try_from_push(push: Push) -> Result<Self> {
+        let raw_data_str = push.data.get();
+        let EventType { event_type } = serde_json::from_str(raw_data_str)?;
+        match event_type.as_str() {
+            "bookTicker" => Ok(Self::BookTicker(serde_json::from_str(raw_data_str)?)),
+            "aggTrade" => Ok(Self::AggTrade(serde_json::from_str(raw_data_str)?)),
+            "trade" => Ok(Self::Trade(serde_json::from_str(raw_data_str)?)),
+            _ => bail!(
+                "Unsupported event type {event_type} of stream {}",
+                push.stream
+            ),
+        }
+    }
+}
+
+impl Data {
+    pub fn try_from_binance_data(binance_data: BinanceData) -> Result<Self> {
+        match binance_data {
+            BinanceData::BookTicker(data) => Ok(Self::Bbo(data.try_into_bbo()?)),
+            BinanceData::AggTrade(data) => Ok(Self::Trade(data.try_into_trade()?)),
+            BinanceData::Trade(data) => Ok(Self::Trade(data.try_into_trade()?)),
+        }
+    }
+
+    pub fn try_from_binance_push(push: Push) -> Result<Self> {
+        let binance_data = BinanceData::try_from_push(push)?;
+        Self::try_from_binance_data(binance_data)
+    }
+}
+
+/// 币安的数量以币计，换算为与OKX相同的合约张数，使同一InstId的数据可以混用
+fn parse_contracts(instrument_id: InstId, size: &str) -> Result<f64> {
+    let Some(profile) = INSTRUMENT_PROFILES.get(&instrument_id) else {
+        bail!("No instrument profile for {instrument_id:?} to convert the size into contracts");
+    };
+    Ok(size.parse::<f64>()? / profile.size_scale)
+}
+
+/// {"e":"bookTicker","u":400900217,"E":1568014460893,"T":1568014460891,"s":"BNBUSDT",
+/// "b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66000000"}
+#[derive(Debug, Deserialize)]
+pub struct BookTickerData {
+    /// 撮合时间
+    #[serde(rename = "T")]
+    ts: i64,
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "b")]
+    bid_price: String,
+    #[serde(rename = "B")]
+    bid_size: String,
+    #[serde(rename = "a")]
+    ask_price: String,
+    #[serde(rename = "A")]
+    ask_size: String,
+}
+
+impl BookTickerData {
+    /// 币安不推送挂单数量，order_count记为0
+    pub fn try_into_bbo(self) -> Result<Bbo> {
+        let instrument_id = InstId::try_from_binance_symbol(&self.symbol)?;
+        Ok(Bbo {
+            ts: self.ts.into(),
+            instrument_id,
+            best_bid: Level {
+                price: self.bid_price.parse()?,
+                size: parse_contracts(instrument_id, &self.bid_size)?,
+                order_count: 0,
+            },
+            best_ask: Level {
+                price: self.ask_price.parse()?,
+                size: parse_contracts(instrument_id, &self.ask_size)?,
+                order_count: 0,
+            },
+        })
+    }
+}
+
+/// {"e":"aggTrade","E":123456789,"s":"BTCUSDT","a":5933014,"p":"0.001","q":"100",
+/// "f":100,"l":105,"T":123456785,"m":true}
+#[derive(Debug, Deserialize)]
+pub struct AggTradeData {
+    /// 成交时间
+    #[serde(rename = "T")]
+    ts: i64,
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "a")]
+    agg_trade_id: u64,
+    #[serde(rename = "p")]
+    price: String,
+    #[serde(rename = "q")]
+    size: String,
+    #[serde(rename = "f")]
+    first_trade_id: u64,
+    #[serde(rename = "l")]
+    last_trade_id: u64,
+    /// 买方是否为maker。为真时主动方为卖方。
+    #[serde(rename = "m")]
+    is_buyer_maker: bool,
+}
+
+impl AggTradeData {
+    pub fn try_into_trade(self) -> Result<Trade> {
+        let instrument_id = InstId::try_from_binance_symbol(&self.symbol)?;
+        Ok(Trade {
+            ts: self.ts,
+            instrument_id,
+            trade_id: self.agg_trade_id.to_string().into(),
+            price: self.price.parse()?,
+            size: parse_contracts(instrument_id, &self.size)?,
+            side: !self.is_buyer_maker,
+            order_count: (self.last_trade_id - self.first_trade_id + 1) as i32,
+        })
+    }
+}
+
+/// {"e":"trade","E":123456789,"T":123456785,"s":"BNBUSDT","t":12345,"p":"0.001",
+/// "q":"100","X":"MARKET","m":true}
+#[derive(Debug, Deserialize)]
+pub struct TradeData {
+    #[serde(rename = "T")]
+    ts: i64,
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "t")]
+    trade_id: u64,
+    #[serde(rename = "p")]
+    price: String,
+    #[serde(rename = "q")]
+    size: String,
+    #[serde(rename = "m")]
+    is_buyer_maker: bool,
+}
+
+impl TradeData {
+    pub fn try_into_trade(self) -> Result<Trade> {
+        let instrument_id = InstId::try_from_binance_symbol(&self.symbol)?;
+        Ok(Trade {
+            ts: self.ts,
+            instrument_id,
+            trade_id: self.trade_id.to_string().into(),
+            price: self.price.parse()?,
+            size: parse_contracts(instrument_id, &self.size)?,
+            side: !self.is_buyer_maker,
+            order_count: 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Data {
+        let push: Push = serde_json::from_str(text).unwrap();
+        Data::try_from_binance_push(push).unwrap()
+    }
+
+    #[test]
+    fn test_parse_book_ticker() {
+        let text = r#"{"stream":"ethusdt@bookTicker","data":{"e":"bookTicker","u":400900217,"E":1568014460893,"T":1568014460891,"s":"ETHUSDT","b":"2535.19","B":"31.21","a":"2536.52","A":"40.66"}}"#;
+        let Data::Bbo(bbo) = parse(text) else {
+            panic!("Expected bbo");
+        };
+        assert_eq!(bbo.ts.as_millis(), 1568014460891);
+        assert_eq!(bbo.instrument_id, InstId::EthUsdtSwap);
+        assert_eq!(bbo.best_bid.price, 2535.19);
+        // 数量由币换算为面值0.1 ETH的合约张数
+        assert!((bbo.best_bid.size - 312.1).abs() < 1e-9);
+        assert_eq!(bbo.best_ask.price, 2536.52);
+        assert!((bbo.best_ask.size - 406.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_agg_trade() {
+        let text = r#"{"stream":"btcusdt@aggTrade","data":{"e":"aggTrade","E":123456789,"s":"BTCUSDT","a":5933014,"p":"65000.1","q":"0.5","f":100,"l":105,"T":123456785,"m":true}}"#;
+        let Data::Trade(trade) = parse(text) else {
+            panic!("Expected trade");
+        };
+        assert_eq!(trade.ts, 123456785);
+        assert_eq!(trade.instrument_id, InstId::BtcUsdtSwap);
+        assert_eq!(trade.trade_id, "5933014");
+        assert_eq!(trade.price, 65000.1);
+        // 面值0.01 BTC
+        assert!((trade.size - 50.).abs() < 1e-9);
+        // 买方为maker，主动方为卖方
+        assert!(!trade.side);
+        assert_eq!(trade.order_count, 6);
+    }
+
+    #[test]
+    fn test_parse_trade() {
+        let text = r#"{"stream":"ethusdt@trade","data":{"e":"trade","E":123456789,"T":123456785,"s":"ETHUSDT","t":12345,"p":"2500.5","q":"1.2","X":"MARKET","m":false}}"#;
+        let Data::Trade(trade) = parse(text) else {
+            panic!("Expected trade");
+        };
+        assert_eq!(trade.trade_id, "12345");
+        assert!((trade.size - 12.).abs() < 1e-9);
+        assert!(trade.side);
+        assert_eq!(trade.order_count, 1);
+    }
+
+    #[test]
+    fn test_subscription_response_is_not_push() {
+        assert!(serde_json::from_str::<Push>(r#"{"result":null,"id":1}"#).is_err());
+    }
+}
@@ -0,0 +1,24 @@
+use anyhow::Result;
+use utils::Duplex;
+
+use crate::{OrderPush, types::InstId};
+
+/// 交易所的抽象。实现者负责建立连接、发送订阅，并将交易所的推送转换为统一的数据格式。
+pub trait Exchange {
+    /// 发送给交易所的请求，包括订阅与下单等
+    type Action;
+    /// 交易所推送的数据
+    type Data;
+
+    /// 建立连接并发送订阅请求。返回的连接可写入Action，并推送Data。
+    fn connect(
+        &self,
+        subscriptions: Vec<Self::Action>,
+    ) -> impl Future<Output = Result<impl Duplex<Self::Action, anyhow::Error, Self::Data> + use<Self>>>;
+
+    /// 查询该产品尚未完全成交的挂单，用于重连后核对本地记录的订单
+    fn open_orders(&self, inst_id: InstId) -> impl Future<Output = Result<Vec<OrderPush>>>;
+
+    /// 生成登录私有频道所需的签名
+    fn sign_login(&self, timestamp: i64) -> String;
+}
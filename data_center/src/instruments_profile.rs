@@ -1,5 +1,11 @@
-use std::{fs, path::Path};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
 
+use anyhow::Result;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use once_cell::sync::Lazy;
 use rustc_hash::FxHashMap;
 use serde::Deserialize;
@@ -9,27 +15,215 @@ use crate::types::InstId;
 pub static INSTRUMENT_PROFILES: Lazy<InstrumentProfiles> = Lazy::new(|| {
     let manifest_dir = env!("CARGO_MANIFEST_DIR");
     let profiles_path = Path::new(manifest_dir).join("instrument_profiles.toml");
-    let profiles_str = fs::read_to_string(profiles_path).unwrap();
-    toml::from_str(&profiles_str).unwrap()
+    InstrumentProfiles::load(profiles_path).unwrap()
 });
 
-type InstrumentProfiles = FxHashMap<InstId, InstrumentProfile>;
+pub type InstrumentProfileMap = FxHashMap<InstId, InstrumentProfile>;
 
-#[derive(Deserialize, Debug)]
+/// 可热更新的产品信息。每次访问得到的是某一时刻的完整快照，reload时整体替换。
+#[derive(Debug)]
+pub struct InstrumentProfiles {
+    path: PathBuf,
+    profiles: RwLock<Arc<InstrumentProfileMap>>,
+}
+
+impl InstrumentProfiles {
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let profiles = read_profiles(&path)?;
+        Ok(Self {
+            path,
+            profiles: RwLock::new(Arc::new(profiles)),
+        })
+    }
+
+    /// The snapshot of all the profiles at the moment
+    pub fn snapshot(&self) -> Arc<InstrumentProfileMap> {
+        self.profiles.read().unwrap().clone()
+    }
+
+    pub fn get(&self, inst_id: &InstId) -> Option<InstrumentProfile> {
+        self.profiles.read().unwrap().get(inst_id).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.profiles.read().unwrap().is_empty()
+    }
+
+    /// Re-read the TOML file and swap the profiles. The old profiles are kept if the file is invalid.
+    pub fn reload(&self) -> Result<()> {
+        let profiles = read_profiles(&self.path)?;
+        *self.profiles.write().unwrap() = Arc::new(profiles);
+        tracing::info!("Instrument profiles reloaded from {:?}", self.path);
+        Ok(())
+    }
+
+    /// Reload the profiles whenever the TOML file changes. The returned watcher must be kept alive.
+    pub fn watch(&'static self) -> Result<RecommendedWatcher> {
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(event) if event.paths.iter().any(|path| path.ends_with(&self.path)) => {
+                    if let Err(e) = self.reload() {
+                        tracing::error!("Failed to reload instrument profiles: {e}");
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Instrument profiles watcher error: {e}"),
+            })?;
+        // 编辑器常以替换文件的方式保存，因此监听所在目录而非文件本身
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    }
+}
+
+fn read_profiles(path: &Path) -> Result<InstrumentProfileMap> {
+    let profiles_str = fs::read_to_string(path)?;
+    Ok(toml::from_str(&profiles_str)?)
+}
+
+/// 合约的结算方式
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContractType {
+    /// 正向合约，以计价货币结算，size为标的资产的数量
+    #[default]
+    Linear,
+    /// 反向合约，以标的资产结算，size为以计价货币计的面值
+    Inverse,
+}
+
+impl ContractType {
+    /// 带方向的持仓规模在给定价格下的价值，以结算货币计。开仓时现金减少该值，平仓时增加该值，两者之差即为盈亏。
+    ///
+    /// 反向合约做多相当于以币做空计价货币，价值为 -size / price
+    pub fn value(&self, size: f64, price: f64) -> f64 {
+        match self {
+            ContractType::Linear => size * price,
+            ContractType::Inverse => -size / price,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
 pub struct InstrumentProfile {
     /// The size of the underlying asset for each unit of the contract
     pub size_scale: f64,
     pub size_digits: i32,
     pub price_digits: i32,
+    /// The minimum size of an order
+    pub min_size: f64,
+    /// The size of an order must be a multiple of the lot size
+    pub lot_size: f64,
+    /// 缺省为正向合约
+    #[serde(default)]
+    pub contract_type: ContractType,
+}
+
+impl InstrumentProfile {
+    /// Round the size down to a multiple of the lot size
+    pub fn round_size(&self, size: f64) -> f64 {
+        // 容忍浮点误差，避免恰为整数倍的size被向下取整
+        let lots = (size / self.lot_size + 1e-9).floor();
+        let factor = 10f64.powi(self.size_digits);
+        (lots * self.lot_size * factor).round() / factor
+    }
+
+    /// Round the size down to a multiple of the lot size. Return None if the rounded size is below
+    /// the minimum size.
+    pub fn conform_size(&self, size: f64) -> Option<f64> {
+        let size = self.round_size(size);
+        if size < self.min_size {
+            None
+        } else {
+            Some(size)
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::instruments_profile::INSTRUMENT_PROFILES;
+    use std::fs;
+
+    use crate::{instruments_profile::INSTRUMENT_PROFILES, types::InstId};
+
+    use super::{ContractType, InstrumentProfile, InstrumentProfiles};
 
     #[test]
     fn test() {
         assert!(!INSTRUMENT_PROFILES.is_empty());
         dbg!(&INSTRUMENT_PROFILES);
     }
+
+    #[test]
+    fn test_conform_size() {
+        let profile = InstrumentProfile {
+            size_scale: 1.,
+            size_digits: 1,
+            price_digits: 2,
+            min_size: 1.,
+            lot_size: 0.5,
+            contract_type: ContractType::Linear,
+        };
+        assert_eq!(profile.round_size(2.7), 2.5);
+        assert_eq!(profile.round_size(3.0), 3.0);
+        assert_eq!(profile.conform_size(1.2), Some(1.0));
+        assert_eq!(profile.conform_size(0.9), None);
+
+        let profile = INSTRUMENT_PROFILES.get(&InstId::EthUsdtSwap).unwrap();
+        assert_eq!(profile.round_size(0.3), 0.3);
+        assert_eq!(profile.round_size(1.239), 1.23);
+        assert_eq!(profile.conform_size(0.005), None);
+    }
+
+    #[test]
+    fn test_contract_type() {
+        let profile: InstrumentProfile = toml::from_str(
+            "size_scale = 100\nsize_digits = 0\nprice_digits = 1\nmin_size = 1\nlot_size = 1\ncontract_type = \"inverse\"\n",
+        )
+        .unwrap();
+        assert_eq!(profile.contract_type, ContractType::Inverse);
+        // 缺省为正向合约
+        let profile = INSTRUMENT_PROFILES.get(&InstId::EthUsdtSwap).unwrap();
+        assert_eq!(profile.contract_type, ContractType::Linear);
+
+        // 价格从100涨到110，正向合约盈利10，反向合约盈利 100 * (1/100 - 1/110) 个币
+        let pnl = |contract_type: ContractType, size| {
+            contract_type.value(size, 110.) - contract_type.value(size, 100.)
+        };
+        assert_eq!(pnl(ContractType::Linear, 1.), 10.);
+        assert!((pnl(ContractType::Inverse, 100.) - (1. - 100. / 110.)).abs() < 1e-12);
+        assert!((pnl(ContractType::Inverse, -100.) + (1. - 100. / 110.)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_reload() {
+        fn profile_toml(price_digits: i32) -> String {
+            format!(
+                "[ETH-USDT-SWAP]\nsize_scale = 0.1\nsize_digits = 2\nprice_digits = {price_digits}\nmin_size = 0.01\nlot_size = 0.01\n"
+            )
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "instrument_profiles_reload_{}.toml",
+            std::process::id()
+        ));
+        fs::write(&path, profile_toml(2)).unwrap();
+        let profiles = InstrumentProfiles::load(&path).unwrap();
+        let snapshot = profiles.snapshot();
+        assert_eq!(profiles.get(&InstId::EthUsdtSwap).unwrap().price_digits, 2);
+
+        fs::write(&path, profile_toml(3)).unwrap();
+        profiles.reload().unwrap();
+        assert_eq!(profiles.get(&InstId::EthUsdtSwap).unwrap().price_digits, 3);
+        // 之前取得的快照不受影响
+        assert_eq!(snapshot[&InstId::EthUsdtSwap].price_digits, 2);
+
+        // 文件无效时保留原有的产品信息
+        fs::write(&path, "invalid").unwrap();
+        assert!(profiles.reload().is_err());
+        assert_eq!(profiles.get(&InstId::EthUsdtSwap).unwrap().price_digits, 3);
+
+        fs::remove_file(&path).unwrap();
+    }
 }
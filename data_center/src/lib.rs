@@ -1,4 +1,7 @@
+pub mod binance_api;
+pub mod exchange;
 pub mod instruments_profile;
+pub mod metrics;
 pub mod okx_api;
 pub mod sql;
 pub mod types;
@@ -9,8 +12,9 @@ use once_cell::sync::Lazy;
 use serde::Deserialize;
 use smartstring::alias::String;
 
-pub use types::{Data, OrderPush, Action};
-pub use terminal::Terminal;
+pub use exchange::Exchange;
+pub use types::{Data, OrderBook, OrderPush, Action};
+pub use terminal::{HistorySpec, Terminal};
 
 static CONFIG: Lazy<Config> = Lazy::new(|| {
     dotenvy::dotenv_override()
@@ -21,11 +25,61 @@ static CONFIG: Lazy<Config> = Lazy::new(|| {
 #[derive(Deserialize, Debug)]
 struct Config {
     pg_host: String,
+    /// 数据库连接池的最大连接数
+    #[serde(default = "default_pg_max_connections")]
+    pg_max_connections: u32,
+    /// 等待数据库连接的毫秒数
+    #[serde(default = "default_pg_acquire_timeout")]
+    pg_acquire_timeout: u64,
+    /// 空闲的数据库连接保留的毫秒数，为0时不关闭空闲连接
+    #[serde(default = "default_pg_idle_timeout")]
+    pg_idle_timeout: u64,
     api_key: String,
     secret_key: String,
     passphrase: String,
+    /// 币安的secret key，仅在使用币安的签名请求时需要
+    #[serde(default)]
+    binance_secret_key: String,
+    /// 监控服务端口，未设置时不启动
+    #[serde(default)]
+    metrics_port: Option<u16>,
     heartbeat_interval: u64,
     heartbeat_timeout: u64,
+    #[serde(default)]
+    heartbeat_policy: utils::HeartbeatPolicy,
+    /// 每order_rate_period毫秒最多发送的下单、改单、撤单请求数
+    #[serde(default = "default_order_rate_limit")]
+    order_rate_limit: u32,
+    #[serde(default = "default_order_rate_period")]
+    order_rate_period: u64,
+    /// 连接时等待每个订阅确认的毫秒数，为0时不等待确认
+    #[serde(default = "default_subscribe_timeout")]
+    subscribe_timeout: u64,
+}
+
+fn default_pg_max_connections() -> u32 {
+    50
+}
+
+fn default_pg_acquire_timeout() -> u64 {
+    30_000
+}
+
+fn default_pg_idle_timeout() -> u64 {
+    600_000
+}
+
+/// OKX下单频率限制为每产品2秒60次
+fn default_order_rate_limit() -> u32 {
+    60
+}
+
+fn default_order_rate_period() -> u64 {
+    2000
+}
+
+fn default_subscribe_timeout() -> u64 {
+    10_000
 }
 
 #[cfg(test)]
@@ -0,0 +1,194 @@
+use std::{
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+};
+
+use anyhow::Result;
+use http_body_util::Full;
+use hyper::{Request, Response, body::Bytes, server::conn::http1, service::service_fn};
+use hyper_util::rt::TokioIo;
+use once_cell::sync::Lazy;
+use tokio::net::TcpListener;
+
+use crate::CONFIG;
+
+/// 实盘运行时的监控指标。各字段均为原子量，可在任意异步任务中低成本更新。
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+#[derive(Default)]
+pub struct Metrics {
+    orders_sent: AtomicU64,
+    fills_received: AtomicU64,
+    /// f64的比特位
+    position: AtomicU64,
+    /// Unix millis timestamp
+    last_bbo_ts: AtomicI64,
+    reconnects: AtomicU64,
+    /// 连接因WebSocket错误而中断的次数，不包括正常关闭
+    ws_errors: AtomicU64,
+    /// 最近一次心跳的往返时间，单位为微秒
+    heartbeat_rtt_us: AtomicU64,
+}
+
+impl Metrics {
+    pub fn inc_orders_sent(&self) {
+        self.orders_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_fills_received(&self) {
+        self.fills_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_position(&self, position: f64) {
+        self.position.store(position.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn position(&self) -> f64 {
+        f64::from_bits(self.position.load(Ordering::Relaxed))
+    }
+
+    pub fn set_last_bbo_ts(&self, ts: i64) {
+        self.last_bbo_ts.store(ts, Ordering::Relaxed);
+    }
+
+    pub fn inc_reconnects(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_ws_errors(&self) {
+        self.ws_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_heartbeat_rtt(&self, rtt: std::time::Duration) {
+        self.heartbeat_rtt_us
+            .store(rtt.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// 以Prometheus文本格式输出
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let mut write_metric =
+            |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+                writeln!(text, "# HELP {name} {help}").unwrap();
+                writeln!(text, "# TYPE {name} {kind}").unwrap();
+                writeln!(text, "{name} {value}").unwrap();
+            };
+        write_metric(
+            "ac_orders_sent_total",
+            "counter",
+            "Number of orders sent to the exchange.",
+            &self.orders_sent.load(Ordering::Relaxed),
+        );
+        write_metric(
+            "ac_fills_received_total",
+            "counter",
+            "Number of fills received from the exchange.",
+            &self.fills_received.load(Ordering::Relaxed),
+        );
+        write_metric(
+            "ac_position",
+            "gauge",
+            "Current position in contracts.",
+            &self.position(),
+        );
+        write_metric(
+            "ac_last_bbo_timestamp_ms",
+            "gauge",
+            "Timestamp of the last received BBO in unix millis.",
+            &self.last_bbo_ts.load(Ordering::Relaxed),
+        );
+        write_metric(
+            "ac_reconnects_total",
+            "counter",
+            "Number of websocket reconnections.",
+            &self.reconnects.load(Ordering::Relaxed),
+        );
+        write_metric(
+            "ac_ws_errors_total",
+            "counter",
+            "Number of websocket connections ended by an error.",
+            &self.ws_errors.load(Ordering::Relaxed),
+        );
+        write_metric(
+            "ac_heartbeat_rtt_seconds",
+            "gauge",
+            "Round trip time of the last heartbeat.",
+            &(self.heartbeat_rtt_us.load(Ordering::Relaxed) as f64 / 1e6),
+        );
+        text
+    }
+}
+
+/// 配置中的监控端口，未配置时不启动监控服务
+pub fn configured_port() -> Option<u16> {
+    CONFIG.metrics_port
+}
+
+/// 在给定端口上提供/metrics服务
+pub async fn serve(port: u16) -> Result<()> {
+    let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).await?;
+    serve_listener(listener).await
+}
+
+pub async fn serve_listener(listener: TcpListener) -> Result<()> {
+    tracing::info!("Serving metrics on {}", listener.local_addr()?);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            let service = service_fn(|_req: Request<hyper::body::Incoming>| async {
+                let response = Response::builder()
+                    .header("Content-Type", "text/plain; version=0.0.4")
+                    .body(Full::new(Bytes::from(METRICS.render())))
+                    .unwrap();
+                Ok::<_, Infallible>(response)
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::warn!("Error serving metrics: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        METRICS.inc_orders_sent();
+        METRICS.inc_fills_received();
+        METRICS.set_position(0.5);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_listener(listener));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        for name in [
+            "ac_orders_sent_total",
+            "ac_fills_received_total",
+            "ac_last_bbo_timestamp_ms",
+            "ac_reconnects_total",
+            "ac_heartbeat_rtt_seconds",
+        ] {
+            assert!(response.contains(&format!("# TYPE {name} ")), "{name}");
+        }
+        assert!(response.contains("ac_fills_received_total 1"));
+        assert!(response.contains("ac_position 0.5"));
+    }
+}
@@ -1,22 +1,28 @@
 pub mod actions;
+pub(crate) mod books;
 pub(crate) mod pushes;
+pub mod rest;
 pub(crate) mod types;
 
 use core::{pin::Pin, task::Poll};
-use std::{task::Context, time::Duration};
+use std::{collections::VecDeque, task::Context, time::Duration};
 
 use crate::{
     CONFIG,
-    types::{Action, Data},
+    exchange::Exchange,
+    metrics::METRICS,
+    types::{Action, ConnState, Data, InstId, OrderPush},
 };
 use anyhow::{Result, anyhow, bail};
 use base64::Engine;
+use books::LocalBook;
 use chrono::Utc;
 use futures::{Sink, Stream, ready};
 use futures_util::{SinkExt, StreamExt};
 use hmac::{Hmac, Mac};
 use pin_project::pin_project;
-use pushes::Push;
+use pushes::{OkxData, OpResponse, Push};
+use rustc_hash::FxHashMap;
 use sha2::Sha256;
 use tokio_tungstenite::{
     connect_async,
@@ -24,12 +30,57 @@ use tokio_tungstenite::{
 };
 use utils::Duplex;
 
-use crate::utils::{AutoReconnect, Heartbeat};
+use crate::utils::{AutoReconnect, Heartbeat, RateLimiter};
 
 const PUBLIC_WS_URL: &str = "wss://ws.okx.com:8443/ws/v5/public";
 const PRIVATE_WS_URL: &str = "wss://ws.okx.com:8443/ws/v5/private";
 const PUBLIC_WS_URL_SIMU: &str = "wss://wspap.okx.com:8443/ws/v5/public";
 const PRIVATE_WS_URL_SIMU: &str = "wss://wspap.okx.com:8443/ws/v5/private";
+/// 登录与订阅时，等待每条确认消息的最长时间
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// OKX交易所。is_simu为真时连接模拟盘。
+#[derive(Clone, Copy, Default)]
+pub struct Okx {
+    pub is_simu: bool,
+}
+
+impl Okx {
+    pub fn new(is_simu: bool) -> Self {
+        Self { is_simu }
+    }
+}
+
+impl Exchange for Okx {
+    type Action = Action;
+    type Data = Data;
+
+    async fn connect(
+        &self,
+        subscriptions: Vec<Action>,
+    ) -> Result<impl Duplex<Action, anyhow::Error, Data> + use<>> {
+        connect_adapted(subscriptions, self.is_simu).await
+    }
+
+    async fn open_orders(&self, inst_id: InstId) -> Result<Vec<OrderPush>> {
+        rest::get_pending_orders(inst_id, self.is_simu).await
+    }
+
+    fn sign_login(&self, timestamp: i64) -> String {
+        sign(&format!("{timestamp}GET/users/self/verify"))
+    }
+}
+
+/// 生成签名：Base64( HMAC-SHA256( timestamp + METHOD + REQUEST_PATH + body, SECRET_KEY ) )
+pub(crate) fn sign(payload: &str) -> String {
+    // 计算 HMAC-SHA256
+    let mut mac = Hmac::<Sha256>::new_from_slice(CONFIG.secret_key.as_bytes()).unwrap();
+    mac.update(payload.as_bytes());
+    let result = mac.finalize().into_bytes();
+
+    // Base64 编码
+    base64::engine::general_purpose::STANDARD.encode(result)
+}
 
 #[derive(Clone, Copy)]
 pub enum OkxWsEndpoint {
@@ -63,34 +114,72 @@ where
 {
     #[pin]
     inner: S,
+    /// 握手期间收到的非事件消息，握手完成后先于新消息发出
+    buffered: VecDeque<Message>,
+    /// 批量请求的回复转换出的多条数据，尚未发出的部分
+    pending_data: VecDeque<Data>,
+    /// 已发出WebSocket错误对应的断开事件，之后Stream结束
+    is_terminated: bool,
+    /// 各产品由books频道维护的本地订单簿。随连接重建而清空，重新订阅后从全量推送开始
+    books: FxHashMap<InstId, LocalBook>,
+    /// 等待每个订阅确认的最长时间。为None时发出订阅后不等待确认
+    subscribe_timeout: Option<Duration>,
 }
 
 impl<S> OkxWsStream<S>
 where
     S: Duplex<Message, tungstenite::Error, Result<Message, tungstenite::Error>>,
 {
+    fn new(inner: S) -> Self {
+        Self {
+            inner,
+            buffered: VecDeque::new(),
+            pending_data: VecDeque::new(),
+            is_terminated: false,
+            books: FxHashMap::default(),
+            subscribe_timeout: Some(HANDSHAKE_TIMEOUT),
+        }
+    }
+
+    fn with_subscribe_timeout(mut self, subscribe_timeout: Option<Duration>) -> Self {
+        self.subscribe_timeout = subscribe_timeout;
+        self
+    }
+
+    /// 依次登录（私有频道）、等待登录确认、订阅、等待每个订阅的确认。全部确认后连接才可用，
+    /// 避免将只订阅了一部分的连接交给调用方。
+    async fn handshake(&mut self, is_private: bool, subscribe_actions: &[Action]) -> Result<()> {
+        if is_private {
+            self.login().await?;
+        }
+        self.subscribe(subscribe_actions).await
+    }
+
+    /// 等待下一条事件消息（login、subscribe、error等）。期间收到的其他消息暂存于buffered
+    async fn next_event(&mut self, timeout: Duration) -> Result<serde_json::Value> {
+        loop {
+            let msg = tokio::time::timeout(timeout, self.inner.next())
+                .await
+                .map_err(|_| anyhow!("Timeout waiting for event message"))?
+                .ok_or(anyhow!("Connection closed during handshake"))??;
+            if let Message::Text(text) = &msg
+                && let Ok(value) = serde_json::from_str::<serde_json::Value>(text)
+                && value.get("event").is_some()
+            {
+                return Ok(value);
+            }
+            self.buffered.push_back(msg);
+        }
+    }
+
     async fn login(&mut self) -> Result<()> {
         dotenvy::dotenv_override()
             .expect("Please set PG_HOST in the .env or the environment variables");
         let api_key = &CONFIG.api_key;
         let passphrase = &CONFIG.passphrase;
-        let secret_key = &CONFIG.secret_key;
-
-        /// 生成签名：Base64( HMAC-SHA256( timestamp + METHOD + REQUEST_PATH, SECRET_KEY ) )
-        fn build_sign(secret_key: &str, timestamp: i64) -> String {
-            // 拼接待签名字符串
-            let payload = format!("{timestamp}GET/users/self/verify");
 
-            // 计算 HMAC-SHA256
-            let mut mac = Hmac::<Sha256>::new_from_slice(secret_key.as_bytes()).unwrap();
-            mac.update(payload.as_bytes());
-            let result = mac.finalize().into_bytes();
-
-            // Base64 编码
-            base64::engine::general_purpose::STANDARD.encode(result)
-        }
         let timestamp = Utc::now().timestamp();
-        let sign = build_sign(&secret_key, timestamp);
+        let sign = Okx::default().sign_login(timestamp);
 
         let login_message = serde_json::json!({
             "op": "login",
@@ -107,39 +196,78 @@ where
             .send(login_message.to_string().into())
             .await
             .map_err(|e| anyhow!("Failed to send login message: {e}"))?;
-        let msg = self.inner.next().await.unwrap().unwrap();
-        let msg: serde_json::Value = serde_json::from_str(&msg.to_string()).unwrap();
-        let event = msg["event"].as_str().unwrap();
-        if event != "login" {
+        let msg = self.next_event(HANDSHAKE_TIMEOUT).await?;
+        if msg["event"] != "login" {
             bail!("Failed to login: {msg:#?}")
         }
         tracing::info!("Login successful");
         Ok(())
     }
+
+    /// 发出所有订阅，并等待每个订阅的确认。任一订阅失败或超时则返回错误
+    async fn subscribe(&mut self, subscribe_actions: &[Action]) -> Result<()> {
+        for action in subscribe_actions {
+            self.send(action.clone()).await?;
+        }
+        let Some(subscribe_timeout) = self.subscribe_timeout else {
+            return Ok(());
+        };
+        let mut pending_acks = subscribe_actions.len();
+        while pending_acks > 0 {
+            let msg = self.next_event(subscribe_timeout).await?;
+            match msg["event"].as_str() {
+                Some("subscribe") => {
+                    tracing::info!("Subscribed: {}", msg["arg"]);
+                    pending_acks -= 1;
+                }
+                Some("error") => bail!("Failed to subscribe: {msg:#?}"),
+                _ => tracing::info!("Receive event during handshake: {msg}"),
+            }
+        }
+        Ok(())
+    }
 }
 
+/// 连接并订阅。订阅确认的等待时间由SUBSCRIBE_TIMEOUT配置，为0时不等待确认
 pub async fn connect(
     endpoint: OkxWsEndpoint,
     subscribe_actions: Vec<Action>,
 ) -> Result<impl Duplex<Action, anyhow::Error, Data>> {
+    let subscribe_timeout =
+        (CONFIG.subscribe_timeout > 0).then(|| Duration::from_millis(CONFIG.subscribe_timeout));
+    connect_url(
+        endpoint.url(),
+        endpoint.is_private(),
+        subscribe_actions,
+        subscribe_timeout,
+    )
+    .await
+}
+
+async fn connect_url(
+    url: &str,
+    is_private: bool,
+    subscribe_actions: Vec<Action>,
+    subscribe_timeout: Option<Duration>,
+) -> Result<impl Duplex<Action, anyhow::Error, Data> + use<>> {
+    let url = url.to_owned();
     let make_connection = move || {
+        let url = url.clone();
         let subscribe_actions = subscribe_actions.clone();
         async move {
-            let (ws_stream, _) = connect_async(endpoint.url()).await?;
+            let (ws_stream, _) = connect_async(url).await?;
             let ws_stream = with_heartbeat(ws_stream);
-            let mut ws_stream = OkxWsStream { inner: ws_stream };
-            if endpoint.is_private() {
-                ws_stream.login().await?;
-            }
-            for request in subscribe_actions {
-                ws_stream.send(request).await?
-            }
+            let mut ws_stream =
+                OkxWsStream::new(ws_stream).with_subscribe_timeout(subscribe_timeout);
+            ws_stream.handshake(is_private, &subscribe_actions).await?;
 
             Ok(ws_stream)
         }
     };
 
-    let ws_stream = AutoReconnect::new(make_connection).await?;
+    let ws_stream = AutoReconnect::new(make_connection)
+        .await?
+        .with_state_events(Data::ConnectionState);
     let ws_stream = Box::pin(ws_stream);
     Ok(ws_stream)
 }
@@ -197,18 +325,33 @@ where
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let mut this = self.project();
 
+        if *this.is_terminated {
+            return Poll::Ready(None);
+        }
+        if let Some(data) = this.pending_data.pop_front() {
+            return Poll::Ready(Some(data));
+        }
+
         loop {
-            // 1. 取出下一条消息；若已结束直接返回 Ready(None)
-            let Some(msg) = ready!(this.inner.as_mut().poll_next(cx)) else {
-                return Poll::Ready(None);
+            // 1. 先取出握手期间暂存的消息，再取出下一条消息；若已结束直接返回 Ready(None)
+            let msg = match this.buffered.pop_front() {
+                Some(msg) => Ok(msg),
+                None => match ready!(this.inner.as_mut().poll_next(cx)) {
+                    Some(msg) => msg,
+                    None => return Poll::Ready(None),
+                },
             };
 
-            // 2. 连接层面先处理错误
+            // 2. 连接层面先处理错误：发出带错误信息的断开事件，下一次轮询时结束，
+            //    以区分正常关闭与连接错误
             let msg = match msg {
                 Ok(m) => m,
                 Err(e) => {
                     tracing::error!("WebSocket error: {e}");
-                    return Poll::Ready(None);
+                    METRICS.inc_ws_errors();
+                    *this.is_terminated = true;
+                    let state = ConnState::Disconnected(e.to_string().into());
+                    return Poll::Ready(Some(Data::ConnectionState(state)));
                 }
             };
 
@@ -220,7 +363,17 @@ where
 
             tracing::debug!("Receive message: {text}");
 
-            // 4. 反序列化 OKX push 帧
+            // 4. 下单、改单、撤单请求的回复
+            if let Ok(response) = serde_json::from_str::<OpResponse>(&text) {
+                tracing::info!("Receive op response: {response:?}");
+                this.pending_data.extend(response.into_data());
+                match this.pending_data.pop_front() {
+                    Some(data) => return Poll::Ready(Some(data)),
+                    None => continue,
+                }
+            }
+
+            // 5. 反序列化 OKX push 帧
             let push: Push = match serde_json::from_str(&text) {
                 Ok(p) => p,
                 Err(_) => {
@@ -229,14 +382,39 @@ where
                 }
             };
 
-            // 5. 事件帧（例如 subscribe、unsubscribe、error 等）
+            // 6. 事件帧（例如 subscribe、unsubscribe、error 等）
             if push.event.is_some() {
                 tracing::info!("Receive event: {push:#?}");
                 continue;
             }
 
-            // 6. 数据帧
-            match Data::try_from_okx_push(push) {
+            // 7. 数据帧
+            let okx_data = match OkxData::try_from_push(push) {
+                Ok(okx_data) => okx_data,
+                Err(e) => {
+                    tracing::info!("Fail to convert push to data: {e}");
+                    continue;
+                }
+            };
+
+            // 8. books频道的推送先应用到本地订单簿。校验失败时结束Stream，
+            // 由AutoReconnect重连并重新订阅，以全量推送重建订单簿
+            if let OkxData::Books(inst_id, action, data) = okx_data {
+                let book = this.books.entry(inst_id).or_default();
+                let order_book = data.ts().and_then(|ts| {
+                    book.apply(action, data)?;
+                    book.try_to_order_book(inst_id, ts)
+                });
+                match order_book {
+                    Ok(order_book) => return Poll::Ready(Some(Data::OrderBook(order_book))),
+                    Err(e) => {
+                        tracing::error!("Book of {inst_id:?} is out of sync: {e}");
+                        return Poll::Ready(None);
+                    }
+                }
+            }
+
+            match Data::try_from_okx_data(okx_data) {
                 Ok(data) => return Poll::Ready(Some(data)),
                 Err(e) => {
                     tracing::info!("Fail to convert push to data: {e}");
@@ -257,17 +435,45 @@ pub struct OkxWsStreamAdapted<S> {
 
     is_public_ended: bool,
     is_private_ended: bool,
+    /// 限制下单、改单、撤单等请求的频率，订阅不受限制
+    rate_limiter: Option<RateLimiter>,
 }
 
 impl Action {
     fn is_private(&self) -> bool {
         match self {
-            Action::SubscribeTrades(_) | Action::SubscribeBboTbt(_) => false,
+            Action::SubscribeTrades(_)
+            | Action::SubscribeBboTbt(_)
+            | Action::SubscribeFundingRate(_)
+            | Action::SubscribeOpenInterest(_)
+            | Action::SubscribeMarkPrice(_)
+            | Action::SubscribeBooks(_) => false,
             Action::SubscribeOrders(_)
+            | Action::SubscribePositions(_)
             | Action::LimitOrder { .. }
             | Action::MarketOrder { .. }
             | Action::AmendOrder { .. }
-            | Action::CancelOrder { .. } => true,
+            | Action::CancelOrder { .. }
+            | Action::BatchOrders(_) => true,
+        }
+    }
+
+    /// 该请求包含的受交易所频率限制的下单、改单、撤单数。批量请求中的每个订单分别计数
+    fn order_count(&self) -> usize {
+        match self {
+            Action::SubscribeTrades(_)
+            | Action::SubscribeBboTbt(_)
+            | Action::SubscribeOrders(_)
+            | Action::SubscribePositions(_)
+            | Action::SubscribeFundingRate(_)
+            | Action::SubscribeOpenInterest(_)
+            | Action::SubscribeMarkPrice(_)
+            | Action::SubscribeBooks(_) => 0,
+            Action::LimitOrder { .. }
+            | Action::MarketOrder { .. }
+            | Action::AmendOrder { .. }
+            | Action::CancelOrder { .. } => 1,
+            Action::BatchOrders(actions) => actions.len(),
         }
     }
 }
@@ -312,6 +518,10 @@ where
         cx: &mut Context<'_>,
     ) -> Poll<std::result::Result<(), Self::Error>> {
         let mut this = self.project();
+        // 不知道下一个请求的类型，因此令牌耗尽时所有请求都需等待
+        if let Some(rate_limiter) = this.rate_limiter {
+            ready!(rate_limiter.poll_ready(cx));
+        }
         ready!(this.public.as_mut().poll_ready(cx))?;
         ready!(this.private.as_mut().poll_ready(cx))?;
         Poll::Ready(Ok(()))
@@ -319,6 +529,11 @@ where
 
     fn start_send(self: Pin<&mut Self>, item: Action) -> std::result::Result<(), Self::Error> {
         let this = self.project();
+        if let Some(rate_limiter) = this.rate_limiter {
+            for _ in 0..item.order_count() {
+                rate_limiter.acquire();
+            }
+        }
         let mut sink = if item.is_private() {
             this.private
         } else {
@@ -371,6 +586,10 @@ pub async fn connect_adapted(
         private: private_ws,
         is_public_ended: false,
         is_private_ended: false,
+        rate_limiter: Some(RateLimiter::new(
+            CONFIG.order_rate_limit,
+            Duration::from_millis(CONFIG.order_rate_period),
+        )),
     };
     Ok(adapted_ws)
 }
@@ -384,4 +603,267 @@ where
         Duration::from_millis(CONFIG.heartbeat_interval),
         Duration::from_millis(CONFIG.heartbeat_timeout),
     )
+    .with_policy(CONFIG.heartbeat_policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{net::TcpListener, sync::oneshot};
+    use tokio_tungstenite::accept_async;
+
+    use super::*;
+    use crate::types::InstId;
+
+    /// 模拟服务端先确认第一个订阅并推送数据，收到信号后才确认第二个订阅
+    #[tokio::test]
+    async fn test_handshake_waits_for_subscribe_acks() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (ack_tx, ack_rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let mut ws_stream = accept_async(tcp_stream).await.unwrap();
+            let mut args = vec![];
+            for _ in 0..2 {
+                let msg = ws_stream.next().await.unwrap().unwrap();
+                let request: serde_json::Value =
+                    serde_json::from_str(msg.to_text().unwrap()).unwrap();
+                assert_eq!(request["op"], "subscribe");
+                args.push(request["args"][0].clone());
+            }
+            let ack = |arg: &serde_json::Value| {
+                let ack =
+                    serde_json::json!({"event": "subscribe", "arg": arg, "connId": "a4d3ae55"});
+                Message::text(ack.to_string())
+            };
+            ws_stream.send(ack(&args[0])).await.unwrap();
+            let push = r#"{"arg":{"channel":"bbo-tbt","instId":"ETH-USDT-SWAP"},"data":[{"asks":[["2536.52","40","0","3"]],"bids":[["2535.19","31","0","2"]],"ts":"1670324386802","seqId":1}]}"#;
+            ws_stream.send(Message::text(push)).await.unwrap();
+            ack_rx.await.unwrap();
+            ws_stream.send(ack(&args[1])).await.unwrap();
+            ws_stream
+        });
+
+        let (ws_stream, _) = connect_async(&url).await.unwrap();
+        let mut ws_stream = OkxWsStream::new(ws_stream);
+        let actions = [
+            Action::SubscribeBboTbt(InstId::EthUsdtSwap),
+            Action::SubscribeTrades(InstId::EthUsdtSwap),
+        ];
+        {
+            let handshake = ws_stream.handshake(false, &actions);
+            tokio::pin!(handshake);
+            // 只收到一个订阅确认时，连接尚未就绪
+            let result = tokio::time::timeout(Duration::from_millis(200), &mut handshake).await;
+            assert!(result.is_err());
+
+            ack_tx.send(()).unwrap();
+            handshake.await.unwrap();
+        }
+
+        // 握手期间收到的数据在握手完成后发出，不会丢失
+        let data = ws_stream.next().await.unwrap();
+        assert!(matches!(data, Data::Bbo(bbo) if bbo.ts.as_millis() == 1670324386802));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_subscribe_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let mut ws_stream = accept_async(tcp_stream).await.unwrap();
+            ws_stream.next().await.unwrap().unwrap();
+            let error =
+                r#"{"event":"error","code":"60012","msg":"Invalid request","connId":"a4d3ae55"}"#;
+            ws_stream.send(Message::text(error)).await.unwrap();
+            ws_stream
+        });
+
+        let (ws_stream, _) = connect_async(&url).await.unwrap();
+        let mut ws_stream = OkxWsStream::new(ws_stream);
+        let actions = [Action::SubscribeTrades(InstId::EthUsdtSwap)];
+        assert!(ws_stream.handshake(false, &actions).await.is_err());
+    }
+
+    /// 模拟服务端对订阅回复error，或不回复任何确认
+    async fn spawn_subscribe_server(error: Option<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let mut ws_stream = accept_async(tcp_stream).await.unwrap();
+            ws_stream.next().await.unwrap().unwrap();
+            if let Some(error) = error {
+                ws_stream.send(Message::text(error)).await.unwrap();
+            }
+            // 保持连接直到客户端断开
+            while ws_stream.next().await.is_some() {}
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_connect_subscribe_confirmation() {
+        let actions = vec![Action::SubscribeTrades(InstId::EthUsdtSwap)];
+        let timeout = Some(Duration::from_millis(200));
+
+        // 订阅失败时connect返回错误
+        let error =
+            r#"{"event":"error","code":"60012","msg":"Invalid request","connId":"a4d3ae55"}"#;
+        let url = spawn_subscribe_server(Some(error)).await;
+        assert!(
+            connect_url(&url, false, actions.clone(), timeout)
+                .await
+                .is_err()
+        );
+
+        // 超时未收到确认时同样返回错误，不等待确认时立即返回
+        let url = spawn_subscribe_server(None).await;
+        assert!(
+            connect_url(&url, false, actions.clone(), timeout)
+                .await
+                .is_err()
+        );
+        let url = spawn_subscribe_server(None).await;
+        assert!(connect_url(&url, false, actions, None).await.is_ok());
+    }
+
+    /// 订单簿校验失败时Stream结束，以便AutoReconnect重新订阅
+    #[tokio::test]
+    async fn test_books_checksum_mismatch_ends_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let mut ws_stream = accept_async(tcp_stream).await.unwrap();
+            let snapshot = r#"{"arg":{"channel":"books","instId":"ETH-USDT-SWAP"},"action":"snapshot","data":[{"asks":[["3366.8","9","0","3"],["3368","8","0","4"]],"bids":[["3366.1","7","0","3"],["3366","6","0","4"]],"ts":"1597026383085","checksum":-1881014294}]}"#;
+            let update = r#"{"arg":{"channel":"books","instId":"ETH-USDT-SWAP"},"action":"update","data":[{"asks":[],"bids":[["3366.1","8","0","3"]],"ts":"1597026383185","checksum":-1881014294}]}"#;
+            ws_stream.send(Message::text(snapshot)).await.unwrap();
+            ws_stream.send(Message::text(update)).await.unwrap();
+            ws_stream
+        });
+
+        let (ws_stream, _) = connect_async(&url).await.unwrap();
+        let mut ws_stream = OkxWsStream::new(ws_stream);
+        let data = ws_stream.next().await.unwrap();
+        let Data::OrderBook(order_book) = data else {
+            panic!("Expected order book: {data:?}");
+        };
+        assert_eq!(order_book.best_bid().unwrap().price, 3366.1);
+        assert_eq!(order_book.asks.len(), 2);
+        assert!(ws_stream.next().await.is_none());
+    }
+
+    /// 服务端不经关闭握手直接断开TCP连接，Stream结束前发出带错误信息的断开事件
+    #[tokio::test]
+    async fn test_disconnected_before_stream_end() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let ws_stream = accept_async(tcp_stream).await.unwrap();
+            // 不发送关闭帧，直接关闭底层TCP连接
+            drop(ws_stream);
+        });
+
+        let (ws_stream, _) = connect_async(&url).await.unwrap();
+        let mut ws_stream = OkxWsStream::new(ws_stream);
+        let data = ws_stream.next().await.unwrap();
+        assert!(
+            matches!(data, Data::ConnectionState(ConnState::Disconnected(_))),
+            "Expected disconnected: {data:?}"
+        );
+        assert!(ws_stream.next().await.is_none());
+    }
+
+    /// 服务端正常关闭连接时，Stream直接结束，不发出断开事件
+    #[tokio::test]
+    async fn test_no_disconnected_after_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let mut ws_stream = accept_async(tcp_stream).await.unwrap();
+            ws_stream.close(None).await.unwrap();
+            // 等待客户端回应关闭帧
+            while ws_stream.next().await.is_some() {}
+        });
+
+        let (ws_stream, _) = connect_async(&url).await.unwrap();
+        let mut ws_stream = OkxWsStream::new(ws_stream);
+        assert!(ws_stream.next().await.is_none());
+    }
+
+    /// 记录发送时间的连接，不推送数据
+    struct RecordingConnection {
+        sent: Vec<(tokio::time::Instant, Action)>,
+    }
+
+    impl Stream for RecordingConnection {
+        type Item = Data;
+
+        fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Data>> {
+            Poll::Pending
+        }
+    }
+
+    impl Sink<Action> for RecordingConnection {
+        type Error = anyhow::Error;
+
+        fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, item: Action) -> Result<()> {
+            self.sent.push((tokio::time::Instant::now(), item));
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_orders() {
+        let period = Duration::from_millis(100);
+        let mut adapted_ws = OkxWsStreamAdapted {
+            public: RecordingConnection { sent: vec![] },
+            private: RecordingConnection { sent: vec![] },
+            is_public_ended: false,
+            is_private_ended: false,
+            rate_limiter: Some(RateLimiter::new(5, period)),
+        };
+        let start = tokio::time::Instant::now();
+        // 订阅不消耗令牌
+        for _ in 0..10 {
+            adapted_ws
+                .send(Action::SubscribeOrders(InstId::EthUsdtSwap))
+                .await
+                .unwrap();
+        }
+        for i in 0..15 {
+            let order = Action::CancelOrder {
+                request_id: i.to_string().into(),
+                inst_id: InstId::EthUsdtSwap,
+                client_order_id: i.to_string().into(),
+            };
+            adapted_ws.send(order).await.unwrap();
+        }
+
+        let sent = &adapted_ws.private.sent;
+        assert_eq!(sent.len(), 25);
+        let order_times: Vec<_> = sent[10..].iter().map(|(ts, _)| *ts - start).collect();
+        // 桶中的5个令牌立即可用，之后每period / 5补充一个
+        assert!(order_times[4] < period / 2);
+        for (i, order_time) in order_times.iter().enumerate().skip(5) {
+            assert!(*order_time >= period * (i as u32 - 4) / 5);
+        }
+        assert!(order_times[14] < period * 3);
+    }
 }
@@ -8,83 +8,132 @@ use super::types::*;
 
 impl Action {
     pub fn to_message(&self) -> Message {
+        self.to_request().to_string().into()
+    }
+
+    fn to_request(&self) -> serde_json::Value {
         match self {
             Action::SubscribeTrades(inst_id) => {
-                serde_json::to_string(&Request::subscribe_trades(*inst_id))
-                    .unwrap()
-                    .into()
+                serde_json::to_value(Request::subscribe_trades(*inst_id)).unwrap()
             }
             Action::SubscribeBboTbt(inst_id) => {
-                serde_json::to_string(&Request::subscribe_bbo_tbt(*inst_id))
-                    .unwrap()
-                    .into()
+                serde_json::to_value(Request::subscribe_bbo_tbt(*inst_id)).unwrap()
             }
             Action::SubscribeOrders(inst_id) => {
                 let inst_type = match inst_id {
                     InstId::EthUsdtSwap | InstId::BtcUsdtSwap => InstType::Swap,
                 };
-                serde_json::to_string(&Request::subscribe_orders(inst_type, *inst_id))
-                    .unwrap()
-                    .into()
+                serde_json::to_value(Request::subscribe_orders(inst_type, *inst_id)).unwrap()
+            }
+            Action::SubscribePositions(inst_id) => {
+                let inst_type = match inst_id {
+                    InstId::EthUsdtSwap | InstId::BtcUsdtSwap => InstType::Swap,
+                };
+                serde_json::to_value(Request::subscribe_positions(inst_type, *inst_id)).unwrap()
+            }
+            Action::SubscribeFundingRate(inst_id) => {
+                serde_json::to_value(Request::subscribe_funding_rate(*inst_id)).unwrap()
+            }
+            Action::SubscribeOpenInterest(inst_id) => {
+                serde_json::to_value(Request::subscribe_open_interest(*inst_id)).unwrap()
+            }
+            Action::SubscribeMarkPrice(inst_id) => {
+                serde_json::to_value(Request::subscribe_mark_price(*inst_id)).unwrap()
+            }
+            Action::SubscribeBooks(inst_id) => {
+                serde_json::to_value(Request::subscribe_books(*inst_id)).unwrap()
             }
             Action::LimitOrder {
                 request_id,
                 side,
                 inst_id,
                 client_order_id,
+                td_mode,
                 size,
                 price,
-            } => serde_json::to_string(&Request::limit_order(
-                request_id.clone(),
-                *side,
-                *inst_id,
-                client_order_id.clone(),
-                size.clone(),
-                price.clone(),
-            ))
-            .unwrap()
-            .into(),
+                reduce_only,
+            } => serde_json::to_value(
+                Request::limit_order(
+                    request_id.clone(),
+                    *side,
+                    *inst_id,
+                    client_order_id.clone(),
+                    *td_mode,
+                    size.clone(),
+                    price.clone(),
+                )
+                .with_reduce_only(*reduce_only),
+            )
+            .unwrap(),
             Action::MarketOrder {
                 request_id,
                 side,
                 inst_id,
                 client_order_id,
+                td_mode,
                 size,
-            } => serde_json::to_string(&Request::market_order(
-                request_id.clone(),
-                *side,
-                *inst_id,
-                client_order_id.clone(),
-                size.clone(),
-            ))
-            .unwrap()
-            .into(),
+                reduce_only,
+            } => serde_json::to_value(
+                Request::market_order(
+                    request_id.clone(),
+                    *side,
+                    *inst_id,
+                    client_order_id.clone(),
+                    *td_mode,
+                    size.clone(),
+                )
+                .with_reduce_only(*reduce_only),
+            )
+            .unwrap(),
             Action::AmendOrder {
                 request_id,
                 inst_id,
                 client_order_id,
                 new_size,
                 new_price,
-            } => serde_json::to_string(&Request::amend_order(
+            } => serde_json::to_value(Request::amend_order(
                 request_id.clone(),
                 *inst_id,
                 client_order_id.clone(),
                 new_size.clone(),
                 new_price.clone(),
             ))
-            .unwrap()
-            .into(),
+            .unwrap(),
             Action::CancelOrder {
                 request_id,
                 inst_id,
                 client_order_id,
-            } => serde_json::to_string(&Request::cancel_order(
+            } => serde_json::to_value(Request::cancel_order(
                 request_id.clone(),
                 *inst_id,
                 client_order_id.clone(),
             ))
-            .unwrap()
-            .into(),
+            .unwrap(),
+            // 以第一个请求的id作为批量请求的id
+            Action::BatchOrders(actions) => {
+                let op = actions
+                    .first()
+                    .and_then(Action::batch_op)
+                    .expect("A batch must consist of orders, amendments or cancellations");
+                debug_assert!(actions.iter().all(|action| action.batch_op() == Some(op)));
+                let requests: Vec<_> = actions.iter().map(Action::to_request).collect();
+                let args: Vec<_> = requests.iter().map(|request| &request["args"][0]).collect();
+                serde_json::json!({
+                    "id": requests[0]["id"],
+                    "op": op,
+                    "args": args,
+                })
+            }
+        }
+    }
+
+    /// 该请求放入批量请求时，批量请求的op
+    pub fn batch_op(&self) -> Option<Op> {
+        match self {
+            Action::LimitOrder { .. } | Action::MarketOrder { .. } => Some(Op::BatchOrders),
+            Action::AmendOrder { .. } => Some(Op::BatchAmendOrders),
+            Action::CancelOrder { .. } => Some(Op::BatchCancelOrders),
+            _ => None,
         }
     }
 }
@@ -98,13 +147,16 @@ pub struct Request<A> {
     args: [A; 1],
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum Op {
     Subscribe,
     Order,
     AmendOrder,
     CancelOrder,
+    BatchOrders,
+    BatchAmendOrders,
+    BatchCancelOrders,
 }
 
 #[derive(Clone, Serialize)]
@@ -140,6 +192,46 @@ impl SubscribeArg {
             inst_id,
         }
     }
+
+    pub fn new_positions(inst_type: InstType, inst_id: InstId) -> Self {
+        Self {
+            channel: Channel::Positions,
+            inst_type: Some(inst_type),
+            inst_id,
+        }
+    }
+
+    pub fn new_funding_rate(inst_id: InstId) -> Self {
+        Self {
+            channel: Channel::FundingRate,
+            inst_type: None,
+            inst_id,
+        }
+    }
+
+    pub fn new_open_interest(inst_id: InstId) -> Self {
+        Self {
+            channel: Channel::OpenInterest,
+            inst_type: None,
+            inst_id,
+        }
+    }
+
+    pub fn new_mark_price(inst_id: InstId) -> Self {
+        Self {
+            channel: Channel::MarkPrice,
+            inst_type: None,
+            inst_id,
+        }
+    }
+
+    pub fn new_books(inst_id: InstId) -> Self {
+        Self {
+            channel: Channel::Books,
+            inst_type: None,
+            inst_id,
+        }
+    }
 }
 
 impl Request<SubscribeArg> {
@@ -170,6 +262,51 @@ impl Request<SubscribeArg> {
         }
     }
 
+    pub fn subscribe_positions(inst_type: InstType, inst_id: InstId) -> Self {
+        let arg = SubscribeArg::new_positions(inst_type, inst_id);
+        Self {
+            id: None,
+            op: Op::Subscribe,
+            args: [arg; 1],
+        }
+    }
+
+    pub fn subscribe_funding_rate(inst_id: InstId) -> Self {
+        let arg = SubscribeArg::new_funding_rate(inst_id);
+        Self {
+            id: None,
+            op: Op::Subscribe,
+            args: [arg; 1],
+        }
+    }
+
+    pub fn subscribe_open_interest(inst_id: InstId) -> Self {
+        let arg = SubscribeArg::new_open_interest(inst_id);
+        Self {
+            id: None,
+            op: Op::Subscribe,
+            args: [arg; 1],
+        }
+    }
+
+    pub fn subscribe_mark_price(inst_id: InstId) -> Self {
+        let arg = SubscribeArg::new_mark_price(inst_id);
+        Self {
+            id: None,
+            op: Op::Subscribe,
+            args: [arg; 1],
+        }
+    }
+
+    pub fn subscribe_books(inst_id: InstId) -> Self {
+        let arg = SubscribeArg::new_books(inst_id);
+        Self {
+            id: None,
+            op: Op::Subscribe,
+            args: [arg; 1],
+        }
+    }
+
     pub fn inst_id(&self) -> InstId {
         self.args[0].inst_id
     }
@@ -189,6 +326,8 @@ pub struct LimitOrderArg {
     ord_type: OrdType,
     sz: String,
     px: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    reduce_only: bool,
 }
 
 impl Request<LimitOrderArg> {
@@ -197,6 +336,7 @@ impl Request<LimitOrderArg> {
         side: Side,
         inst_id: InstId,
         client_order_id: String,
+        td_mode: TdMode,
         size: String,
         price: String,
     ) -> Self {
@@ -204,10 +344,11 @@ impl Request<LimitOrderArg> {
             side,
             inst_id,
             cl_ord_id: client_order_id,
-            td_mode: TdMode::Cross,
+            td_mode,
             ord_type: OrdType::Limit,
             sz: size,
             px: price,
+            reduce_only: false,
         };
         Self {
             id: Some(request_id),
@@ -215,6 +356,11 @@ impl Request<LimitOrderArg> {
             args: [arg; 1],
         }
     }
+
+    pub fn with_reduce_only(mut self, reduce_only: bool) -> Self {
+        self.args[0].reduce_only = reduce_only;
+        self
+    }
 }
 
 #[derive(Serialize, Clone)]
@@ -226,6 +372,8 @@ pub struct MarketOrderArg {
     td_mode: TdMode,
     ord_type: OrdType,
     sz: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    reduce_only: bool,
 }
 
 impl Request<MarketOrderArg> {
@@ -234,15 +382,17 @@ impl Request<MarketOrderArg> {
         side: Side,
         inst_id: InstId,
         client_order_id: String,
+        td_mode: TdMode,
         size: String,
     ) -> Self {
         let arg = MarketOrderArg {
             side,
             inst_id,
             cl_ord_id: client_order_id,
-            td_mode: TdMode::Cross,
+            td_mode,
             ord_type: OrdType::Market,
             sz: size,
+            reduce_only: false,
         };
         Self {
             id: Some(request_id),
@@ -250,6 +400,11 @@ impl Request<MarketOrderArg> {
             args: [arg; 1],
         }
     }
+
+    pub fn with_reduce_only(mut self, reduce_only: bool) -> Self {
+        self.args[0].reduce_only = reduce_only;
+        self
+    }
 }
 
 #[derive(Serialize, Clone)]
@@ -303,3 +458,106 @@ impl Request<CancelOrderArg> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_td_mode() {
+        for (td_mode, expected) in [
+            (TdMode::Cross, "cross"),
+            (TdMode::Isolated, "isolated"),
+            (TdMode::Cash, "cash"),
+        ] {
+            let limit_order = Request::limit_order(
+                "1".into(),
+                Side::Buy,
+                InstId::EthUsdtSwap,
+                "1".into(),
+                td_mode,
+                "0.1".into(),
+                "100".into(),
+            );
+            let json = serde_json::to_value(&limit_order).unwrap();
+            assert_eq!(json["args"][0]["tdMode"], expected);
+            assert_eq!(json["args"][0]["ordType"], "limit");
+
+            let market_order = Action::MarketOrder {
+                request_id: "2".into(),
+                side: Side::Sell,
+                inst_id: InstId::EthUsdtSwap,
+                client_order_id: "2".into(),
+                td_mode,
+                size: "0.1".into(),
+                reduce_only: false,
+            };
+            let json: serde_json::Value =
+                serde_json::from_str(market_order.to_message().to_text().unwrap()).unwrap();
+            assert_eq!(json["args"][0]["tdMode"], expected);
+            assert_eq!(json["args"][0]["ordType"], "market");
+        }
+    }
+
+    #[test]
+    fn test_order_reduce_only() {
+        let limit_order = Action::LimitOrder {
+            request_id: "1".into(),
+            side: Side::Sell,
+            inst_id: InstId::EthUsdtSwap,
+            client_order_id: "1".into(),
+            td_mode: TdMode::Cross,
+            size: "0.1".into(),
+            price: "100".into(),
+            reduce_only: true,
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(limit_order.to_message().to_text().unwrap()).unwrap();
+        assert_eq!(json["args"][0]["reduceOnly"], true);
+
+        // 非只减仓的订单不发送该字段
+        let market_order = Request::market_order(
+            "2".into(),
+            Side::Buy,
+            InstId::EthUsdtSwap,
+            "2".into(),
+            TdMode::Cross,
+            "0.1".into(),
+        );
+        let json = serde_json::to_value(&market_order).unwrap();
+        assert!(json["args"][0].get("reduceOnly").is_none());
+    }
+
+    #[test]
+    fn test_batch_orders() {
+        let limit_order = |request_id: &str, client_order_id: &str| Action::LimitOrder {
+            request_id: request_id.into(),
+            side: Side::Buy,
+            inst_id: InstId::EthUsdtSwap,
+            client_order_id: client_order_id.into(),
+            td_mode: TdMode::Cross,
+            size: "0.1".into(),
+            price: "100".into(),
+            reduce_only: false,
+        };
+        let batch = Action::BatchOrders(vec![limit_order("5", "1"), limit_order("6", "2")]);
+        let json: serde_json::Value =
+            serde_json::from_str(batch.to_message().to_text().unwrap()).unwrap();
+        assert_eq!(json["id"], "5");
+        assert_eq!(json["op"], "batch-orders");
+        assert_eq!(json["args"].as_array().unwrap().len(), 2);
+        assert_eq!(json["args"][1]["clOrdId"], "2");
+        assert_eq!(json["args"][1]["px"], "100");
+
+        let cancel_order = |client_order_id: &str| Action::CancelOrder {
+            request_id: "7".into(),
+            inst_id: InstId::EthUsdtSwap,
+            client_order_id: client_order_id.into(),
+        };
+        let batch = Action::BatchOrders(vec![cancel_order("1"), cancel_order("2")]);
+        let json: serde_json::Value =
+            serde_json::from_str(batch.to_message().to_text().unwrap()).unwrap();
+        assert_eq!(json["op"], "batch-cancel-orders");
+        assert_eq!(json["args"][0]["clOrdId"], "1");
+    }
+}
@@ -0,0 +1,202 @@
+use std::cmp::Ordering;
+
+use anyhow::{Result, bail};
+use crc::{CRC_32_ISO_HDLC, Crc};
+use serde::Deserialize;
+use smartstring::alias::String;
+
+use super::types::BookAction;
+use crate::types::{InstId, Level, OrderBook};
+
+/// 校验和覆盖的档数
+const CHECKSUM_DEPTH: usize = 25;
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// books频道的推送：
+/// {"asks":[["8476.98","415","0","13"]],"bids":[["8476.97","256","0","12"]],"ts":"1597026383085","checksum":-855196043}
+#[derive(Debug, Deserialize)]
+pub struct BooksData {
+    /// [价格, 数量, 已弃用, 订单数]。增量推送中数量为0表示删除该价位
+    asks: Vec<[String; 4]>,
+    bids: Vec<[String; 4]>,
+    ts: String,
+    checksum: i32,
+}
+
+/// 保留交易所推送的原始字符串，校验和须以原始字符串计算
+#[derive(Debug, Clone)]
+struct BookLevel {
+    price: f64,
+    raw: [String; 4],
+}
+
+impl BookLevel {
+    fn try_from_raw(raw: [String; 4]) -> Result<Self> {
+        Ok(Self {
+            price: raw[0].parse()?,
+            raw,
+        })
+    }
+
+    fn is_deleted(&self) -> bool {
+        self.raw[1].parse::<f64>().is_ok_and(|size| size == 0.)
+    }
+
+    fn try_to_level(&self) -> Result<Level> {
+        Ok(Level {
+            price: self.price,
+            size: self.raw[1].parse()?,
+            order_count: self.raw[3].parse()?,
+        })
+    }
+}
+
+/// 由books频道的全量与增量推送维护的本地订单簿。bids按价格从高到低、asks按价格从低到高排列
+#[derive(Debug, Default)]
+pub struct LocalBook {
+    bids: Vec<BookLevel>,
+    asks: Vec<BookLevel>,
+    /// 是否已收到全量推送
+    is_initialized: bool,
+}
+
+impl LocalBook {
+    /// 应用一次推送并校验。返回错误时本地订单簿已与交易所不一致，须重新订阅以获取全量推送
+    pub fn apply(&mut self, action: BookAction, data: BooksData) -> Result<()> {
+        let bids = parse_levels(data.bids)?;
+        let asks = parse_levels(data.asks)?;
+        match action {
+            BookAction::Snapshot => {
+                self.bids = bids;
+                self.asks = asks;
+                self.is_initialized = true;
+            }
+            BookAction::Update => {
+                if !self.is_initialized {
+                    bail!("Receive a book update before the snapshot");
+                }
+                // 买方价格从高到低排列
+                merge_levels(&mut self.bids, bids, |a, b| b.total_cmp(&a));
+                merge_levels(&mut self.asks, asks, |a, b| a.total_cmp(&b));
+            }
+        }
+
+        let checksum = self.checksum();
+        if checksum != data.checksum {
+            self.is_initialized = false;
+            bail!(
+                "Book checksum mismatch: computed {checksum}, expected {}",
+                data.checksum
+            );
+        }
+        Ok(())
+    }
+
+    /// OKX的校验和：交替取前25档的买价:买量与卖价:卖量，某侧不足25档时跳过，
+    /// 以冒号连接后计算CRC32，并视为有符号整数
+    pub fn checksum(&self) -> i32 {
+        let mut fields = vec![];
+        for i in 0..CHECKSUM_DEPTH {
+            for side in [&self.bids, &self.asks] {
+                if let Some(level) = side.get(i) {
+                    fields.push(level.raw[0].as_str());
+                    fields.push(level.raw[1].as_str());
+                }
+            }
+        }
+        CRC32.checksum(fields.join(":").as_bytes()) as i32
+    }
+
+    pub fn try_to_order_book(&self, instrument_id: InstId, ts: i64) -> Result<OrderBook> {
+        let to_levels = |levels: &[BookLevel]| -> Result<Vec<Level>> {
+            levels.iter().map(BookLevel::try_to_level).collect()
+        };
+        Ok(OrderBook {
+            ts: ts.into(),
+            instrument_id,
+            bids: to_levels(&self.bids)?,
+            asks: to_levels(&self.asks)?,
+        })
+    }
+}
+
+impl BooksData {
+    pub fn ts(&self) -> Result<i64> {
+        Ok(self.ts.parse()?)
+    }
+}
+
+fn parse_levels(raw_levels: Vec<[String; 4]>) -> Result<Vec<BookLevel>> {
+    raw_levels
+        .into_iter()
+        .map(BookLevel::try_from_raw)
+        .collect()
+}
+
+/// 将增量合并入已排序的档位：相同价位替换，数量为0时删除，新价位按顺序插入
+fn merge_levels(
+    levels: &mut Vec<BookLevel>,
+    updates: Vec<BookLevel>,
+    cmp: impl Fn(f64, f64) -> Ordering,
+) {
+    for update in updates {
+        let position = levels.binary_search_by(|level| cmp(level.price, update.price));
+        match (position, update.is_deleted()) {
+            (Ok(i), true) => {
+                levels.remove(i);
+            }
+            (Ok(i), false) => levels[i] = update,
+            (Err(_), true) => {}
+            (Err(i), false) => levels.insert(i, update),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn books_data(bids: &[[&str; 2]], asks: &[[&str; 2]], checksum: i32) -> BooksData {
+        let to_raw = |levels: &[[&str; 2]]| {
+            levels
+                .iter()
+                .map(|[price, size]| [(*price).into(), (*size).into(), "0".into(), "1".into()])
+                .collect()
+        };
+        BooksData {
+            asks: to_raw(asks),
+            bids: to_raw(bids),
+            ts: "1597026383085".into(),
+            checksum,
+        }
+    }
+
+    #[test]
+    fn test_book_checksum() {
+        // 校验字符串为 3366.1:7:3366.8:9:3366:6:3368:8
+        let snapshot = books_data(
+            &[["3366.1", "7"], ["3366", "6"]],
+            &[["3366.8", "9"], ["3368", "8"]],
+            -1881014294,
+        );
+        let mut book = LocalBook::default();
+        book.apply(BookAction::Snapshot, snapshot).unwrap();
+        assert_eq!(book.checksum(), -1881014294);
+
+        // 删除3366的买单，新增3367.5的卖单。校验字符串为 3366.1:7:3366.8:9:3367.5:2:3368:8
+        let update = books_data(&[["3366", "0"]], &[["3367.5", "2"]], -1616571040);
+        book.apply(BookAction::Update, update).unwrap();
+        let order_book = book
+            .try_to_order_book(InstId::EthUsdtSwap, 1597026383085)
+            .unwrap();
+        let prices = |levels: &[Level]| levels.iter().map(|level| level.price).collect::<Vec<_>>();
+        assert_eq!(prices(&order_book.bids), [3366.1]);
+        assert_eq!(prices(&order_book.asks), [3366.8, 3367.5, 3368.]);
+
+        // 与交易所不一致的订单簿校验失败，之后的增量也被拒绝，直到收到新的全量
+        let corrupted = books_data(&[["3366.1", "8"]], &[], -1616571040);
+        assert!(book.apply(BookAction::Update, corrupted).is_err());
+        let update = books_data(&[["3366.1", "7"]], &[], -1616571040);
+        assert!(book.apply(BookAction::Update, update).is_err());
+    }
+}
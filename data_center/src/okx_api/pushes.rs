@@ -1,12 +1,15 @@
 use std::u64;
 
-use anyhow::{Ok, Result, anyhow};
+use anyhow::{Ok, Result, anyhow, bail};
 use serde::Deserialize;
 use serde_json::value::RawValue;
 use smartstring::alias::String;
 
-use super::types::*;
-use crate::types::{Bbo, InstId, OrderPush, OrderPushType, Side, Trade};
+use super::{books::BooksData, types::*};
+use crate::types::{
+    Bbo, FundingRate, InstId, Level, MarkPrice, OpenInterest, OrderOp, OrderPush, OrderPushType,
+    PositionPush, Side, Trade,
+};
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -19,22 +22,109 @@ pub struct Arg {
 pub struct Push<'a> {
     pub event: Option<String>,
     pub arg: Arg,
+    /// 仅books频道有，区分全量与增量
+    #[serde(default)]
+    pub action: Option<BookAction>,
     #[serde(borrow)]
-    pub data: Option<[&'a RawValue; 1]>,
+    pub data: Option<Vec<&'a RawValue>>,
+}
+
+/// 下单、改单、撤单请求的回复：
+/// {"id":"1512","op":"order","code":"1","msg":"","data":[{"clOrdId":"1","ordId":"","sCode":"51008","sMsg":"Insufficient balance"}]}
+#[derive(Debug, Deserialize)]
+pub struct OpResponse {
+    /// 请求中的id
+    #[serde(default)]
+    pub id: String,
+    pub op: String,
+    pub code: String,
+    pub msg: String,
+    #[serde(default)]
+    pub data: Vec<OpResponseData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpResponseData {
+    #[serde(default)]
+    pub cl_ord_id: String,
+    pub s_code: String,
+    pub s_msg: String,
+}
+
+impl OpResponse {
+    /// 每个订单的结果对应一条数据：成功时为OrderAck，失败时为OrderError。非订单请求返回空。
+    ///
+    /// 批量请求只有一个id，即第一个请求的id，各订单的数据都带有该id，以order_id区分
+    pub fn into_data(self) -> Vec<crate::types::Data> {
+        let op = match self.op.as_str() {
+            "order" | "batch-orders" => OrderOp::Place,
+            "amend-order" | "batch-amend-orders" => OrderOp::Amend,
+            "cancel-order" | "batch-cancel-orders" => OrderOp::Cancel,
+            _ => return vec![],
+        };
+        if self.data.is_empty() {
+            tracing::warn!(
+                "Op response {} without order results: code {}, msg {}",
+                self.id,
+                self.code,
+                self.msg
+            );
+        }
+        self.data
+            .into_iter()
+            .filter_map(|item| {
+                let Some(order_id) = item.cl_ord_id.parse().ok() else {
+                    tracing::warn!("Op response without cl_ord_id: {item:?}");
+                    return None;
+                };
+                let request_id = self.id.clone();
+                let data = if item.s_code == "0" {
+                    crate::types::Data::OrderAck {
+                        request_id,
+                        order_id,
+                    }
+                } else {
+                    crate::types::Data::OrderError {
+                        request_id,
+                        order_id,
+                        op,
+                        code: item.s_code,
+                        msg: item.s_msg,
+                    }
+                };
+                Some(data)
+            })
+            .collect()
+    }
 }
 
 pub enum OkxData {
     Trades(TradesData),
     BboTbt(InstId, DepthData),
     Orders(InstId, OrdersData),
+    /// 无持仓时为None
+    Positions(InstId, Option<PositionsData>),
+    FundingRate(FundingRateData),
+    OpenInterest(OpenInterestData),
+    MarkPrice(MarkPriceData),
+    /// 须应用到本地订单簿上，不能直接转换为Data
+    Books(InstId, BookAction, BooksData),
 }
 
 impl OkxData {
     pub fn try_from_push(push: Push) -> Result<Self> {
-        let raw_data = push.data.ok_or(anyhow!("Push without data: {push:#?}"))?;
-        let raw_data = *raw_data
-            .first()
+        let raw_data = push
+            .data
+            .as_ref()
             .ok_or(anyhow!("Push without data: {push:#?}"))?;
+        let Some(raw_data) = raw_data.first() else {
+            // 无持仓时，持仓频道推送空的data
+            if push.arg.channel == Channel::Positions {
+                return Ok(OkxData::Positions(push.arg.inst_id, None));
+            }
+            return Err(anyhow!("Push without data: {:?}", push.arg));
+        };
         let raw_data_str = raw_data.get();
         match push.arg.channel {
             Channel::Trades => {
@@ -49,6 +139,29 @@ impl OkxData {
                 let data = serde_json::from_str(raw_data_str)?;
                 Ok(OkxData::Orders(push.arg.inst_id, data))
             }
+            Channel::Positions => {
+                let data = serde_json::from_str(raw_data_str)?;
+                Ok(OkxData::Positions(push.arg.inst_id, Some(data)))
+            }
+            Channel::FundingRate => {
+                let data = serde_json::from_str(raw_data_str)?;
+                Ok(OkxData::FundingRate(data))
+            }
+            Channel::OpenInterest => {
+                let data = serde_json::from_str(raw_data_str)?;
+                Ok(OkxData::OpenInterest(data))
+            }
+            Channel::MarkPrice => {
+                let data = serde_json::from_str(raw_data_str)?;
+                Ok(OkxData::MarkPrice(data))
+            }
+            Channel::Books => {
+                let action = push
+                    .action
+                    .ok_or(anyhow!("Books push without action: {:?}", push.arg))?;
+                let data = serde_json::from_str(raw_data_str)?;
+                Ok(OkxData::Books(push.arg.inst_id, action, data))
+            }
         }
     }
 }
@@ -68,6 +181,32 @@ impl crate::types::Data {
                 let order_push = data.try_into_order_push(inst_id)?;
                 Ok(Self::Order(order_push))
             }
+            OkxData::Positions(inst_id, data) => {
+                let position_push = match data {
+                    Some(data) => data.try_into_position_push(inst_id)?,
+                    None => PositionPush {
+                        ts: chrono::Utc::now().timestamp_millis(),
+                        inst_id,
+                        size: 0.,
+                    },
+                };
+                Ok(Self::Position(position_push))
+            }
+            OkxData::FundingRate(data) => {
+                let funding_rate = data.try_into_funding_rate()?;
+                Ok(Self::FundingRate(funding_rate))
+            }
+            OkxData::OpenInterest(data) => {
+                let open_interest = data.try_into_open_interest()?;
+                Ok(Self::OpenInterest(open_interest))
+            }
+            OkxData::MarkPrice(data) => {
+                let mark_price = data.try_into_mark_price()?;
+                Ok(Self::MarkPrice(mark_price))
+            }
+            OkxData::Books(inst_id, ..) => Err(anyhow!(
+                "Books push of {inst_id:?} must be applied to a local book"
+            )),
         }
     }
 
@@ -124,22 +263,35 @@ pub struct DepthData {
 }
 
 impl DepthData {
+    /// 某一侧为空或最优档数量为0时返回错误，由调用者跳过该帧，而不是生成价格或数量为0的Bbo
     pub fn try_into_bbo(self, instrument_id: InstId) -> Result<Bbo> {
         let ts = self.ts.parse::<i64>()?;
 
         Ok(Bbo {
-            ts,
+            ts: ts.into(),
             instrument_id,
-            bid_price: self.bids[0][0].parse::<f64>()?,
-            bid_size: self.bids[0][1].parse::<f64>()?,
-            bid_order_count: self.bids[0][3].parse::<i32>()?,
-            ask_price: self.asks[0][0].parse::<f64>()?,
-            ask_size: self.asks[0][1].parse::<f64>()?,
-            ask_order_count: self.asks[0][3].parse::<i32>()?,
+            best_bid: parse_top_level(&self.bids, "bid")?,
+            best_ask: parse_top_level(&self.asks, "ask")?,
         })
     }
 }
 
+/// 解析最优档：[价格, 数量, 已弃用, 订单数]
+fn parse_top_level(levels: &[[String; 4]], side: &str) -> Result<Level> {
+    let Some(level) = levels.first() else {
+        bail!("Empty {side} side in bbo-tbt push");
+    };
+    let level = Level {
+        price: level[0].parse::<f64>()?,
+        size: level[1].parse::<f64>()?,
+        order_count: level[3].parse::<i32>()?,
+    };
+    if level.size <= 0. {
+        bail!("Zero-size best {side} in bbo-tbt push: {level:?}");
+    }
+    Ok(level)
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OrdersData {
@@ -155,6 +307,10 @@ pub struct OrdersData {
     amend_result: String,
     exec_type: String,
     ord_type: OrdType,
+    /// "true" 或 "false"
+    #[serde(default)]
+    reduce_only: String,
+    u_time: String,
 }
 
 impl OrdersData {
@@ -185,6 +341,7 @@ impl OrdersData {
         };
 
         Ok(OrderPush {
+            ts: self.u_time.parse()?,
             order_id: self
                 .cl_ord_id
                 .parse()
@@ -199,6 +356,327 @@ impl OrdersData {
             ord_type: self.ord_type,
             exec_type,
             push_type,
+            reduce_only: self.reduce_only == "true",
+        })
+    }
+}
+
+/// 单向持仓模式下的持仓，pos带有方向
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionsData {
+    pos: String,
+    u_time: String,
+}
+
+impl PositionsData {
+    pub fn try_into_position_push(self, inst_id: InstId) -> Result<PositionPush> {
+        // 平仓后pos可能为空字符串
+        let size = if self.pos.is_empty() {
+            0.
+        } else {
+            self.pos.parse::<f64>()?
+        };
+        Ok(PositionPush {
+            ts: self.u_time.parse()?,
+            inst_id,
+            size,
+        })
+    }
+}
+
+/// 资金费率推送，数值均为字符串
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FundingRateData {
+    inst_id: InstId,
+    funding_rate: String,
+    funding_time: String,
+    ts: String,
+}
+
+impl FundingRateData {
+    pub fn try_into_funding_rate(self) -> Result<FundingRate> {
+        Ok(FundingRate {
+            ts: self.ts.parse()?,
+            inst_id: self.inst_id,
+            funding_rate: self.funding_rate.parse()?,
+            funding_time: self.funding_time.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenInterestData {
+    inst_id: InstId,
+    /// 以张为单位
+    oi: String,
+    /// 以币为单位
+    oi_ccy: String,
+    ts: String,
+}
+
+impl OpenInterestData {
+    pub fn try_into_open_interest(self) -> Result<OpenInterest> {
+        Ok(OpenInterest {
+            ts: self.ts.parse()?,
+            inst_id: self.inst_id,
+            open_interest: self.oi.parse()?,
+            open_interest_ccy: self.oi_ccy.parse()?,
         })
     }
 }
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkPriceData {
+    inst_id: InstId,
+    mark_px: String,
+    ts: String,
+}
+
+impl MarkPriceData {
+    pub fn try_into_mark_price(self) -> Result<MarkPrice> {
+        Ok(MarkPrice {
+            ts: self.ts.parse()?,
+            inst_id: self.inst_id,
+            mark_px: self.mark_px.parse()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Data;
+
+    #[test]
+    fn test_bbo_tbt_into_levels() {
+        let text = r#"{"arg":{"channel":"bbo-tbt","instId":"ETH-USDT-SWAP"},"data":[{"asks":[["2536.52","40","0","3"]],"bids":[["2535.19","31","0","2"]],"ts":"1670324386802","seqId":1}]}"#;
+        let push: Push = serde_json::from_str(text).unwrap();
+        let Data::Bbo(bbo) = Data::try_from_okx_push(push).unwrap() else {
+            panic!("Expected bbo");
+        };
+        assert_eq!(bbo.ts.as_millis(), 1670324386802);
+        assert_eq!(
+            bbo.best_bid,
+            Level {
+                price: 2535.19,
+                size: 31.,
+                order_count: 2,
+            }
+        );
+        assert_eq!(
+            bbo.best_ask,
+            Level {
+                price: 2536.52,
+                size: 40.,
+                order_count: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_bbo_tbt_empty_side() {
+        let parse = |bids: &str, asks: &str| {
+            let text = format!(
+                r#"{{"arg":{{"channel":"bbo-tbt","instId":"ETH-USDT-SWAP"}},"data":[{{"asks":{asks},"bids":{bids},"ts":"1670324386802","seqId":1}}]}}"#
+            );
+            let push: Push = serde_json::from_str(&text).unwrap();
+            Data::try_from_okx_push(push)
+        };
+
+        // 某一侧为空或最优档数量为0时不生成Bbo，由行情流跳过该帧
+        let err = parse("[]", r#"[["2536.52","40","0","3"]]"#).unwrap_err();
+        assert!(err.to_string().contains("Empty bid side"));
+        let err = parse(
+            r#"[["2535.19","31","0","2"]]"#,
+            r#"[["2536.52","0","0","0"]]"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Zero-size best ask"));
+
+        let Data::Bbo(bbo) = parse(
+            r#"[["2535.19","31","0","2"]]"#,
+            r#"[["2536.52","40","0","3"]]"#,
+        )
+        .unwrap() else {
+            panic!("Expected bbo");
+        };
+        assert_eq!(bbo.best_bid.price, 2535.19);
+        assert_eq!(bbo.best_ask.size, 40.);
+    }
+
+    #[test]
+    fn test_order_push_type() {
+        let push_type = |state: &str, fill_sz: &str, cancel_source: &str, amend_result: &str| {
+            let text = format!(
+                r#"{{"arg":{{"channel":"orders","instType":"SWAP","instId":"ETH-USDT-SWAP"}},"data":[{{"clOrdId":"42","state":"{state}","side":"buy","px":"2535.19","sz":"2","fillSz":"{fill_sz}","accFillSz":"{fill_sz}","fillPnl":"0","cancelSource":"{cancel_source}","amendResult":"{amend_result}","execType":"M","ordType":"limit","reduceOnly":"false","uTime":"1670324386802"}}]}}"#
+            );
+            let push: Push = serde_json::from_str(&text).unwrap();
+            let Data::Order(order_push) = Data::try_from_okx_push(push).unwrap() else {
+                panic!("Expected order push");
+            };
+            assert_eq!(order_push.order_id, 42);
+            assert_eq!(order_push.inst_id, InstId::EthUsdtSwap);
+            assert!(matches!(order_push.ord_type, OrdType::Limit));
+            order_push.push_type
+        };
+
+        assert!(matches!(
+            push_type("live", "0", "", ""),
+            OrderPushType::Placed
+        ));
+        assert!(matches!(
+            push_type("live", "0", "", "0"),
+            OrderPushType::Amended
+        ));
+        assert!(matches!(
+            push_type("partially_filled", "1", "", ""),
+            OrderPushType::Fill
+        ));
+        assert!(matches!(
+            push_type("filled", "2", "", ""),
+            OrderPushType::Fill
+        ));
+        assert!(matches!(
+            push_type("canceled", "0", "1", ""),
+            OrderPushType::Canceled
+        ));
+    }
+
+    #[test]
+    fn test_order_error_response() {
+        let text = r#"{"id":"1512","op":"order","data":[{"clOrdId":"42","ordId":"","tag":"","ts":"1695190491421","sCode":"51008","sMsg":"Order failed. Insufficient USDT balance in account."}],"code":"1","msg":"","inTime":"1695190491421339","outTime":"1695190491423240"}"#;
+        let response: OpResponse = serde_json::from_str(text).unwrap();
+        let [
+            Data::OrderError {
+                request_id,
+                order_id,
+                op: OrderOp::Place,
+                code,
+                msg,
+            },
+        ] = &response.into_data()[..]
+        else {
+            panic!("Expected order error");
+        };
+        assert_eq!(request_id, "1512");
+        assert_eq!(*order_id, 42);
+        assert_eq!(code, "51008");
+        assert_eq!(msg, "Order failed. Insufficient USDT balance in account.");
+    }
+
+    #[test]
+    fn test_order_ack_response() {
+        let text = r#"{"id":"1512","op":"cancel-order","data":[{"clOrdId":"42","ordId":"12345689","ts":"1695190491421","sCode":"0","sMsg":""}],"code":"0","msg":"","inTime":"1695190491421339","outTime":"1695190491423240"}"#;
+        let response: OpResponse = serde_json::from_str(text).unwrap();
+        assert!(matches!(
+            &response.into_data()[..],
+            [Data::OrderAck { request_id, order_id: 42 }] if request_id == "1512"
+        ));
+    }
+
+    #[test]
+    fn test_batch_order_response() {
+        // 批量请求中第二个订单失败，每个订单各有一条结果
+        let text = r#"{"id":"7","op":"batch-orders","data":[{"clOrdId":"1","ordId":"101","sCode":"0","sMsg":""},{"clOrdId":"2","ordId":"","sCode":"51008","sMsg":"Insufficient balance"}],"code":"2","msg":"","inTime":"1695190491421339","outTime":"1695190491423240"}"#;
+        let response: OpResponse = serde_json::from_str(text).unwrap();
+        let data = response.into_data();
+        assert_eq!(data.len(), 2);
+        assert!(matches!(
+            &data[0],
+            Data::OrderAck { request_id, order_id: 1 } if request_id == "7"
+        ));
+        assert!(matches!(
+            &data[1],
+            Data::OrderError { request_id, order_id: 2, op: OrderOp::Place, code, .. }
+                if request_id == "7" && code == "51008"
+        ));
+    }
+
+    #[test]
+    fn test_positions_push() {
+        let text = r#"{"arg":{"channel":"positions","uid":"77982378738415879","instType":"SWAP","instId":"ETH-USDT-SWAP"},"data":[{"adl":"1","availPos":"","avgPx":"2566.31","cTime":"1619507758793","ccy":"USDT","instId":"ETH-USDT-SWAP","instType":"SWAP","lever":"10","mgnMode":"cross","pos":"-2","posSide":"net","uTime":"1619507761462","upl":"0.1"}]}"#;
+        let push: Push = serde_json::from_str(text).unwrap();
+        let Data::Position(position) = Data::try_from_okx_push(push).unwrap() else {
+            panic!("Expected position");
+        };
+        assert_eq!(
+            position,
+            PositionPush {
+                ts: 1619507761462,
+                inst_id: InstId::EthUsdtSwap,
+                size: -2.,
+            }
+        );
+
+        // 无持仓时data为空
+        let text = r#"{"arg":{"channel":"positions","uid":"77982378738415879","instType":"SWAP","instId":"ETH-USDT-SWAP"},"data":[]}"#;
+        let push: Push = serde_json::from_str(text).unwrap();
+        let Data::Position(position) = Data::try_from_okx_push(push).unwrap() else {
+            panic!("Expected position");
+        };
+        assert_eq!(position.size, 0.);
+    }
+
+    #[test]
+    fn test_push_is_not_op_response() {
+        let text = r#"{"event":"subscribe","arg":{"channel":"bbo-tbt","instId":"ETH-USDT-SWAP"},"connId":"a4d3ae55"}"#;
+        assert!(serde_json::from_str::<OpResponse>(text).is_err());
+    }
+
+    #[test]
+    fn test_funding_rate_push() {
+        let text = r#"{"arg":{"channel":"funding-rate","instId":"ETH-USDT-SWAP"},"data":[{"fundingRate":"0.0001875391284828","fundingTime":"1700726400000","impactValue":"","instId":"ETH-USDT-SWAP","instType":"SWAP","interestRate":"","maxFundingRate":"0.00375","method":"current_period","minFundingRate":"-0.00375","nextFundingRate":"","nextFundingTime":"1700755200000","premium":"0.0001233824646391","settFundingRate":"0.0001699799259033","settState":"settled","ts":"1700724675402"}]}"#;
+        let push: Push = serde_json::from_str(text).unwrap();
+        let Data::FundingRate(funding_rate) = Data::try_from_okx_push(push).unwrap() else {
+            panic!("Expected funding rate");
+        };
+        assert_eq!(
+            funding_rate,
+            FundingRate {
+                ts: 1700724675402,
+                inst_id: InstId::EthUsdtSwap,
+                funding_rate: 0.0001875391284828,
+                funding_time: 1700726400000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_open_interest_push() {
+        let text = r#"{"arg":{"channel":"open-interest","instId":"BTC-USDT-SWAP"},"data":[{"instId":"BTC-USDT-SWAP","instType":"SWAP","oi":"2216113.01","oiCcy":"22161.1301","oiUsd":"1468781256.56","ts":"1700724675402"}]}"#;
+        let push: Push = serde_json::from_str(text).unwrap();
+        let Data::OpenInterest(open_interest) = Data::try_from_okx_push(push).unwrap() else {
+            panic!("Expected open interest");
+        };
+        assert_eq!(
+            open_interest,
+            OpenInterest {
+                ts: 1700724675402,
+                inst_id: InstId::BtcUsdtSwap,
+                open_interest: 2216113.01,
+                open_interest_ccy: 22161.1301,
+            }
+        );
+    }
+
+    #[test]
+    fn test_mark_price_push() {
+        let text = r#"{"arg":{"channel":"mark-price","instId":"ETH-USDT-SWAP"},"data":[{"instType":"SWAP","instId":"ETH-USDT-SWAP","markPx":"2536.42","ts":"1597026383085"}]}"#;
+        let push: Push = serde_json::from_str(text).unwrap();
+        let Data::MarkPrice(mark_price) = Data::try_from_okx_push(push).unwrap() else {
+            panic!("Expected mark price");
+        };
+        assert_eq!(
+            mark_price,
+            MarkPrice {
+                ts: 1597026383085,
+                inst_id: InstId::EthUsdtSwap,
+                mark_px: 2536.42,
+            }
+        );
+    }
+}
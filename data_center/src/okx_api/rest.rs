@@ -0,0 +1,252 @@
+//! OKX的REST接口。WebSocket没有提供查询接口，重连后核对挂单等需要通过REST查询。
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Request, body::Bytes};
+use hyper_util::rt::TokioIo;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, de::DeserializeOwned};
+use smartstring::alias::String;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{ClientConfig, RootCertStore, pki_types::ServerName},
+};
+
+use super::{sign, types::OrderState};
+use crate::{
+    CONFIG,
+    types::{InstId, OrdType, OrderPush, OrderPushType, Side},
+};
+
+const REST_HOST: &str = "www.okx.com";
+/// orders-pending每页返回的最大挂单数
+const PENDING_ORDERS_PAGE_LIMIT: usize = 100;
+/// 单个REST请求从建立连接到读完回复的最长时间
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 以系统根证书建立TLS连接，所有请求共用
+static TLS_CONNECTOR: Lazy<TlsConnector> = Lazy::new(|| {
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+});
+
+#[derive(Deserialize)]
+struct RestResponse<T> {
+    code: String,
+    msg: String,
+    data: Vec<T>,
+}
+
+/// 查询该产品尚未完全成交的挂单。每页最多返回PENDING_ORDERS_PAGE_LIMIT个，逐页查询直到取完
+pub async fn get_pending_orders(inst_id: InstId, is_simu: bool) -> Result<Vec<OrderPush>> {
+    let mut orders = vec![];
+    let mut after = None;
+    loop {
+        let request_path = pending_orders_path(inst_id, after.as_deref());
+        let page: Vec<PendingOrderData> = get_private(&request_path, is_simu).await?;
+        after = next_page_cursor(&page);
+        orders.extend(page);
+        if after.is_none() {
+            break;
+        }
+    }
+    Ok(into_order_pushes(orders))
+}
+
+/// after为上一页最后一个挂单的ordId，返回比它更早的挂单
+fn pending_orders_path(inst_id: InstId, after: Option<&str>) -> std::string::String {
+    let mut request_path = format!(
+        "/api/v5/trade/orders-pending?instId={}&limit={PENDING_ORDERS_PAGE_LIMIT}",
+        inst_id.as_str()
+    );
+    if let Some(after) = after {
+        request_path.push_str(&format!("&after={after}"));
+    }
+    request_path
+}
+
+/// 本页已满时返回下一页的游标，否则已是最后一页
+fn next_page_cursor(page: &[PendingOrderData]) -> Option<String> {
+    if page.len() < PENDING_ORDERS_PAGE_LIMIT {
+        return None;
+    }
+    page.last().map(|order| order.ord_id.clone())
+}
+
+/// 没有clOrdId的挂单不是由本系统发出的，忽略；其余无法解析的挂单逐个记录错误后跳过
+fn into_order_pushes(orders: Vec<PendingOrderData>) -> Vec<OrderPush> {
+    orders
+        .into_iter()
+        .filter(|order| !order.cl_ord_id.is_empty())
+        .filter_map(|order| {
+            let cl_ord_id = order.cl_ord_id.clone();
+            order
+                .try_into_order_push()
+                .inspect_err(|e| tracing::error!("Failed to parse pending order {cl_ord_id}: {e}"))
+                .ok()
+        })
+        .collect()
+}
+
+/// 以GET请求需要签名的接口，返回data字段
+async fn get_private<T: DeserializeOwned>(request_path: &str, is_simu: bool) -> Result<Vec<T>> {
+    let timestamp = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let sign = sign(&format!("{timestamp}GET{request_path}"));
+    let mut request = Request::get(request_path)
+        .header("Host", REST_HOST)
+        .header("OK-ACCESS-KEY", CONFIG.api_key.as_str())
+        .header("OK-ACCESS-SIGN", sign)
+        .header("OK-ACCESS-TIMESTAMP", timestamp)
+        .header("OK-ACCESS-PASSPHRASE", CONFIG.passphrase.as_str());
+    if is_simu {
+        request = request.header("x-simulated-trading", "1");
+    }
+    let body = tokio::time::timeout(REQUEST_TIMEOUT, send(request.body(Empty::new())?))
+        .await
+        .context("REST request timed out")??;
+    parse_response(&body)
+}
+
+fn parse_response<T: DeserializeOwned>(body: &[u8]) -> Result<Vec<T>> {
+    let response: RestResponse<T> =
+        serde_json::from_slice(body).context("Failed to parse the REST response")?;
+    if response.code != "0" {
+        bail!("OKX REST error {}: {}", response.code, response.msg);
+    }
+    Ok(response.data)
+}
+
+async fn send(request: Request<Empty<Bytes>>) -> Result<Bytes> {
+    let tcp_stream = TcpStream::connect((REST_HOST, 443)).await?;
+    let tls_stream = TLS_CONNECTOR
+        .connect(ServerName::try_from(REST_HOST)?, tcp_stream)
+        .await?;
+    let (mut sender, conn) =
+        hyper::client::conn::http1::handshake(TokioIo::new(tls_stream)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            tracing::error!("REST connection error: {e}");
+        }
+    });
+
+    let response = sender.send_request(request).await?;
+    Ok(response.into_body().collect().await?.to_bytes())
+}
+
+/// GET /api/v5/trade/orders-pending 返回的挂单
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingOrderData {
+    /// 交易所的订单id，用于分页
+    ord_id: String,
+    cl_ord_id: String,
+    inst_id: InstId,
+    state: OrderState,
+    side: Side,
+    px: String,
+    sz: String,
+    acc_fill_sz: String,
+    /// limit、post_only、ioc等。市价单不会挂在订单簿上
+    ord_type: String,
+    /// "true" 或 "false"
+    #[serde(default)]
+    reduce_only: String,
+    u_time: String,
+}
+
+impl PendingOrderData {
+    fn try_into_order_push(self) -> Result<OrderPush> {
+        let ord_type = match self.ord_type.as_str() {
+            "market" => OrdType::Market,
+            _ => OrdType::Limit,
+        };
+        Ok(OrderPush {
+            ts: self.u_time.parse()?,
+            order_id: self.cl_ord_id.parse()?,
+            inst_id: self.inst_id,
+            state: self.state,
+            size: self.sz.parse()?,
+            filled_size: 0.,
+            acc_filled_size: self.acc_fill_sz.parse()?,
+            price: self.px.parse()?,
+            side: matches!(self.side, Side::Buy),
+            ord_type,
+            exec_type: None,
+            push_type: OrderPushType::Placed,
+            reduce_only: self.reduce_only == "true",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pending_orders() {
+        let body = r#"{"code":"0","msg":"","data":[
+            {"accFillSz":"0.1","clOrdId":"65659","instId":"ETH-USDT-SWAP","ordId":"1","ordType":"post_only","px":"2500.5","reduceOnly":"true","side":"sell","state":"partially_filled","sz":"0.3","uTime":"1700000000123"},
+            {"accFillSz":"0","clOrdId":"","instId":"ETH-USDT-SWAP","ordId":"2","ordType":"limit","px":"2400","reduceOnly":"false","side":"buy","state":"live","sz":"1","uTime":"1700000000456"}
+        ]}"#;
+        let orders: Vec<PendingOrderData> = parse_response(body.as_bytes()).unwrap();
+        let orders = into_order_pushes(orders);
+        // 没有clOrdId的挂单被忽略
+        assert_eq!(orders.len(), 1);
+        let order = &orders[0];
+        assert_eq!(order.ts, 1700000000123);
+        assert_eq!(order.order_id, 65659);
+        assert_eq!(order.price, 2500.5);
+        assert_eq!(order.size, 0.3);
+        assert_eq!(order.acc_filled_size, 0.1);
+        assert!(!order.side);
+        assert!(order.reduce_only);
+        assert!(matches!(order.ord_type, OrdType::Limit));
+
+        // 无法解析的挂单被跳过，不影响其余挂单
+        let body = r#"{"code":"0","msg":"","data":[
+            {"accFillSz":"0","clOrdId":"1","instId":"ETH-USDT-SWAP","ordId":"1","ordType":"limit","px":"","reduceOnly":"false","side":"buy","state":"live","sz":"1","uTime":"1700000000456"},
+            {"accFillSz":"0","clOrdId":"2","instId":"ETH-USDT-SWAP","ordId":"2","ordType":"limit","px":"2400","reduceOnly":"false","side":"buy","state":"live","sz":"1","uTime":"1700000000456"}
+        ]}"#;
+        let orders = into_order_pushes(parse_response(body.as_bytes()).unwrap());
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].order_id, 2);
+
+        let body = r#"{"code":"50113","msg":"Invalid Sign","data":[]}"#;
+        assert!(parse_response::<PendingOrderData>(body.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_pending_orders_pages() {
+        assert_eq!(
+            pending_orders_path(InstId::EthUsdtSwap, None),
+            "/api/v5/trade/orders-pending?instId=ETH-USDT-SWAP&limit=100"
+        );
+        assert_eq!(
+            pending_orders_path(InstId::EthUsdtSwap, Some("42")),
+            "/api/v5/trade/orders-pending?instId=ETH-USDT-SWAP&limit=100&after=42"
+        );
+
+        let order = |ord_id: usize| {
+            format!(
+                r#"{{"accFillSz":"0","clOrdId":"{ord_id}","instId":"ETH-USDT-SWAP","ordId":"{ord_id}","ordType":"limit","px":"2400","side":"buy","state":"live","sz":"1","uTime":"1700000000456"}}"#
+            )
+        };
+        let page = |len: usize| {
+            let data: Vec<_> = (0..len).map(|i| order(1000 - i)).collect();
+            let body = format!(r#"{{"code":"0","msg":"","data":[{}]}}"#, data.join(","));
+            parse_response::<PendingOrderData>(body.as_bytes()).unwrap()
+        };
+        // 满页时以最后一个挂单的ordId继续查询，不满一页时结束
+        assert_eq!(next_page_cursor(&page(100)).as_deref(), Some("901"));
+        assert_eq!(next_page_cursor(&page(99)), None);
+        assert_eq!(next_page_cursor(&page(0)), None);
+    }
+}
@@ -14,6 +14,20 @@ pub enum Channel {
     Trades,
     BboTbt,
     Orders,
+    Positions,
+    FundingRate,
+    OpenInterest,
+    MarkPrice,
+    /// 400档深度，先推送全量，之后推送增量
+    Books,
+}
+
+/// books频道推送的类型
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BookAction {
+    Snapshot,
+    Update,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
@@ -22,10 +36,17 @@ pub enum InstType {
     Swap,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
+/// 交易模式
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
 #[serde(rename_all = "kebab-case")]
 pub enum TdMode {
+    /// 全仓保证金
+    #[default]
     Cross,
+    /// 逐仓保证金
+    Isolated,
+    /// 非保证金（现货）
+    Cash,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
@@ -35,15 +56,17 @@ pub enum OrdType {
     Market,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub enum OrderState {
     Canceled,
     Live,
+    /// OKX实际推送的是partially_filled
+    #[serde(alias = "partially_filled")]
     PartiallyFilled,
     Filled,
 }
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ExecType {
     T,
     M,
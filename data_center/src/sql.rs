@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use chrono::{DateTime, Duration, Utc};
 use either::Either;
 use futures::{Stream, StreamExt};
@@ -11,17 +11,64 @@ use utils::TsStreamMerger;
 
 use crate::{
     CONFIG,
-    types::{Bbo, InstId, Level1, Level1Stream, Trade},
+    types::{
+        Bbo, Candle, FundingRate, InstId, Level1, Level1Stream, OpenInterest, OrderPush, Trade,
+    },
 };
 
 pub static POOL: Lazy<PgPool> = Lazy::new(|| {
-    let pg_host = &CONFIG.pg_host;
-    PgPoolOptions::new()
-        .max_connections(50)
-        .connect_lazy(&pg_host)
+    PoolConfig::from_env()
+        .connect_lazy(&CONFIG.pg_host)
         .unwrap()
 });
 
+/// 数据库连接池的配置，默认值见.env.example
+///
+/// 本模块的查询与写入函数都使用[`POOL`]，其配置只能通过.env或环境变量修改。
+/// with_*方法只作用于调用方自行以[`PoolConfig::connect_lazy`]创建并直接交给sqlx使用的连接池
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    /// 等待空闲连接的最长时间，超时则返回错误
+    pub acquire_timeout: std::time::Duration,
+    /// 空闲连接保留的最长时间，为None时不关闭空闲连接
+    pub idle_timeout: Option<std::time::Duration>,
+}
+
+impl PoolConfig {
+    /// 读取.env或环境变量中的配置
+    pub fn from_env() -> Self {
+        Self {
+            max_connections: CONFIG.pg_max_connections,
+            acquire_timeout: std::time::Duration::from_millis(CONFIG.pg_acquire_timeout),
+            idle_timeout: match CONFIG.pg_idle_timeout {
+                0 => None,
+                ms => Some(std::time::Duration::from_millis(ms)),
+            },
+        }
+    }
+
+    pub fn with_max_connections(mut self, max_connections: u32) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    pub fn with_acquire_timeout(mut self, acquire_timeout: std::time::Duration) -> Self {
+        self.acquire_timeout = acquire_timeout;
+        self
+    }
+
+    /// 创建连接池，连接在首次使用时才建立。需要在tokio运行时中调用
+    pub fn connect_lazy(&self, url: &str) -> Result<PgPool> {
+        let pool = PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+            .connect_lazy(url)?;
+        Ok(pool)
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct QueryOption {
     pub instruments: Vec<InstId>,
@@ -39,6 +86,12 @@ impl QueryOption {
         self
     }
 
+    pub fn with_range(mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        self.start = Some(start);
+        self.end = Some(end);
+        self
+    }
+
     pub fn with_duration(mut self, duration: Duration) -> Self {
         let end = Utc::now();
         let start = end - duration;
@@ -73,14 +126,79 @@ pub async fn insert_bbo(bbo: &Bbo) -> Result<()> {
         (ts, instrument_id, price_ask, size_ask, order_count_ask, price_bid, size_bid, order_count_bid)
         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         ON CONFLICT DO NOTHING",
-        bbo.ts,
+        bbo.ts.as_millis(),
         bbo.instrument_id.as_str(),
-        bbo.ask_price,
-        bbo.ask_size,
-        bbo.ask_order_count,
-        bbo.bid_price,
-        bbo.bid_size,
-        bbo.bid_order_count
+        bbo.best_ask.price,
+        bbo.best_ask.size,
+        bbo.best_ask.order_count,
+        bbo.best_bid.price,
+        bbo.best_bid.size,
+        bbo.best_bid.order_count
+    )
+    .execute(&*POOL)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_funding_rate(funding_rate: &FundingRate) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO okx_funding_rate
+        (ts, instrument_id, funding_rate, funding_time)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING",
+        funding_rate.ts,
+        funding_rate.inst_id.as_str(),
+        funding_rate.funding_rate,
+        funding_rate.funding_time
+    )
+    .execute(&*POOL)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_open_interest(open_interest: &OpenInterest) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO okx_open_interest
+        (ts, instrument_id, open_interest, open_interest_ccy)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING",
+        open_interest.ts,
+        open_interest.inst_id.as_str(),
+        open_interest.open_interest,
+        open_interest.open_interest_ccy
+    )
+    .execute(&*POOL)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_order_push(order_push: &OrderPush) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO okx_orders
+        (ts, order_id, instrument_id, state, size, filled_size, acc_filled_size, price, side,
+        ord_type, exec_type, push_type, reduce_only)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+        ON CONFLICT DO NOTHING",
+        order_push.ts,
+        order_push.order_id as i64,
+        order_push.inst_id.as_str(),
+        serde_plain::to_string(&order_push.state)?,
+        order_push.size,
+        order_push.filled_size,
+        order_push.acc_filled_size,
+        order_push.price,
+        order_push.side,
+        serde_plain::to_string(&order_push.ord_type)?,
+        order_push
+            .exec_type
+            .as_ref()
+            .map(serde_plain::to_string)
+            .transpose()?,
+        serde_plain::to_string(&order_push.push_type)?,
+        order_push.reduce_only
     )
     .execute(&*POOL)
     .await?;
@@ -127,6 +245,79 @@ pub fn query_trade(query_option: QueryOption) -> impl Stream<Item = Trade> + Sen
     }
 }
 
+/// 在数据库中将成交聚合为K线，比读出全部成交再用BarAggregator聚合开销小得多。ts为所在时间桶的起始时间。
+///
+/// 与BarAggregator不同，没有成交的时间桶不发出K线。K线不区分产品，因此query_option须恰好指定一个产品，
+/// 否则返回错误。interval不足1ms时同样返回错误
+pub fn query_candles(
+    query_option: QueryOption,
+    interval: Duration,
+) -> Result<impl Stream<Item = Candle> + Send> {
+    let interval = interval.num_milliseconds();
+    if interval <= 0 {
+        bail!("Candle interval must be at least 1ms, got {interval}ms");
+    }
+    if query_option.instruments.len() != 1 {
+        bail!(
+            "Candles must be queried for exactly one instrument, got {:?}",
+            query_option.instruments
+        );
+    }
+    Ok(async_stream::stream! {
+        let mut builder = sqlx::QueryBuilder::<Postgres>::new(
+            "SELECT DISTINCT bucket AS ts,
+                first_value(price) OVER w AS open,
+                max(price) OVER w AS high,
+                min(price) OVER w AS low,
+                last_value(price) OVER w AS close,
+                sum(size) OVER w AS volume
+            FROM (SELECT ts / "
+        );
+        builder.push_bind(interval);
+        builder.push(" * ");
+        builder.push_bind(interval);
+        builder.push(" AS bucket, ts, trade_id, price, size FROM okx_trades WHERE 1=1");
+
+        if !query_option.instruments.is_empty() {
+            builder.push(" AND instrument_id IN (");
+            let mut sep = builder.separated(", ");
+            for id in &query_option.instruments {
+                sep.push_bind(id.as_str());
+            }
+            sep.push_unseparated(")");
+        }
+
+        if let Some(t) = query_option.start {
+            builder.push(" AND ts >= ");
+            builder.push_bind(t.timestamp_millis());
+        }
+        if let Some(t) = query_option.end {
+            builder.push(" AND ts <= ");
+            builder.push_bind(t.timestamp_millis());
+        }
+
+        builder.push(
+            ") trades
+            WINDOW w AS (
+                PARTITION BY bucket ORDER BY ts, trade_id
+                ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING
+            )
+            ORDER BY ts ASC"
+        );
+
+        let mut rows =
+            builder.build_query_as::<Candle>()
+                   .fetch(&*POOL);
+
+        while let Some(row) = rows.next().await {
+            match row {
+                Ok(row) => yield row,
+                Err(e) => tracing::error!("Error fetching candles: {:?}", e),
+            }
+        }
+    })
+}
+
 pub fn query_bbo(query_option: QueryOption) -> impl Stream<Item = Bbo> + Send {
     async_stream::stream! {
         let mut builder = sqlx::QueryBuilder::<Postgres>::new(
@@ -166,6 +357,45 @@ pub fn query_bbo(query_option: QueryOption) -> impl Stream<Item = Bbo> + Send {
     }
 }
 
+pub fn query_order_push(query_option: QueryOption) -> impl Stream<Item = OrderPush> + Send {
+    async_stream::stream! {
+        let mut builder = sqlx::QueryBuilder::<Postgres>::new(
+            "SELECT * FROM okx_orders WHERE 1=1"
+        );
+
+        if !query_option.instruments.is_empty() {
+            builder.push(" AND instrument_id IN (");
+            let mut sep = builder.separated(", ");
+            for id in &query_option.instruments {
+                sep.push_bind(id.as_str());
+            }
+            sep.push_unseparated(")");
+        }
+
+        if let Some(t) = query_option.start {
+            builder.push(" AND ts >= ");
+            builder.push_bind(t.timestamp_millis());
+        }
+        if let Some(t) = query_option.end {
+            builder.push(" AND ts <= ");
+            builder.push_bind(t.timestamp_millis());
+        }
+
+        builder.push(" ORDER BY ts ASC");
+
+        let mut rows =
+            builder.build_query_as::<OrderPush>()
+                   .fetch(&*POOL);
+
+        while let Some(row) = rows.next().await {
+            match row {
+                Ok(row) => yield row,
+                Err(e) => tracing::error!("Error fetching order pushes: {:?}", e),
+            }
+        }
+    }
+}
+
 pub fn query_bbo_trade(query_option: QueryOption) -> impl Stream<Item = Either<Bbo, Trade>> + Send {
     let bbo_stream = query_bbo(query_option.clone());
     let trade_stream = query_trade(query_option);
@@ -178,3 +408,65 @@ pub fn query_level1(query_option: QueryOption) -> impl Stream<Item = Level1> + S
 
     Level1Stream::new(bbo_trade_stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pool_config() {
+        let config = PoolConfig {
+            max_connections: 2,
+            acquire_timeout: std::time::Duration::from_secs(1),
+            idle_timeout: None,
+        };
+        // 不会建立连接，因此不需要数据库
+        let pool = config
+            .connect_lazy("postgresql://localhost:5432/ac")
+            .unwrap();
+        assert_eq!(pool.options().get_max_connections(), 2);
+        assert_eq!(
+            pool.options().get_acquire_timeout(),
+            std::time::Duration::from_secs(1)
+        );
+        assert_eq!(pool.options().get_idle_timeout(), None);
+    }
+
+    /// 需要.env中配置的数据库
+    #[tokio::test]
+    #[ignore = "requires a database"]
+    async fn test_pool_max_connections() {
+        let pool = PoolConfig::from_env()
+            .with_max_connections(2)
+            .with_acquire_timeout(std::time::Duration::from_millis(200))
+            .connect_lazy(&CONFIG.pg_host)
+            .unwrap();
+        let first = pool.acquire().await.unwrap();
+        let _second = pool.acquire().await.unwrap();
+        // 连接用尽时等待acquire_timeout后返回错误，而不是一直等待
+        assert!(matches!(
+            pool.acquire().await,
+            Err(sqlx::Error::PoolTimedOut)
+        ));
+
+        drop(first);
+        assert!(pool.acquire().await.is_ok());
+    }
+
+    #[test]
+    fn test_query_candles_zero_interval() {
+        let query_option = QueryOption::new().with_instrument(InstId::EthUsdtSwap);
+        let result = query_candles(query_option, Duration::microseconds(500));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_query_candles_requires_one_instrument() {
+        let interval = Duration::minutes(1);
+        assert!(query_candles(QueryOption::new(), interval).is_err());
+        let query_option = QueryOption::new()
+            .with_instrument(InstId::EthUsdtSwap)
+            .with_instrument(InstId::BtcUsdtSwap);
+        assert!(query_candles(query_option, interval).is_err());
+    }
+}
@@ -4,54 +4,141 @@ use anyhow::Result;
 use chrono::{DateTime, Duration, Utc};
 use futures::{Sink, Stream, StreamExt, ready};
 use pin_project::pin_project;
-use utils::Duplex;
+use rustc_hash::FxHashMap;
+use utils::{Duplex, Timestamp, TsStreamMerger};
 
 use crate::{
     Data, delegate_sink,
-    okx_api::{OkxWsEndpoint, connect, connect_adapted},
+    exchange::Exchange,
+    okx_api::Okx,
     sql::{QueryOption, query_bbo},
-    types::{Action, InstId},
+    types::{Action, Bbo, InstId},
 };
 
+type BboStream = Pin<Box<dyn Stream<Item = Bbo>>>;
+
 // 解析订阅并建立连接，推送数据。还可接收写入以发送消息。
 // 推送的是可以直接拿去用的Data。
 #[pin_project]
 pub struct Terminal {
     history_stream: Pin<Box<dyn Stream<Item = Data>>>,
     is_history_ended: bool,
+    /// 各产品最后一条历史Bbo的时间戳。实时Bbo越过该时间戳之前，与历史重叠的实时Bbo被丢弃，
+    /// 使历史与实时数据衔接处严格递增、没有重复
+    history_last_ts: FxHashMap<InstId, Timestamp>,
 
     #[pin]
     ws_stream: Box<dyn Duplex<Action, anyhow::Error, Data>>,
 }
 
+/// Terminal在推送实时数据之前回放的历史数据
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistorySpec {
+    /// 当前时刻之前的一段时间
+    Duration(Duration),
+    /// 固定的时间范围，用于复现预热
+    Range(DateTime<Utc>, DateTime<Utc>),
+    /// 不回放历史数据，直接推送实时数据
+    None,
+}
+
+/// 时长为零时不回放历史数据
+impl From<Duration> for HistorySpec {
+    fn from(duration: Duration) -> Self {
+        if duration.is_zero() {
+            Self::None
+        } else {
+            Self::Duration(duration)
+        }
+    }
+}
+
 impl Terminal {
     pub async fn new_okx(
         is_simu: bool,
         subscribe_actions: Vec<Action>,
-        history_duration: Duration,
+        history: impl Into<HistorySpec>,
     ) -> Result<Self> {
+        Self::new(&Okx::new(is_simu), subscribe_actions, history).await
+    }
+
+    /// 先推送数据库中history指定的历史数据，再推送交易所的实时数据。history可以直接传入时长。
+    pub async fn new<E>(
+        exchange: &E,
+        subscribe_actions: Vec<Action>,
+        history: impl Into<HistorySpec>,
+    ) -> Result<Self>
+    where
+        E: Exchange<Action = Action, Data = Data> + 'static,
+    {
         for action in &subscribe_actions {
             if !matches!(
                 action,
-                Action::SubscribeOrders(_) | Action::SubscribeBboTbt(_)
+                Action::SubscribeOrders(_)
+                    | Action::SubscribePositions(_)
+                    | Action::SubscribeBboTbt(_)
+                    | Action::SubscribeMarkPrice(_)
             ) {
                 unimplemented!()
             }
-        };
-        let history_stream = query_bbo(
-            QueryOption::new()
-                .with_instrument(InstId::EthUsdtSwap)
-                .with_duration(history_duration),
-        )
-        .map(Data::Bbo);
-        let ws_stream = connect_adapted(subscribe_actions, is_simu).await?;
-
-        Ok(Self {
-            history_stream: Box::pin(history_stream),
-            is_history_ended: false,
+        }
+        let query_options = history_query_options(&subscribe_actions, history.into());
+        let history_stream = (!query_options.is_empty()).then(|| {
+            let history_streams = query_options
+                .into_iter()
+                .map(|query_option| -> BboStream { Box::pin(query_bbo(query_option)) });
+            Box::pin(merge_by_ts(history_streams).map(Data::Bbo))
+                as Pin<Box<dyn Stream<Item = Data>>>
+        });
+        let ws_stream = exchange.connect(subscribe_actions).await?;
+
+        Ok(Self::from_streams(history_stream, ws_stream))
+    }
+
+    /// history_stream为None时直接推送实时数据
+    fn from_streams(
+        history_stream: Option<Pin<Box<dyn Stream<Item = Data>>>>,
+        ws_stream: impl Duplex<Action, anyhow::Error, Data> + 'static,
+    ) -> Self {
+        let is_history_ended = history_stream.is_none();
+        Self {
+            history_stream: history_stream.unwrap_or_else(|| Box::pin(futures::stream::empty())),
+            is_history_ended,
+            history_last_ts: Default::default(),
             ws_stream: Box::new(ws_stream),
-        })
+        }
+    }
+}
+
+/// 每个订阅了Bbo的产品各一个历史数据查询
+fn history_query_options(subscribe_actions: &[Action], history: HistorySpec) -> Vec<QueryOption> {
+    let mut instruments = vec![];
+    for action in subscribe_actions {
+        if let Action::SubscribeBboTbt(inst_id) = action
+            && !instruments.contains(inst_id)
+        {
+            instruments.push(*inst_id);
+        }
     }
+    instruments
+        .into_iter()
+        .filter_map(|inst_id| {
+            let query_option = QueryOption::new().with_instrument(inst_id);
+            match history {
+                HistorySpec::Duration(duration) => Some(query_option.with_duration(duration)),
+                HistorySpec::Range(start, end) => Some(query_option.with_range(start, end)),
+                HistorySpec::None => None,
+            }
+        })
+        .collect()
+}
+
+/// 按ts顺序合并各产品的历史Bbo，使多个产品的预热数据按时间先后推送
+fn merge_by_ts(streams: impl IntoIterator<Item = BboStream>) -> BboStream {
+    streams
+        .into_iter()
+        .reduce(|merged, stream| Box::pin(TsStreamMerger::new(merged, stream)))
+        .unwrap_or_else(|| Box::pin(futures::stream::empty()))
 }
 
 impl Stream for Terminal {
@@ -65,13 +152,32 @@ impl Stream for Terminal {
 
         if !*this.is_history_ended {
             match ready!(this.history_stream.as_mut().poll_next(cx)) {
-                Some(data) => return Poll::Ready(Some(data)),
+                Some(data) => {
+                    if let Data::Bbo(bbo) = &data {
+                        this.history_last_ts.insert(bbo.instrument_id, bbo.ts);
+                    }
+                    return Poll::Ready(Some(data));
+                }
                 None => *this.is_history_ended = true,
             };
         }
 
-        let data = ready!(this.ws_stream.as_mut().poll_next(cx));
-        return Poll::Ready(data);
+        loop {
+            let Some(data) = ready!(this.ws_stream.as_mut().poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+            // 衔接处的实时Bbo若不晚于最后一条历史Bbo，则已包含在历史数据中
+            if let Data::Bbo(bbo) = &data
+                && let Some(last_ts) = this.history_last_ts.get(&bbo.instrument_id)
+            {
+                if bbo.ts <= *last_ts {
+                    tracing::debug!("Drop live bbo overlapping with history at {}", bbo.ts);
+                    continue;
+                }
+                this.history_last_ts.remove(&bbo.instrument_id);
+            }
+            return Poll::Ready(Some(data));
+        }
     }
 }
 
@@ -80,3 +186,198 @@ impl Sink<Action> for Terminal {
 
     delegate_sink!(ws_stream, Action);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, task::Context};
+
+    use super::*;
+    use crate::types::{Level, OrderPush, PositionPush};
+
+    /// 依次推送预设数据的连接，丢弃发送的Action
+    struct TestConnection(VecDeque<Data>);
+
+    impl Stream for TestConnection {
+        type Item = Data;
+
+        fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Data>> {
+            Poll::Ready(self.0.pop_front())
+        }
+    }
+
+    impl Sink<Action> for TestConnection {
+        type Error = anyhow::Error;
+
+        fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(self: Pin<&mut Self>, _item: Action) -> Result<()> {
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn create_bbo(ts: i64) -> Data {
+        let level = Level {
+            price: 100.,
+            size: 1.,
+            order_count: 1,
+        };
+        Data::Bbo(Bbo {
+            ts: ts.into(),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: level,
+            best_ask: level,
+        })
+    }
+
+    #[test]
+    fn test_history_query_options() {
+        let duration = Duration::minutes(1).into();
+        let instruments = |actions: &[Action], history| {
+            history_query_options(actions, history)
+                .into_iter()
+                .map(|query_option| query_option.instruments)
+                .collect::<Vec<_>>()
+        };
+        let actions = [
+            Action::SubscribeBboTbt(InstId::BtcUsdtSwap),
+            Action::SubscribeOrders(InstId::BtcUsdtSwap),
+        ];
+        assert_eq!(
+            instruments(&actions, duration),
+            vec![vec![InstId::BtcUsdtSwap]]
+        );
+
+        let actions = [
+            Action::SubscribeBboTbt(InstId::EthUsdtSwap),
+            Action::SubscribeBboTbt(InstId::BtcUsdtSwap),
+            Action::SubscribeBboTbt(InstId::EthUsdtSwap),
+        ];
+        assert_eq!(
+            instruments(&actions, duration),
+            vec![vec![InstId::EthUsdtSwap], vec![InstId::BtcUsdtSwap]]
+        );
+
+        // 没有订阅Bbo时不查询历史数据
+        let actions = [Action::SubscribeOrders(InstId::BtcUsdtSwap)];
+        assert!(instruments(&actions, duration).is_empty());
+        let actions = [Action::SubscribeBboTbt(InstId::BtcUsdtSwap)];
+        assert!(instruments(&actions, Duration::zero().into()).is_empty());
+        assert!(instruments(&actions, HistorySpec::None).is_empty());
+
+        let start = DateTime::from_timestamp_millis(1000).unwrap();
+        let end = DateTime::from_timestamp_millis(2000).unwrap();
+        let query_options = history_query_options(&actions, HistorySpec::Range(start, end));
+        assert_eq!(query_options[0].start, Some(start));
+        assert_eq!(query_options[0].end, Some(end));
+    }
+
+    struct TestExchange;
+
+    impl Exchange for TestExchange {
+        type Action = Action;
+        type Data = Data;
+
+        async fn connect(
+            &self,
+            _subscriptions: Vec<Action>,
+        ) -> Result<impl Duplex<Action, anyhow::Error, Data> + use<>> {
+            Ok(TestConnection(VecDeque::from([create_bbo(1000)])))
+        }
+
+        fn sign_login(&self, _timestamp: i64) -> String {
+            String::new()
+        }
+
+        async fn open_orders(&self, _inst_id: InstId) -> Result<Vec<OrderPush>> {
+            Ok(vec![])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_history_spec_none() {
+        let actions = vec![Action::SubscribeBboTbt(InstId::EthUsdtSwap)];
+        let mut terminal = Terminal::new(&TestExchange, actions, HistorySpec::None)
+            .await
+            .unwrap();
+        assert!(terminal.is_history_ended);
+        assert!(matches!(terminal.next().await, Some(Data::Bbo(_))));
+    }
+
+    #[tokio::test]
+    async fn test_merge_history_by_ts() {
+        let bbo = |inst_id, ts| {
+            let Data::Bbo(bbo) = create_bbo(ts) else {
+                unreachable!()
+            };
+            Bbo {
+                instrument_id: inst_id,
+                ..bbo
+            }
+        };
+        let eth = [1000, 2500, 4000].map(|ts| bbo(InstId::EthUsdtSwap, ts));
+        let btc = [1500, 2000, 3000].map(|ts| bbo(InstId::BtcUsdtSwap, ts));
+        let streams: [BboStream; 2] = [
+            Box::pin(futures::stream::iter(eth)),
+            Box::pin(futures::stream::iter(btc)),
+        ];
+
+        let merged: Vec<_> = merge_by_ts(streams)
+            .map(|bbo| (bbo.instrument_id, bbo.ts.as_millis()))
+            .collect()
+            .await;
+        assert_eq!(
+            merged,
+            vec![
+                (InstId::EthUsdtSwap, 1000),
+                (InstId::BtcUsdtSwap, 1500),
+                (InstId::BtcUsdtSwap, 2000),
+                (InstId::EthUsdtSwap, 2500),
+                (InstId::BtcUsdtSwap, 3000),
+                (InstId::EthUsdtSwap, 4000),
+            ]
+        );
+        assert!(merge_by_ts([]).next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_history_live_seam() {
+        let history = futures::stream::iter([1000, 2000, 3000].map(create_bbo));
+        // 历史回放期间到达的实时数据与历史重叠
+        let position = Data::Position(PositionPush {
+            ts: 2500,
+            inst_id: InstId::EthUsdtSwap,
+            size: 1.,
+        });
+        let live = [
+            create_bbo(2000),
+            position,
+            create_bbo(3000),
+            create_bbo(4000),
+            create_bbo(5000),
+        ];
+        let terminal = Terminal::from_streams(Some(Box::pin(history)), TestConnection(live.into()));
+
+        let data: Vec<_> = terminal.collect().await;
+        let bbo_ts: Vec<_> = data
+            .iter()
+            .filter_map(|data| match data {
+                Data::Bbo(bbo) => Some(bbo.ts.as_millis()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(bbo_ts, vec![1000, 2000, 3000, 4000, 5000]);
+        // 非行情数据不受影响
+        assert_eq!(data.len(), 6);
+        assert!(matches!(data[3], Data::Position(_)));
+    }
+}
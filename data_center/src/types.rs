@@ -6,29 +6,42 @@ use pin_project::pin_project;
 use serde::{Deserialize, Serialize};
 use smartstring::alias::String;
 use sqlx::{FromRow, Row, postgres::PgRow};
-use utils::Timestamped;
+use utils::{Timestamp, Timestamped};
 
-pub use crate::okx_api::types::{ExecType, InstId, OrdType, OrderState};
+pub use crate::okx_api::types::{ExecType, InstId, OrdType, OrderState, TdMode};
 
 #[derive(Serialize, Clone, Debug)]
 pub enum Action {
     SubscribeTrades(InstId),
     SubscribeBboTbt(InstId),
     SubscribeOrders(InstId),
+    SubscribePositions(InstId),
+    /// 永续合约的资金费率，公共频道
+    SubscribeFundingRate(InstId),
+    /// 合约的持仓总量，公共频道
+    SubscribeOpenInterest(InstId),
+    /// 标记价格，公共频道
+    SubscribeMarkPrice(InstId),
+    /// 400档深度，公共频道。推送的是校验通过的本地订单簿
+    SubscribeBooks(InstId),
     LimitOrder {
         request_id: String,
         side: Side,
         inst_id: InstId,
         client_order_id: String,
+        td_mode: TdMode,
         size: String,
         price: String,
+        reduce_only: bool,
     },
     MarketOrder {
         request_id: String,
         side: Side,
         inst_id: InstId,
         client_order_id: String,
+        td_mode: TdMode,
         size: String,
+        reduce_only: bool,
     },
     AmendOrder {
         request_id: String,
@@ -42,6 +55,19 @@ pub enum Action {
         inst_id: InstId,
         client_order_id: String,
     },
+    /// 以一个请求发送的多个下单、改单或撤单。OKX要求同一批中的请求为同一种操作，且不超过MAX_BATCH_SIZE个
+    BatchOrders(Vec<Action>),
+}
+
+/// 一次批量请求最多包含的订单数
+pub const MAX_BATCH_SIZE: usize = 20;
+
+/// 订单请求的种类
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderOp {
+    Place,
+    Amend,
+    Cancel,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
@@ -66,6 +92,42 @@ pub enum Data {
     Trade(Trade),
     Bbo(Bbo),
     Order(OrderPush),
+    Position(PositionPush),
+    FundingRate(FundingRate),
+    OpenInterest(OpenInterest),
+    MarkPrice(MarkPrice),
+    /// 由books频道维护的订单簿，每次更新且校验和通过后推送
+    OrderBook(OrderBook),
+    /// 下单、改单或撤单请求被交易所接受。批量请求中每个订单各有一条，request_id均为第一个请求的id
+    OrderAck {
+        request_id: String,
+        order_id: u64,
+    },
+    /// 下单、改单或撤单请求被交易所拒绝。批量请求中每个失败的订单各有一条
+    OrderError {
+        request_id: String,
+        order_id: u64,
+        op: OrderOp,
+        code: String,
+        msg: String,
+    },
+    /// 底层连接的状态变化。重连后策略的指标可能已过时，持仓也可能有偏差
+    ConnectionState(ConnState),
+}
+
+/// 连接状态
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnState {
+    /// 首次建立连接
+    Connected,
+    /// 连接因WebSocket错误中断，附带错误信息。持续出现（如认证失败）时应告警而非只等待重连
+    Disconnected(String),
+    /// 连接中断，正在重连
+    Reconnecting,
+    /// 重连成功
+    Reconnected,
+    /// 向交易所的REST请求失败，附带错误信息。例如全部撤单时无法查询挂单，挂单可能仍未撤销
+    RequestFailed(String),
 }
 
 #[derive(Debug, Clone)]
@@ -75,8 +137,10 @@ pub struct Trade {
     pub instrument_id: InstId,
     pub trade_id: String,
     pub price: f64,
+    /// 该笔成交的总数量。OKX将同一价格的多个挂单合并为一笔推送，size已是合计
     pub size: f64,
     pub side: bool,
+    /// 合并为该笔成交的挂单数，不是数量的乘数
     pub order_count: i32,
 }
 
@@ -86,7 +150,8 @@ impl Timestamped for Trade {
     }
 }
 
-#[derive(Debug, Clone)]
+/// 订单簿中的一档
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
 pub struct Level {
     pub price: f64,
     pub size: f64,
@@ -96,25 +161,47 @@ pub struct Level {
 /// "Best bid and offer"
 #[derive(Debug, Clone)]
 pub struct Bbo {
-    /// Unix millis timestamp
-    pub ts: i64,
+    pub ts: Timestamp,
     pub instrument_id: InstId,
-    pub bid_price: f64,
-    pub bid_size: f64,
-    pub bid_order_count: i32,
-    pub ask_price: f64,
-    pub ask_size: f64,
-    pub ask_order_count: i32,
+    pub best_bid: Level,
+    pub best_ask: Level,
 }
 
 impl Timestamped for Bbo {
     fn get_ts(&self) -> i64 {
-        self.ts
+        self.ts.as_millis()
+    }
+}
+
+/// 多档订单簿。bids按价格从高到低、asks按价格从低到高排列
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrderBook {
+    pub ts: Timestamp,
+    pub instrument_id: InstId,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+impl OrderBook {
+    pub fn best_bid(&self) -> Option<&Level> {
+        self.bids.first()
+    }
+
+    pub fn best_ask(&self) -> Option<&Level> {
+        self.asks.first()
+    }
+}
+
+impl Timestamped for OrderBook {
+    fn get_ts(&self) -> i64 {
+        self.ts.as_millis()
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct OrderPush {
+    /// 订单最近一次更新的时间，Unix millis timestamp
+    pub ts: i64,
     pub order_id: u64,
     pub inst_id: InstId,
     pub state: OrderState,
@@ -126,9 +213,53 @@ pub struct OrderPush {
     pub ord_type: OrdType,
     pub exec_type: Option<ExecType>,
     pub push_type: OrderPushType,
+    pub reduce_only: bool,
 }
 
-#[derive(Debug, Clone)]
+/// 交易所推送的持仓，单向持仓模式下多头为正、空头为负
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionPush {
+    /// Unix millis timestamp
+    pub ts: i64,
+    pub inst_id: InstId,
+    pub size: f64,
+}
+
+/// 永续合约的资金费率
+#[derive(Debug, Clone, PartialEq)]
+pub struct FundingRate {
+    /// Unix millis timestamp
+    pub ts: i64,
+    pub inst_id: InstId,
+    /// 当期资金费率，例如0.0001表示0.01%
+    pub funding_rate: f64,
+    /// 当期资金费的收取时间，Unix millis timestamp
+    pub funding_time: i64,
+}
+
+/// 合约的持仓总量
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenInterest {
+    /// Unix millis timestamp
+    pub ts: i64,
+    pub inst_id: InstId,
+    /// 以张为单位的持仓总量
+    pub open_interest: f64,
+    /// 以币为单位的持仓总量
+    pub open_interest_ccy: f64,
+}
+
+/// 交易所用于计算保证金与未实现盈亏的标记价格
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkPrice {
+    /// Unix millis timestamp
+    pub ts: i64,
+    pub inst_id: InstId,
+    pub mark_px: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum OrderPushType {
     Placed,
     Amended,
@@ -154,15 +285,54 @@ impl FromRow<'_, PgRow> for Trade {
 impl FromRow<'_, PgRow> for Bbo {
     fn from_row(row: &'_ PgRow) -> Result<Self, sqlx::Error> {
         Ok(Bbo {
-            ts: row.try_get("ts")?,
+            ts: row.try_get::<i64, _>("ts")?.into(),
             instrument_id: serde_plain::from_str(row.try_get::<&str, _>("instrument_id")?)
                 .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
-            ask_price: row.try_get("price_ask")?,
-            ask_size: row.try_get("size_ask")?,
-            ask_order_count: row.try_get("order_count_ask")?,
-            bid_price: row.try_get("price_bid")?,
-            bid_size: row.try_get("size_bid")?,
-            bid_order_count: row.try_get("order_count_bid")?,
+            best_ask: Level {
+                price: row.try_get("price_ask")?,
+                size: row.try_get("size_ask")?,
+                order_count: row.try_get("order_count_ask")?,
+            },
+            best_bid: Level {
+                price: row.try_get("price_bid")?,
+                size: row.try_get("size_bid")?,
+                order_count: row.try_get("order_count_bid")?,
+            },
+        })
+    }
+}
+
+impl Timestamped for OrderPush {
+    fn get_ts(&self) -> i64 {
+        self.ts
+    }
+}
+
+/// 枚举字段以serde的字符串形式存储
+impl FromRow<'_, PgRow> for OrderPush {
+    fn from_row(row: &'_ PgRow) -> Result<Self, sqlx::Error> {
+        fn decode<T: serde::de::DeserializeOwned>(s: &str) -> Result<T, sqlx::Error> {
+            serde_plain::from_str(s).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+        }
+
+        Ok(OrderPush {
+            ts: row.try_get("ts")?,
+            // 以BIGINT存储，按位转换回u64
+            order_id: row.try_get::<i64, _>("order_id")? as u64,
+            inst_id: decode(row.try_get("instrument_id")?)?,
+            state: decode(row.try_get("state")?)?,
+            size: row.try_get("size")?,
+            filled_size: row.try_get("filled_size")?,
+            acc_filled_size: row.try_get("acc_filled_size")?,
+            price: row.try_get("price")?,
+            side: row.try_get("side")?,
+            ord_type: decode(row.try_get("ord_type")?)?,
+            exec_type: row
+                .try_get::<Option<&str>, _>("exec_type")?
+                .map(decode)
+                .transpose()?,
+            push_type: decode(row.try_get("push_type")?)?,
+            reduce_only: row.try_get("reduce_only")?,
         })
     }
 }
@@ -186,6 +356,14 @@ pub struct Level1 {
     volume: f64,
     buying_volume: f64,
     selling_volume: f64,
+    /// 从数据流开始累计的成交量加权平均价，尚无成交时为None
+    vwap: Option<f64>,
+}
+
+impl Level1 {
+    pub fn vwap(&self) -> Option<f64> {
+        self.vwap
+    }
 }
 
 #[pin_project]
@@ -197,6 +375,10 @@ pub struct Level1Stream<S> {
     volume: f64,
     buying_volume: f64,
     selling_volume: f64,
+
+    /// 累计成交额与成交量，用于计算VWAP
+    cum_notional: f64,
+    cum_volume: f64,
 }
 
 impl<S> Level1Stream<S>
@@ -210,6 +392,8 @@ where
             volume: 0.,
             buying_volume: 0.,
             selling_volume: 0.,
+            cum_notional: 0.,
+            cum_volume: 0.,
         }
     }
 }
@@ -239,17 +423,21 @@ where
                         volume: *this.volume,
                         buying_volume: *this.buying_volume,
                         selling_volume: *this.selling_volume,
+                        vwap: (*this.cum_volume > 0.)
+                            .then(|| *this.cum_notional / *this.cum_volume),
                     };
                     *this.weighted_price = 0.;
                     *this.volume = 0.;
                     return Poll::Ready(Some(level1));
                 }
                 Either::Right(trade) => {
-                    let size = trade.size * trade.order_count as f64;
+                    let size = trade.size;
                     *this.weighted_price = (*this.weighted_price * *this.volume
                         + trade.price * size)
                         / (*this.volume + size);
                     *this.volume += size;
+                    *this.cum_notional += trade.price * size;
+                    *this.cum_volume += size;
                     if trade.side {
                         *this.buying_volume += trade.size
                     } else {
@@ -260,3 +448,404 @@ where
         }
     }
 }
+
+/// K线。ts为所在时间桶的起始时间
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candle {
+    /// Unix millis timestamp
+    pub ts: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+impl Candle {
+    fn new(ts: i64, price: f64, volume: f64) -> Self {
+        Self {
+            ts,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume,
+        }
+    }
+
+    /// 没有数据的时间桶，价格沿用上一根K线的收盘价
+    fn empty(ts: i64, close: f64) -> Self {
+        Self::new(ts, close, 0.)
+    }
+
+    fn update(&mut self, price: f64, volume: f64) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume += volume;
+    }
+}
+
+impl FromRow<'_, PgRow> for Candle {
+    fn from_row(row: &'_ PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Candle {
+            ts: row.try_get("ts")?,
+            open: row.try_get("open")?,
+            high: row.try_get("high")?,
+            low: row.try_get("low")?,
+            close: row.try_get("close")?,
+            volume: row.try_get("volume")?,
+        })
+    }
+}
+
+impl Timestamped for Candle {
+    fn get_ts(&self) -> i64 {
+        self.ts
+    }
+}
+
+/// 可以聚合为K线的数据
+pub trait BarSource: Timestamped {
+    fn bar_price(&self) -> f64;
+    fn bar_volume(&self) -> f64;
+}
+
+impl BarSource for Trade {
+    fn bar_price(&self) -> f64 {
+        self.price
+    }
+
+    fn bar_volume(&self) -> f64 {
+        self.size
+    }
+}
+
+/// BBO以中间价计入K线，不计成交量
+impl BarSource for Bbo {
+    fn bar_price(&self) -> f64 {
+        (self.best_bid.price + self.best_ask.price) / 2.
+    }
+
+    fn bar_volume(&self) -> f64 {
+        0.
+    }
+}
+
+/// 将数据按固定时长的时间桶聚合为K线，在时间桶结束时发出。
+/// 没有数据的时间桶发出沿用上一收盘价、成交量为0的K线；数据流结束时发出最后一根未完成的K线。
+#[pin_project]
+pub struct BarAggregator<S, T> {
+    #[pin]
+    stream: S,
+    /// 时间桶的长度，单位为毫秒
+    interval: i64,
+    /// 当前时间桶的K线
+    candle: Option<Candle>,
+    /// 属于之后时间桶的数据，待空时间桶发出后处理
+    pending: Option<T>,
+}
+
+impl<S, T> BarAggregator<S, T>
+where
+    S: Stream<Item = T>,
+    T: BarSource,
+{
+    /// interval不足1ms时panic
+    pub fn new(stream: S, interval: chrono::Duration) -> Self {
+        let interval = interval.num_milliseconds();
+        assert!(
+            interval > 0,
+            "Bar interval must be at least 1ms, got {interval}ms"
+        );
+        Self {
+            stream,
+            interval,
+            candle: None,
+            pending: None,
+        }
+    }
+}
+
+impl<S, T> Stream for BarAggregator<S, T>
+where
+    S: Stream<Item = T>,
+    T: BarSource,
+{
+    type Item = Candle;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            let item = match this.pending.take() {
+                Some(item) => item,
+                None => match ready!(this.stream.as_mut().poll_next(cx)) {
+                    Some(item) => item,
+                    None => return Poll::Ready(this.candle.take()),
+                },
+            };
+            let ts = item.get_ts();
+            let bucket_ts = ts - ts.rem_euclid(*this.interval);
+            let (price, volume) = (item.bar_price(), item.bar_volume());
+
+            match this.candle {
+                None => *this.candle = Some(Candle::new(bucket_ts, price, volume)),
+                // 乱序到达的较早数据计入当前K线
+                Some(candle) if bucket_ts <= candle.ts => candle.update(price, volume),
+                Some(candle) => {
+                    let closed = candle.clone();
+                    let next_ts = closed.ts + *this.interval;
+                    if next_ts < bucket_ts {
+                        *this.candle = Some(Candle::empty(next_ts, closed.close));
+                        *this.pending = Some(item);
+                    } else {
+                        *this.candle = Some(Candle::new(bucket_ts, price, volume));
+                    }
+                    return Poll::Ready(Some(closed));
+                }
+            }
+        }
+    }
+}
+
+/// 将连续的同产品、同方向、同价格，且距该组第一笔成交不足window的成交合并为一笔。
+/// 合并后的成交沿用第一笔的ts与trade_id，数量与订单数为各笔之和。
+/// 价格或方向改变、超出时间窗口时发出合并后的成交，数据流结束时发出最后一笔
+#[pin_project]
+pub struct TradeAggregator<S> {
+    #[pin]
+    stream: S,
+    /// 时间窗口的长度，单位为毫秒
+    window: i64,
+    /// 正在合并的成交
+    aggregated: Option<Trade>,
+}
+
+impl<S> TradeAggregator<S>
+where
+    S: Stream<Item = Trade>,
+{
+    pub fn new(stream: S, window: chrono::Duration) -> Self {
+        Self {
+            stream,
+            window: window.num_milliseconds(),
+            aggregated: None,
+        }
+    }
+}
+
+impl<S> Stream for TradeAggregator<S>
+where
+    S: Stream<Item = Trade>,
+{
+    type Item = Trade;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            let Some(trade) = ready!(this.stream.as_mut().poll_next(cx)) else {
+                return Poll::Ready(this.aggregated.take());
+            };
+
+            match this.aggregated {
+                Some(aggregated)
+                    if aggregated.instrument_id == trade.instrument_id
+                        && aggregated.side == trade.side
+                        && aggregated.price == trade.price
+                        && trade.ts - aggregated.ts < *this.window =>
+                {
+                    aggregated.size += trade.size;
+                    aggregated.order_count += trade.order_count;
+                }
+                _ => {
+                    if let Some(closed) = this.aggregated.replace(trade) {
+                        return Poll::Ready(Some(closed));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn create_trade(ts: i64, price: f64, size: f64) -> Trade {
+        Trade {
+            ts,
+            instrument_id: InstId::EthUsdtSwap,
+            trade_id: ts.to_string().into(),
+            price,
+            size,
+            side: true,
+            order_count: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_level1_vwap() {
+        let bbo = Bbo {
+            ts: Timestamp::from_millis(3000),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: Level::default(),
+            best_ask: Level::default(),
+        };
+        let items = vec![
+            Either::Right(create_trade(1000, 100., 1.)),
+            Either::Left(bbo.clone()),
+            Either::Right(create_trade(4000, 103., 2.)),
+            Either::Left(bbo),
+        ];
+        let vwaps: Vec<_> = Level1Stream::new(futures::stream::iter(items))
+            .map(|level1| level1.vwap())
+            .collect()
+            .await;
+        assert_eq!(vwaps, vec![Some(100.), Some(102.)]);
+    }
+
+    #[tokio::test]
+    async fn test_level1_vwap_before_trades() {
+        let bbo = Bbo {
+            ts: Timestamp::from_millis(1000),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: Level::default(),
+            best_ask: Level::default(),
+        };
+        let items = vec![
+            Either::Left(bbo.clone()),
+            Either::Right(create_trade(2000, 100., 1.)),
+            Either::Left(bbo),
+        ];
+        let vwaps: Vec<_> = Level1Stream::new(futures::stream::iter(items))
+            .map(|level1| level1.vwap())
+            .collect()
+            .await;
+        assert_eq!(vwaps, vec![None, Some(100.)]);
+    }
+
+    #[tokio::test]
+    async fn test_level1_volume_ignores_order_count() {
+        let trade = |ts, price, size, order_count| Trade {
+            order_count,
+            ..create_trade(ts, price, size)
+        };
+        let bbo = Bbo {
+            ts: Timestamp::from_millis(3000),
+            instrument_id: InstId::EthUsdtSwap,
+            best_bid: Level::default(),
+            best_ask: Level::default(),
+        };
+        let items = vec![
+            Either::Right(trade(1000, 100., 2., 5)),
+            Either::Right(trade(2000, 106., 1., 3)),
+            Either::Left(bbo),
+        ];
+        let level1s: Vec<_> = Level1Stream::new(futures::stream::iter(items))
+            .collect()
+            .await;
+
+        // 成交量为各笔size之和，order_count不参与加权
+        let level1 = &level1s[0];
+        assert_eq!(level1.volume, 3.);
+        assert_eq!(level1.last_price, 102.);
+        assert_eq!(level1.vwap(), Some(102.));
+    }
+
+    #[test]
+    #[should_panic(expected = "Bar interval must be at least 1ms")]
+    fn test_bar_aggregator_zero_interval() {
+        let trades = futures::stream::iter(vec![create_trade(0, 100., 1.)]);
+        BarAggregator::new(trades, chrono::Duration::microseconds(500));
+    }
+
+    #[tokio::test]
+    async fn test_bar_aggregator() {
+        const MINUTE: i64 = 60_000;
+        let trades = vec![
+            create_trade(10_000, 100., 1.),
+            create_trade(20_000, 102., 2.),
+            create_trade(30_000, 99., 1.),
+            create_trade(40_000, 101., 1.),
+            // 第二分钟没有成交
+            create_trade(2 * MINUTE + 15_000, 103., 3.),
+        ];
+        let candles: Vec<_> =
+            BarAggregator::new(futures::stream::iter(trades), chrono::Duration::minutes(1))
+                .collect()
+                .await;
+
+        assert_eq!(
+            candles,
+            vec![
+                Candle {
+                    ts: 0,
+                    open: 100.,
+                    high: 102.,
+                    low: 99.,
+                    close: 101.,
+                    volume: 5.,
+                },
+                Candle::empty(MINUTE, 101.),
+                Candle::new(2 * MINUTE, 103., 3.),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trade_aggregator() {
+        let sell = |ts, price, size| Trade {
+            side: false,
+            ..create_trade(ts, price, size)
+        };
+        let trades = vec![
+            // 同一价格的一串买单合并为一笔
+            create_trade(1000, 100., 1.),
+            create_trade(1010, 100., 2.),
+            create_trade(1020, 100., 0.5),
+            // 方向改变
+            sell(1030, 100., 1.),
+            // 价格改变
+            sell(1040, 99., 1.),
+            // 同价同向，但超出时间窗口
+            sell(2040, 99., 1.),
+        ];
+        let aggregated: Vec<_> =
+            TradeAggregator::new(futures::stream::iter(trades), chrono::Duration::seconds(1))
+                .collect()
+                .await;
+
+        let summary: Vec<_> = aggregated
+            .iter()
+            .map(|trade| {
+                (
+                    trade.ts,
+                    trade.side,
+                    trade.price,
+                    trade.size,
+                    trade.order_count,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1000, true, 100., 3.5, 3),
+                (1030, false, 100., 1., 1),
+                (1040, false, 99., 1., 1),
+                (2040, false, 99., 1., 1),
+            ]
+        );
+        assert_eq!(aggregated[0].trade_id, "1000");
+    }
+}
@@ -1,15 +1,19 @@
 use anyhow::Result;
 use futures::{Sink, Stream, ready};
 use pin_project::pin_project;
+use serde::Deserialize;
 use std::collections::VecDeque;
 use std::fmt::Display;
+use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time::Interval;
 use tokio_tungstenite::tungstenite::{self, Message};
 use utils::Duplex;
 
+use crate::{metrics::METRICS, types::ConnState};
+
 #[macro_export]
 macro_rules! delegate_sink {
     ($field:ident, $item:ty) => {
@@ -37,6 +41,70 @@ macro_rules! delegate_sink {
     };
 }
 
+/// 令牌桶限流：桶中最多有capacity个令牌，每个period补充capacity个
+pub struct RateLimiter {
+    capacity: f64,
+    period: Duration,
+    tokens: f64,
+    last_refill: tokio::time::Instant,
+    sleep: Pin<Box<tokio::time::Sleep>>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        assert!(
+            capacity > 0,
+            "The capacity of the rate limiter must be positive"
+        );
+        let now = tokio::time::Instant::now();
+        Self {
+            capacity: capacity as f64,
+            period,
+            tokens: capacity as f64,
+            last_refill: now,
+            sleep: Box::pin(tokio::time::sleep_until(now)),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = tokio::time::Instant::now();
+        let elapsed = now - self.last_refill;
+        let refilled = elapsed.as_secs_f64() / self.period.as_secs_f64() * self.capacity;
+        self.tokens = (self.tokens + refilled).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// 有可用的令牌时返回Ready，否则在补充出一个令牌后唤醒
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            self.refill();
+            // 容忍浮点误差
+            if self.tokens >= 1. - 1e-9 {
+                return Poll::Ready(());
+            }
+            let wait = self.period.mul_f64((1. - self.tokens) / self.capacity);
+            self.sleep.as_mut().reset(self.last_refill + wait);
+            ready!(self.sleep.as_mut().poll(cx));
+        }
+    }
+
+    /// 消耗一个令牌。应在poll_ready返回Ready后调用
+    pub fn acquire(&mut self) {
+        self.tokens -= 1.;
+    }
+}
+
+/// 心跳的发送策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HeartbeatPolicy {
+    /// 在给定时间内未收到任何消息时发送ping，收到任意消息即视为连接正常
+    #[default]
+    OnIdle,
+    /// 无论是否收到消息，都按固定间隔发送ping，只有收到pong才视为连接正常
+    FixedInterval,
+}
+
 /// 实现底层流的心跳机制。在给定时间未接收到新消息后发送 ping 消消息，并注册需要接收 pong 消息。若未在给定时间内收到pong，发出错误。
 #[pin_project]
 pub struct Heartbeat<S> {
@@ -46,6 +114,11 @@ pub struct Heartbeat<S> {
     pong_timer: Interval,
     is_waiting_pong: bool,
     is_started: bool,
+    /// 心跳消息，默认为文本"ping"（OKX）
+    ping_message: Message,
+    /// 上次发送ping的时间，用于计算心跳的往返时间
+    ping_sent_at: Option<Instant>,
+    policy: HeartbeatPolicy,
 }
 
 impl<S> Heartbeat<S>
@@ -61,8 +134,22 @@ where
             pong_timer,
             is_waiting_pong: false,
             is_started: false,
+            ping_message: Message::text("ping"),
+            ping_sent_at: None,
+            policy: HeartbeatPolicy::OnIdle,
         }
     }
+
+    pub fn with_policy(mut self, policy: HeartbeatPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// 设置心跳消息，例如使用协议层的Ping帧
+    pub fn with_ping_message(mut self, ping_message: Message) -> Self {
+        self.ping_message = ping_message;
+        self
+    }
 }
 
 impl<S> Sink<Message> for Heartbeat<S>
@@ -98,19 +185,22 @@ where
         // 2. 若距离上次收到消息的时间到达心跳间隔，则发送ping消息并注册计时器
         if this.ping_ticker.poll_tick(cx).is_ready() {
             tracing::debug!("Sending ping");
-            if let Err(e) = this.inner.as_mut().start_send("ping".into()) {
+            if let Err(e) = this.inner.as_mut().start_send(this.ping_message.clone()) {
                 tracing::error!("Failed to send heartbeat: {e}");
                 return Poll::Ready(None);
             }
             let _ = this.inner.as_mut().poll_flush(cx)?;
 
-            // 发送ping消息后，注册pong计时器
-            *this.is_waiting_pong = true;
-            this.pong_timer.reset();
-            // 将pong计时器注册到当前上下文
-            if this.pong_timer.poll_tick(cx).is_ready() {
-                tracing::error!("The duration of pong timer is zero");
-                return Poll::Ready(None);
+            // 发送ping消息后，注册pong计时器；若仍在等待上一次的pong，则不推迟超时
+            if !*this.is_waiting_pong {
+                *this.is_waiting_pong = true;
+                *this.ping_sent_at = Some(Instant::now());
+                this.pong_timer.reset();
+                // 将pong计时器注册到当前上下文
+                if this.pong_timer.poll_tick(cx).is_ready() {
+                    tracing::error!("The duration of pong timer is zero");
+                    return Poll::Ready(None);
+                }
             }
         }
 
@@ -119,13 +209,21 @@ where
             let Some(msg) = ready!(this.inner.as_mut().poll_next(cx)) else {
                 return Poll::Ready(None);
             };
-            // 在收到任意消息后，重置心跳计时器
-            this.ping_ticker.reset();
-            // 并且结束等待pong
-            *this.is_waiting_pong = false;
+            let is_pong = matches!(msg, Ok(ref m) if *m == Message::text("pong") || m.is_pong());
+            if *this.policy == HeartbeatPolicy::OnIdle {
+                // 在收到任意消息后，重置心跳计时器
+                this.ping_ticker.reset();
+                // 并且结束等待pong
+                *this.is_waiting_pong = false;
+            } else if is_pong {
+                *this.is_waiting_pong = false;
+            }
 
-            if matches!(msg, Ok(ref m) if *m == Message::text("pong")) {
+            if is_pong {
                 tracing::debug!("Received pong");
+                if let Some(ping_sent_at) = this.ping_sent_at.take() {
+                    METRICS.set_heartbeat_rtt(ping_sent_at.elapsed());
+                }
             } else {
                 break msg;
             }
@@ -138,19 +236,27 @@ where
 
 /// Auto reconnect when the inner Stream returns a None or the inner Sink returns an Error
 #[pin_project(project = AutoReconeectProj)]
-pub struct AutoReconnect<MkConn, Fut, S, I> {
+pub struct AutoReconnect<MkConn, Fut, S, I>
+where
+    S: Stream,
+{
     make_conn: MkConn,
     #[pin]
     conn_future: Option<Fut>,
     #[pin]
     curr_conn: Option<S>,
     sink_buf: VecDeque<I>,
+    /// 将连接状态的变化转换为Stream的数据。为None时不推送连接状态
+    state_to_item: Option<fn(ConnState) -> S::Item>,
+    /// 尚未推送的连接状态
+    state_events: VecDeque<ConnState>,
 }
 
 impl<MkConn, Fut, S, I> AutoReconnect<MkConn, Fut, S, I>
 where
     MkConn: FnMut() -> Fut,
     Fut: Future<Output = Result<S>>,
+    S: Stream,
 {
     pub async fn new(mut make_connection: MkConn) -> Result<Self> {
         let inner = make_connection().await?;
@@ -159,18 +265,38 @@ where
             conn_future: None,
             curr_conn: Some(inner),
             sink_buf: VecDeque::new(),
+            state_to_item: None,
+            state_events: VecDeque::new(),
         })
     }
+
+    /// 在Stream中推送连接状态：先推送Connected，此后每次重连推送Reconnecting与Reconnected
+    pub fn with_state_events(mut self, state_to_item: fn(ConnState) -> S::Item) -> Self {
+        self.state_to_item = Some(state_to_item);
+        self.state_events.push_back(ConnState::Connected);
+        self
+    }
 }
 
 impl<MkConn, Fut, S, I, E> AutoReconeectProj<'_, MkConn, Fut, S, I>
 where
     MkConn: FnMut() -> Fut,
     Fut: Future<Output = Result<S, E>>,
+    S: Stream,
     E: Display,
 {
+    fn push_state(&mut self, state: ConnState) {
+        if self.state_to_item.is_some() {
+            self.state_events.push_back(state);
+        }
+    }
+
     fn close_conn_and_set_conn_future(&mut self) {
         tracing::info!("Reconnecting");
+        // 重连失败后再次重连时，不重复推送Reconnecting
+        if self.curr_conn.is_some() {
+            self.push_state(ConnState::Reconnecting);
+        }
         self.curr_conn.set(None);
         self.conn_future.set(Some((self.make_conn)()));
     }
@@ -181,6 +307,8 @@ where
             match conn_res {
                 Ok(conn) => {
                     self.curr_conn.set(Some(conn));
+                    METRICS.inc_reconnects();
+                    self.push_state(ConnState::Reconnected);
 
                     tracing::info!("Reconnected");
                     return Poll::Ready(());
@@ -206,6 +334,11 @@ where
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let mut this = self.project();
         loop {
+            if let Some(state_to_item) = this.state_to_item
+                && let Some(state) = this.state_events.pop_front()
+            {
+                return Poll::Ready(Some(state_to_item(state)));
+            }
             if let Some(conn) = this.curr_conn.as_mut().as_pin_mut() {
                 match conn.poll_next(cx) {
                     Poll::Ready(Some(msg)) => return Poll::Ready(Some(msg)),
@@ -226,7 +359,7 @@ impl<MkConn, Fut, S, I, E> Sink<I> for AutoReconnect<MkConn, Fut, S, I>
 where
     MkConn: FnMut() -> Fut,
     Fut: Future<Output = Result<S, E>>,
-    S: Sink<I>,
+    S: Sink<I> + Stream,
     I: Clone,
     E: Display,
     <S as Sink<I>>::Error: Display,
@@ -390,6 +523,88 @@ mod tests {
         server.await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_heartbeat_fixed_interval() {
+        let (server_tx, client_rx) = mpsc::channel(10);
+        let (client_tx, mut server_rx) = mpsc::channel(10);
+
+        let duplex = TestDuplex {
+            rx: ReceiverStream::new(client_rx),
+            tx: client_tx,
+        };
+
+        let mut hb = Heartbeat::new(duplex, Duration::from_millis(30), Duration::from_millis(20))
+            .with_policy(HeartbeatPolicy::FixedInterval);
+
+        // Client
+        let client = tokio::spawn(async move {
+            for i in 0..20 {
+                assert!(
+                    matches!(hb.next().await, Some(Ok(ref m)) if *m == Message::text(i.to_string()))
+                );
+            }
+        });
+
+        // Server：每5ms推送一条消息，并回应ping
+        let server = tokio::spawn(async move {
+            let mut ping_count = 0;
+            for i in 0..20 {
+                server_tx.send(Message::text(i.to_string())).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                while let Ok(msg) = server_rx.try_recv() {
+                    assert_eq!(msg, Message::text("ping"));
+                    ping_count += 1;
+                    server_tx.send(Message::text("pong")).await.unwrap();
+                }
+            }
+            ping_count
+        });
+
+        client.await.unwrap();
+        // 100ms内消息不断到达，仍应按30ms的间隔发送ping
+        assert!(server.await.unwrap() >= 2);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_fixed_interval_no_pong() {
+        let (server_tx, client_rx) = mpsc::channel(100);
+        let (client_tx, mut server_rx) = mpsc::channel(100);
+
+        let duplex = TestDuplex {
+            rx: ReceiverStream::new(client_rx),
+            tx: client_tx,
+        };
+
+        // pong超时长于ping间隔，若每次ping都重置超时则永远不会触发
+        let mut hb = Heartbeat::new(duplex, Duration::from_millis(10), Duration::from_millis(30))
+            .with_policy(HeartbeatPolicy::FixedInterval);
+
+        // Client：应在超时后关闭
+        let client = tokio::spawn(async move {
+            let mut count = 0;
+            while let Some(msg) = hb.next().await {
+                assert!(msg.is_ok());
+                count += 1;
+            }
+            count
+        });
+
+        // Server：持续推送消息，但从不回应ping
+        let server = tokio::spawn(async move {
+            for i in 0..40 {
+                if server_tx.send(Message::text(i.to_string())).await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                while server_rx.try_recv().is_ok() {}
+            }
+        });
+
+        // 约200ms内持续有消息，客户端应在约40ms时因pong超时而关闭
+        assert!(client.await.unwrap() < 40);
+        server.await.unwrap();
+    }
+
     // Simple test message for AutoReconnect testing
     #[derive(Debug, Clone, PartialEq, Eq)]
     struct TestMsg(u32);
@@ -538,4 +753,42 @@ mod tests {
             assert_eq!(auto_conn.next().await.unwrap(), i);
         }
     }
+
+    #[derive(Debug, PartialEq)]
+    enum TestEvent {
+        Msg(i32),
+        State(ConnState),
+    }
+
+    #[tokio::test]
+    async fn test_auto_reconnect_state_events() {
+        let make_connection = move || {
+            let stream = stream::iter(vec![1, 2]).map(TestEvent::Msg);
+            async move { Ok::<_, anyhow::Error>(stream) }
+        };
+
+        let auto_conn: AutoReconnect<_, _, _, ()> = AutoReconnect::new(make_connection)
+            .await
+            .unwrap()
+            .with_state_events(TestEvent::State);
+        pin_mut!(auto_conn);
+
+        let mut events = vec![];
+        for _ in 0..7 {
+            events.push(auto_conn.next().await.unwrap());
+        }
+        // 连接结束后先推送Reconnecting，重连成功后推送Reconnected，再推送新连接的数据
+        assert_eq!(
+            events,
+            vec![
+                TestEvent::State(ConnState::Connected),
+                TestEvent::Msg(1),
+                TestEvent::Msg(2),
+                TestEvent::State(ConnState::Reconnecting),
+                TestEvent::State(ConnState::Reconnected),
+                TestEvent::Msg(1),
+                TestEvent::Msg(2),
+            ]
+        );
+    }
 }
@@ -1,7 +1,10 @@
 use data_center::{
     self,
-    sql::{QueryOption, query_bbo, query_level1},
-    types::InstId,
+    sql::{
+        POOL, QueryOption, insert_order_push, insert_trade, query_bbo, query_candles, query_level1,
+        query_order_push,
+    },
+    types::{Candle, ExecType, InstId, OrdType, OrderPush, OrderPushType, OrderState, Trade},
 };
 use futures::{StreamExt, pin_mut};
 
@@ -32,3 +35,115 @@ async fn test_retrieve_level1() {
     let data = level1_stream.next().await;
     assert!(dbg!(data).is_some());
 }
+
+/// 需要.env中配置的数据库已建好okx_orders表
+#[tokio::test]
+#[ignore = "requires a database"]
+async fn test_order_push_round_trip() {
+    let ts = chrono::Utc::now().timestamp_millis();
+    let order_push = OrderPush {
+        ts,
+        order_id: u64::MAX << 16 | 1,
+        inst_id: InstId::EthUsdtSwap,
+        state: OrderState::PartiallyFilled,
+        size: 2.,
+        filled_size: 0.5,
+        acc_filled_size: 1.,
+        price: 2500.5,
+        side: false,
+        ord_type: OrdType::Limit,
+        exec_type: Some(ExecType::M),
+        push_type: OrderPushType::Fill,
+        reduce_only: true,
+    };
+    insert_order_push(&order_push).await.unwrap();
+
+    let time = chrono::DateTime::from_timestamp_millis(ts).unwrap();
+    let query_option = QueryOption::new()
+        .with_instrument(InstId::EthUsdtSwap)
+        .with_range(time, time);
+    let order_pushes: Vec<_> = query_order_push(query_option).collect().await;
+    let loaded = order_pushes
+        .iter()
+        .find(|loaded| loaded.order_id == order_push.order_id)
+        .expect("The inserted order push should be read back");
+    assert_eq!(loaded.ts, ts);
+    assert!(matches!(loaded.state, OrderState::PartiallyFilled));
+    assert_eq!(loaded.size, 2.);
+    assert_eq!(loaded.filled_size, 0.5);
+    assert_eq!(loaded.acc_filled_size, 1.);
+    assert_eq!(loaded.price, 2500.5);
+    assert!(!loaded.side);
+    assert!(matches!(loaded.ord_type, OrdType::Limit));
+    assert!(matches!(loaded.exec_type, Some(ExecType::M)));
+    assert!(matches!(loaded.push_type, OrderPushType::Fill));
+    assert!(loaded.reduce_only);
+}
+
+async fn delete_candle_test_trades() {
+    sqlx::query("DELETE FROM okx_trades WHERE trade_id LIKE 'candle-test-%'")
+        .execute(&*POOL)
+        .await
+        .unwrap();
+}
+
+/// 需要.env中配置的数据库已建好okx_trades表
+#[tokio::test]
+#[ignore = "requires a database"]
+async fn test_query_candles() {
+    // 以远早于真实行情的固定时间（2000-01-01）为起点，测试前后都清除本测试写入的成交
+    let start = 946_684_800_000;
+    delete_candle_test_trades().await;
+    // (相对start的ms, price, size)
+    let raw_trades = [
+        (1_000, 100., 1.),
+        (20_000, 103., 0.5),
+        (30_000, 99., 2.),
+        (59_999, 101., 1.5),
+        (60_000, 101., 1.),
+        (200_000, 98., 3.),
+        (230_000, 97., 0.2),
+    ];
+    for (i, (offset, price, size)) in raw_trades.into_iter().enumerate() {
+        let trade = Trade {
+            ts: start + offset,
+            instrument_id: InstId::BtcUsdtSwap,
+            trade_id: format!("candle-test-{i}").as_str().into(),
+            price,
+            size,
+            side: true,
+            order_count: 1,
+        };
+        insert_trade(&trade).await.unwrap();
+    }
+
+    let query_option = QueryOption::new()
+        .with_instrument(InstId::BtcUsdtSwap)
+        .with_range(
+            chrono::DateTime::from_timestamp_millis(start).unwrap(),
+            chrono::DateTime::from_timestamp_millis(start + 239_999).unwrap(),
+        );
+    let candles: Vec<_> = query_candles(query_option, chrono::Duration::minutes(1))
+        .unwrap()
+        .collect()
+        .await;
+    delete_candle_test_trades().await;
+
+    let candle = |minute: i64, open, high, low, close, volume| Candle {
+        ts: start + minute * 60_000,
+        open,
+        high,
+        low,
+        close,
+        volume,
+    };
+    // [60s, 120s)只有一笔成交；[120s, 180s)没有成交，不发出K线
+    assert_eq!(
+        candles,
+        vec![
+            candle(0, 100., 103., 99., 101., 5.),
+            candle(1, 101., 101., 101., 101., 1.),
+            candle(3, 98., 98., 97., 97., 3.2),
+        ]
+    );
+}
@@ -1,42 +1,98 @@
 use std::time::Duration;
 
 use anyhow::Result;
-use futures::{Sink, Stream};
+use futures::{Sink, Stream, ready};
 use pin_project::pin_project;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
 use std::marker::PhantomData;
+use std::num::TryFromIntError;
+use std::ops::{Add, AddAssign, Sub};
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use tokio::time::sleep;
-use tracing_appender::rolling;
-use tracing_subscriber::{EnvFilter, prelude::*};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{Sleep, sleep};
+use tracing_appender::{
+    non_blocking::{NonBlocking, WorkerGuard},
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{EnvFilter, Layer, fmt::MakeWriter, prelude::*, registry::LookupSpan};
 
-pub fn init_tracing() -> tracing_appender::non_blocking::WorkerGuard {
-    // 滚动文件（按天）
-    let file_appender = rolling::daily("./logs", "log");
+/// 日志文件的配置
+#[derive(Debug, Clone)]
+pub struct TracingConfig {
+    /// 日志文件所在目录
+    pub dir: PathBuf,
+    /// 日志文件名前缀
+    pub prefix: String,
+    /// 滚动周期，对应rolling::minutely/hourly/daily/never
+    pub rotation: Rotation,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("./logs"),
+            prefix: "log".to_string(),
+            rotation: Rotation::DAILY,
+        }
+    }
+}
+
+impl TracingConfig {
+    /// 非阻塞的滚动文件writer + 后台线程
+    fn file_writer(&self) -> (NonBlocking, WorkerGuard) {
+        let file_appender =
+            RollingFileAppender::new(self.rotation.clone(), &self.dir, &self.prefix);
+        tracing_appender::non_blocking(file_appender)
+    }
+}
+
+pub fn init_tracing(config: TracingConfig) -> WorkerGuard {
+    init_tracing_with_format(config, false)
+}
+
+/// 与init_tracing相同，但文件层输出按行分隔的JSON，便于导入Loki/Elasticsearch等日志系统。终端输出仍为可读格式。
+pub fn init_tracing_json(config: TracingConfig) -> WorkerGuard {
+    init_tracing_with_format(config, true)
+}
 
-    // 非阻塞 writer + 后台线程
-    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
+fn init_tracing_with_format(config: TracingConfig, json: bool) -> WorkerGuard {
+    let (non_blocking, guard) = config.file_writer();
 
     // 终端输出层
     let stdout_layer = tracing_subscriber::fmt::layer()
         .with_target(false)
         .with_writer(std::io::stdout);
 
-    // 文件层（禁掉 ANSI，防止控制字符写进文件）
-    let file_layer = tracing_subscriber::fmt::layer()
-        .with_ansi(false)
-        .with_writer(non_blocking);
-
     // 组合全局 Subscriber
     tracing_subscriber::registry()
         .with(EnvFilter::from_default_env()) // 支持 RUST_LOG=info,my_crate=debug
         .with(stdout_layer)
-        .with(file_layer)
+        .with(file_layer(non_blocking, json))
         .init();
 
     guard // 别忘了把 guard 保存在 main 里！
 }
 
+/// 文件层（禁掉 ANSI，防止控制字符写进文件）
+fn file_layer<S, W>(writer: W, json: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_writer(writer);
+    if json {
+        layer.json().boxed()
+    } else {
+        layer.boxed()
+    }
+}
+
 pub fn spawn_with_retry<Fut, F>(task: F, delay: Duration) -> tokio::task::JoinHandle<()>
 where
     F: Fn() -> Fut + Send + Sync + 'static,
@@ -55,6 +111,138 @@ where
     })
 }
 
+/// 为Sink的每次发送提供重试。start_send或poll_flush出错且is_retryable判定可重试时，按指数退避重新发送，
+/// 自上次flush成功以来失败max_attempts次后返回错误。重试保持数据的顺序；flush失败时会重发上次flush成功以来的全部数据，
+/// 因此同一数据可能被inner接收多次。
+#[pin_project]
+pub struct RetrySink<S, I, F> {
+    #[pin]
+    inner: S,
+    is_retryable: F,
+    max_attempts: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍
+    backoff: Duration,
+    /// 待交给inner的数据
+    queue: VecDeque<I>,
+    /// 已交给inner但尚未flush成功的数据
+    unflushed: VecDeque<I>,
+    /// 自上次flush成功以来失败的次数
+    failed_attempts: u32,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S, I, F> RetrySink<S, I, F> {
+    pub fn new(inner: S, is_retryable: F, max_attempts: u32, backoff: Duration) -> Self {
+        Self {
+            inner,
+            is_retryable,
+            max_attempts,
+            backoff,
+            queue: VecDeque::new(),
+            unflushed: VecDeque::new(),
+            failed_attempts: 0,
+            delay: None,
+        }
+    }
+}
+
+impl<S, I, F> RetrySink<S, I, F>
+where
+    S: Sink<I>,
+    I: Clone,
+    F: Fn(&S::Error) -> bool,
+{
+    /// 处理inner的错误。可重试时设置退避并返回Ok，否则丢弃待发送的数据并返回错误
+    fn on_error(self: Pin<&mut Self>, e: S::Error) -> Result<(), S::Error> {
+        let this = self.project();
+        *this.failed_attempts += 1;
+        if *this.failed_attempts < *this.max_attempts && (this.is_retryable)(&e) {
+            let backoff = *this.backoff * 2u32.pow(*this.failed_attempts - 1);
+            tracing::warn!("Sink error, retry in {backoff:?}");
+            *this.delay = Some(Box::pin(sleep(backoff)));
+            Ok(())
+        } else {
+            *this.failed_attempts = 0;
+            this.queue.clear();
+            this.unflushed.clear();
+            Err(e)
+        }
+    }
+
+    /// 将queue中的数据依次交给inner
+    fn poll_send_queue(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), S::Error>> {
+        loop {
+            let mut this = self.as_mut().project();
+            if let Some(delay) = this.delay.as_mut() {
+                ready!(delay.as_mut().poll(cx));
+                *this.delay = None;
+            }
+            let Some(item) = this.queue.front() else {
+                return Poll::Ready(Ok(()));
+            };
+            let result = match ready!(this.inner.as_mut().poll_ready(cx)) {
+                Ok(()) => this.inner.as_mut().start_send(item.clone()),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {
+                    let item = this.queue.pop_front().unwrap();
+                    this.unflushed.push_back(item);
+                }
+                Err(e) => self.as_mut().on_error(e)?,
+            }
+        }
+    }
+}
+
+impl<S, I, F> Sink<I> for RetrySink<S, I, F>
+where
+    S: Sink<I>,
+    I: Clone,
+    F: Fn(&S::Error) -> bool,
+{
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_send_queue(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        self.project().queue.push_back(item);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        loop {
+            ready!(self.as_mut().poll_send_queue(cx))?;
+            let this = self.as_mut().project();
+            match ready!(this.inner.poll_flush(cx)) {
+                Ok(()) => {
+                    this.unflushed.clear();
+                    *this.failed_attempts = 0;
+                    return Poll::Ready(Ok(()));
+                }
+                Err(e) => {
+                    self.as_mut().on_error(e)?;
+                    // 重发未flush成功的数据，保持顺序
+                    let this = self.as_mut().project();
+                    while let Some(item) = this.unflushed.pop_back() {
+                        this.queue.push_front(item);
+                    }
+                }
+            }
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.project().inner.poll_close(cx)
+    }
+}
+
 pub trait Duplex<I, IE, O>: Sink<I, Error = IE> + Stream<Item = O> + Unpin {}
 impl<T, I, IE, O> Duplex<I, IE, O> for T where T: Sink<I, Error = IE> + Stream<Item = O> + Unpin {}
 
@@ -62,7 +250,120 @@ pub trait Timestamped {
     fn get_ts(&self) -> i64;
 }
 
-///合并两个实现Timestamped的Stream并按ts顺序发出。ts相等时优先stream1。
+/// Unix millis timestamp。以i64存储，早于1970年的时间为负数；与u64之间的转换不会回绕。
+///
+/// 两个Timestamp相减得到以毫秒计的i64时长，Timestamp与i64毫秒相加减得到新的Timestamp。
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub const fn from_millis(millis: i64) -> Self {
+        Self(millis)
+    }
+
+    pub const fn as_millis(self) -> i64 {
+        self.0
+    }
+
+    pub fn now() -> Self {
+        let millis = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_millis() as i64,
+            Err(e) => -(e.duration().as_millis() as i64),
+        };
+        Self(millis)
+    }
+
+    /// 向下取整为interval毫秒的整数倍。负数时间戳同样向更早的方向取整
+    pub fn floor_to(self, interval: i64) -> Self {
+        Self(self.0 - self.0.rem_euclid(interval))
+    }
+}
+
+impl From<i64> for Timestamp {
+    fn from(millis: i64) -> Self {
+        Self(millis)
+    }
+}
+
+impl From<Timestamp> for i64 {
+    fn from(ts: Timestamp) -> Self {
+        ts.0
+    }
+}
+
+/// 超出i64范围的u64时间戳返回错误，而不是回绕为负数
+impl TryFrom<u64> for Timestamp {
+    type Error = TryFromIntError;
+
+    fn try_from(millis: u64) -> Result<Self, Self::Error> {
+        i64::try_from(millis).map(Self)
+    }
+}
+
+/// 早于1970年的时间戳返回错误，而不是回绕为很大的正数
+impl TryFrom<Timestamp> for u64 {
+    type Error = TryFromIntError;
+
+    fn try_from(ts: Timestamp) -> Result<Self, Self::Error> {
+        u64::try_from(ts.0)
+    }
+}
+
+impl Add<i64> for Timestamp {
+    type Output = Self;
+
+    fn add(self, millis: i64) -> Self {
+        Self(self.0 + millis)
+    }
+}
+
+impl AddAssign<i64> for Timestamp {
+    fn add_assign(&mut self, millis: i64) {
+        self.0 += millis;
+    }
+}
+
+impl Sub<i64> for Timestamp {
+    type Output = Self;
+
+    fn sub(self, millis: i64) -> Self {
+        Self(self.0 - millis)
+    }
+}
+
+/// 两个时间戳之间相隔的毫秒数，可以为负
+impl Sub for Timestamp {
+    type Output = i64;
+
+    fn sub(self, other: Self) -> i64 {
+        self.0 - other.0
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Timestamped for Timestamp {
+    fn get_ts(&self) -> i64 {
+        self.0
+    }
+}
+
+/// ts相等时TsStreamMerger优先发出哪个Stream的数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TieBreak {
+    #[default]
+    PreferFirst,
+    PreferSecond,
+}
+
+///合并两个实现Timestamped的Stream并按ts顺序发出。ts相等时按tie_break决定先后，默认优先stream1。
 #[pin_project]
 pub struct TsStreamMerger<S1, S2, T1, T2, T> {
     #[pin]
@@ -73,6 +374,7 @@ pub struct TsStreamMerger<S1, S2, T1, T2, T> {
     buffer2: Option<T2>,
     stream1_ended: bool,
     stream2_ended: bool,
+    tie_break: TieBreak,
     _marker: PhantomData<T>,
 }
 
@@ -85,6 +387,10 @@ where
     T: From<T1> + From<T2>,
 {
     pub fn new(stream1: S1, stream2: S2) -> Self {
+        Self::new_with_tiebreak(stream1, stream2, TieBreak::PreferFirst)
+    }
+
+    pub fn new_with_tiebreak(stream1: S1, stream2: S2, tie_break: TieBreak) -> Self {
         Self {
             stream1,
             stream2,
@@ -92,6 +398,7 @@ where
             buffer2: None,
             stream1_ended: false,
             stream2_ended: false,
+            tie_break,
             _marker: PhantomData,
         }
     }
@@ -130,7 +437,11 @@ where
         // Compare timestamps and return items in order
         match (&this.buffer1, &this.buffer2) {
             (Some(item1), Some(item2)) => {
-                if item1.get_ts() <= item2.get_ts() {
+                let is_first = match this.tie_break {
+                    TieBreak::PreferFirst => item1.get_ts() <= item2.get_ts(),
+                    TieBreak::PreferSecond => item1.get_ts() < item2.get_ts(),
+                };
+                if is_first {
                     Poll::Ready(Some(T::from(this.buffer1.take().unwrap())))
                 } else {
                     Poll::Ready(Some(T::from(this.buffer2.take().unwrap())))
@@ -148,3 +459,511 @@ where
         }
     }
 }
+
+/// 丢弃与前一条数据的key相同的数据，例如最优价与数量都未变化的BBO
+#[pin_project]
+pub struct DedupConsecutive<S, F, K> {
+    #[pin]
+    stream: S,
+    key_fn: F,
+    last_key: Option<K>,
+}
+
+impl<S, F, K> DedupConsecutive<S, F, K>
+where
+    S: Stream,
+    F: FnMut(&S::Item) -> K,
+    K: PartialEq,
+{
+    pub fn new(stream: S, key_fn: F) -> Self {
+        Self {
+            stream,
+            key_fn,
+            last_key: None,
+        }
+    }
+}
+
+impl<S, F, K> Stream for DedupConsecutive<S, F, K>
+where
+    S: Stream,
+    F: FnMut(&S::Item) -> K,
+    K: PartialEq,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            let Some(item) = ready!(this.stream.as_mut().poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+            let key = (this.key_fn)(&item);
+            if this.last_key.as_ref() != Some(&key) {
+                *this.last_key = Some(key);
+                return Poll::Ready(Some(item));
+            }
+        }
+    }
+}
+
+/// 按数据自身的时间戳降采样：距上一条发出的数据至少interval毫秒的数据才会发出，其余丢弃
+#[pin_project]
+pub struct Throttle<S> {
+    #[pin]
+    stream: S,
+    interval: i64,
+    last_emitted_ts: Option<i64>,
+}
+
+impl<S> Throttle<S>
+where
+    S: Stream,
+    S::Item: Timestamped,
+{
+    pub fn new(stream: S, interval: Duration) -> Self {
+        Self {
+            stream,
+            interval: interval.as_millis() as i64,
+            last_emitted_ts: None,
+        }
+    }
+}
+
+impl<S> Stream for Throttle<S>
+where
+    S: Stream,
+    S::Item: Timestamped,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            let Some(item) = ready!(this.stream.as_mut().poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+            let ts = item.get_ts();
+            if this
+                .last_emitted_ts
+                .is_none_or(|last_ts| ts - last_ts >= *this.interval)
+            {
+                *this.last_emitted_ts = Some(ts);
+                return Poll::Ready(Some(item));
+            }
+        }
+    }
+}
+
+/// 数据源超过max_staleness没有推送新数据，见[`StalenessGuard`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaleData {
+    /// 最后一条数据的时间戳，尚未收到数据时为None
+    pub last_ts: Option<i64>,
+    pub max_staleness: Duration,
+}
+
+impl fmt::Display for StaleData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "No data for {:?} since ts {:?}",
+            self.max_staleness, self.last_ts
+        )
+    }
+}
+
+impl std::error::Error for StaleData {}
+
+/// 按系统时间监控数据源是否停止推送。连接与心跳正常但行情停止推送时，策略会基于过期的价格交易。
+///
+/// 超过max_staleness没有新数据时发出一次`Err(StaleData)`，使下游可以停止交易；每条数据都会重新计时，
+/// 数据恢复后照常发出。计时从创建时开始，因此需要在tokio运行时中创建
+#[pin_project]
+pub struct StalenessGuard<S> {
+    #[pin]
+    stream: S,
+    max_staleness: Duration,
+    timer: Pin<Box<Sleep>>,
+    last_ts: Option<i64>,
+    /// 本次停顿是否已发出StaleData
+    is_stale: bool,
+}
+
+impl<S> StalenessGuard<S>
+where
+    S: Stream,
+    S::Item: Timestamped,
+{
+    pub fn new(stream: S, max_staleness: Duration) -> Self {
+        Self {
+            stream,
+            max_staleness,
+            timer: Box::pin(sleep(max_staleness)),
+            last_ts: None,
+            is_stale: false,
+        }
+    }
+}
+
+impl<S> Stream for StalenessGuard<S>
+where
+    S: Stream,
+    S::Item: Timestamped,
+{
+    type Item = Result<S::Item, StaleData>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if let Poll::Ready(item) = this.stream.poll_next(cx) {
+            let Some(item) = item else {
+                return Poll::Ready(None);
+            };
+            *this.last_ts = Some(item.get_ts());
+            *this.is_stale = false;
+            let deadline = tokio::time::Instant::now() + *this.max_staleness;
+            this.timer.as_mut().reset(deadline);
+            return Poll::Ready(Some(Ok(item)));
+        }
+        if !*this.is_stale && this.timer.as_mut().poll(cx).is_ready() {
+            *this.is_stale = true;
+            return Poll::Ready(Some(Err(StaleData {
+                last_ts: *this.last_ts,
+                max_staleness: *this.max_staleness,
+            })));
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use futures::{SinkExt, StreamExt};
+
+    use super::*;
+
+    /// 将日志写入共享的内存缓冲区
+    #[derive(Clone, Default)]
+    struct BufWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for BufWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for BufWriter {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// start_send与poll_flush分别先失败若干次的Sink。flush失败时丢弃缓冲的数据，模拟连接重置。
+    #[derive(Default)]
+    struct FlakySink {
+        send_failures: usize,
+        flush_failures: usize,
+        buffer: Vec<i32>,
+        flushed: Vec<i32>,
+    }
+
+    impl Sink<i32> for FlakySink {
+        type Error = &'static str;
+
+        fn poll_ready(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, item: i32) -> Result<(), Self::Error> {
+            if self.send_failures > 0 {
+                self.send_failures -= 1;
+                return Err("send failed");
+            }
+            self.buffer.push(item);
+            Ok(())
+        }
+
+        fn poll_flush(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            if self.flush_failures > 0 {
+                self.flush_failures -= 1;
+                self.buffer.clear();
+                return Poll::Ready(Err("flush failed"));
+            }
+            let buffer = std::mem::take(&mut self.buffer);
+            self.flushed.extend(buffer);
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn retry_sink(inner: FlakySink) -> RetrySink<FlakySink, i32, fn(&&'static str) -> bool> {
+        RetrySink::new(inner, |_| true, 3, Duration::from_millis(1))
+    }
+
+    #[tokio::test]
+    async fn test_retry_sink_send() {
+        let mut sink = retry_sink(FlakySink {
+            send_failures: 1,
+            ..Default::default()
+        });
+        sink.send(1).await.unwrap();
+        sink.send(2).await.unwrap();
+        assert_eq!(sink.inner.flushed, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_retry_sink_flush() {
+        let mut sink = retry_sink(FlakySink {
+            flush_failures: 1,
+            ..Default::default()
+        });
+        sink.feed(1).await.unwrap();
+        sink.feed(2).await.unwrap();
+        sink.flush().await.unwrap();
+        // flush失败后按顺序重发
+        assert_eq!(sink.inner.flushed, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_retry_sink_gives_up() {
+        let mut sink = retry_sink(FlakySink {
+            send_failures: 3,
+            ..Default::default()
+        });
+        assert_eq!(sink.send(1).await, Err("send failed"));
+
+        let mut sink = RetrySink::new(
+            FlakySink {
+                send_failures: 1,
+                ..Default::default()
+            },
+            |e: &&str| *e != "send failed",
+            3,
+            Duration::from_millis(1),
+        );
+        assert_eq!(sink.send(1).await, Err("send failed"));
+        sink.send(2).await.unwrap();
+        assert_eq!(sink.inner.flushed, vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_dedup_consecutive() {
+        // (ts, bid_price, bid_size, ask_price, ask_size)
+        let bbos = vec![
+            (1, 100., 1., 101., 1.),
+            (2, 100., 1., 101., 1.),
+            (3, 100., 1., 101., 1.),
+            (4, 100., 2., 101., 1.),
+            (5, 100., 1., 101., 1.),
+        ];
+        let deduped: Vec<_> = DedupConsecutive::new(futures::stream::iter(bbos), |bbo| {
+            (bbo.1, bbo.2, bbo.3, bbo.4)
+        })
+        .map(|bbo| bbo.0)
+        .collect()
+        .await;
+        assert_eq!(deduped, vec![1, 4, 5]);
+    }
+
+    struct Tick(i64);
+
+    impl Timestamped for Tick {
+        fn get_ts(&self) -> i64 {
+            self.0
+        }
+    }
+
+    struct Tock(i64);
+
+    impl Timestamped for Tock {
+        fn get_ts(&self) -> i64 {
+            self.0
+        }
+    }
+
+    /// 合并后的数据，记录来源与ts
+    #[derive(Debug, PartialEq)]
+    enum Merged {
+        Tick(i64),
+        Tock(i64),
+    }
+
+    impl From<Tick> for Merged {
+        fn from(tick: Tick) -> Self {
+            Merged::Tick(tick.0)
+        }
+    }
+
+    impl From<Tock> for Merged {
+        fn from(tock: Tock) -> Self {
+            Merged::Tock(tock.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ts_stream_merger_tie_break() {
+        let ticks = || futures::stream::iter([Tick(1), Tick(2), Tick(2)]);
+        let tocks = || futures::stream::iter([Tock(2), Tock(3)]);
+
+        let merged: Vec<Merged> = TsStreamMerger::new(ticks(), tocks()).collect().await;
+        assert_eq!(
+            merged,
+            vec![
+                Merged::Tick(1),
+                Merged::Tick(2),
+                Merged::Tick(2),
+                Merged::Tock(2),
+                Merged::Tock(3),
+            ]
+        );
+
+        let merged: Vec<Merged> =
+            TsStreamMerger::new_with_tiebreak(ticks(), tocks(), TieBreak::PreferSecond)
+                .collect()
+                .await;
+        assert_eq!(
+            merged,
+            vec![
+                Merged::Tick(1),
+                Merged::Tock(2),
+                Merged::Tick(2),
+                Merged::Tick(2),
+                Merged::Tock(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timestamp_conversion() {
+        // 负数时间戳保持为负数，不会回绕为很大的正数
+        let ts = Timestamp::from_millis(-1500);
+        assert_eq!(i64::from(ts), -1500);
+        assert!(u64::try_from(ts).is_err());
+        assert_eq!(ts.floor_to(1000), Timestamp::from_millis(-2000));
+        assert_eq!(serde_json::to_string(&ts).unwrap(), "-1500");
+        assert_eq!(serde_json::from_str::<Timestamp>("-1500").unwrap(), ts);
+
+        // 超出i64范围的u64时间戳无法转换，不会回绕为负数
+        assert!(Timestamp::try_from(u64::MAX).is_err());
+        let ts = Timestamp::try_from(i64::MAX as u64).unwrap();
+        assert_eq!(ts.as_millis(), i64::MAX);
+        assert_eq!(u64::try_from(ts).unwrap(), i64::MAX as u64);
+
+        // 时间戳之差可以为负
+        let earlier = Timestamp::from_millis(1000);
+        let later = earlier + 500;
+        assert_eq!(later - earlier, 500);
+        assert_eq!(earlier - later, -500);
+        assert!(earlier < later);
+        assert_eq!(later.floor_to(1000), earlier);
+    }
+
+    #[tokio::test]
+    async fn test_throttle() {
+        let ticks = (0..=20).map(|i| Tick(1000 + i * 100));
+        let throttled: Vec<_> =
+            Throttle::new(futures::stream::iter(ticks), Duration::from_millis(500))
+                .map(|tick| tick.0)
+                .collect()
+                .await;
+        assert_eq!(throttled, vec![1000, 1500, 2000, 2500, 3000]);
+    }
+
+    #[test]
+    fn test_log_dir() {
+        let dir = std::env::temp_dir().join(format!("ac_log_test_{}", std::process::id()));
+        let config = TracingConfig {
+            dir: dir.clone(),
+            prefix: "test".to_string(),
+            rotation: Rotation::HOURLY,
+        };
+        let (writer, guard) = config.file_writer();
+        let subscriber = tracing_subscriber::registry().with(file_layer(writer, false));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("hello");
+        });
+        // 丢弃guard以确保日志写入文件
+        drop(guard);
+
+        let files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let file_name = files[0].file_name().unwrap().to_str().unwrap();
+        assert!(file_name.starts_with("test."));
+        assert!(
+            std::fs::read_to_string(&files[0])
+                .unwrap()
+                .contains("hello")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_file_layer() {
+        let writer = BufWriter::default();
+        let subscriber = tracing_subscriber::registry().with(file_layer(writer.clone(), true));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(order_id = 42, "order placed");
+        });
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+        assert!(line["timestamp"].is_string());
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["fields"]["message"], "order placed");
+        assert_eq!(line["fields"]["order_id"], 42);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_staleness_guard() {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let mut guard = StalenessGuard::new(rx, Duration::from_secs(5));
+        let next_ts = |item: Option<Result<Tick, StaleData>>| item.unwrap().unwrap().0;
+
+        tx.unbounded_send(Tick(1000)).unwrap();
+        assert_eq!(next_ts(guard.next().await), 1000);
+        // 间隔短于max_staleness时不触发，且每条数据重新计时
+        tokio::time::sleep(Duration::from_secs(4)).await;
+        tx.unbounded_send(Tick(2000)).unwrap();
+        assert_eq!(next_ts(guard.next().await), 2000);
+
+        // 停止推送超过max_staleness后触发一次
+        let start = tokio::time::Instant::now();
+        let stale = guard.next().await.unwrap().err().unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+        assert_eq!(stale.last_ts, Some(2000));
+
+        // 数据恢复后照常发出
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        tx.unbounded_send(Tick(3000)).unwrap();
+        assert_eq!(next_ts(guard.next().await), 3000);
+
+        drop(tx);
+        assert!(guard.next().await.is_none());
+    }
+}